@@ -0,0 +1,11 @@
+use std::{env, path::PathBuf};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("boros_descriptor.bin"))
+        .compile_protos(&["proto/boros/v1/boros.proto"], &["proto"])?;
+
+    Ok(())
+}
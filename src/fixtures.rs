@@ -0,0 +1,6 @@
+//! Transactions shared by the tests.
+
+/// CBOR in hex of a valid transaction.
+pub const TX_CBOR: &str = "84a300d90102828258202000fbfaa10c6b316fe4b23c60b42313fc2ad89b51c7397f82a4a5ca97bd62a9008258202000fbfaa10c6b316fe4b23c60b42313fc2ad89b51c7397f82a4a5ca97bd62a901018182581d606e2e6d54e1a27ad640786852e20c22fb982ebcee4773a2926aae4b391b00000001a1166016021a0002aa3da100d9010281825820524e506f6a872c4ee3ee6d4b9913670c4b441860b3aa5438de92a676e20f527b5840233e9119fa6a3c58ab42bc384f506c2906104ebb059ab4ea6cc79305ff46c7e194d634d23ff775f92e51246e328711e6cbf38aeda01a1885f922047323c68c04f5f6";
+/// Hash of [`TX_CBOR`].
+pub const TX_HASH: &str = "75a310ef2c5fcf8d634b1f673a57b9937d0bb4e21f4137a29ea4b50300418e3f";
@@ -19,6 +19,9 @@ use tonic::{
 use tracing::info;
 
 pub type Point = (u64, Vec<u8>);
+
+/// Origin of the chain, before its first block.
+pub const ORIGIN: Point = (0, Vec::new());
 pub type ChainSyncStream = Pin<Box<dyn Stream<Item = anyhow::Result<Event>> + Send>>;
 
 #[derive(Debug)]
@@ -110,22 +113,27 @@ impl U5cDataAdapter for U5cDataAdapterImpl {
             },
         );
 
-        let follow_tip_request = match &self.cursor {
-            Some((slot, hash)) => {
-                info!("U5C starting from slot {}", slot);
-                FollowTipRequest {
-                    intersect: vec![BlockRef {
-                        index: *slot,
-                        hash: hash.clone().into(),
-                    }],
-                    ..Default::default()
-                }
+        // an empty intersect follows from the origin, so the tip is
+        // intersected on explicitly
+        let point = match &self.cursor {
+            Some(point) if *point == ORIGIN => {
+                info!("U5C starting from origin");
+                ORIGIN
+            }
+            Some(point) => {
+                info!("U5C starting from slot {}", point.0);
+                point.clone()
             }
             None => {
-                info!("U5C starting from tip");
-                FollowTipRequest::default()
+                let tip = self.fetch_tip().await?;
+                info!("U5C starting from tip at slot {}", tip.0);
+                tip
             }
         };
+        let follow_tip_request = FollowTipRequest {
+            intersect: intersect(&point),
+            ..Default::default()
+        };
 
         let mut tip_stream = client
             .follow_tip(tonic::Request::new(follow_tip_request))
@@ -166,6 +174,18 @@ impl U5cDataAdapter for U5cDataAdapterImpl {
     }
 }
 
+/// Blocks to follow the chain from, none for the origin.
+fn intersect(point: &Point) -> Vec<BlockRef> {
+    if *point == ORIGIN {
+        return vec![];
+    }
+
+    vec![BlockRef {
+        index: point.0,
+        hash: point.1.clone().into(),
+    }]
+}
+
 #[cfg(test)]
 mod u5c_tests {
     use super::*;
@@ -227,4 +247,17 @@ mod u5c_tests {
         assert!(results.contains_key("abc123#1"));
         assert!(!results.contains_key("missing#2"));
     }
+
+    #[test]
+    fn it_should_intersect_nothing_from_origin() {
+        assert!(intersect(&ORIGIN).is_empty());
+    }
+
+    #[test]
+    fn it_should_intersect_on_the_point() {
+        let blocks = intersect(&(42, vec![0xab, 0xcd]));
+        assert!(blocks.len() == 1);
+        assert!(blocks[0].index == 42);
+        assert!(blocks[0].hash.to_vec() == vec![0xab, 0xcd]);
+    }
 }
@@ -1,20 +1,47 @@
-use std::{env, error::Error, path, sync::Arc};
+use std::{env, error::Error, sync::Arc, time::Duration};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use config::{builder::DefaultState, ConfigBuilder};
 use dotenv::dotenv;
 use serde::Deserialize;
-use storage::sqlite::{SqliteCursor, SqliteStorage, SqliteTransaction};
-use tokio::try_join;
-use tracing::Level;
-use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use storage::{
+    clock::{Clock, SystemClock},
+    postgres::{PostgresCursor, PostgresStorage, PostgresTransaction},
+    sqlite::{SqliteCursor, SqliteStorage, SqliteTransaction},
+    timed::TimedTransactionStore,
+    watch::WatchedTransactionStore,
+    CursorStore, Database, TransactionStore, ROLLBACK_WINDOW_SLOTS,
+};
+use tokio::{
+    sync::{broadcast, watch, Notify},
+    try_join,
+};
+use tracing::{info, Level, Subscriber};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, EnvFilter, Layer,
+};
 
+#[cfg(test)]
+mod fixtures;
 mod ledger;
+mod metrics;
 mod pipeline;
 mod server;
+mod shutdown;
 mod storage;
+mod validation;
+
+/// Every option with its default, or commented when it's optional, printed by
+/// `--print-config`.
+const SAMPLE_CONFIG: &str = include_str!("../examples/config.toml");
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    if env::args().skip(1).any(|arg| arg == "--print-config") {
+        print!("{SAMPLE_CONFIG}");
+        return Ok(());
+    }
+
     dotenv().ok();
 
     let env_filter = EnvFilter::builder()
@@ -23,22 +50,141 @@ async fn main() -> Result<()> {
         .from_env_lossy();
 
     tracing_subscriber::registry()
-        .with(fmt::layer())
+        .with(log_layer(env::var("LOG_FORMAT").ok().as_deref()))
         .with(env_filter)
         .init();
 
     let config = Config::new().expect("invalid config file");
+    config.validate().context("invalid config")?;
+
+    // the store and the stages read the time from the same clock
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+
+    let (tx_storage, cursor_storage, database): (
+        Arc<dyn TransactionStore>,
+        Arc<dyn CursorStore>,
+        Database,
+    ) = match config.storage.backend {
+        storage::Backend::Sqlite => {
+            let storage = Arc::new(SqliteStorage::new(&config.storage).await?);
+            storage
+                .migrate()
+                .await
+                .context("failed to migrate the sqlite database")?;
+
+            if let Some(secs) = config.storage.maintenance_interval_secs {
+                tokio::spawn(storage::sqlite::maintain(
+                    storage.clone(),
+                    Duration::from_secs(secs),
+                ));
+            }
+
+            (
+                Arc::new(
+                    SqliteTransaction::new(storage.clone())
+                        .with_ordering(config.pipeline.ordering.clone())
+                        .with_clock(clock.clone()),
+                ),
+                Arc::new(SqliteCursor::new(storage.clone())),
+                Database::Sqlite(storage),
+            )
+        }
+        storage::Backend::Postgres => {
+            let url = config
+                .storage
+                .url
+                .as_deref()
+                .context("storage.url is required for the postgres backend")?;
+
+            let storage = Arc::new(PostgresStorage::new(url, &config.storage).await?);
+            storage
+                .migrate()
+                .await
+                .context("failed to migrate the postgres database")?;
+
+            if let Some(secs) = config.storage.maintenance_interval_secs {
+                tokio::spawn(storage::postgres::maintain(
+                    storage.clone(),
+                    Duration::from_secs(secs),
+                ));
+            }
+
+            (
+                Arc::new(
+                    PostgresTransaction::new(storage.clone())
+                        .with_ordering(config.pipeline.ordering.clone()),
+                ),
+                Arc::new(PostgresCursor::new(storage.clone())),
+                Database::Postgres(storage),
+            )
+        }
+    };
+
+    let tx_storage: Arc<dyn TransactionStore> = Arc::new(TimedTransactionStore::new(
+        tx_storage,
+        config.storage.slow_query_ms.map(Duration::from_millis),
+    ));
+
+    // every write is published so the server can stream the status changes
+    let (tx_events, _) = broadcast::channel(1024);
+    let tx_storage: Arc<dyn TransactionStore> =
+        Arc::new(WatchedTransactionStore::new(tx_storage, tx_events.clone()));
+
+    if let Some(secs) = config.storage.confirmed_retention_secs {
+        tokio::spawn(storage::retention::run(
+            tx_storage.clone(),
+            cursor_storage.clone(),
+            Duration::from_secs(secs),
+        ));
+    }
+
+    let shutdown = shutdown::listen();
 
-    let storage = Arc::new(SqliteStorage::new(path::Path::new(&config.storage.db_path)).await?);
-    storage.migrate().await?;
+    // peers connected by the fanout, consulted by the readiness probe
+    let (healthy_peers_tx, healthy_peers_rx) = watch::channel(Vec::new());
 
-    let tx_storage = Arc::new(SqliteTransaction::new(storage.clone()));
-    let cursor_storage = Arc::new(SqliteCursor::new(storage.clone()));
+    // submissions wait for room here, so a burst the pipeline can't keep up
+    // with is pushed back to the clients
+    let (ingest, submitted) = pipeline::ingest::buffer(config.pipeline.ingest_buffer);
+    // wakes up the fanout when a transaction is resubmitted
+    let fanout = Arc::new(Notify::new());
 
-    let pipeline = pipeline::run(config.clone(), tx_storage.clone(), cursor_storage.clone());
-    let server = server::run(config.server, tx_storage.clone());
+    let pipeline = pipeline::run(
+        config.clone(),
+        tx_storage.clone(),
+        cursor_storage.clone(),
+        submitted,
+        fanout.clone(),
+        healthy_peers_tx,
+        clock,
+        shutdown.clone(),
+    );
+    let http = server::http::run(
+        config.server.clone(),
+        tx_storage.clone(),
+        ingest.clone(),
+        shutdown.clone(),
+    );
+    let health = server::health::run(
+        config.server.clone(),
+        tx_storage.clone(),
+        healthy_peers_rx,
+        shutdown.clone(),
+    );
+    let metrics = metrics::run(config.server.clone(), tx_storage.clone(), shutdown.clone());
+    let server = server::run(
+        config.server,
+        tx_storage.clone(),
+        tx_events,
+        ingest,
+        fanout,
+        shutdown,
+    );
 
-    try_join!(pipeline, server)?;
+    try_join!(pipeline, server, http, health, metrics)?;
+
+    database.close().await;
+    info!("shutdown complete");
 
     Ok(())
 }
@@ -47,6 +193,8 @@ async fn main() -> Result<()> {
 struct Config {
     server: server::Config,
     storage: storage::Config,
+    #[serde(default)]
+    pipeline: pipeline::Config,
     peer_manager: pipeline::fanout::PeerManagerConfig,
     monitor: pipeline::monitor::Config,
     u5c: ledger::u5c::Config,
@@ -54,16 +202,419 @@ struct Config {
 
 impl Config {
     pub fn new() -> Result<Self, Box<dyn Error>> {
-        let config = config::Config::builder()
-            .add_source(
-                config::File::with_name(&env::var("BOROS_CONFIG").unwrap_or("boros.toml".into()))
-                    .required(false),
-            )
+        let files = parse_list(&env::var("BOROS_CONFIG").unwrap_or("boros.toml".into()));
+        let config = with_files(config::Config::builder(), &files)
             .add_source(config::File::with_name("/etc/boros/config.toml").required(false))
             .add_source(config::Environment::with_prefix("boros").separator("_"))
+            // the `_` separator splits peer_manager, so the peers are read apart
+            .set_override_option(
+                "peer_manager.peers",
+                env::var("BOROS_PEER_MANAGER_PEERS")
+                    .ok()
+                    .map(|peers| parse_list(&peers)),
+            )?
             .build()?
             .try_deserialize()?;
 
         Ok(config)
     }
+
+    /// The config of [`SAMPLE_CONFIG`], read like the config files.
+    #[cfg(test)]
+    pub fn sample() -> Result<Self, Box<dyn Error>> {
+        let config = config::Config::builder()
+            .add_source(config::File::from_str(
+                SAMPLE_CONFIG,
+                config::FileFormat::Toml,
+            ))
+            .build()?
+            .try_deserialize()?;
+
+        Ok(config)
+    }
+
+    /// Checks what deserializing can't, so a bad config stops the startup with a
+    /// clear message instead of failing later on.
+    pub fn validate(&self) -> Result<()> {
+        match self.storage.backend {
+            storage::Backend::Sqlite if self.storage.db_path.trim().is_empty() => {
+                bail!("storage.db_path is required for the sqlite backend")
+            }
+            storage::Backend::Postgres if self.storage.url.is_none() => {
+                bail!("storage.url is required for the postgres backend")
+            }
+            _ => {}
+        }
+        if self
+            .storage
+            .confirmed_retention_secs
+            .is_some_and(|secs| secs < ROLLBACK_WINDOW_SLOTS)
+        {
+            bail!(
+                "storage.confirmed_retention_secs must be at least the rollback window, {ROLLBACK_WINDOW_SLOTS} seconds"
+            );
+        }
+
+        if self.peer_manager.peers.is_empty() && self.peer_manager.local_socket.is_none() {
+            bail!("peer_manager.peers requires at least one peer when there's no local_socket");
+        }
+        if let Some(peer) = self.peer_manager.peers.iter().find(|p| p.trim().is_empty()) {
+            bail!("peer_manager.peers has an empty peer {peer:?}");
+        }
+
+        if self.peer_manager.strategy == pipeline::fanout::Strategy::MinPeers
+            && !(1..=self.peer_manager.peers.len()).contains(&self.peer_manager.min_peers)
+        {
+            bail!("peer_manager.min_peers must be between 1 and the number of peers");
+        }
+
+        pipeline::fanout::tx_submit_peer::version_table(
+            self.peer_manager.protocol_version,
+            self.peer_manager.network.magic(),
+        )
+        .context("invalid peer_manager.protocol_version")?;
+
+        if self.pipeline.workers == 0 {
+            bail!("pipeline.workers requires at least one worker");
+        }
+
+        if self.server.listen_address.port() == 0 {
+            bail!("server.listen_address requires a port");
+        }
+        let ports = [
+            ("server.http_port", self.server.http_port),
+            ("server.health_port", self.server.health_port),
+            ("server.metrics_port", self.server.metrics_port),
+        ];
+        for (name, port) in ports {
+            match port {
+                Some(0) => bail!("{name} must not be 0"),
+                Some(port) if port == self.server.listen_address.port() => {
+                    bail!("{name} is already used by server.listen_address")
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats the logs as JSON for the log aggregators with `json`, the fields of
+/// the spans, like the tx id, being keys of the `span` object. Human readable
+/// text otherwise.
+fn log_layer<S>(format: Option<&str>) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    match format {
+        Some("json") => fmt::layer().json().boxed(),
+        _ => fmt::layer().boxed(),
+    }
+}
+
+/// Layers the config files in order, each one overriding the keys of the ones
+/// before it. The files that don't exist are skipped.
+fn with_files(
+    builder: ConfigBuilder<DefaultState>,
+    files: &[String],
+) -> ConfigBuilder<DefaultState> {
+    files.iter().fold(builder, |builder, file| {
+        builder.add_source(config::File::with_name(file).required(false))
+    })
+}
+
+/// Splits a comma separated env var, an empty one is an empty list.
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod config_tests {
+    use config::FileFormat;
+
+    use crate::storage::QueueOrder;
+
+    use super::{parse_list, with_files, Config, SAMPLE_CONFIG};
+
+    const CONFIG: &str = r#"
+        [storage]
+        db_path = "boros.db"
+
+        [server]
+        listen_address = "0.0.0.0:50052"
+
+        [peer_manager]
+        network = "preprod"
+        peers = ["Node:3001"]
+
+        [monitor]
+        retry_slot_diff = 1000
+
+        [u5c]
+        uri = "https://preprod.utxorpc-v0.demeter.run"
+
+        [u5c.metadata]
+        "dmtr-api-key" = "key"
+    "#;
+
+    fn config(toml: &str) -> Config {
+        config::Config::builder()
+            .add_source(config::File::from_str(toml, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap()
+    }
+
+    #[test]
+    fn it_should_read_peers() {
+        let toml = CONFIG.replace("[\"Node:3001\"]", "[\"Node:3001\", \"10.0.0.1:3001\"]");
+        let config = config(&toml);
+        assert!(config.peer_manager.peers == vec!["Node:3001", "10.0.0.1:3001"]);
+    }
+
+    #[test]
+    fn it_should_default_to_no_peers() {
+        let config = config(&CONFIG.replace("peers = [\"Node:3001\"]", ""));
+        assert!(config.peer_manager.peers.is_empty());
+    }
+
+    #[test]
+    fn it_should_override_peers_from_list() {
+        let config: Config = config::Config::builder()
+            .add_source(config::File::from_str(CONFIG, FileFormat::Toml))
+            .set_override_option(
+                "peer_manager.peers",
+                Some(parse_list("Node:3001, 10.0.0.1:3001")),
+            )
+            .unwrap()
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+        assert!(config.peer_manager.peers == vec!["Node:3001", "10.0.0.1:3001"]);
+    }
+
+    #[test]
+    fn it_should_layer_files() {
+        let dir = std::env::temp_dir();
+        let base = dir.join(format!("boros-{}-base.toml", std::process::id()));
+        let env = dir.join(format!("boros-{}-env.toml", std::process::id()));
+        std::fs::write(&base, CONFIG).unwrap();
+        std::fs::write(&env, "[storage]\ndb_path = \"env.db\"\n").unwrap();
+
+        let files = vec![
+            base.display().to_string(),
+            dir.join("boros-missing.toml").display().to_string(),
+            env.display().to_string(),
+        ];
+        let config: Config = with_files(config::Config::builder(), &files)
+            .build()
+            .unwrap()
+            .try_deserialize()
+            .unwrap();
+
+        // the later file wins, the keys it doesn't set are kept from the base
+        assert!(config.storage.db_path == "env.db");
+        assert!(config.peer_manager.peers == vec!["Node:3001"]);
+
+        std::fs::remove_file(base).unwrap();
+        std::fs::remove_file(env).unwrap();
+    }
+
+    #[test]
+    fn it_should_read_sample() {
+        let config = Config::sample().unwrap();
+        assert!(config.validate().is_ok());
+        assert!(config.storage.write_retries == 3);
+        assert!(config.pipeline.backoff.max_secs == 600);
+        assert!(config.peer_manager.peers == vec!["Node:3001", "Node:3001"]);
+
+        // the optional sections are documented even when commented
+        for option in ["url", "uds_path", "[server.tls]", "namespace_weights"] {
+            assert!(SAMPLE_CONFIG.contains(option));
+        }
+    }
+
+    #[test]
+    fn it_should_parse_list() {
+        assert!(parse_list("Node:3001,Node:3002") == vec!["Node:3001", "Node:3002"]);
+        assert!(parse_list(" Node:3001 , ") == vec!["Node:3001"]);
+        assert!(parse_list("").is_empty());
+    }
+
+    #[test]
+    fn it_should_read_ordering() {
+        assert!(config(CONFIG).pipeline.ordering == QueueOrder::Priority);
+
+        let fifo = config(&format!("[pipeline]\nordering = \"fifo\"\n{CONFIG}"));
+        assert!(fifo.pipeline.ordering == QueueOrder::Fifo);
+    }
+
+    #[test]
+    fn it_should_fail_unknown_ordering() {
+        let result = config::Config::builder()
+            .add_source(config::File::from_str(
+                &format!("[pipeline]\nordering = \"lifo\"\n{CONFIG}"),
+                FileFormat::Toml,
+            ))
+            .build()
+            .unwrap()
+            .try_deserialize::<Config>();
+        assert!(result.is_err());
+    }
+
+    fn validation_error(config: Config) -> String {
+        config.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn it_should_validate_config() {
+        assert!(config(CONFIG).validate().is_ok());
+    }
+
+    #[test]
+    fn it_should_fail_empty_db_path() {
+        let error = validation_error(config(&CONFIG.replace("boros.db", "")));
+        assert!(error.contains("storage.db_path"));
+    }
+
+    #[test]
+    fn it_should_fail_postgres_without_url() {
+        let toml = CONFIG.replace("[storage]", "[storage]\nbackend = \"postgres\"");
+        let error = validation_error(config(&toml));
+        assert!(error.contains("storage.url"));
+    }
+
+    #[test]
+    fn it_should_fail_retention_within_rollback_window() {
+        let toml = CONFIG.replace("[storage]", "[storage]\nconfirmed_retention_secs = 3600");
+        let error = validation_error(config(&toml));
+        assert!(error.contains("storage.confirmed_retention_secs"));
+
+        let toml = CONFIG.replace("[storage]", "[storage]\nconfirmed_retention_secs = 604800");
+        assert!(config(&toml).validate().is_ok());
+    }
+
+    #[test]
+    fn it_should_fail_without_peers() {
+        let error = validation_error(config(&CONFIG.replace("\"Node:3001\"", "")));
+        assert!(error.contains("at least one peer"));
+    }
+
+    #[test]
+    fn it_should_validate_local_socket_without_peers() {
+        let toml = CONFIG.replace(
+            "peers = [\"Node:3001\"]",
+            "peers = []\nlocal_socket = \"/ipc/node.socket\"",
+        );
+        assert!(config(&toml).validate().is_ok());
+    }
+
+    #[test]
+    fn it_should_fail_empty_peer() {
+        let error = validation_error(config(&CONFIG.replace("Node:3001", "")));
+        assert!(error.contains("empty peer"));
+    }
+
+    #[test]
+    fn it_should_fail_min_peers_above_peers() {
+        let toml = CONFIG.replace(
+            "[peer_manager]",
+            "[peer_manager]\nstrategy = \"min_peers\"\nmin_peers = 2",
+        );
+        let error = validation_error(config(&toml));
+        assert!(error.contains("peer_manager.min_peers"));
+
+        assert!(config(&toml.replace("min_peers = 2", "min_peers = 1"))
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn it_should_read_the_former_quorum_names() {
+        let toml = CONFIG.replace(
+            "[peer_manager]",
+            "[peer_manager]\nstrategy = \"quorum\"\nquorum = 2",
+        );
+        let config = config(&toml);
+        assert!(config.peer_manager.strategy == pipeline::fanout::Strategy::MinPeers);
+        assert!(config.peer_manager.min_peers == 2);
+    }
+
+    #[test]
+    fn it_should_fail_unsupported_protocol_version() {
+        let toml = CONFIG.replace("[peer_manager]", "[peer_manager]\nprotocol_version = 1");
+        let error = validation_error(config(&toml));
+        assert!(error.contains("peer_manager.protocol_version"));
+
+        assert!(
+            config(&toml.replace("protocol_version = 1", "protocol_version = \"auto\""))
+                .validate()
+                .is_ok()
+        );
+        assert!(
+            config(&toml.replace("protocol_version = 1", "protocol_version = 13"))
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn it_should_fail_without_workers() {
+        let error = validation_error(config(&format!("[pipeline]\nworkers = 0\n{CONFIG}")));
+        assert!(error.contains("pipeline.workers"));
+    }
+
+    #[test]
+    fn it_should_fail_listen_address_without_port() {
+        let error = validation_error(config(&CONFIG.replace(":50052", ":0")));
+        assert!(error.contains("server.listen_address"));
+    }
+
+    #[test]
+    fn it_should_fail_zero_port() {
+        let toml = CONFIG.replace("[peer_manager]", "http_port = 0\n[peer_manager]");
+        let error = validation_error(config(&toml));
+        assert!(error.contains("server.http_port"));
+    }
+
+    #[test]
+    fn it_should_fail_port_used_by_listen_address() {
+        let toml = CONFIG.replace("[peer_manager]", "metrics_port = 50052\n[peer_manager]");
+        let error = validation_error(config(&toml));
+        assert!(error.contains("server.metrics_port"));
+    }
+}
+
+#[cfg(test)]
+mod log_tests {
+    use tracing::{info, info_span};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::log_layer;
+
+    fn log_with(format: Option<&str>) {
+        let subscriber = tracing_subscriber::registry().with(log_layer(format));
+        tracing::subscriber::with_default(subscriber, || {
+            info_span!("submit", tx_id = "hex").in_scope(|| info!("submitting transaction"));
+        });
+    }
+
+    #[test]
+    fn it_should_log_json() {
+        log_with(Some("json"));
+    }
+
+    #[test]
+    fn it_should_log_text() {
+        log_with(Some("text"));
+        log_with(None);
+    }
 }
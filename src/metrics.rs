@@ -0,0 +1,236 @@
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, LazyLock},
+};
+
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tokio::sync::watch;
+use tracing::error;
+
+use crate::{
+    server::{serve, Config},
+    storage::{TransactionStatus, TransactionStore},
+};
+
+pub struct Metrics {
+    registry: Registry,
+    /// Number of transactions stored in each status, refreshed on every scrape.
+    pub transactions: IntGaugeVec,
+    pub submitted: IntCounter,
+    pub failed: IntCounter,
+    /// Seconds from the transaction being received to being confirmed on chain.
+    pub confirmation_seconds: Histogram,
+    /// Seconds spent processing a transaction in each pipeline stage.
+    pub stage_seconds: HistogramVec,
+    /// Seconds taken by the storage queries the pipeline runs the most.
+    pub storage_seconds: HistogramVec,
+    /// Storage queries slower than `storage.slow_query_ms`.
+    pub slow_queries: IntCounterVec,
+    /// Seconds since the oldest transaction still queued was created, 0 when
+    /// the queue is empty.
+    pub queue_age_seconds: IntGauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("boros".into()), None).unwrap();
+
+        let transactions = IntGaugeVec::new(
+            Opts::new("transactions", "Number of transactions by status"),
+            &["status"],
+        )
+        .unwrap();
+        let submitted =
+            IntCounter::new("submitted_total", "Transactions submitted to the peers").unwrap();
+        let failed = IntCounter::new("failed_total", "Transactions that failed").unwrap();
+        let confirmation_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "confirmation_seconds",
+                "Time from a transaction being received to being confirmed",
+            )
+            .buckets(vec![10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0]),
+        )
+        .unwrap();
+        let stage_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "stage_seconds",
+                "Time spent processing a transaction in a pipeline stage",
+            )
+            .buckets(vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0]),
+            &["stage"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(transactions.clone())).unwrap();
+        registry.register(Box::new(submitted.clone())).unwrap();
+        registry.register(Box::new(failed.clone())).unwrap();
+        registry
+            .register(Box::new(confirmation_seconds.clone()))
+            .unwrap();
+        let storage_seconds = HistogramVec::new(
+            HistogramOpts::new("storage_seconds", "Time taken by a storage query")
+                .buckets(vec![0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]),
+            &["method"],
+        )
+        .unwrap();
+        let slow_queries = IntCounterVec::new(
+            Opts::new(
+                "slow_queries_total",
+                "Storage queries slower than the configured threshold",
+            ),
+            &["method"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(stage_seconds.clone())).unwrap();
+        registry
+            .register(Box::new(storage_seconds.clone()))
+            .unwrap();
+        registry.register(Box::new(slow_queries.clone())).unwrap();
+        let queue_age_seconds = IntGauge::new(
+            "queue_age_seconds",
+            "Age of the oldest pending, validated or in flight transaction",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(queue_age_seconds.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            transactions,
+            submitted,
+            failed,
+            confirmation_seconds,
+            stage_seconds,
+            storage_seconds,
+            slow_queries,
+            queue_age_seconds,
+        }
+    }
+
+    /// Awaits `future` and observes how long it took in `stage_seconds`.
+    pub async fn time_stage<T>(&self, stage: &str, future: impl Future<Output = T>) -> T {
+        let timer = self.stage_seconds.with_label_values(&[stage]).start_timer();
+        let output = future.await;
+        timer.observe_duration();
+        output
+    }
+
+    async fn render(&self, tx_storage: &dyn TransactionStore) -> Result<String> {
+        let counts = tx_storage.status_counts().await?;
+        for status in [
+            TransactionStatus::Pending,
+            TransactionStatus::Validated,
+            TransactionStatus::InFlight,
+            TransactionStatus::Confirmed,
+            TransactionStatus::Failed,
+            TransactionStatus::DeadLetter,
+            TransactionStatus::Cancelled,
+            TransactionStatus::BlockedByDependency,
+        ] {
+            let count = counts.get(&status).copied().unwrap_or_default();
+            self.transactions
+                .with_label_values(&[&status.to_string()])
+                .set(count);
+        }
+
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Serves the Prometheus `/metrics` endpoint.
+pub async fn run(
+    config: Config,
+    tx_storage: Arc<dyn TransactionStore>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let Some(port) = config.metrics_port else {
+        return Ok(());
+    };
+    let address = SocketAddr::new(config.listen_address.ip(), port);
+
+    serve("Metrics", address, router(tx_storage), shutdown).await
+}
+
+fn router(tx_storage: Arc<dyn TransactionStore>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(tx_storage)
+}
+
+async fn metrics(
+    State(tx_storage): State<Arc<dyn TransactionStore>>,
+) -> Result<String, StatusCode> {
+    METRICS.render(tx_storage.as_ref()).await.map_err(|error| {
+        error!(?error, "failed to render metrics");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use crate::storage::{
+        mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore,
+    };
+
+    use super::{router, METRICS};
+
+    #[tokio::test]
+    async fn it_should_render_metrics() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+        METRICS.submitted.inc();
+
+        let response = router(storage)
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"boros_transactions{status="inflight"} 1"#));
+        assert!(body.contains(r#"boros_transactions{status="pending"} 0"#));
+        assert!(body.contains("boros_submitted_total"));
+    }
+
+    #[tokio::test]
+    async fn it_should_time_stage() {
+        let output = METRICS.time_stage("test", async { 1 }).await;
+        assert!(output == 1);
+
+        let response = router(Arc::new(MockTransactionStore::default()))
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"boros_stage_seconds_count{stage="test"}"#));
+    }
+}
@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use pallas::{
+    ledger::traverse::MultiEraTx,
+    network::{
+        facades::NodeClient,
+        miniprotocols::localtxsubmission::{EraTx, Response},
+    },
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Answer of the node to a transaction submitted over LocalTxSubmission.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LocalSubmission {
+    Accepted,
+    /// The node validated the transaction against its ledger and refused it,
+    /// with the CBOR of the reason it gave.
+    Rejected(Vec<u8>),
+}
+
+#[async_trait::async_trait]
+pub trait LocalTxSubmit: Send + Sync {
+    /// Where the transactions are submitted to, recorded as the peer of the
+    /// submissions.
+    fn address(&self) -> &str;
+    async fn submit(&self, raw: &[u8]) -> Result<LocalSubmission>;
+}
+
+/// Submits to a cardano-node over its unix socket with the node-to-client
+/// LocalTxSubmission mini-protocol, so the node answers whether it took the
+/// transaction instead of pulling it like the node-to-node peers.
+pub struct LocalTxSubmitClient {
+    socket: String,
+    network_magic: u64,
+    client: Mutex<Option<NodeClient>>,
+}
+
+impl LocalTxSubmitClient {
+    pub fn new(socket: &str, network_magic: u64) -> Self {
+        Self {
+            socket: socket.to_string(),
+            network_magic,
+            client: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LocalTxSubmit for LocalTxSubmitClient {
+    fn address(&self) -> &str {
+        &self.socket
+    }
+
+    async fn submit(&self, raw: &[u8]) -> Result<LocalSubmission> {
+        // the era tags the transaction for the hard fork combinator of the node
+        let era = MultiEraTx::decode(raw)?.era() as u16;
+
+        let mut client = self.client.lock().await;
+        let connected = match client.as_mut() {
+            Some(connected) => connected,
+            None => {
+                let connected = NodeClient::connect(&self.socket, self.network_magic)
+                    .await
+                    .with_context(|| format!("failed to connect to node socket {}", self.socket))?;
+                info!(socket = %self.socket, "connected to local node");
+                client.insert(connected)
+            }
+        };
+
+        match connected
+            .submission()
+            .submit_tx(EraTx(era, raw.to_vec()))
+            .await
+        {
+            Ok(Response::Accepted) => Ok(LocalSubmission::Accepted),
+            Ok(Response::Rejected(reason)) => Ok(LocalSubmission::Rejected(reason.0)),
+            Err(error) => {
+                // reconnected on the next submission
+                warn!(?error, socket = %self.socket, "local node connection failed");
+                *client = None;
+                Err(error.into())
+            }
+        }
+    }
+}
@@ -1,12 +1,12 @@
 use itertools::Itertools;
 use pallas::{crypto::hash::Hash, ledger::traverse::MultiEraTx};
 use std::{
-    collections::HashMap,
+    collections::VecDeque,
     sync::{Arc, RwLock},
 };
 use thiserror::Error;
 use tokio::sync::broadcast;
-use tracing::debug;
+use tracing::{debug, warn};
 
 type TxHash = Hash<32>;
 
@@ -47,22 +47,58 @@ pub struct Event {
 struct MempoolState {
     pending: Vec<Tx>,
     inflight: Vec<Tx>,
-    acknowledged: HashMap<TxHash, Tx>,
+    acknowledged: VecDeque<Tx>,
 }
 
-/// A very basic, FIFO, single consumer mempool
+impl MempoolState {
+    fn len(&self) -> usize {
+        self.pending.len() + self.inflight.len() + self.acknowledged.len()
+    }
+
+    /// Drops the oldest acknowledged txs, then the oldest pending ones, until
+    /// there's room for `capacity`. Inflight txs are kept, the peer still has
+    /// to acknowledge them.
+    fn evict(&mut self, capacity: usize) {
+        while self.len() > capacity {
+            if self.acknowledged.pop_front().is_some() {
+                continue;
+            }
+
+            if self.pending.is_empty() {
+                break;
+            }
+
+            let tx = self.pending.remove(0);
+            warn!(tx = %tx.hash, "mempool full, dropping pending tx");
+        }
+    }
+}
+
+/// A very basic, FIFO, single consumer mempool, holding at most `capacity` txs
+///
+/// Clones share the same state behind an `RwLock`, so the fanout handing txs
+/// in and the peer task requesting them can use it from different threads.
+/// `receive_raw`, `request`, `acknowledge` and `clear` take the write lock,
+/// `find_inflight`, `pending_total` and `len` the read lock. Every operation
+/// holds the lock only for its own duration, so each one is atomic but a
+/// sequence of them isn't.
 #[derive(Clone)]
 pub struct Mempool {
     mempool: Arc<RwLock<MempoolState>>,
     updates: broadcast::Sender<Event>,
+    capacity: usize,
 }
 
 impl Mempool {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize) -> Self {
         let mempool = Arc::new(RwLock::new(MempoolState::default()));
         let (updates, _) = broadcast::channel(16);
 
-        Self { mempool, updates }
+        Self {
+            mempool,
+            updates,
+            capacity: capacity.max(1),
+        }
     }
 
     pub fn notify(&self, new_stage: TxStage, tx: Tx) {
@@ -76,6 +112,7 @@ impl Mempool {
 
         state.pending.push(tx.clone());
         self.notify(TxStage::Pending, tx);
+        state.evict(self.capacity);
 
         debug!(
             pending = state.pending.len(),
@@ -136,7 +173,7 @@ impl Mempool {
         let selected = state.inflight.drain(..count).collect_vec();
 
         for tx in selected {
-            state.acknowledged.insert(tx.hash, tx.clone());
+            state.acknowledged.push_back(tx.clone());
             self.notify(TxStage::Acknowledged, tx.clone());
         }
 
@@ -157,4 +194,110 @@ impl Mempool {
         let state = self.mempool.read().unwrap();
         state.pending.len()
     }
+
+    /// Txs held in any stage.
+    pub fn len(&self) -> usize {
+        self.mempool.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.mempool.write().unwrap();
+        *state = MempoolState::default();
+    }
+}
+
+#[cfg(test)]
+mod mempool_tests {
+    use crate::fixtures::TX_CBOR;
+
+    use super::Mempool;
+
+    fn fill(mempool: &Mempool, count: usize) {
+        let raw = hex::decode(TX_CBOR).unwrap();
+        for _ in 0..count {
+            mempool.receive_raw(&raw).unwrap();
+            assert!(mempool.len() <= mempool.capacity());
+        }
+    }
+
+    #[test]
+    fn it_should_evict_oldest_pending_past_capacity() {
+        let mempool = Mempool::new(3);
+
+        fill(&mempool, 5);
+        assert!(mempool.len() == 3);
+        assert!(mempool.pending_total() == 3);
+    }
+
+    #[test]
+    fn it_should_evict_acknowledged_before_pending() {
+        let mempool = Mempool::new(3);
+
+        fill(&mempool, 2);
+        mempool.request(2);
+        mempool.acknowledge(2);
+
+        fill(&mempool, 2);
+        assert!(mempool.len() == 3);
+        assert!(mempool.pending_total() == 2);
+    }
+
+    #[test]
+    fn it_should_keep_inflight_txs() {
+        let mempool = Mempool::new(2);
+
+        fill(&mempool, 2);
+        mempool.request(2);
+
+        fill(&mempool, 1);
+        assert!(mempool.len() == 2);
+        assert!(mempool.pending_total() == 0);
+    }
+
+    #[test]
+    fn it_should_receive_and_read_concurrently() {
+        let mempool = Mempool::new(1000);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let mempool = mempool.clone();
+                scope.spawn(move || {
+                    let raw = hex::decode(TX_CBOR).unwrap();
+                    for _ in 0..100 {
+                        mempool.receive_raw(&raw).unwrap();
+                        assert!(mempool.len() <= mempool.capacity());
+                    }
+                });
+            }
+
+            for _ in 0..4 {
+                let mempool = mempool.clone();
+                scope.spawn(move || {
+                    for _ in 0..100 {
+                        assert!(mempool.pending_total() <= 400);
+                    }
+                });
+            }
+        });
+
+        assert!(mempool.pending_total() == 400);
+    }
+
+    #[test]
+    fn it_should_clear() {
+        let mempool = Mempool::new(3);
+
+        fill(&mempool, 2);
+        mempool.clear();
+        assert!(mempool.is_empty());
+        assert!(mempool.capacity() == 3);
+    }
 }
@@ -1,60 +1,116 @@
-use std::{sync::Arc, time::Duration};
+use std::{pin::pin, sync::Arc, time::Duration};
 
+use async_stream::stream;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
 use gasket::framework::*;
+use local_tx_submit::{LocalSubmission, LocalTxSubmit};
+use rejection::Rejection;
 use serde::Deserialize;
-use tokio::time::sleep;
-use tracing::info;
+use tokio::{
+    sync::{watch, Notify},
+    time::{sleep, timeout},
+};
+use tracing::{info, info_span, warn, Instrument};
 use tx_submit_peer_manager::TxSubmitPeerManager;
 
 use crate::{
     ledger::u5c::U5cDataAdapter,
-    storage::{sqlite::SqliteTransaction, Transaction, TransactionStatus},
+    metrics::METRICS,
+    storage::{
+        clock::{Clock, SystemClock},
+        Transaction, TransactionStatus, TransactionStore,
+    },
 };
 
+use super::{log_transition, scheduler::NamespaceScheduler, Config as PipelineConfig};
+
+pub mod local_tx_submit;
 pub mod mempool;
+pub mod rate_limit;
+pub mod rejection;
 pub mod tx_submit_peer;
 pub mod tx_submit_peer_manager;
 
+/// Submitted transactions read at once when warming up the mempools.
+const WARM_UP_PAGE_SIZE: i64 = 500;
+
 #[derive(Stage)]
 #[stage(name = "fanout", unit = "Transaction", worker = "Worker")]
 pub struct Stage {
+    id: String,
     config: PeerManagerConfig,
+    pipeline: PipelineConfig,
+    peers: watch::Receiver<Vec<String>>,
+    healthy_peers: watch::Sender<Vec<String>>,
     adapter: Arc<dyn U5cDataAdapter>,
-    storage: Arc<SqliteTransaction>,
+    storage: Arc<dyn TransactionStore>,
+    peer_manager: Arc<TxSubmitPeerManager>,
+    local: Option<Arc<dyn LocalTxSubmit>>,
+    wake: Option<Arc<Notify>>,
+    clock: Arc<dyn Clock>,
 }
 impl Stage {
     pub fn new(
+        id: String,
         config: PeerManagerConfig,
+        pipeline: PipelineConfig,
+        peers: watch::Receiver<Vec<String>>,
+        healthy_peers: watch::Sender<Vec<String>>,
         adapter: Arc<dyn U5cDataAdapter>,
-        storage: Arc<SqliteTransaction>,
+        storage: Arc<dyn TransactionStore>,
+        peer_manager: Arc<TxSubmitPeerManager>,
     ) -> Self {
         Self {
+            id,
             config,
+            pipeline,
+            peers,
+            healthy_peers,
             adapter,
             storage,
+            peer_manager,
+            local: None,
+            wake: None,
+            clock: Arc::new(SystemClock),
         }
     }
+
+    /// Submits to a local node over node-to-client instead of the peers.
+    pub fn with_local_submit(mut self, local: Arc<dyn LocalTxSubmit>) -> Self {
+        self.local = Some(local);
+        self
+    }
+
+    /// Wakes up an idle worker as soon as a transaction is made claimable out
+    /// of the pipeline, e.g. resubmitted, instead of waiting for the next poll.
+    pub fn with_wake(mut self, wake: Arc<Notify>) -> Self {
+        self.wake = Some(wake);
+        self
+    }
+
+    /// Reads the current time from `clock`, the one the store compares the
+    /// retry and expiry times with.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 }
 
 pub struct Worker {
-    tx_submit_peer_manager: TxSubmitPeerManager,
+    id: String,
+    scheduler: NamespaceScheduler,
+    /// Every peer is down, so no transaction is claimed until one recovers.
+    paused: bool,
 }
 
 #[async_trait::async_trait(?Send)]
 impl gasket::framework::Worker<Stage> for Worker {
     async fn bootstrap(stage: &Stage) -> Result<Self, WorkerError> {
-        // Load configuration and Start Clients
-        let peer_addresses = stage.config.peers.clone();
-
-        info!("Peer Addresses: {:?}", peer_addresses);
-
-        // Proof of Concept: TxSubmitPeerManager
-        // Pass Config Network Magic and Peer Addresses
-        let mut tx_submit_peer_manager = TxSubmitPeerManager::new(2, peer_addresses);
-        tx_submit_peer_manager.init().await.unwrap();
-
         Ok(Self {
-            tx_submit_peer_manager,
+            id: stage.id.clone(),
+            scheduler: NamespaceScheduler::new(&stage.pipeline.namespace_weights),
+            paused: false,
         })
     }
 
@@ -62,41 +118,429 @@ impl gasket::framework::Worker<Stage> for Worker {
         &mut self,
         stage: &mut Stage,
     ) -> Result<WorkSchedule<Transaction>, WorkerError> {
-        if let Some(tx) = stage
-            .storage
-            .next(TransactionStatus::Validated)
+        if let Some(local) = &stage.local {
+            stage
+                .healthy_peers
+                .send_replace(vec![local.address().to_string()]);
+        } else if !self.check_peers(stage).await {
+            sleep(Duration::from_secs(1)).await;
+            return Ok(WorkSchedule::Idle);
+        }
+
+        if let Some(tx) = self
+            .scheduler
+            .claim_next(
+                stage.storage.as_ref(),
+                TransactionStatus::Validated,
+                &self.id,
+            )
             .await
             .or_retry()?
         {
+            info_span!("claim", tx_id = %tx.id).in_scope(|| {
+                log_transition(&tx.id, &TransactionStatus::Validated, &tx.status);
+            });
             return Ok(WorkSchedule::Unit(tx));
         }
 
-        sleep(Duration::from_secs(1)).await;
+        match &stage.wake {
+            Some(wake) => {
+                let _ = timeout(Duration::from_secs(1), wake.notified()).await;
+            }
+            None => sleep(Duration::from_secs(1)).await,
+        }
         Ok(WorkSchedule::Idle)
     }
 
     async fn execute(&mut self, unit: &Transaction, stage: &mut Stage) -> Result<(), WorkerError> {
+        let span = info_span!("submit", tx_id = %unit.id);
+        METRICS
+            .time_stage("submit", self.submit(unit, stage).instrument(span))
+            .await
+    }
+}
+
+impl Worker {
+    /// Reloads and reconnects the peers, false when every peer is down.
+    async fn check_peers(&mut self, stage: &mut Stage) -> bool {
+        if stage.peers.has_changed().unwrap_or_default() {
+            let peer_addresses = stage.peers.borrow_and_update().clone();
+            info!("Reloading Peer Addresses: {:?}", peer_addresses);
+            stage.peer_manager.update_peers(peer_addresses).await;
+        }
+
+        stage.peer_manager.check_peers().await;
+        let healthy_peers = stage.peer_manager.healthy_peers();
+        let no_healthy_peer = healthy_peers.is_empty();
+        stage.healthy_peers.send_replace(healthy_peers);
+
+        // claiming with every peer down would only burn the attempts of the
+        // transactions, they wait as validated until a peer reconnects
+        if no_healthy_peer {
+            if !self.paused {
+                warn!("every peer is down, claiming paused until one recovers");
+                self.paused = true;
+            }
+            return false;
+        }
+        if self.paused {
+            info!("a peer recovered, claiming resumed");
+            self.paused = false;
+        }
+
+        true
+    }
+
+    async fn submit(&mut self, unit: &Transaction, stage: &Stage) -> Result<(), WorkerError> {
         let mut transaction = unit.clone();
-        info!("fanout {}", transaction.id);
+        info!("submitting transaction");
+
+        if transaction
+            .expires_at
+            .is_some_and(|at| at <= stage.clock.now())
+        {
+            let reason = "expired before being submitted";
+            warn!(reason, "transaction rejected");
+            stage
+                .storage
+                .mark_failed(&transaction.id, reason)
+                .await
+                .or_retry()?;
+            METRICS.failed.inc();
+            log_transition(
+                &transaction.id,
+                &transaction.status,
+                &TransactionStatus::Failed,
+            );
+            return Ok(());
+        }
+
+        // the peers are checked before claiming, one dropping since then
+        // releases the transaction instead of holding it in flight
+        if stage.local.is_none() && stage.peer_manager.healthy_peers().is_empty() {
+            warn!("every peer dropped since the claim, releasing the transaction");
+            transaction.status = TransactionStatus::Validated;
+            transaction.claimed_by = None;
+            transaction.claimed_at = None;
+            stage.storage.update(&transaction).await.or_retry()?;
+            return Ok(());
+        }
 
         let tip = stage.adapter.fetch_tip().await.or_retry()?;
 
-        self.tx_submit_peer_manager
-            .add_tx(transaction.raw.clone())
-            .await;
+        let (submissions, rejection) = match &stage.local {
+            Some(local) => submit_local(local.as_ref(), &transaction)
+                .await
+                .or_retry()?,
+            None => {
+                let submissions = stage.peer_manager.add_tx(transaction.raw.clone()).await;
+                let submitted = stage
+                    .config
+                    .strategy
+                    .is_submitted(&submissions, stage.config.min_peers);
+                // the node-to-node protocol gives no reason, a peer just doesn't take it
+                let rejection =
+                    (!submitted).then(|| Rejection::transient("not accepted by enough peers"));
+                (submissions, rejection)
+            }
+        };
+        METRICS.submitted.inc();
+
+        for (peer, accepted) in submissions {
+            // the history is for debugging, it doesn't fail the submission
+            if let Err(error) = stage
+                .storage
+                .record_submission(&transaction.id, &peer, accepted)
+                .await
+            {
+                warn!(?error, %peer, "failed to record submission");
+            }
+        }
+
+        if let Some(rejection) = rejection {
+            retry_later(
+                stage.storage.as_ref(),
+                &stage.pipeline,
+                &mut transaction,
+                &rejection,
+                stage.clock.now(),
+            )
+            .await
+            .or_retry()?;
+            log_transition(
+                &transaction.id,
+                &TransactionStatus::InFlight,
+                &transaction.status,
+            );
+            return Ok(());
+        }
 
         transaction.status = TransactionStatus::InFlight;
         transaction.slot = Some(tip.0);
 
         stage.storage.update(&transaction).await.or_retry()?;
+        info!(slot = tip.0, "transaction submitted to peers");
 
         Ok(())
     }
 }
 
+/// Submits to the local node, which answers right away. The rejection is read
+/// from the reason the node gave, so it's kept as the error of the transaction.
+async fn submit_local(
+    local: &dyn LocalTxSubmit,
+    tx: &Transaction,
+) -> anyhow::Result<(Vec<(String, bool)>, Option<Rejection>)> {
+    let peer = local.address().to_string();
+
+    match local.submit(&tx.raw).await? {
+        LocalSubmission::Accepted => Ok((vec![(peer, true)], None)),
+        LocalSubmission::Rejected(reason) => {
+            let rejection = Rejection::from_node(&reason);
+            warn!(reason = %rejection.reason, "transaction rejected by the local node");
+            Ok((vec![(peer, false)], Some(rejection)))
+        }
+    }
+}
+
+/// Counts a submission not queued for enough peers for the strategy, e.g. all
+/// of them timed out, or rejected by the local node, as a failed attempt, so
+/// the transaction is retried after the backoff instead of waiting in flight
+/// for the monitor to notice it never reached the chain.
+///
+/// A rejection that can't pass on another attempt moves it to dead-letter
+/// right away. The reason is kept as its error, and the backoff starts at
+/// `now`.
+async fn retry_later(
+    storage: &dyn TransactionStore,
+    pipeline: &PipelineConfig,
+    tx: &mut Transaction,
+    rejection: &Rejection,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    tx.attempts = storage.increment_attempts(&tx.id).await?;
+    tx.slot = None;
+    tx.claimed_by = None;
+    tx.error = Some(rejection.reason.clone());
+
+    if tx.attempts >= pipeline.max_attempts || !rejection.retryable {
+        tx.status = TransactionStatus::DeadLetter;
+        METRICS.failed.inc();
+    } else {
+        let delay = pipeline.backoff.delay(tx.attempts - 1);
+        tx.status = TransactionStatus::Pending;
+        tx.next_retry_at = Some(now + chrono::Duration::from_std(delay)?);
+    }
+
+    Ok(storage.update(tx).await?)
+}
+
+/// Connects to the peers, once for every worker so each peer has a single
+/// connection. The mempools start empty, so the transactions submitted before
+/// a restart are handed to the peers again instead of waiting for the monitor
+/// retry.
+pub async fn connect_peers(
+    config: &PeerManagerConfig,
+    storage: Arc<dyn TransactionStore>,
+) -> anyhow::Result<Arc<TxSubmitPeerManager>> {
+    info!("Peer Addresses: {:?}", config.peers);
+
+    let network_magic = config.network.magic();
+    info!("Network: {:?} ({network_magic})", config.network);
+
+    let peer_manager = TxSubmitPeerManager::new(
+        network_magic,
+        config.max_tx_per_sec,
+        config.mempool_capacity,
+        Duration::from_millis(config.submit_timeout_ms),
+        config.strategy.clone(),
+        config.protocol_version,
+        config.peers.clone(),
+    );
+    peer_manager.init().await?;
+
+    let mut warmed_up = 0;
+    let mut txs = pin!(submitted(storage, WARM_UP_PAGE_SIZE));
+    while let Some(tx) = txs.next().await {
+        peer_manager.add_tx(tx?.raw).await;
+        warmed_up += 1;
+    }
+    info!(warmed_up, "mempools warmed up");
+
+    Ok(Arc::new(peer_manager))
+}
+
+/// Transactions already submitted to the peers, read in pages so a large
+/// backlog isn't loaded at once.
+fn submitted(
+    storage: Arc<dyn TransactionStore>,
+    page_size: i64,
+) -> impl Stream<Item = anyhow::Result<Transaction>> {
+    stream! {
+        let mut offset = 0;
+        loop {
+            let page = match storage
+                .list(
+                    Some(TransactionStatus::InFlight),
+                    None,
+                    None,
+                    page_size,
+                    offset,
+                )
+                .await
+            {
+                Ok(page) => page,
+                Err(error) => {
+                    yield Err(error);
+                    break;
+                }
+            };
+
+            let len = page.len() as i64;
+            // claimed but never submitted ones are released to pending instead
+            for tx in page.into_iter().filter(|tx| tx.slot.is_some()) {
+                yield Ok(tx);
+            }
+
+            if len < page_size {
+                break;
+            }
+            offset += len;
+        }
+    }
+}
+
+/// Id the `index`th fanout worker of this process claims transactions with.
+pub fn worker_id(index: usize) -> String {
+    format!("fanout-{}-{index}", std::process::id())
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Preprod,
+    Preview,
+}
+
+impl Network {
+    pub fn magic(&self) -> u64 {
+        match self {
+            Network::Mainnet => 764824073,
+            Network::Preprod => 1,
+            Network::Preview => 2,
+        }
+    }
+
+    /// Network id of the addresses, 1 on mainnet and 0 on the testnets.
+    pub fn id(&self) -> u8 {
+        match self {
+            Network::Mainnet => 1,
+            Network::Preprod | Network::Preview => 0,
+        }
+    }
+}
+
+/// How a transaction is handed to the peers and when it counts as submitted.
+///
+/// A peer counts once the transaction is queued in its connection for the
+/// TxSubmission protocol, not once the node fetched or validated it, so none
+/// of them is a quorum of nodes accepting the transaction.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Every healthy peer, submitted once it's queued for any of them.
+    #[default]
+    Broadcast,
+    /// A single peer per transaction, the healthy ones taking turns.
+    RoundRobin,
+    /// Every healthy peer, submitted once it's queued for `min_peers` of them.
+    #[serde(alias = "quorum")]
+    MinPeers,
+}
+
+impl Strategy {
+    pub fn is_submitted(&self, submissions: &[(String, bool)], min_peers: usize) -> bool {
+        let queued = submissions.iter().filter(|(_, queued)| *queued).count();
+
+        match self {
+            Strategy::Broadcast | Strategy::RoundRobin => queued > 0,
+            Strategy::MinPeers => queued >= min_peers,
+        }
+    }
+}
+
+/// Node-to-node protocol versions proposed to the peers in the handshake.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ProtocolVersion {
+    /// Every version boros supports, the peer picks the latest it knows.
+    #[default]
+    Auto,
+    /// Only this version, the peers that don't support it refuse.
+    Version(u64),
+}
+
+impl<'de> Deserialize<'de> for ProtocolVersion {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(version) => Ok(Self::Version(version)),
+            Raw::Text(text) if text == "auto" => Ok(Self::Auto),
+            Raw::Text(text) => text.parse().map(Self::Version).map_err(|_| {
+                serde::de::Error::custom(format!(
+                    "invalid protocol version {text}, expected a number or auto"
+                ))
+            }),
+        }
+    }
+}
+
 #[derive(Deserialize, Clone)]
 pub struct PeerManagerConfig {
-    peers: Vec<String>,
+    #[serde(default)]
+    pub network: Network,
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// Caps the transactions handed to each peer per second, unlimited when
+    /// not set.
+    pub max_tx_per_sec: Option<u32>,
+    /// Transactions kept in memory for each peer, the oldest are dropped past
+    /// it.
+    #[serde(default = "default_mempool_capacity")]
+    pub mempool_capacity: usize,
+    /// Time a peer has to take a transaction before the submission to it is
+    /// given up on.
+    #[serde(default = "default_submit_timeout_ms")]
+    pub submit_timeout_ms: u64,
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Peers a transaction must be queued for with the min_peers strategy.
+    #[serde(default = "default_min_peers", alias = "quorum")]
+    pub min_peers: usize,
+    #[serde(default)]
+    pub protocol_version: ProtocolVersion,
+    /// Unix socket of a local cardano-node, the transactions are submitted to
+    /// it over node-to-client LocalTxSubmission instead of the peers when set.
+    pub local_socket: Option<String>,
+}
+
+fn default_mempool_capacity() -> usize {
+    10_000
+}
+
+fn default_submit_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_min_peers() -> usize {
+    1
 }
 
 // Test for Fanout Stage
@@ -105,11 +549,29 @@ pub mod mock_ouroboros_tx_submit_server;
 
 #[cfg(test)]
 mod fanout_tests {
-    use std::{sync::Arc, time::Duration};
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
     use hex::decode;
     use mock_ouroboros_tx_submit_server::MockOuroborosTxSubmitPeerServer;
     use pallas::ledger::traverse::MultiEraTx;
+    use tokio::net::TcpListener;
+
+    use crate::{
+        fixtures::TX_CBOR,
+        ledger::u5c::{ChainSyncStream, Point},
+        storage::{
+            clock::MockClock,
+            mock::MockTransactionStore,
+            sqlite::{SqliteStorage, SqliteTransaction},
+        },
+    };
 
     use super::*;
 
@@ -124,14 +586,15 @@ mod fanout_tests {
         peer_server.clone().init().await;
 
         tokio::time::sleep(Duration::from_millis(200)).await;
-        let mut tx_submit_peer_client = tx_submit_peer::TxSubmitPeer::new("127.0.0.1:3001", 2);
+        let mut tx_submit_peer_client =
+            tx_submit_peer::TxSubmitPeer::new("127.0.0.1:3001", 2, ProtocolVersion::Auto, 16);
 
         tx_submit_peer_client.init().await.unwrap();
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
         // add txs to peer client
-        let cbor_data = "84a300d90102828258202000fbfaa10c6b316fe4b23c60b42313fc2ad89b51c7397f82a4a5ca97bd62a9008258202000fbfaa10c6b316fe4b23c60b42313fc2ad89b51c7397f82a4a5ca97bd62a901018182581d606e2e6d54e1a27ad640786852e20c22fb982ebcee4773a2926aae4b391b00000001a1166016021a0002aa3da100d9010281825820524e506f6a872c4ee3ee6d4b9913670c4b441860b3aa5438de92a676e20f527b5840233e9119fa6a3c58ab42bc384f506c2906104ebb059ab4ea6cc79305ff46c7e194d634d23ff775f92e51246e328711e6cbf38aeda01a1885f922047323c68c04f5f6";
+        let cbor_data = TX_CBOR;
 
         // Read the raw bytes from the request body.
         let raw_cbor = match decode(cbor_data) {
@@ -148,7 +611,10 @@ mod fanout_tests {
         tracing::info!("Tx Hash: {:?}", tx_id);
 
         // There is a deadlock here, need to debug
-        tx_submit_peer_client.add_tx(raw_cbor.clone()).await;
+        tx_submit_peer_client
+            .add_tx(raw_cbor.clone())
+            .await
+            .unwrap();
 
         // wait for server to stop
         loop {
@@ -177,4 +643,430 @@ mod fanout_tests {
         }
         assert!(found);
     }
+
+    #[tokio::test]
+    async fn it_should_read_submitted_in_pages() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let mut txs: Vec<Transaction> = (0..5)
+            .map(|i| Transaction {
+                id: format!("submitted{i}"),
+                status: TransactionStatus::InFlight,
+                slot: Some(100),
+                ..Default::default()
+            })
+            .collect();
+        txs.push(Transaction {
+            id: "claimed".into(),
+            status: TransactionStatus::InFlight,
+            ..Default::default()
+        });
+        txs.push(Transaction {
+            id: "pending".into(),
+            ..Default::default()
+        });
+        storage.create(&txs).await.unwrap();
+
+        let mut ids: Vec<String> = submitted(storage, 2)
+            .map(|tx| tx.unwrap().id)
+            .collect()
+            .await;
+        ids.sort();
+        assert!(ids == (0..5).map(|i| format!("submitted{i}")).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn it_should_retry_when_no_peer_accepted() {
+        let storage = MockTransactionStore::default();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                claimed_by: Some(worker_id(0)),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let mut tx = storage.get("hex").await.unwrap().unwrap();
+        retry_later(
+            &storage,
+            &PipelineConfig::default(),
+            &mut tx,
+            &Rejection::transient("not accepted by enough peers"),
+            chrono::Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+        assert!(tx.attempts == 1);
+        assert!(tx.claimed_by.is_none());
+        assert!(tx.next_retry_at.is_some_and(|at| at > chrono::Utc::now()));
+    }
+
+    #[tokio::test]
+    async fn it_should_dead_letter_when_no_peer_accepted_the_last_attempt() {
+        let storage = MockTransactionStore::default();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                attempts: PipelineConfig::default().max_attempts - 1,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let mut tx = storage.get("hex").await.unwrap().unwrap();
+        retry_later(
+            &storage,
+            &PipelineConfig::default(),
+            &mut tx,
+            &Rejection::transient("not accepted by enough peers"),
+            chrono::Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::DeadLetter);
+        assert!(tx.attempts == PipelineConfig::default().max_attempts);
+    }
+
+    fn no_peers() -> Arc<TxSubmitPeerManager> {
+        Arc::new(TxSubmitPeerManager::new(
+            2,
+            None,
+            16,
+            Duration::from_secs(1),
+            Strategy::Broadcast,
+            ProtocolVersion::Auto,
+            vec![],
+        ))
+    }
+
+    struct MockU5cDataAdapter;
+
+    #[async_trait::async_trait]
+    impl U5cDataAdapter for MockU5cDataAdapter {
+        async fn fetch_tip(&self) -> anyhow::Result<Point> {
+            Ok((100, vec![]))
+        }
+
+        async fn fetch_utxos(
+            &self,
+            _utxo_refs: &[String],
+        ) -> anyhow::Result<HashMap<String, Vec<u8>>> {
+            Err(anyhow::anyhow!("not used in test"))
+        }
+
+        async fn stream(&self) -> anyhow::Result<ChainSyncStream> {
+            Err(anyhow::anyhow!("not used in test"))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_should_not_claim_without_healthy_peers() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Validated,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let (_, peers) = watch::channel(vec![]);
+        let (healthy_peers, _) = watch::channel(vec!["stale".to_string()]);
+        let mut stage = Stage::new(
+            worker_id(0),
+            serde_json::from_str(r#"{ "peers": [] }"#).unwrap(),
+            PipelineConfig::default(),
+            peers,
+            healthy_peers,
+            Arc::new(MockU5cDataAdapter),
+            storage.clone(),
+            no_peers(),
+        );
+        let mut worker = Worker {
+            id: stage.id.clone(),
+            scheduler: NamespaceScheduler::new(&HashMap::new()),
+            paused: false,
+        };
+
+        let schedule =
+            <Worker as gasket::framework::Worker<Stage>>::schedule(&mut worker, &mut stage)
+                .await
+                .unwrap();
+        assert!(matches!(schedule, WorkSchedule::Idle));
+        assert!(worker.paused);
+        assert!(stage.healthy_peers.borrow().is_empty());
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Validated);
+        assert!(tx.claimed_by.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_release_claim_when_peers_dropped() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                claimed_by: Some(worker_id(0)),
+                claimed_at: Some(chrono::Utc::now()),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let (_, peers) = watch::channel(vec![]);
+        let (healthy_peers, _) = watch::channel(vec![]);
+        let stage = Stage::new(
+            worker_id(0),
+            serde_json::from_str(r#"{ "peers": [] }"#).unwrap(),
+            PipelineConfig::default(),
+            peers,
+            healthy_peers,
+            Arc::new(MockU5cDataAdapter),
+            storage.clone(),
+            no_peers(),
+        );
+        let mut worker = Worker {
+            id: stage.id.clone(),
+            scheduler: NamespaceScheduler::new(&HashMap::new()),
+            paused: false,
+        };
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        worker.submit(&tx, &stage).await.unwrap();
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Validated);
+        assert!(tx.claimed_by.is_none());
+        assert!(tx.attempts == 0);
+    }
+
+    #[tokio::test]
+    async fn it_should_connect_once_per_peer_across_workers() {
+        // fake peer counting the connections, dropped before the handshake
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = listener.local_addr().unwrap().to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let accepted = accepted.clone();
+            async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                    drop(socket);
+                }
+            }
+        });
+
+        let storage = Arc::new(MockTransactionStore::default());
+        let config: PeerManagerConfig =
+            serde_json::from_str(&format!(r#"{{ "peers": ["{peer_addr}"] }}"#)).unwrap();
+        let peer_manager = connect_peers(&config, storage.clone()).await.unwrap();
+
+        let (_, peers) = watch::channel(vec![peer_addr.clone()]);
+        let (healthy_peers, _) = watch::channel(vec![]);
+        let mut workers = Vec::new();
+        for index in 0..4 {
+            let stage = Stage::new(
+                worker_id(index),
+                config.clone(),
+                PipelineConfig::default(),
+                peers.clone(),
+                healthy_peers.clone(),
+                Arc::new(MockU5cDataAdapter),
+                storage.clone(),
+                peer_manager.clone(),
+            );
+            let worker = <Worker as gasket::framework::Worker<Stage>>::bootstrap(&stage)
+                .await
+                .unwrap();
+            workers.push((stage, worker));
+        }
+        assert!(accepted.load(Ordering::SeqCst) == 1);
+
+        // every worker checks the peers once the backoff elapsed, a single one
+        // of them reconnects
+        tokio::time::pause();
+        tokio::time::advance(Duration::from_millis(1100)).await;
+        futures::future::join_all(
+            workers
+                .iter_mut()
+                .map(|(stage, worker)| worker.check_peers(stage)),
+        )
+        .await;
+        assert!(accepted.load(Ordering::SeqCst) == 2);
+    }
+
+    struct MockLocalTxSubmit(LocalSubmission);
+
+    #[async_trait::async_trait]
+    impl LocalTxSubmit for MockLocalTxSubmit {
+        fn address(&self) -> &str {
+            "node.socket"
+        }
+
+        async fn submit(&self, _raw: &[u8]) -> anyhow::Result<LocalSubmission> {
+            Ok(self.0.clone())
+        }
+    }
+
+    async fn local_worker(
+        submission: LocalSubmission,
+    ) -> (Arc<MockTransactionStore>, Stage, Worker) {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                claimed_by: Some(worker_id(0)),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let (_, peers) = watch::channel(vec![]);
+        let (healthy_peers, _) = watch::channel(vec![]);
+        let stage = Stage::new(
+            worker_id(0),
+            serde_json::from_str(r#"{ "local_socket": "node.socket" }"#).unwrap(),
+            PipelineConfig::default(),
+            peers,
+            healthy_peers,
+            Arc::new(MockU5cDataAdapter),
+            storage.clone(),
+            no_peers(),
+        )
+        .with_local_submit(Arc::new(MockLocalTxSubmit(submission)));
+        let worker = Worker {
+            id: stage.id.clone(),
+            scheduler: NamespaceScheduler::new(&HashMap::new()),
+            paused: false,
+        };
+
+        (storage, stage, worker)
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_to_local_node() {
+        let (storage, stage, mut worker) = local_worker(LocalSubmission::Accepted).await;
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        worker.submit(&tx, &stage).await.unwrap();
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::InFlight);
+        assert!(tx.slot == Some(100));
+
+        let submissions = storage.submissions_for("hex").await.unwrap();
+        assert!(submissions.len() == 1);
+        assert!(submissions[0].peer == "node.socket");
+        assert!(submissions[0].accepted);
+    }
+
+    #[tokio::test]
+    async fn it_should_keep_rejection_of_local_node() {
+        // [6, [[1, [0, [1, []]]]]]
+        let reason = hex::decode("82068182018200820180").unwrap();
+        let (storage, stage, mut worker) = local_worker(LocalSubmission::Rejected(reason)).await;
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        worker.submit(&tx, &stage).await.unwrap();
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+        assert!(tx.attempts == 1);
+        assert!(tx.error.as_deref() == Some("rejected by the node: BadInputsUTxO"));
+
+        let submissions = storage.submissions_for("hex").await.unwrap();
+        assert!(submissions.len() == 1);
+        assert!(!submissions[0].accepted);
+    }
+
+    #[tokio::test]
+    async fn it_should_retry_on_the_stage_clock() {
+        // a day behind the wall clock, the backoff only elapses on this one
+        let clock = Arc::new(MockClock::new(
+            chrono::Utc::now() - chrono::Duration::days(1),
+        ));
+        let storage = Arc::new(
+            SqliteTransaction::new(Arc::new(SqliteStorage::ephemeral().await.unwrap()))
+                .with_clock(clock.clone()),
+        );
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                claimed_by: Some(worker_id(0)),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        // [6, [[1, [0, [1, []]]]]]
+        let reason = hex::decode("82068182018200820180").unwrap();
+        let (_, peers) = watch::channel(vec![]);
+        let (healthy_peers, _) = watch::channel(vec![]);
+        let stage = Stage::new(
+            worker_id(0),
+            serde_json::from_str(r#"{ "local_socket": "node.socket" }"#).unwrap(),
+            PipelineConfig::default(),
+            peers,
+            healthy_peers,
+            Arc::new(MockU5cDataAdapter),
+            storage.clone(),
+            no_peers(),
+        )
+        .with_local_submit(Arc::new(MockLocalTxSubmit(LocalSubmission::Rejected(
+            reason,
+        ))))
+        .with_clock(clock.clone());
+        let mut worker = Worker {
+            id: stage.id.clone(),
+            scheduler: NamespaceScheduler::new(&HashMap::new()),
+            paused: false,
+        };
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        worker.submit(&tx, &stage).await.unwrap();
+
+        let next = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(next.is_none());
+
+        let delay = PipelineConfig::default().backoff.delay(0);
+        clock.advance(chrono::Duration::from_std(delay).unwrap());
+
+        let next = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(next.is_some_and(|tx| tx.id == "hex" && tx.attempts == 1));
+    }
+
+    #[tokio::test]
+    async fn it_should_dead_letter_deterministic_rejection() {
+        // [6, [[1, [0, [5, 100, 200]]]]]
+        let reason = hex::decode("820681820182008305186418c8").unwrap();
+        let (storage, stage, mut worker) = local_worker(LocalSubmission::Rejected(reason)).await;
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        worker.submit(&tx, &stage).await.unwrap();
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::DeadLetter);
+        assert!(tx.attempts == 1);
+        assert!(tx.error.as_deref() == Some("rejected by the node: FeeTooSmallUTxO"));
+    }
+
+    #[test]
+    fn it_should_map_network_magic() {
+        assert!(Network::default() == Network::Mainnet);
+        assert!(Network::Mainnet.magic() == 764824073);
+        assert!(Network::Preprod.magic() == 1);
+        assert!(Network::Preview.magic() == 2);
+    }
 }
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+
+/// Token bucket refilled with `rate` tokens per second, holding at most `rate`
+/// tokens so a burst is never larger than one second of submissions.
+pub struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32) -> Self {
+        let rate = rate.max(1) as f64;
+
+        Self {
+            rate,
+            tokens: rate,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Takes a token, waiting for the bucket to refill when it's empty.
+    pub async fn acquire(&mut self) {
+        loop {
+            self.refill();
+
+            if self.tokens >= 1.0 {
+                self.tokens -= 1.0;
+                return;
+            }
+
+            sleep(Duration::from_secs_f64((1.0 - self.tokens) / self.rate)).await;
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::TokenBucket;
+
+    #[tokio::test(start_paused = true)]
+    async fn it_should_allow_a_burst_up_to_the_rate() {
+        let mut bucket = TokenBucket::new(5);
+        let started_at = Instant::now();
+
+        for _ in 0..5 {
+            bucket.acquire().await;
+        }
+
+        assert!(started_at.elapsed() < Duration::from_millis(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_should_spread_submissions_over_time() {
+        let mut bucket = TokenBucket::new(5);
+        let started_at = Instant::now();
+
+        // the first 5 use the full bucket, the other 10 wait 200ms each
+        for _ in 0..15 {
+            bucket.acquire().await;
+        }
+
+        let elapsed = started_at.elapsed();
+        assert!(elapsed >= Duration::from_secs(2));
+        assert!(elapsed < Duration::from_millis(2100));
+    }
+}
@@ -0,0 +1,311 @@
+use pallas::codec::minicbor::Decoder;
+
+/// Ledger failures that fail the same way on every attempt, e.g. a bad script
+/// or a fee too low, so retrying them only burns the attempts.
+const DETERMINISTIC_FAILURES: &[&str] = &[
+    "ValueNotConservedUTxO",
+    "FeeTooSmallUTxO",
+    "MaxTxSizeUTxO",
+    "OutputTooSmallUTxO",
+    "OutputTooBigUTxO",
+    "InputSetEmptyUTxO",
+    "WrongNetwork",
+    "MissingVKeyWitnessesUTXOW",
+    "InvalidWitnessesUTXOW",
+    "MissingScriptWitnessesUTXOW",
+    "ScriptWitnessNotValidatingUTXOW",
+    "MalformedScriptWitnesses",
+    "ValidationTagMismatch",
+    "ExUnitsTooBigUTxO",
+    "InsufficientCollateral",
+    "NoCollateralInputs",
+    "TooManyCollateralInputs",
+    "MissingRedeemers",
+    "ExtraRedeemers",
+    "PPViewHashesDontMatch",
+];
+
+/// Index of the Conway era in the hard fork combinator of the node, the reject
+/// reasons of the other eras aren't read.
+const CONWAY_ERA: u8 = 6;
+
+/// `ConwayLedgerPredFailure` by tag, starting at 1.
+const LEDGER_FAILURES: &[&str] = &[
+    "ConwayUtxowFailure",
+    "ConwayCertsFailure",
+    "ConwayGovFailure",
+    "ConwayWdrlNotDelegatedToDRep",
+    "ConwayTreasuryValueMismatch",
+    "ConwayTxRefScriptsSizeTooBig",
+    "ConwayMempoolFailure",
+];
+
+/// `ConwayUtxowPredFailure` by tag.
+const UTXOW_FAILURES: &[&str] = &[
+    "UtxoFailure",
+    "InvalidWitnessesUTXOW",
+    "MissingVKeyWitnessesUTXOW",
+    "MissingScriptWitnessesUTXOW",
+    "ScriptWitnessNotValidatingUTXOW",
+    "MissingTxBodyMetadataHash",
+    "MissingTxMetadata",
+    "ConflictingMetadataHash",
+    "InvalidMetadata",
+    "ExtraneousScriptWitnessesUTXOW",
+    "MissingRedeemers",
+    "MissingRequiredDatums",
+    "NotAllowedSupplementalDatums",
+    "PPViewHashesDontMatch",
+    "UnspendableUTxONoDatumHash",
+    "ExtraRedeemers",
+    "MalformedScriptWitnesses",
+    "MalformedReferenceScripts",
+];
+
+/// `ConwayUtxoPredFailure` by tag.
+const UTXO_FAILURES: &[&str] = &[
+    "UtxosFailure",
+    "BadInputsUTxO",
+    "OutsideValidityIntervalUTxO",
+    "MaxTxSizeUTxO",
+    "InputSetEmptyUTxO",
+    "FeeTooSmallUTxO",
+    "ValueNotConservedUTxO",
+    "WrongNetwork",
+    "WrongNetworkWithdrawal",
+    "OutputTooSmallUTxO",
+    "OutputBootAddrAttrsTooBig",
+    "OutputTooBigUTxO",
+    "InsufficientCollateral",
+    "ScriptsNotPaidUTxO",
+    "ExUnitsTooBigUTxO",
+    "CollateralContainsNonADA",
+    "WrongNetworkInTxBody",
+    "OutsideForecast",
+    "TooManyCollateralInputs",
+    "NoCollateralInputs",
+    "IncorrectTotalCollateralField",
+    "BabbageOutputTooSmallUTxO",
+    "BabbageNonDisjointRefInputs",
+];
+
+/// `ConwayUtxosPredFailure` by tag.
+const UTXOS_FAILURES: &[&str] = &["ValidationTagMismatch", "CollectErrors"];
+
+/// Why a transaction wasn't submitted, and whether another attempt can
+/// succeed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejection {
+    pub reason: String,
+    pub retryable: bool,
+}
+
+impl Rejection {
+    /// A rejection without a reason from the ledger, e.g. no peer took the
+    /// transaction in time, always retried.
+    pub fn transient(reason: &str) -> Self {
+        Self {
+            reason: reason.to_string(),
+            retryable: true,
+        }
+    }
+
+    /// Reads the ledger failures of the reject reason a node answered with,
+    /// the CBOR of its `ApplyTxErr`. It's retried unless one of them is
+    /// deterministic, a reason that can't be read is retried too and kept in
+    /// hex.
+    pub fn from_node(raw: &[u8]) -> Self {
+        let Some(failures) = conway_failures(raw) else {
+            return Self {
+                reason: format!("rejected by the node: {}", hex::encode(raw)),
+                retryable: true,
+            };
+        };
+
+        let names: Vec<&str> = failures.iter().map(|(name, _)| *name).collect();
+        Self {
+            reason: format!("rejected by the node: {}", names.join(", ")),
+            retryable: !failures.iter().any(|(_, deterministic)| *deterministic),
+        }
+    }
+}
+
+/// The rules of the Conway ledger a failure is nested in, e.g. a
+/// `FeeTooSmallUTxO` is a UTXO failure within a UTXOW one within the LEDGER.
+#[derive(Clone, Copy)]
+enum Rule {
+    Ledger,
+    Utxow,
+    Utxo,
+    Utxos,
+}
+
+impl Rule {
+    fn name(&self, tag: u8) -> Option<&'static str> {
+        match self {
+            Rule::Ledger => LEDGER_FAILURES.get((tag as usize).checked_sub(1)?),
+            Rule::Utxow => UTXOW_FAILURES.get(tag as usize),
+            Rule::Utxo => UTXO_FAILURES.get(tag as usize),
+            Rule::Utxos => UTXOS_FAILURES.get(tag as usize),
+        }
+        .copied()
+    }
+
+    /// The rule whose failure the variant `tag` wraps.
+    fn nested(&self, tag: u8) -> Option<Rule> {
+        match (self, tag) {
+            (Rule::Ledger, 1) => Some(Rule::Utxow),
+            (Rule::Utxow, 0) => Some(Rule::Utxo),
+            (Rule::Utxo, 0) => Some(Rule::Utxos),
+            _ => None,
+        }
+    }
+}
+
+/// Ledger failures of a Conway reject reason, `[era, [failure, ...]]`, with
+/// whether each is deterministic. `None` when it's from another era or can't
+/// be read.
+fn conway_failures(raw: &[u8]) -> Option<Vec<(&'static str, bool)>> {
+    let mut decoder = Decoder::new(raw);
+
+    // the reason may come wrapped in single element lists
+    let mut len = decoder.array().ok()??;
+    while len == 1 {
+        len = decoder.array().ok()??;
+    }
+    if len != 2 || decoder.u8().ok()? != CONWAY_ERA {
+        return None;
+    }
+
+    let count = decoder.array().ok()??;
+    let failures = (0..count)
+        .map(|_| failure(&mut decoder, Rule::Ledger))
+        .collect::<Option<Vec<_>>>()?;
+
+    (!failures.is_empty()).then_some(failures)
+}
+
+/// Reads a failure of `rule`, `[tag, field, ...]`, down to the innermost
+/// failure it wraps.
+fn failure(decoder: &mut Decoder, rule: Rule) -> Option<(&'static str, bool)> {
+    let len = decoder.array().ok()??;
+    let fields = len.checked_sub(1)?;
+    let tag = decoder.u8().ok()?;
+
+    if let Some(nested) = rule.nested(tag).filter(|_| fields > 0) {
+        let failure = failure(decoder, nested)?;
+        skip(decoder, fields - 1)?;
+        return Some(failure);
+    }
+
+    let name = rule.name(tag)?;
+    let deterministic = match (rule, tag) {
+        (Rule::Utxo, 2) if fields == 2 => is_expired(decoder)?,
+        _ => {
+            skip(decoder, fields)?;
+            DETERMINISTIC_FAILURES.contains(&name)
+        }
+    };
+
+    Some((name, deterministic))
+}
+
+/// Whether an `OutsideValidityIntervalUTxO`, `[[invalid_before?],
+/// [invalid_hereafter?]], slot`, is past the upper bound of the interval. The
+/// slot only grows so it never passes again, unlike one before the lower
+/// bound.
+fn is_expired(decoder: &mut Decoder) -> Option<bool> {
+    decoder.array().ok()?;
+    decoder.skip().ok()?;
+    let invalid_hereafter = match decoder.array().ok()? {
+        Some(0) => None,
+        Some(1) => Some(decoder.u64().ok()?),
+        _ => return None,
+    };
+    let slot = decoder.u64().ok()?;
+
+    Some(invalid_hereafter.is_some_and(|ttl| slot >= ttl))
+}
+
+fn skip(decoder: &mut Decoder, fields: u64) -> Option<()> {
+    for _ in 0..fields {
+        decoder.skip().ok()?;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod rejection_tests {
+    use pallas::{
+        codec::minicbor,
+        network::miniprotocols::localtxsubmission::{EraTx, Message, RejectReason},
+    };
+
+    use super::Rejection;
+
+    fn from_node(reason: &str) -> Rejection {
+        Rejection::from_node(&hex::decode(reason).unwrap())
+    }
+
+    #[test]
+    fn it_should_not_retry_value_not_conserved() {
+        // [6, [[1, [0, [6, 1, 2]]]]]
+        let rejection = from_node("8206818201820083060102");
+        assert!(!rejection.retryable);
+        assert!(rejection.reason == "rejected by the node: ValueNotConservedUTxO");
+    }
+
+    #[test]
+    fn it_should_not_retry_when_any_failure_is_deterministic() {
+        // [6, [[1, [0, [1, []]]], [1, [0, [5, 100, 200]]]]]
+        let rejection = from_node("82068282018200820180820182008305186418c8");
+        assert!(!rejection.retryable);
+        assert!(rejection.reason == "rejected by the node: BadInputsUTxO, FeeTooSmallUTxO");
+    }
+
+    #[test]
+    fn it_should_retry_bad_inputs() {
+        // [6, [[1, [0, [1, []]]]]]
+        let rejection = from_node("82068182018200820180");
+        assert!(rejection.retryable);
+        assert!(rejection.reason == "rejected by the node: BadInputsUTxO");
+    }
+
+    #[test]
+    fn it_should_not_retry_past_the_validity_interval() {
+        // [6, [[1, [0, [2, [[], [100]], 200]]]]]
+        let rejection = from_node("820681820182008302828081186418c8");
+        assert!(!rejection.retryable);
+        assert!(rejection.reason == "rejected by the node: OutsideValidityIntervalUTxO");
+    }
+
+    #[test]
+    fn it_should_retry_before_the_validity_interval() {
+        // [6, [[1, [0, [2, [[300], []], 200]]]]]
+        let rejection = from_node("820681820182008302828119012c8018c8");
+        assert!(rejection.retryable);
+    }
+
+    #[test]
+    fn it_should_retry_unknown_reason() {
+        // a reason of the babbage era
+        let rejection = from_node("820580");
+        assert!(rejection.retryable);
+        assert!(rejection.reason == "rejected by the node: 820580");
+    }
+
+    #[test]
+    fn it_should_read_the_reason_of_a_reject_message() {
+        // MsgRejectTx [2, reason] with the reason wrapped in a single element list
+        let bytes = hex::decode("820281820681820182008305186418c8").unwrap();
+
+        let message: Message<EraTx, RejectReason> = minicbor::decode(&bytes).unwrap();
+        let Message::RejectTx(reason) = message else {
+            panic!("expected a reject");
+        };
+
+        let rejection = Rejection::from_node(&reason.0);
+        assert!(!rejection.retryable);
+        assert!(rejection.reason == "rejected by the node: FeeTooSmallUTxO");
+    }
+}
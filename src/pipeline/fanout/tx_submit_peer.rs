@@ -1,67 +1,145 @@
 use std::fmt::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+use anyhow::bail;
 use itertools::Itertools;
 use pallas::crypto::hash::Hash;
-use pallas::network::miniprotocols::txsubmission::{EraTxBody, EraTxId, Request};
-use pallas::network::{facades::PeerClient, miniprotocols::txsubmission::TxIdAndSize};
+use pallas::network::facades::{KeepAliveLoop, PeerClient};
+use pallas::network::miniprotocols::handshake::{self, n2n, Confirmation, RefuseReason};
+use pallas::network::miniprotocols::txsubmission::{self, EraTxBody, EraTxId, Request};
+use pallas::network::miniprotocols::{
+    blockfetch, chainsync, keepalive, peersharing, txsubmission::TxIdAndSize,
+    PROTOCOL_N2N_BLOCK_FETCH, PROTOCOL_N2N_CHAIN_SYNC, PROTOCOL_N2N_HANDSHAKE,
+    PROTOCOL_N2N_KEEP_ALIVE, PROTOCOL_N2N_PEER_SHARING, PROTOCOL_N2N_TX_SUBMISSION,
+};
+use pallas::network::multiplexer::{Bearer, Plexer};
 use tokio::sync::{Mutex, RwLock};
 use tokio::task;
 use tracing::{error, info};
 
-use super::mempool::{self, Mempool};
+use super::mempool::{self, Mempool, MempoolError};
+use super::ProtocolVersion;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(20);
 
 pub struct TxSubmitPeer {
     mempool: Arc<Mutex<Mempool>>,
     client: Arc<Mutex<Option<PeerClient>>>,
     peer_addr: String,
     network_magic: u64,
+    protocol_version: ProtocolVersion,
     unfulfilled_request: Arc<RwLock<Option<usize>>>,
+    connected: Arc<AtomicBool>,
+    task: std::sync::Mutex<Option<task::JoinHandle<()>>>,
 }
 
 impl TxSubmitPeer {
-    pub fn new(peer_addr: &str, network_magic: u64) -> Self {
+    pub fn new(
+        peer_addr: &str,
+        network_magic: u64,
+        protocol_version: ProtocolVersion,
+        mempool_capacity: usize,
+    ) -> Self {
         TxSubmitPeer {
-            mempool: Arc::new(Mutex::new(Mempool::new())),
+            mempool: Arc::new(Mutex::new(Mempool::new(mempool_capacity))),
             client: Arc::new(Mutex::new(None)),
             peer_addr: peer_addr.to_string(),
             network_magic,
+            protocol_version,
             unfulfilled_request: Arc::new(RwLock::new(None)),
+            connected: Arc::new(AtomicBool::new(false)),
+            task: std::sync::Mutex::new(None),
         }
     }
 
-    pub async fn init(&mut self) -> Result<(), Error> {
-        let mut client = PeerClient::connect(&self.peer_addr, self.network_magic)
-            .await
-            .map_err(|e| {
-                error!(error=?e, peer=%self.peer_addr, "Failed to connect to peer");
-                e
-            })
-            .unwrap();
-
-        client
-            .txsubmission()
-            .send_init()
-            .await
-            .map_err(|e| {
-                error!(error=?e, peer=%self.peer_addr, "Failed to send init message");
-                e
-            })
-            .unwrap();
+    pub async fn init(&mut self) -> anyhow::Result<()> {
+        let mut client = self.connect().await.map_err(|e| {
+            error!(
+                error=?e,
+                peer=%self.peer_addr,
+                network_magic=self.network_magic,
+                "Failed to connect to peer, check the network magic matches the peer"
+            );
+            e
+        })?;
+
+        client.txsubmission().send_init().await.map_err(|e| {
+            error!(error=?e, peer=%self.peer_addr, "Failed to send init message");
+            e
+        })?;
 
         self.client = Arc::new(Mutex::new(Some(client)));
+        self.connected.store(true, Ordering::SeqCst);
 
-        self.start_background_task();
+        self.task = std::sync::Mutex::new(Some(self.start_background_task()));
 
         Ok(())
     }
 
-    fn start_background_task(&self) {
+    /// Same as `PeerClient::connect`, but proposing the versions of
+    /// `protocol_version` in the handshake.
+    async fn connect(&self) -> anyhow::Result<PeerClient> {
+        let versions = version_table(self.protocol_version, self.network_magic)?;
+
+        let bearer = Bearer::connect_tcp(&self.peer_addr).await?;
+        let mut plexer = Plexer::new(bearer);
+
+        let channel0 = plexer.subscribe_client(PROTOCOL_N2N_HANDSHAKE);
+        let channel2 = plexer.subscribe_client(PROTOCOL_N2N_CHAIN_SYNC);
+        let channel3 = plexer.subscribe_client(PROTOCOL_N2N_BLOCK_FETCH);
+        let channel4 = plexer.subscribe_client(PROTOCOL_N2N_TX_SUBMISSION);
+        let channel8 = plexer.subscribe_client(PROTOCOL_N2N_KEEP_ALIVE);
+        let channel10 = plexer.subscribe_client(PROTOCOL_N2N_PEER_SHARING);
+
+        let plexer = plexer.spawn();
+
+        let mut handshake = handshake::N2NClient::new(channel0);
+        match handshake.handshake(versions).await? {
+            Confirmation::Accepted(version, _) => {
+                info!(peer=%self.peer_addr, version, "Handshake accepted");
+            }
+            Confirmation::Rejected(RefuseReason::VersionMismatch(supported)) => {
+                error!(
+                    peer=%self.peer_addr,
+                    ?supported,
+                    protocol_version=?self.protocol_version,
+                    "Handshake refused, the peer doesn't support the proposed versions"
+                );
+                plexer.abort().await;
+                bail!("peer supports the protocol versions {supported:?} only");
+            }
+            Confirmation::Rejected(reason) => {
+                error!(peer=%self.peer_addr, ?reason, "Handshake refused");
+                plexer.abort().await;
+                bail!("handshake refused: {reason:?}");
+            }
+            Confirmation::QueryReply(_) => {
+                plexer.abort().await;
+                bail!("unexpected query reply to the handshake");
+            }
+        }
+
+        let keepalive =
+            KeepAliveLoop::client(keepalive::Client::new(channel8), KEEP_ALIVE_INTERVAL).spawn();
+
+        Ok(PeerClient {
+            plexer,
+            keepalive,
+            chainsync: chainsync::Client::new(channel2),
+            blockfetch: blockfetch::Client::new(channel3),
+            txsubmission: txsubmission::Client::new(channel4),
+            peersharing: peersharing::Client::new(channel10),
+        })
+    }
+
+    fn start_background_task(&self) -> task::JoinHandle<()> {
         let client_arc = Arc::clone(&self.client);
         let mempool_arc = Arc::clone(&self.mempool);
         let unfulfilled_request_arc = Arc::clone(&self.unfulfilled_request);
         let peer_addr = self.peer_addr.clone();
+        let connected_arc = Arc::clone(&self.connected);
 
         task::spawn(async move {
             loop {
@@ -192,17 +270,44 @@ impl TxSubmitPeer {
             }
 
             // No client available; abort the connection
+            connected_arc.store(false, Ordering::SeqCst);
             let mut final_client_guard = client_arc.lock().await;
             if let Some(client) = final_client_guard.take() {
                 error!(peer=%peer_addr, "Aborting tx submit peer client connection...");
                 client.abort().await
             }
-        });
+        })
     }
 
-    pub async fn add_tx(&self, tx: Vec<u8>) {
+    /// Stops serving requests and closes the connection to the peer.
+    pub async fn close(&self) {
+        let task = self.task.lock().unwrap().take();
+        if let Some(task) = task {
+            task.abort();
+            let _ = task.await;
+        }
+
+        self.connected.store(false, Ordering::SeqCst);
+        if let Some(client) = self.client.lock().await.take() {
+            info!(peer=%self.peer_addr, "Closing tx submit peer client connection...");
+            client.abort().await
+        }
+    }
+
+    #[cfg(test)]
+    pub fn mempool(&self) -> Arc<Mutex<Mempool>> {
+        Arc::clone(&self.mempool)
+    }
+
+    /// False once the background task stopped, the peer must be reconnected.
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    pub async fn add_tx(&self, tx: Vec<u8>) -> Result<(), MempoolError> {
         let mempool = self.mempool.lock().await;
-        mempool.receive_raw(&tx).unwrap();
+        mempool.receive_raw(&tx)?;
+        Ok(())
     }
 
     async fn process_unfulfilled(
@@ -299,3 +404,49 @@ impl TxSubmitPeer {
         Ok(())
     }
 }
+
+/// Versions proposed in the handshake. With `auto` every version from 7 on is
+/// proposed and the peer picks the latest one it supports.
+pub fn version_table(
+    protocol_version: ProtocolVersion,
+    network_magic: u64,
+) -> anyhow::Result<n2n::VersionTable> {
+    let mut versions = n2n::VersionTable::v7_and_above(network_magic);
+
+    if let ProtocolVersion::Version(version) = protocol_version {
+        let supported = versions.values.keys().copied().sorted().collect_vec();
+        versions.values.retain(|v, _| *v == version);
+        if versions.values.is_empty() {
+            bail!("protocol version {version} not supported, expected one of {supported:?}");
+        }
+    }
+
+    Ok(versions)
+}
+
+#[cfg(test)]
+mod tx_submit_peer_tests {
+    use super::*;
+
+    #[test]
+    fn it_should_propose_configured_version() {
+        let versions = version_table(ProtocolVersion::Version(11), 2).unwrap();
+
+        assert!(versions.values.keys().copied().collect_vec() == vec![11]);
+        assert!(versions.values[&11].network_magic == 2);
+    }
+
+    #[test]
+    fn it_should_propose_every_version_on_auto() {
+        let versions = version_table(ProtocolVersion::Auto, 2).unwrap();
+
+        let proposed = versions.values.keys().copied().sorted().collect_vec();
+        assert!(proposed.first() == Some(&7));
+        assert!(proposed.len() > 1);
+    }
+
+    #[test]
+    fn it_should_fail_unsupported_version() {
+        assert!(version_table(ProtocolVersion::Version(1), 2).is_err());
+    }
+}
@@ -1,38 +1,524 @@
 use std::collections::HashMap;
-use std::fmt::Error;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex as StdMutex,
+};
+use std::time::Duration;
 
-use super::tx_submit_peer::TxSubmitPeer;
+use anyhow::Context;
+use futures::future::join_all;
+use tokio::{
+    sync::Mutex,
+    time::{timeout, Instant},
+};
+use tracing::{info, warn};
 
+use super::{rate_limit::TokenBucket, tx_submit_peer::TxSubmitPeer, ProtocolVersion, Strategy};
+
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Default)]
+pub struct PeerHealth {
+    pub connected: bool,
+    pub last_error: Option<String>,
+    pub retries: u32,
+    pub next_retry: Option<Instant>,
+}
+
+struct PeerState {
+    peer: Option<Arc<TxSubmitPeer>>,
+    health: PeerHealth,
+    limiter: Option<Arc<Mutex<TokenBucket>>>,
+    /// A worker is connecting it, the others leave it alone.
+    connecting: bool,
+}
+
+impl PeerState {
+    fn new(max_tx_per_sec: Option<u32>) -> Self {
+        Self {
+            peer: None,
+            health: PeerHealth::default(),
+            limiter: max_tx_per_sec.map(|rate| Arc::new(Mutex::new(TokenBucket::new(rate)))),
+            connecting: false,
+        }
+    }
+}
+
+/// Connections to the peers, shared by every fanout worker so each peer has a
+/// single connection however many workers claim. The peers are locked only to
+/// read or change their state, never while connecting or submitting.
 pub struct TxSubmitPeerManager {
     network_magic: u64,
-    peers: HashMap<String, Option<TxSubmitPeer>>,
+    protocol_version: ProtocolVersion,
+    max_tx_per_sec: Option<u32>,
+    mempool_capacity: usize,
+    submit_timeout: Duration,
+    strategy: Strategy,
+    /// Turn of the next peer with the round robin strategy.
+    next_peer: AtomicUsize,
+    peers: StdMutex<HashMap<String, PeerState>>,
 }
 
 impl TxSubmitPeerManager {
-    pub fn new(network_magic: u64, peer_addresses: Vec<String>) -> Self {
+    pub fn new(
+        network_magic: u64,
+        max_tx_per_sec: Option<u32>,
+        mempool_capacity: usize,
+        submit_timeout: Duration,
+        strategy: Strategy,
+        protocol_version: ProtocolVersion,
+        peer_addresses: Vec<String>,
+    ) -> Self {
         TxSubmitPeerManager {
             network_magic,
-            peers: peer_addresses
+            protocol_version,
+            max_tx_per_sec,
+            mempool_capacity,
+            submit_timeout,
+            strategy,
+            next_peer: AtomicUsize::new(0),
+            peers: StdMutex::new(
+                peer_addresses
+                    .into_iter()
+                    .map(|peer_addr| (peer_addr, PeerState::new(max_tx_per_sec)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Connects to every peer. A peer that fails isn't fatal, it's scheduled
+    /// to reconnect in `check_peers`.
+    pub async fn init(&self) -> anyhow::Result<()> {
+        let peer_addrs = self.take_for_connect(|_| true);
+        self.connect_all(peer_addrs).await;
+        Ok(())
+    }
+
+    /// Detects dropped connections and reconnects the peers whose backoff
+    /// elapsed.
+    pub async fn check_peers(&self) {
+        let now = Instant::now();
+
+        {
+            let mut peers = self.peers.lock().unwrap();
+            for (peer_addr, state) in peers.iter_mut() {
+                if !state.health.connected
+                    || state.peer.as_ref().is_some_and(|peer| peer.is_connected())
+                {
+                    continue;
+                }
+
+                warn!(peer=%peer_addr, "Peer connection dropped");
+                state.peer = None;
+                Self::schedule_retry(&mut state.health, "connection dropped".into());
+            }
+        }
+
+        let peer_addrs = self.take_for_connect(|health| {
+            !health.connected && health.next_retry.is_some_and(|at| at <= now)
+        });
+        for peer_addr in &peer_addrs {
+            info!(peer=%peer_addr, "Reconnecting peer");
+        }
+        self.connect_all(peer_addrs).await;
+    }
+
+    /// Applies a new peer list: removed peers are closed, new ones connected
+    /// and the connections to the unchanged peers are kept as they are.
+    pub async fn update_peers(&self, peer_addresses: Vec<String>) {
+        let removed: Vec<Arc<TxSubmitPeer>> = {
+            let mut peers = self.peers.lock().unwrap();
+
+            let removed_addrs: Vec<String> = peers
+                .keys()
+                .filter(|peer_addr| !peer_addresses.contains(peer_addr))
+                .cloned()
+                .collect();
+            let removed = removed_addrs
                 .into_iter()
-                .map(|peer_addr| (peer_addr, None))
-                .collect(),
+                .filter_map(|peer_addr| {
+                    info!(peer=%peer_addr, "Removing peer");
+                    peers.remove(&peer_addr).and_then(|state| state.peer)
+                })
+                .collect();
+
+            for peer_addr in &peer_addresses {
+                if !peers.contains_key(peer_addr) {
+                    info!(peer=%peer_addr, "Adding peer");
+                    peers.insert(peer_addr.clone(), PeerState::new(self.max_tx_per_sec));
+                }
+            }
+
+            removed
+        };
+
+        for peer in removed {
+            peer.close().await;
         }
+
+        let peer_addrs = self.take_for_connect(|health| {
+            !health.connected && health.retries == 0 && health.next_retry.is_none()
+        });
+        self.connect_all(peer_addrs).await;
+    }
+
+    pub fn healthy_peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, state)| state.health.connected)
+            .map(|(peer_addr, _)| peer_addr.clone())
+            .collect();
+        peers.sort();
+        peers
+    }
+
+    pub fn health(&self, peer_addr: &str) -> Option<PeerHealth> {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(peer_addr)
+            .map(|state| state.health.clone())
+    }
+
+    /// Hands the tx to the connected peers the strategy picks. A peer over its
+    /// rate limit is waited for, the others don't wait on it, and a peer that
+    /// doesn't take the tx within the submit timeout is given up on. Returns
+    /// the peers it was handed to and whether each of them took it.
+    pub async fn add_tx(&self, tx: Vec<u8>) -> Vec<(String, bool)> {
+        let targets = self.targets();
+        let submit_timeout = self.submit_timeout;
+
+        let peers: Vec<(String, Arc<TxSubmitPeer>, Option<Arc<Mutex<TokenBucket>>>)> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(peer_addr, state)| state.health.connected && targets.contains(peer_addr))
+            .filter_map(|(peer_addr, state)| {
+                let peer = state.peer.clone()?;
+                Some((peer_addr.clone(), peer, state.limiter.clone()))
+            })
+            .collect();
+
+        let submissions = peers.into_iter().map(|(peer_addr, peer, limiter)| {
+            let tx = tx.clone();
+
+            async move {
+                if let Some(limiter) = limiter {
+                    limiter.lock().await.acquire().await;
+                }
+                let accepted = match timeout(submit_timeout, peer.add_tx(tx)).await {
+                    Ok(Ok(())) => true,
+                    Ok(Err(error)) => {
+                        warn!(peer=%peer_addr, ?error, "Peer rejected tx");
+                        false
+                    }
+                    Err(_) => {
+                        warn!(peer=%peer_addr, ?submit_timeout, "Peer submission timed out");
+                        false
+                    }
+                };
+                (peer_addr, accepted)
+            }
+        });
+
+        join_all(submissions).await
     }
 
-    pub async fn init(&mut self) -> Result<(), Error> {
-        for (peer_addr, peer) in self.peers.iter_mut() {
-            let mut txsubmitpeer = TxSubmitPeer::new(peer_addr, self.network_magic);
-            txsubmitpeer.init().await.unwrap();
-            *peer = Some(txsubmitpeer);
+    /// Peers the next tx is handed to, a single one taking turns with the
+    /// round robin strategy.
+    fn targets(&self) -> Vec<String> {
+        let healthy = self.healthy_peers();
+
+        match self.strategy {
+            Strategy::RoundRobin if !healthy.is_empty() => {
+                let turn = self.next_peer.fetch_add(1, Ordering::Relaxed);
+                vec![healthy[turn % healthy.len()].clone()]
+            }
+            _ => healthy,
         }
-        Ok(())
     }
 
-    pub async fn add_tx(&self, tx: Vec<u8>) {
-        for (_, peer) in self.peers.iter() {
-            if let Some(peer) = peer {
-                peer.add_tx(tx.clone()).await;
+    /// Marks the peers matching `due` and not being connected already as
+    /// connecting, so concurrent workers don't open a connection each.
+    fn take_for_connect(&self, due: impl Fn(&PeerHealth) -> bool) -> Vec<String> {
+        let mut peers = self.peers.lock().unwrap();
+
+        peers
+            .iter_mut()
+            .filter(|(_, state)| !state.connecting && state.peer.is_none() && due(&state.health))
+            .map(|(peer_addr, state)| {
+                state.connecting = true;
+                peer_addr.clone()
+            })
+            .collect()
+    }
+
+    async fn connect_all(&self, peer_addrs: Vec<String>) {
+        for peer_addr in peer_addrs {
+            let connected = self.connect(&peer_addr).await;
+
+            let removed = {
+                let mut peers = self.peers.lock().unwrap();
+                match (peers.get_mut(&peer_addr), connected) {
+                    (Some(state), Ok(peer)) => {
+                        state.connecting = false;
+                        state.peer = Some(Arc::new(peer));
+                        state.health.connected = true;
+                        state.health.retries = 0;
+                        state.health.next_retry = None;
+                        None
+                    }
+                    (Some(state), Err(error)) => {
+                        warn!(peer=%peer_addr, ?error, "Peer connection failed");
+                        state.connecting = false;
+                        Self::schedule_retry(&mut state.health, format!("{error:#}"));
+                        None
+                    }
+                    (None, connected) => connected.ok(),
+                }
+            };
+
+            // removed while connecting, the new connection is closed
+            if let Some(peer) = removed {
+                peer.close().await;
+            }
+        }
+    }
+
+    async fn connect(&self, peer_addr: &str) -> anyhow::Result<TxSubmitPeer> {
+        let mut txsubmitpeer = TxSubmitPeer::new(
+            peer_addr,
+            self.network_magic,
+            self.protocol_version,
+            self.mempool_capacity,
+        );
+
+        txsubmitpeer
+            .init()
+            .await
+            .with_context(|| format!("failed to init peer {peer_addr}"))?;
+        Ok(txsubmitpeer)
+    }
+
+    fn schedule_retry(health: &mut PeerHealth, error: String) {
+        health.connected = false;
+        health.last_error = Some(error);
+        health.next_retry = Some(Instant::now() + backoff(health.retries));
+        health.retries += 1;
+    }
+}
+
+/// Exponential backoff between reconnect attempts, capped at `BACKOFF_MAX`.
+fn backoff(retries: u32) -> Duration {
+    BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(retries))
+        .min(BACKOFF_MAX)
+}
+
+#[cfg(test)]
+mod tx_submit_peer_manager_tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{net::TcpListener, sync::Mutex};
+
+    use crate::{fixtures::TX_CBOR, pipeline::fanout::mempool::Mempool};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[test]
+    fn it_should_backoff_exponentially() {
+        assert!(backoff(0) == Duration::from_secs(1));
+        assert!(backoff(1) == Duration::from_secs(2));
+        assert!(backoff(3) == Duration::from_secs(8));
+        assert!(backoff(10) == BACKOFF_MAX);
+        assert!(backoff(u32::MAX) == BACKOFF_MAX);
+    }
+
+    async fn closed_peer_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn it_should_update_peers() {
+        let removed = closed_peer_addr().await;
+        let unchanged = closed_peer_addr().await;
+        let added = closed_peer_addr().await;
+
+        let manager = TxSubmitPeerManager::new(
+            2,
+            None,
+            16,
+            TIMEOUT,
+            Strategy::Broadcast,
+            ProtocolVersion::Auto,
+            vec![removed.clone(), unchanged.clone()],
+        );
+        manager.init().await.unwrap();
+
+        let next_retry = manager.health(&unchanged).unwrap().next_retry;
+
+        manager
+            .update_peers(vec![unchanged.clone(), added.clone()])
+            .await;
+
+        assert!(manager.health(&removed).is_none());
+        assert!(manager.health(&added).unwrap().retries == 1);
+
+        // the unchanged peer keeps its state, it's not reconnected by the reload
+        let health = manager.health(&unchanged).unwrap();
+        assert!(health.retries == 1);
+        assert!(health.next_retry == next_retry);
+    }
+
+    #[tokio::test]
+    async fn it_should_schedule_reconnect_when_peer_drops() {
+        // fake peer accepting the connection and dropping it before the handshake
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                drop(socket);
             }
+        });
+
+        let manager = TxSubmitPeerManager::new(
+            2,
+            None,
+            16,
+            TIMEOUT,
+            Strategy::Broadcast,
+            ProtocolVersion::Auto,
+            vec![peer_addr.clone()],
+        );
+        manager.init().await.unwrap();
+
+        assert!(manager.healthy_peers().is_empty());
+
+        let health = manager.health(&peer_addr).unwrap();
+        assert!(!health.connected);
+        assert!(health.last_error.is_some());
+        assert!(health.retries == 1);
+        assert!(health.next_retry.is_some());
+
+        // the backoff did not elapse yet, so nothing is attempted
+        tokio::time::pause();
+        manager.check_peers().await;
+        assert!(manager.health(&peer_addr).unwrap().retries == 1);
+
+        tokio::time::advance(Duration::from_millis(1100)).await;
+        manager.check_peers().await;
+
+        let health = manager.health(&peer_addr).unwrap();
+        assert!(health.retries == 2);
+        assert!(health.next_retry.is_some());
+    }
+
+    /// Connected peer not backed by a node, it takes every valid tx handed to
+    /// it. Holding the returned mempool hangs it, as while it waits on a node
+    /// that stopped reading.
+    fn stub_peer(manager: &TxSubmitPeerManager, peer_addr: &str) -> Arc<Mutex<Mempool>> {
+        let peer = TxSubmitPeer::new(peer_addr, 2, ProtocolVersion::Auto, 16);
+        let mempool = peer.mempool();
+
+        let mut state = PeerState::new(None);
+        state.peer = Some(Arc::new(peer));
+        state.health.connected = true;
+        manager
+            .peers
+            .lock()
+            .unwrap()
+            .insert(peer_addr.to_string(), state);
+
+        mempool
+    }
+
+    fn stub_manager(strategy: Strategy) -> TxSubmitPeerManager {
+        TxSubmitPeerManager::new(
+            2,
+            None,
+            16,
+            Duration::from_millis(50),
+            strategy,
+            ProtocolVersion::Auto,
+            vec![],
+        )
+    }
+
+    async fn add_tx(manager: &TxSubmitPeerManager) -> Vec<(String, bool)> {
+        let tx = hex::decode(TX_CBOR).unwrap();
+        let mut submissions = tokio::time::timeout(Duration::from_secs(1), manager.add_tx(tx))
+            .await
+            .unwrap();
+        submissions.sort();
+        submissions
+    }
+
+    #[tokio::test]
+    async fn it_should_time_out_hung_peer() {
+        let manager = stub_manager(Strategy::Broadcast);
+        let mempool = stub_peer(&manager, "peer");
+        let _held = mempool.lock().await;
+
+        let submissions = add_tx(&manager).await;
+        assert!(submissions == vec![("peer".to_string(), false)]);
+    }
+
+    #[tokio::test]
+    async fn it_should_broadcast_to_every_peer() {
+        let manager = stub_manager(Strategy::Broadcast);
+        stub_peer(&manager, "a");
+        stub_peer(&manager, "b");
+        let hung = stub_peer(&manager, "c");
+        let _held = hung.lock().await;
+
+        let submissions = add_tx(&manager).await;
+        assert!(
+            submissions
+                == vec![
+                    ("a".to_string(), true),
+                    ("b".to_string(), true),
+                    ("c".to_string(), false)
+                ]
+        );
+        assert!(Strategy::Broadcast.is_submitted(&submissions, 1));
+    }
+
+    #[tokio::test]
+    async fn it_should_take_turns_with_round_robin() {
+        let manager = stub_manager(Strategy::RoundRobin);
+        for peer_addr in ["a", "b", "c"] {
+            stub_peer(&manager, peer_addr);
+        }
+
+        let mut picked = Vec::new();
+        for _ in 0..4 {
+            let submissions = add_tx(&manager).await;
+            assert!(submissions.len() == 1);
+            assert!(Strategy::RoundRobin.is_submitted(&submissions, 1));
+            picked.push(submissions[0].0.clone());
         }
+        assert!(picked == vec!["a", "b", "c", "a"]);
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_once_queued_for_min_peers() {
+        let manager = stub_manager(Strategy::MinPeers);
+        stub_peer(&manager, "a");
+        stub_peer(&manager, "b");
+        let hung = stub_peer(&manager, "c");
+        let _held = hung.lock().await;
+
+        let submissions = add_tx(&manager).await;
+        assert!(submissions.len() == 3);
+        assert!(Strategy::MinPeers.is_submitted(&submissions, 2));
+        assert!(!Strategy::MinPeers.is_submitted(&submissions, 3));
     }
 }
@@ -1,20 +1,113 @@
 use std::{sync::Arc, time::Duration};
 
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
 use gasket::framework::*;
-use tokio::time::sleep;
-use tracing::info;
+use pallas::ledger::traverse::MultiEraTx;
+use thiserror::Error;
+use tokio::{
+    sync::mpsc,
+    time::{sleep, timeout},
+};
+use tracing::{info, info_span, warn, Instrument};
 
-use crate::storage::{sqlite::SqliteTransaction, Transaction, TransactionStatus};
+use crate::{
+    metrics::METRICS,
+    storage::{
+        clock::{Clock, SystemClock},
+        Transaction, TransactionStatus, TransactionStore,
+    },
+    validation::{compute_tx_hash, tx_network_ids},
+};
+
+use super::{
+    fanout::Network, log_transition, scheduler::NamespaceScheduler, Config as PipelineConfig,
+};
+
+/// Time a submission waits for room in the ingest buffer before it's rejected.
+const BUFFER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The ingest buffer stayed full, the pipeline is behind the submissions.
+#[derive(Debug, Error)]
+#[error("ingest buffer is full, retry later")]
+pub struct BufferFullError;
+
+/// Bounded buffer between the servers and the ingest stage, with room for
+/// `capacity` transactions.
+pub fn buffer(capacity: usize) -> (IngestSender, mpsc::Receiver<String>) {
+    let (sender, receiver) = mpsc::channel(capacity.max(1));
+    (IngestSender(sender), receiver)
+}
+
+/// Submitting half of the ingest buffer. The servers take a slot for every
+/// transaction submitted and the ingest stage frees one for every transaction
+/// it ingests, so a burst faster than the pipeline is pushed back to the
+/// clients instead of piling up.
+#[derive(Clone)]
+pub struct IngestSender(mpsc::Sender<String>);
+
+impl IngestSender {
+    /// Takes a slot for each of `count` transactions, waiting up to
+    /// [`BUFFER_TIMEOUT`] for the ingest stage to free them, a batch larger
+    /// than the buffer waits for it to be empty. No slot is taken once the
+    /// pipeline is stopped.
+    pub async fn reserve(&self, count: usize) -> Result<Vec<mpsc::Permit<'_, String>>> {
+        let count = count.min(self.0.max_capacity());
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        match timeout(BUFFER_TIMEOUT, self.0.reserve_many(count)).await {
+            Ok(Ok(permits)) => Ok(permits.collect()),
+            Ok(Err(_)) => Ok(vec![]),
+            Err(_) => bail!(BufferFullError),
+        }
+    }
+}
 
 #[derive(Stage)]
 #[stage(name = "ingest", unit = "Transaction", worker = "Worker")]
 pub struct Stage {
-    storage: Arc<SqliteTransaction>,
+    pipeline: PipelineConfig,
+    storage: Arc<dyn TransactionStore>,
+    scheduler: NamespaceScheduler,
+    submitted: Option<mpsc::Receiver<String>>,
+    network: Network,
+    clock: Arc<dyn Clock>,
 }
 
 impl Stage {
-    pub fn new(storage: Arc<SqliteTransaction>) -> Self {
-        Self { storage }
+    pub fn new(pipeline: PipelineConfig, storage: Arc<dyn TransactionStore>) -> Self {
+        let scheduler = NamespaceScheduler::new(&pipeline.namespace_weights);
+        Self {
+            pipeline,
+            storage,
+            scheduler,
+            submitted: None,
+            network: Network::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Network the transactions are checked against when
+    /// `enforce_network` is set.
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+
+    /// Frees the slots of the ingest buffer as the transactions are ingested,
+    /// and wakes up on a submission instead of waiting for the next poll.
+    pub fn with_buffer(mut self, submitted: mpsc::Receiver<String>) -> Self {
+        self.submitted = Some(submitted);
+        self
+    }
+
+    /// Reads the current time from `clock`, the one the store compares the
+    /// retry and expiry times with.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
 }
 
@@ -31,26 +124,361 @@ impl gasket::framework::Worker<Stage> for Worker {
         stage: &mut Stage,
     ) -> Result<WorkSchedule<Transaction>, WorkerError> {
         if let Some(tx) = stage
-            .storage
-            .next(TransactionStatus::Pending)
+            .scheduler
+            .next(stage.storage.as_ref(), TransactionStatus::Pending)
             .await
             .or_retry()?
         {
+            if let Some(submitted) = stage.submitted.as_mut() {
+                let _ = submitted.try_recv();
+            }
             return Ok(WorkSchedule::Unit(tx));
         }
 
-        sleep(Duration::from_secs(1)).await;
+        // still polls, not every pending tx comes through the buffer, e.g. the
+        // reclaimed ones
+        match stage.submitted.as_mut() {
+            Some(submitted) => {
+                if let Ok(None) = timeout(Duration::from_secs(1), submitted.recv()).await {
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+            None => sleep(Duration::from_secs(1)).await,
+        }
         Ok(WorkSchedule::Idle)
     }
 
     async fn execute(&mut self, unit: &Transaction, stage: &mut Stage) -> Result<(), WorkerError> {
-        let mut transaction = unit.clone();
+        let span = info_span!("ingest", tx_id = %unit.id);
+        METRICS
+            .time_stage("ingest", ingest(unit, stage).instrument(span))
+            .await
+    }
+}
+
+async fn ingest(unit: &Transaction, stage: &Stage) -> Result<(), WorkerError> {
+    let mut transaction = unit.clone();
+
+    info!("ingesting transaction");
+
+    let checked = validate(&transaction, &stage.pipeline, stage.clock.now()).and_then(|ttl| {
+        if stage.pipeline.enforce_network {
+            check_network(&transaction, &stage.network)?;
+        }
+        Ok(ttl)
+    });
+
+    let ttl = match checked {
+        Ok(ttl) => ttl,
+        Err(reason) => {
+            warn!(%reason, "transaction rejected");
+            stage
+                .storage
+                .mark_failed(&transaction.id, &reason)
+                .await
+                .or_retry()?;
+            METRICS.failed.inc();
+            log_transition(
+                &transaction.id,
+                &transaction.status,
+                &TransactionStatus::Failed,
+            );
+            return Ok(());
+        }
+    };
+
+    transaction.ttl = ttl;
+    transaction.status = TransactionStatus::Validated;
+    stage.storage.update(&transaction).await.or_retry()?;
+    log_transition(&transaction.id, &unit.status, &transaction.status);
+
+    Ok(())
+}
+
+/// Checks the tx didn't pass the `expires_at` of the client, the id matches the
+/// tx body, since it drives dependency resolution, and that the size and fee
+/// are within the configured limits, so a tx relays would never accept isn't
+/// submitted. Returns the ttl read from the tx body, the error is the reason to
+/// fail the tx.
+fn validate(
+    transaction: &Transaction,
+    pipeline: &PipelineConfig,
+    now: DateTime<Utc>,
+) -> Result<Option<u64>, String> {
+    let _span = info_span!("validate").entered();
+    let _timer = METRICS
+        .stage_seconds
+        .with_label_values(&["validate"])
+        .start_timer();
+
+    if let Some(expires_at) = transaction.expires_at.filter(|at| *at <= now) {
+        return Err(format!("expired before being submitted: {expires_at}"));
+    }
+
+    match compute_tx_hash(&transaction.raw) {
+        Ok(hash) if hash == transaction.id => {}
+        Ok(hash) => return Err(format!("id mismatch: computed {hash}")),
+        Err(error) => return Err(format!("malformed cbor: {error}")),
+    }
+
+    let size = transaction.raw.len() as u64;
+    if let Some(max_size) = pipeline.max_tx_size_bytes {
+        if size > max_size {
+            return Err(format!("tx too large: {size} bytes, max {max_size}"));
+        }
+    }
+
+    if let Some(min_fee) = pipeline.min_fee_lovelace {
+        let fee =
+            parse_fee(&transaction.raw).map_err(|error| format!("malformed cbor: {error}"))?;
+        if fee < min_fee {
+            return Err(format!("fee too low: {fee} lovelace, min {min_fee}"));
+        }
+    }
+
+    parse_ttl(&transaction.raw).map_err(|error| format!("malformed cbor: {error}"))
+}
+
+/// Checks the addresses of the tx are of `network`, as the peers would reject a
+/// tx of another network on every attempt. The error is the reason to fail the
+/// tx.
+fn check_network(transaction: &Transaction, network: &Network) -> Result<(), String> {
+    let ids =
+        tx_network_ids(&transaction.raw).map_err(|error| format!("malformed cbor: {error}"))?;
+
+    match ids.into_iter().find(|id| *id != network.id()) {
+        Some(id) => Err(format!(
+            "network mismatch: address of network {id}, expected {}",
+            network.id()
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Reads the ttl (upper bound of the validity interval) from the tx body.
+/// Transactions without a ttl are valid forever and return None.
+pub fn parse_ttl(raw: &[u8]) -> Result<Option<u64>> {
+    let tx = MultiEraTx::decode(raw)?;
+    Ok(tx.ttl())
+}
 
-        info!("ingest {}", transaction.id);
+/// Reads the fee from the tx body, zero when it's missing.
+pub fn parse_fee(raw: &[u8]) -> Result<u64> {
+    let tx = MultiEraTx::decode(raw)?;
+    Ok(tx.fee().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod ingest_tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+    use gasket::framework::{WorkSchedule, Worker as _};
+
+    use crate::{
+        fixtures::{TX_CBOR, TX_HASH},
+        metrics::METRICS,
+        pipeline::{fanout::Network, Config as PipelineConfig},
+        storage::{mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore},
+    };
+
+    use super::{buffer, check_network, parse_ttl, validate, BufferFullError, Stage, Worker};
+
+    #[test]
+    fn it_should_parse_tx_without_ttl() {
+        let raw = hex::decode(TX_CBOR).unwrap();
+
+        let result = parse_ttl(&raw);
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_time_ingest_stages() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction::new(
+                TX_HASH.into(),
+                hex::decode(TX_CBOR).unwrap(),
+            )])
+            .await
+            .unwrap();
+        let mut stage = Stage::new(PipelineConfig::default(), storage.clone());
+
+        let count = |stage: &str| {
+            METRICS
+                .stage_seconds
+                .with_label_values(&[stage])
+                .get_sample_count()
+        };
+        let (ingested, validated) = (count("ingest"), count("validate"));
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        Worker.execute(&tx, &mut stage).await.unwrap();
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Validated);
+        assert!(count("ingest") > ingested);
+        assert!(count("validate") > validated);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_expired_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let mut tx = Transaction::new(TX_HASH.into(), hex::decode(TX_CBOR).unwrap());
+        tx.expires_at = Some(Utc::now() - Duration::seconds(1));
+        storage.create(&vec![tx]).await.unwrap();
+        let mut stage = Stage::new(PipelineConfig::default(), storage.clone());
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        Worker.execute(&tx, &mut stage).await.unwrap();
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Failed);
+        assert!(tx
+            .error
+            .is_some_and(|error| error.starts_with("expired before being submitted")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_should_push_back_when_buffer_is_full() {
+        let (ingest, mut submitted) = buffer(2);
+
+        let permits = ingest.reserve(2).await.unwrap();
+        assert!(permits.len() == 2);
+        permits
+            .into_iter()
+            .for_each(|permit| permit.send(TX_HASH.into()));
+
+        let result = ingest.reserve(1).await;
+        assert!(result.is_err_and(|error| error.is::<BufferFullError>()));
+
+        submitted.recv().await.unwrap();
+        let result = ingest.reserve(1).await;
+        assert!(result.is_ok_and(|permits| permits.len() == 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_should_free_buffer_when_ingesting() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction::new(
+                TX_HASH.into(),
+                hex::decode(TX_CBOR).unwrap(),
+            )])
+            .await
+            .unwrap();
+        let (ingest, submitted) = buffer(1);
+        ingest
+            .reserve(1)
+            .await
+            .unwrap()
+            .remove(0)
+            .send(TX_HASH.into());
+        let mut stage =
+            Stage::new(PipelineConfig::default(), storage.clone()).with_buffer(submitted);
 
-        transaction.status = TransactionStatus::Validated;
-        stage.storage.update(&transaction).await.or_retry()?;
+        let schedule = Worker.schedule(&mut stage).await.unwrap();
+        assert!(matches!(schedule, WorkSchedule::Unit(_)));
+
+        let result = ingest.reserve(1).await;
+        assert!(result.is_ok_and(|permits| permits.len() == 1));
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_tx_of_other_network() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction::new(
+                TX_HASH.into(),
+                hex::decode(TX_CBOR).unwrap(),
+            )])
+            .await
+            .unwrap();
+        let pipeline = PipelineConfig {
+            enforce_network: true,
+            ..Default::default()
+        };
+        let mut stage = Stage::new(pipeline, storage.clone()).with_network(Network::Mainnet);
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        Worker.execute(&tx, &mut stage).await.unwrap();
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Failed);
+        assert!(tx
+            .error
+            .is_some_and(|error| error.starts_with("network mismatch")));
+    }
+
+    #[test]
+    fn it_should_check_network() {
+        let transaction = Transaction::new(TX_HASH.into(), hex::decode(TX_CBOR).unwrap());
+
+        assert!(check_network(&transaction, &Network::Preprod).is_ok());
+        assert!(check_network(&transaction, &Network::Mainnet).is_err());
+    }
+
+    #[test]
+    fn it_should_validate_tx() {
+        let transaction = Transaction::new(TX_HASH.into(), hex::decode(TX_CBOR).unwrap());
+
+        let result = validate(&transaction, &PipelineConfig::default(), Utc::now());
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[test]
+    fn it_should_validate_tx_within_limits() {
+        let transaction = Transaction::new(TX_HASH.into(), hex::decode(TX_CBOR).unwrap());
+        let pipeline = PipelineConfig {
+            max_tx_size_bytes: Some(16384),
+            min_fee_lovelace: Some(155381),
+            ..Default::default()
+        };
+
+        let result = validate(&transaction, &pipeline, Utc::now());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn it_should_fail_validate_oversized_tx() {
+        let transaction = Transaction::new(TX_HASH.into(), hex::decode(TX_CBOR).unwrap());
+        let pipeline = PipelineConfig {
+            max_tx_size_bytes: Some(100),
+            ..Default::default()
+        };
+
+        let result = validate(&transaction, &pipeline, Utc::now());
+        assert!(result.is_err());
+        assert!(result.unwrap_err().starts_with("tx too large"));
+    }
+
+    #[test]
+    fn it_should_fail_validate_low_fee() {
+        let transaction = Transaction::new(TX_HASH.into(), hex::decode(TX_CBOR).unwrap());
+        let pipeline = PipelineConfig {
+            min_fee_lovelace: Some(1_000_000),
+            ..Default::default()
+        };
+
+        let result = validate(&transaction, &pipeline, Utc::now());
+        assert!(result.is_err());
+        assert!(result.unwrap_err().starts_with("fee too low"));
+    }
+
+    #[test]
+    fn it_should_fail_validate_id_mismatch() {
+        let transaction = Transaction::new("hex".into(), hex::decode(TX_CBOR).unwrap());
+
+        let result = validate(&transaction, &PipelineConfig::default(), Utc::now());
+        assert!(result.is_err());
+        assert!(result.unwrap_err().starts_with("id mismatch"));
+    }
 
-        Ok(())
+    #[test]
+    fn it_should_fail_parse_malformed_cbor() {
+        let result = parse_ttl(&[0xde, 0xad, 0xbe, 0xef]);
+        assert!(result.is_err());
     }
 }
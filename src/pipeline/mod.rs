@@ -1,52 +1,376 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::Result;
+use serde::Deserialize;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch, Notify},
+    time::sleep,
+};
+use tracing::{error, info};
 
 use crate::{
     ledger::u5c::{Point, U5cDataAdapterImpl},
-    storage::{
-        sqlite::{SqliteCursor, SqliteTransaction},
-        Cursor,
-    },
-    Config,
+    shutdown,
+    storage::{clock::Clock, Cursor, CursorStore, QueueOrder, TransactionStatus, TransactionStore},
 };
 
+use fanout::local_tx_submit::{LocalTxSubmit, LocalTxSubmitClient};
+
 pub mod fanout;
 pub mod ingest;
 pub mod monitor;
+pub mod queue_age;
+pub mod reclaim;
+pub mod scheduler;
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    #[serde(default = "default_lease_timeout_secs")]
+    pub lease_timeout_secs: u64,
+    #[serde(default = "default_reclaim_interval_secs")]
+    pub reclaim_interval_secs: u64,
+    #[serde(default)]
+    pub backoff: BackoffConfig,
+    /// Submissions of a transaction before it's moved to dead-letter.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Rejects transactions with a larger CBOR, no limit when not set.
+    pub max_tx_size_bytes: Option<u64>,
+    /// Rejects transactions paying a lower fee, no limit when not set.
+    pub min_fee_lovelace: Option<u64>,
+    #[serde(default)]
+    pub ordering: QueueOrder,
+    /// Share of the picks of each namespace, the namespaces without a weight
+    /// are only picked when the weighted ones are idle. No fairness when empty.
+    #[serde(default)]
+    pub namespace_weights: HashMap<String, u32>,
+    /// Fanout workers claiming and submitting transactions concurrently.
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// Transactions submitted and not ingested yet, the submissions wait for
+    /// room and are rejected when there's none.
+    #[serde(default = "default_ingest_buffer")]
+    pub ingest_buffer: usize,
+    /// Rejects the transactions paying to or withdrawing from an address of
+    /// another network than the one of the peers.
+    #[serde(default)]
+    pub enforce_network: bool,
+    /// Warns when the oldest transaction still queued is older, in seconds,
+    /// never when not set.
+    pub max_queue_age_secs: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            lease_timeout_secs: default_lease_timeout_secs(),
+            reclaim_interval_secs: default_reclaim_interval_secs(),
+            backoff: Default::default(),
+            max_attempts: default_max_attempts(),
+            max_tx_size_bytes: None,
+            min_fee_lovelace: None,
+            ordering: QueueOrder::default(),
+            namespace_weights: HashMap::new(),
+            workers: default_workers(),
+            ingest_buffer: default_ingest_buffer(),
+            enforce_network: false,
+            max_queue_age_secs: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BackoffConfig {
+    #[serde(default = "default_backoff_base_secs")]
+    pub base_secs: u64,
+    #[serde(default = "default_backoff_max_secs")]
+    pub max_secs: u64,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            base_secs: default_backoff_base_secs(),
+            max_secs: default_backoff_max_secs(),
+        }
+    }
+}
+
+impl BackoffConfig {
+    /// Exponential delay before resubmitting a transaction that already failed
+    /// `attempts` times, capped at `max_secs`.
+    pub fn delay(&self, attempts: u32) -> Duration {
+        let secs = self
+            .base_secs
+            .saturating_mul(2u64.saturating_pow(attempts))
+            .min(self.max_secs);
+
+        Duration::from_secs(secs)
+    }
+}
+
+fn default_lease_timeout_secs() -> u64 {
+    300
+}
+
+fn default_reclaim_interval_secs() -> u64 {
+    60
+}
+
+fn default_max_attempts() -> u32 {
+    10
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+fn default_ingest_buffer() -> usize {
+    1000
+}
+
+fn default_backoff_base_secs() -> u64 {
+    10
+}
+
+fn default_backoff_max_secs() -> u64 {
+    600
+}
 
 pub async fn run(
-    config: Config,
-    tx_storage: Arc<SqliteTransaction>,
-    cursor_storage: Arc<SqliteCursor>,
+    config: crate::Config,
+    tx_storage: Arc<dyn TransactionStore>,
+    cursor_storage: Arc<dyn CursorStore>,
+    submitted: mpsc::Receiver<String>,
+    wake: Arc<Notify>,
+    healthy_peers: watch::Sender<Vec<String>>,
+    clock: Arc<dyn Clock>,
+    shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
-    let cursor = cursor_storage.current().await?.map(|c| c.into());
+    let cursor = match &config.monitor.start {
+        monitor::Start::Tip => None,
+        start => match cursor_storage.current().await? {
+            Some(cursor) => Some(cursor.into()),
+            None => start.initial_point(),
+        },
+    };
     let adapter = Arc::new(U5cDataAdapterImpl::try_new(config.u5c, cursor).await?);
 
-    let ingest = ingest::Stage::new(tx_storage.clone());
-    let fanout = fanout::Stage::new(config.peer_manager, adapter.clone(), tx_storage.clone());
+    let ingest = ingest::Stage::new(config.pipeline.clone(), tx_storage.clone())
+        .with_buffer(submitted)
+        .with_network(config.peer_manager.network.clone())
+        .with_clock(clock.clone());
+    let (peers_tx, peers_rx) = watch::channel(config.peer_manager.peers.clone());
+    tokio::spawn(reload_peers(peers_tx));
+
+    // a single connection to each peer, shared by the workers
+    let peer_manager = fanout::connect_peers(&config.peer_manager, tx_storage.clone()).await?;
+
+    // every worker claims on its own, the claim being atomic a transaction is
+    // submitted by a single one of them
+    let worker_ids: Vec<String> = (0..config.pipeline.workers)
+        .map(fanout::worker_id)
+        .collect();
+    // a single connection to the local node, shared by the workers
+    let local: Option<Arc<dyn LocalTxSubmit>> =
+        config.peer_manager.local_socket.as_ref().map(|socket| {
+            Arc::new(LocalTxSubmitClient::new(
+                socket,
+                config.peer_manager.network.magic(),
+            )) as Arc<dyn LocalTxSubmit>
+        });
+    let fanouts: Vec<fanout::Stage> = worker_ids
+        .iter()
+        .map(|id| {
+            let stage = fanout::Stage::new(
+                id.clone(),
+                config.peer_manager.clone(),
+                config.pipeline.clone(),
+                peers_rx.clone(),
+                healthy_peers.clone(),
+                adapter.clone(),
+                tx_storage.clone(),
+                peer_manager.clone(),
+            )
+            .with_wake(wake.clone())
+            .with_clock(clock.clone());
+            match &local {
+                Some(local) => stage.with_local_submit(local.clone()),
+                None => stage,
+            }
+        })
+        .collect();
 
     let monitor = monitor::Stage::new(
         config.monitor,
+        config.pipeline.clone(),
         adapter.clone(),
         tx_storage.clone(),
         cursor_storage.clone(),
-    );
+    )
+    .with_clock(clock);
+
+    tokio::spawn(queue_age::run(config.pipeline.clone(), tx_storage.clone()));
+    tokio::spawn(reclaim::run(config.pipeline, tx_storage.clone()));
 
     let policy: gasket::runtime::Policy = Default::default();
 
-    let ingest = gasket::runtime::spawn_stage(ingest, policy.clone());
-    let fanout = gasket::runtime::spawn_stage(fanout, policy.clone());
-    let monitor = gasket::runtime::spawn_stage(monitor, policy.clone());
+    let mut tethers = vec![gasket::runtime::spawn_stage(ingest, policy.clone())];
+    tethers.extend(
+        fanouts
+            .into_iter()
+            .map(|fanout| gasket::runtime::spawn_stage(fanout, policy.clone())),
+    );
+    tethers.push(gasket::runtime::spawn_stage(monitor, policy.clone()));
+
+    let daemon = gasket::daemon::Daemon::new(tethers);
+    drain(daemon, shutdown, tx_storage.as_ref(), &worker_ids).await
+}
+
+/// Runs until a stage stops or the shutdown is requested, then tears the stages
+/// down and gives back the transactions the fanout workers claimed but didn't
+/// submit, so they are picked again on the next start.
+async fn drain(
+    daemon: gasket::daemon::Daemon,
+    shutdown: watch::Receiver<bool>,
+    tx_storage: &dyn TransactionStore,
+    worker_ids: &[String],
+) -> Result<()> {
+    let requested = shutdown::requested(shutdown);
+    tokio::pin!(requested);
+
+    loop {
+        tokio::select! {
+            _ = &mut requested => break,
+            _ = sleep(Duration::from_secs(5)) => {
+                if daemon.should_stop() {
+                    break;
+                }
+            }
+        }
+    }
+
+    info!("stopping pipeline");
+    daemon.teardown();
 
-    let daemon = gasket::daemon::Daemon::new(vec![ingest, fanout, monitor]);
-    daemon.block();
+    for worker_id in worker_ids {
+        let count = tx_storage.release_claims(worker_id).await?;
+        info!(count, worker_id, "re-queued claimed transactions");
+    }
 
     Ok(())
 }
 
+/// Re-reads the config file on SIGHUP and publishes the peer list to the
+/// fanout stage, so relays can be added or removed without a restart.
+async fn reload_peers(peers: watch::Sender<Vec<String>>) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(error) => {
+            error!(
+                ?error,
+                "failed to listen for SIGHUP, peers can't be reloaded"
+            );
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        let config = match crate::Config::new() {
+            Ok(config) => config,
+            Err(error) => {
+                error!(%error, "failed to reload config");
+                continue;
+            }
+        };
+
+        info!("reloading peers from config");
+        peers.send_if_modified(|current| {
+            if *current == config.peer_manager.peers {
+                return false;
+            }
+            *current = config.peer_manager.peers;
+            true
+        });
+    }
+}
+
+/// Logs a status change of a transaction, so its whole lifecycle can be
+/// followed by filtering on the id.
+fn log_transition(id: &str, from: &TransactionStatus, to: &TransactionStatus) {
+    info!(tx_id = id, %from, %to, "transaction status changed");
+}
+
 impl From<Cursor> for Point {
     fn from(value: Cursor) -> Self {
         (value.slot, value.hash)
     }
 }
+
+#[cfg(test)]
+mod pipeline_tests {
+    use std::time::Duration;
+
+    use tokio::sync::watch;
+
+    use crate::storage::{
+        mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore,
+    };
+
+    use super::{drain, BackoffConfig};
+
+    #[test]
+    fn it_should_backoff_exponentially() {
+        let backoff = BackoffConfig {
+            base_secs: 10,
+            max_secs: 600,
+        };
+
+        assert!(backoff.delay(0) == Duration::from_secs(10));
+        assert!(backoff.delay(1) == Duration::from_secs(20));
+        assert!(backoff.delay(3) == Duration::from_secs(80));
+        assert!(backoff.delay(10) == Duration::from_secs(600));
+        assert!(backoff.delay(u32::MAX) == Duration::from_secs(600));
+    }
+
+    #[tokio::test]
+    async fn it_should_requeue_claimed_on_shutdown() {
+        let storage = MockTransactionStore::default();
+        let transactions: Vec<Transaction> = (0..2)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                status: TransactionStatus::Validated,
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let claimed = storage
+            .claim_next(TransactionStatus::Validated, None, "worker")
+            .await
+            .unwrap()
+            .unwrap();
+        let mut submitted = storage
+            .claim_next(TransactionStatus::Validated, None, "worker")
+            .await
+            .unwrap()
+            .unwrap();
+        submitted.slot = Some(1);
+        storage.update(&submitted).await.unwrap();
+
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        shutdown_tx.send_replace(true);
+
+        let daemon = gasket::daemon::Daemon::new(vec![]);
+        let result = drain(daemon, shutdown, &storage, &["worker".into()]).await;
+        assert!(result.is_ok());
+
+        let result = storage.get(&claimed.id).await.unwrap().unwrap();
+        assert!(result.status == TransactionStatus::Pending);
+        assert!(result.claimed_by.is_none());
+
+        let result = storage.get(&submitted.id).await.unwrap().unwrap();
+        assert!(result.status == TransactionStatus::InFlight);
+    }
+}
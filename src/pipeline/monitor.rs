@@ -3,43 +3,114 @@ use std::sync::Arc;
 use futures::TryStreamExt;
 use gasket::framework::*;
 use serde::Deserialize;
-use tracing::info;
+use tracing::{info, info_span};
 
 use crate::{
-    ledger::u5c::{ChainSyncStream, Event, U5cDataAdapter},
+    ledger::u5c::{ChainSyncStream, Event, U5cDataAdapter, ORIGIN},
+    metrics::METRICS,
     storage::{
-        sqlite::{SqliteCursor, SqliteTransaction},
-        Cursor, Transaction, TransactionStatus,
+        clock::{Clock, SystemClock},
+        Cursor, CursorStore, Transaction, TransactionStatus, TransactionStore,
     },
 };
 
+use super::{log_transition, Config as PipelineConfig};
+
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub retry_slot_diff: u64,
+    #[serde(default)]
+    pub start: Start,
+}
+
+/// Where the chain follower starts when boros boots.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Start {
+    /// Resumes from the last block processed, intersecting on the stored cursor.
+    #[default]
+    Cursor,
+    /// Follows from the chain tip, skipping the blocks made while boros was down.
+    Tip,
+    /// Resumes from the last block processed, the origin of the chain when
+    /// there's none.
+    Origin,
+    /// Resumes from the last block processed, this block when there's none.
+    Point { slot: u64, hash: Vec<u8> },
+}
+
+impl Start {
+    /// Point to intersect on when no block was processed yet, the tip when
+    /// None.
+    pub fn initial_point(&self) -> Option<(u64, Vec<u8>)> {
+        match self {
+            Start::Cursor | Start::Tip => None,
+            Start::Origin => Some(ORIGIN),
+            Start::Point { slot, hash } => Some((*slot, hash.clone())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Start {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Name(String),
+            Point { slot: u64, hash: String },
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Name(name) => match name.as_str() {
+                "cursor" => Ok(Start::Cursor),
+                "tip" => Ok(Start::Tip),
+                "origin" => Ok(Start::Origin),
+                _ => Err(serde::de::Error::custom(format!(
+                    "invalid start {name}, expected cursor, tip, origin or {{ slot, hash }}"
+                ))),
+            },
+            Raw::Point { slot, hash } => Ok(Start::Point {
+                slot,
+                hash: hex::decode(&hash)
+                    .map_err(|_| serde::de::Error::custom(format!("invalid start hash {hash}")))?,
+            }),
+        }
+    }
 }
 
 #[derive(Stage)]
 #[stage(name = "monitor", unit = "Event", worker = "Worker")]
 pub struct Stage {
     config: Config,
+    pipeline: PipelineConfig,
     adapter: Arc<dyn U5cDataAdapter>,
-    storage: Arc<SqliteTransaction>,
-    cursor: Arc<SqliteCursor>,
+    storage: Arc<dyn TransactionStore>,
+    cursor: Arc<dyn CursorStore>,
+    clock: Arc<dyn Clock>,
 }
 impl Stage {
     pub fn new(
         config: Config,
+        pipeline: PipelineConfig,
         adapter: Arc<dyn U5cDataAdapter>,
-        storage: Arc<SqliteTransaction>,
-        cursor: Arc<SqliteCursor>,
+        storage: Arc<dyn TransactionStore>,
+        cursor: Arc<dyn CursorStore>,
     ) -> Self {
         Self {
             config,
+            pipeline,
             adapter,
             storage,
             cursor,
+            clock: Arc::new(SystemClock),
         }
     }
+
+    /// Reads the current time from `clock`, the one the store compares the
+    /// retry and expiry times with.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
 }
 
 pub struct Worker {
@@ -63,70 +134,122 @@ impl gasket::framework::Worker<Stage> for Worker {
     }
 
     async fn execute(&mut self, unit: &Event, stage: &mut Stage) -> Result<(), WorkerError> {
+        METRICS
+            .time_stage("confirm", self.confirm(unit, stage))
+            .await
+    }
+}
+
+impl Worker {
+    async fn confirm(&self, unit: &Event, stage: &Stage) -> Result<(), WorkerError> {
         let (slot, hash) = match unit {
             Event::RollForward((slot, hash), txs) => {
                 info!("Slot {slot} RollForward");
 
-                let txs_inflight = stage
+                // only the transactions of the block are looked up, not the
+                // whole in flight queue
+                let ids: Vec<String> = txs.iter().map(|tx| hex::encode(&tx.hash)).collect();
+                let txs_confirm: Vec<Transaction> = stage
                     .storage
-                    .find(TransactionStatus::InFlight)
+                    .get_many(&ids)
                     .await
-                    .or_retry()?;
-
-                let txs_confirm: Vec<Transaction> = txs_inflight
-                    .clone()
+                    .or_retry()?
                     .into_iter()
-                    .filter(|itx| txs.iter().any(|tx| hex::encode(&tx.hash) == itx.id))
+                    .filter(|tx| tx.status == TransactionStatus::InFlight)
                     .map(|mut tx| {
                         tx.status = TransactionStatus::Confirmed;
                         tx.slot = Some(*slot);
+                        tx.confirmed_slot = Some(*slot);
                         tx
                     })
                     .collect();
                 if !txs_confirm.is_empty() {
                     info!("Confirmed {} transactions", txs_confirm.len());
-                    stage.storage.update_batch(&txs_confirm).await.or_retry()?;
+                    let ids: Vec<String> = txs_confirm.iter().map(|tx| tx.id.clone()).collect();
+                    stage
+                        .storage
+                        .mark_confirmed_many(&ids, *slot)
+                        .await
+                        .or_retry()?;
+
+                    let now = stage.clock.now();
+                    for tx in &txs_confirm {
+                        info_span!("confirm", tx_id = %tx.id, slot).in_scope(|| {
+                            log_transition(&tx.id, &TransactionStatus::InFlight, &tx.status);
+                        });
+                        let elapsed = (now - tx.created_at).num_milliseconds() as f64 / 1000.0;
+                        METRICS.confirmation_seconds.observe(elapsed);
+                    }
                 }
 
-                let txs_retry: Vec<Transaction> = txs_inflight
-                    .into_iter()
-                    .filter(|itx| {
-                        (slot - itx.slot.unwrap()) > stage.config.retry_slot_diff
-                            && !txs_confirm.iter().any(|tx| tx.id == itx.id)
-                    })
-                    .map(|mut tx| {
-                        tx.status = TransactionStatus::Pending;
-                        tx.slot = None;
-                        tx
-                    })
-                    .collect();
+                // a claimed transaction has no slot until the fanout submits it,
+                // and the ones of this block are already confirmed
+                let mut txs_retry = stage
+                    .storage
+                    .find_in_flight_before(slot.saturating_sub(stage.config.retry_slot_diff))
+                    .await
+                    .or_retry()?;
+                let now = stage.clock.now();
+                for tx in txs_retry.iter_mut() {
+                    // not seen on chain, so the submission failed
+                    tx.attempts = stage.storage.increment_attempts(&tx.id).await.or_retry()?;
+                    tx.slot = None;
+                    tx.claimed_by = None;
+
+                    if tx.attempts >= stage.pipeline.max_attempts {
+                        tx.status = TransactionStatus::DeadLetter;
+                        METRICS.failed.inc();
+                        continue;
+                    }
+
+                    // the peers get a break before the next submission
+                    let delay = stage.pipeline.backoff.delay(tx.attempts - 1);
+                    tx.status = TransactionStatus::Pending;
+                    tx.next_retry_at = Some(now + chrono::Duration::from_std(delay).or_panic()?);
+                }
                 if !txs_retry.is_empty() {
                     info!("Slot {slot} Retry {} transactions", txs_retry.len());
                     stage.storage.update_batch(&txs_retry).await.or_retry()?;
+
+                    for tx in &txs_retry {
+                        info_span!("retry", tx_id = %tx.id, slot).in_scope(|| {
+                            log_transition(&tx.id, &TransactionStatus::InFlight, &tx.status);
+                        });
+                    }
+                }
+
+                let txs_expired = stage.storage.expire_past_ttl(*slot).await.or_retry()?;
+                if txs_expired > 0 {
+                    info!("Slot {slot} Expired {txs_expired} transactions");
+                    METRICS.failed.inc_by(txs_expired);
+                }
+
+                let txs_past_deadline = stage
+                    .storage
+                    .expire_past_deadline(stage.clock.now())
+                    .await
+                    .or_retry()?;
+                if txs_past_deadline > 0 {
+                    info!(
+                        "Slot {slot} Expired {txs_past_deadline} transactions past their deadline"
+                    );
+                    METRICS.failed.inc_by(txs_past_deadline);
                 }
 
                 (slot, hash)
             }
             Event::Rollback((slot, hash)) => {
+                // only read to log which transactions are reverted
                 let txs = stage.storage.find_to_rollback(*slot).await.or_retry()?;
+                let reverted = stage.storage.rollback_to(*slot).await.or_retry()?;
 
-                let txs = txs
-                    .into_iter()
-                    .map(|mut tx| {
-                        if tx.slot.unwrap() > *slot {
-                            tx.status = TransactionStatus::InFlight;
-                            tx.slot = None;
-                            return tx;
-                        }
-
-                        tx.slot = Some(*slot);
-                        tx
-                    })
-                    .collect();
-
-                stage.storage.update_batch(&txs).await.or_retry()?;
+                for tx in &txs {
+                    info_span!("rollback", tx_id = %tx.id, slot).in_scope(|| {
+                        log_transition(&tx.id, &tx.status, &TransactionStatus::Pending);
+                    });
+                }
 
-                info!("Slot {slot} Rollback {} transactions", txs.len());
+                info!("Slot {slot} Rollback {reverted} transactions");
 
                 (slot, hash)
             }
@@ -141,3 +264,282 @@ impl gasket::framework::Worker<Stage> for Worker {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod monitor_tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use pallas::interop::utxorpc::spec::cardano::Tx;
+
+    use crate::{
+        ledger::u5c::{ChainSyncStream, Event, Point, U5cDataAdapter},
+        storage::{
+            mock::MockTransactionStore,
+            sqlite::{SqliteCursor, SqliteStorage},
+            CursorStore, Transaction, TransactionStatus, TransactionStore,
+        },
+    };
+
+    use super::{Config, PipelineConfig, Stage, Start, Worker};
+
+    struct MockU5cDataAdapter;
+
+    #[async_trait::async_trait]
+    impl U5cDataAdapter for MockU5cDataAdapter {
+        async fn fetch_tip(&self) -> anyhow::Result<Point> {
+            Err(anyhow::anyhow!("not used in test"))
+        }
+
+        async fn fetch_utxos(
+            &self,
+            _utxo_refs: &[String],
+        ) -> anyhow::Result<HashMap<String, Vec<u8>>> {
+            Err(anyhow::anyhow!("not used in test"))
+        }
+
+        async fn stream(&self) -> anyhow::Result<ChainSyncStream> {
+            Ok(Box::pin(futures::stream::empty()))
+        }
+    }
+
+    async fn monitor_stage(
+        storage: Arc<MockTransactionStore>,
+    ) -> (Stage, Worker, Arc<SqliteCursor>) {
+        let cursor = Arc::new(SqliteCursor::new(Arc::new(
+            SqliteStorage::ephemeral().await.unwrap(),
+        )));
+        let stage = Stage::new(
+            Config {
+                retry_slot_diff: 1000,
+                start: Start::Cursor,
+            },
+            PipelineConfig::default(),
+            Arc::new(MockU5cDataAdapter),
+            storage,
+            cursor.clone(),
+        );
+        let worker = Worker {
+            stream: Box::pin(futures::stream::empty()),
+        };
+
+        (stage, worker, cursor)
+    }
+
+    #[tokio::test]
+    async fn it_should_confirm_seen_transactions() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let seen_hash = vec![1; 32];
+        storage
+            .create(&vec![
+                Transaction {
+                    id: hex::encode(&seen_hash),
+                    status: TransactionStatus::InFlight,
+                    slot: Some(10),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "unseen".into(),
+                    status: TransactionStatus::InFlight,
+                    slot: Some(10),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let (mut stage, mut worker, cursor) = monitor_stage(storage.clone()).await;
+
+        let block_tx = Tx {
+            hash: seen_hash.clone().into(),
+            ..Default::default()
+        };
+        let event = Event::RollForward((20, vec![2; 32]), vec![block_tx]);
+        let result =
+            <Worker as gasket::framework::Worker<Stage>>::execute(&mut worker, &event, &mut stage)
+                .await;
+        assert!(result.is_ok());
+
+        let tx = storage
+            .get(&hex::encode(&seen_hash))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(tx.status == TransactionStatus::Confirmed);
+        assert!(tx.slot == Some(20));
+        assert!(tx.confirmed_slot == Some(20));
+
+        // not seen yet, but still within the retry window
+        let tx = storage.get("unseen").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::InFlight);
+        assert!(tx.slot == Some(10));
+
+        assert!(cursor.current().await.unwrap().unwrap().slot == 20);
+    }
+
+    #[tokio::test]
+    async fn it_should_confirm_instead_of_retry_a_late_transaction() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let seen_hash = vec![1; 32];
+        storage
+            .create(&vec![Transaction {
+                id: hex::encode(&seen_hash),
+                status: TransactionStatus::InFlight,
+                slot: Some(10),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let (mut stage, mut worker, _) = monitor_stage(storage.clone()).await;
+
+        let block_tx = Tx {
+            hash: seen_hash.clone().into(),
+            ..Default::default()
+        };
+        let event = Event::RollForward((2000, vec![2; 32]), vec![block_tx]);
+        let result =
+            <Worker as gasket::framework::Worker<Stage>>::execute(&mut worker, &event, &mut stage)
+                .await;
+        assert!(result.is_ok());
+
+        let tx = storage
+            .get(&hex::encode(&seen_hash))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(tx.status == TransactionStatus::Confirmed);
+        assert!(tx.attempts == 0);
+    }
+
+    #[tokio::test]
+    async fn it_should_backoff_unseen_transactions() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                slot: Some(10),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let (mut stage, mut worker, _) = monitor_stage(storage.clone()).await;
+
+        let event = Event::RollForward((2000, vec![2; 32]), vec![]);
+        let result =
+            <Worker as gasket::framework::Worker<Stage>>::execute(&mut worker, &event, &mut stage)
+                .await;
+        assert!(result.is_ok());
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+        assert!(tx.attempts == 1);
+        assert!(tx.next_retry_at.is_some_and(|at| at > chrono::Utc::now()));
+
+        // the backoff keeps it from being picked right away
+        let result = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_dead_letter_after_max_attempts() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                slot: Some(10),
+                attempts: PipelineConfig::default().max_attempts - 1,
+                error: Some("peer rejected".into()),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let (mut stage, mut worker, _) = monitor_stage(storage.clone()).await;
+
+        let event = Event::RollForward((2000, vec![2; 32]), vec![]);
+        let result =
+            <Worker as gasket::framework::Worker<Stage>>::execute(&mut worker, &event, &mut stage)
+                .await;
+        assert!(result.is_ok());
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::DeadLetter);
+        assert!(tx.attempts == PipelineConfig::default().max_attempts);
+        assert!(tx.error.as_deref() == Some("peer rejected"));
+
+        let result = storage.list_dead_letter(10, 0).await.unwrap();
+        assert!(result.len() == 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_revert_confirmed_on_rollback() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "kept".into(),
+                    status: TransactionStatus::Confirmed,
+                    slot: Some(10),
+                    confirmed_slot: Some(10),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "reverted".into(),
+                    status: TransactionStatus::Confirmed,
+                    slot: Some(30),
+                    confirmed_slot: Some(30),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let (mut stage, mut worker, cursor) = monitor_stage(storage.clone()).await;
+
+        let event = Event::Rollback((20, vec![2; 32]));
+        let result =
+            <Worker as gasket::framework::Worker<Stage>>::execute(&mut worker, &event, &mut stage)
+                .await;
+        assert!(result.is_ok());
+
+        let tx = storage.get("kept").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Confirmed);
+
+        let tx = storage.get("reverted").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+        assert!(tx.slot.is_none());
+        assert!(tx.confirmed_slot.is_none());
+
+        assert!(cursor.current().await.unwrap().unwrap().slot == 20);
+    }
+
+    #[test]
+    fn it_should_parse_start() {
+        let start: Start = serde_json::from_str("\"origin\"").unwrap();
+        assert!(start == Start::Origin);
+        assert!(start.initial_point() == Some((0, vec![])));
+
+        let start: Start = serde_json::from_str(r#"{ "slot": 42, "hash": "abcd" }"#).unwrap();
+        assert!(
+            start
+                == Start::Point {
+                    slot: 42,
+                    hash: vec![0xab, 0xcd]
+                }
+        );
+        assert!(start.initial_point() == Some((42, vec![0xab, 0xcd])));
+
+        let start: Start = serde_json::from_str("\"tip\"").unwrap();
+        assert!(start.initial_point().is_none());
+    }
+
+    #[test]
+    fn it_should_fail_parse_unknown_start() {
+        assert!(serde_json::from_str::<Start>("\"genesis\"").is_err());
+        assert!(serde_json::from_str::<Start>(r#"{ "slot": 42, "hash": "zz" }"#).is_err());
+    }
+}
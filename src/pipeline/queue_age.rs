@@ -0,0 +1,91 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use chrono::Utc;
+use tokio::time::interval;
+use tracing::{error, warn};
+
+use crate::{metrics::METRICS, storage::TransactionStore};
+
+use super::Config;
+
+/// How often the age of the queue is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Periodically publishes the age of the oldest transaction still queued, and
+/// warns when it's older than `max_queue_age_secs`, as the transactions are
+/// then getting stale instead of reaching the chain.
+pub async fn run(config: Config, storage: Arc<dyn TransactionStore>) {
+    let mut interval = interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = check(storage.as_ref(), config.max_queue_age_secs).await {
+            error!(?error, "failed to check the queue age");
+        }
+    }
+}
+
+/// Seconds since the oldest queued transaction was created, 0 when the queue
+/// is empty.
+async fn check(storage: &dyn TransactionStore, max_age_secs: Option<u64>) -> Result<u64> {
+    let age_secs = match storage.oldest_queued_at().await? {
+        Some(oldest) => (Utc::now() - oldest).num_seconds().max(0) as u64,
+        None => 0,
+    };
+    METRICS.queue_age_seconds.set(age_secs as i64);
+
+    if let Some(max_age_secs) = max_age_secs.filter(|max_age_secs| age_secs > *max_age_secs) {
+        warn!(age_secs, max_age_secs, "oldest queued transaction is stale");
+    }
+
+    Ok(age_secs)
+}
+
+#[cfg(test)]
+mod queue_age_tests {
+    use chrono::{Duration, Utc};
+
+    use crate::storage::{
+        mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore,
+    };
+
+    use super::check;
+
+    #[tokio::test]
+    async fn it_should_compute_age_of_oldest_queued() {
+        let storage = MockTransactionStore::default();
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    status: TransactionStatus::Confirmed,
+                    created_at: Utc::now() - Duration::hours(1),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    created_at: Utc::now() - Duration::minutes(10),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex3".into(),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let result = check(&storage, Some(60)).await;
+        assert!(result.is_ok_and(|age| (600..660).contains(&age)));
+    }
+
+    #[tokio::test]
+    async fn it_should_compute_no_age_when_queue_is_empty() {
+        let storage = MockTransactionStore::default();
+
+        let result = check(&storage, None).await;
+        assert!(result.is_ok_and(|age| age == 0));
+    }
+}
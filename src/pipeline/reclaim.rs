@@ -0,0 +1,25 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::interval;
+use tracing::{error, info};
+
+use crate::storage::TransactionStore;
+
+use super::Config;
+
+/// Periodically gives back the transactions claimed by a worker that stopped before
+/// submitting them, so they aren't stuck `InFlight` forever.
+pub async fn run(config: Config, storage: Arc<dyn TransactionStore>) {
+    let lease = Duration::from_secs(config.lease_timeout_secs);
+    let mut interval = interval(Duration::from_secs(config.reclaim_interval_secs));
+
+    loop {
+        interval.tick().await;
+
+        match storage.reclaim_expired(lease).await {
+            Ok(0) => {}
+            Ok(count) => info!(count, "reclaimed expired transactions"),
+            Err(error) => error!(?error, "failed to reclaim expired transactions"),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+
+use crate::storage::{Transaction, TransactionStatus, TransactionStore};
+
+/// Spreads the picks across the namespaces proportionally to their weights
+/// (smooth weighted round-robin), so a busy dApp can't starve the others.
+pub struct NamespaceScheduler {
+    /// Namespace, weight and current credit.
+    namespaces: Vec<(String, i64, i64)>,
+    total: i64,
+}
+
+impl NamespaceScheduler {
+    pub fn new(weights: &HashMap<String, u32>) -> Self {
+        let mut namespaces: Vec<(String, i64, i64)> = weights
+            .iter()
+            .filter(|(_, weight)| **weight > 0)
+            .map(|(namespace, weight)| (namespace.clone(), *weight as i64, 0))
+            .collect();
+        // the map order is random, sorting makes the picks deterministic
+        namespaces.sort();
+
+        let total = namespaces.iter().map(|(_, weight, _)| weight).sum();
+        Self { namespaces, total }
+    }
+
+    /// Namespace the next transaction should be taken from, none when there
+    /// are no weights.
+    pub fn pick(&mut self) -> Option<&str> {
+        for (_, weight, current) in self.namespaces.iter_mut() {
+            *current += *weight;
+        }
+
+        let (namespace, _, current) = self
+            .namespaces
+            .iter_mut()
+            .max_by_key(|(_, _, current)| *current)?;
+        *current -= self.total;
+        Some(namespace.as_str())
+    }
+
+    /// The picked namespace followed by the other weighted ones, none when
+    /// there are no weights.
+    fn candidates(&mut self) -> Option<Vec<String>> {
+        let picked = self.pick()?.to_string();
+
+        let others = self
+            .namespaces
+            .iter()
+            .map(|(namespace, _, _)| namespace.clone())
+            .filter(|namespace| *namespace != picked);
+        Some(std::iter::once(picked.clone()).chain(others).collect())
+    }
+
+    /// Next transaction in `status` of the picked namespace. When it has none
+    /// the other weighted namespaces are tried, and then any namespace, so the
+    /// idle share isn't wasted and unweighted namespaces are still served.
+    pub async fn next(
+        &mut self,
+        storage: &dyn TransactionStore,
+        status: TransactionStatus,
+    ) -> Result<Option<Transaction>> {
+        for namespace in self.candidates().into_iter().flatten() {
+            if let Some(tx) = storage.next(status.clone(), Some(&namespace)).await? {
+                return Ok(Some(tx));
+            }
+        }
+
+        Ok(storage.next(status, None).await?)
+    }
+
+    /// Claims the next transaction in `status` for `worker_id`, going through
+    /// the namespaces as `next` does.
+    pub async fn claim_next(
+        &mut self,
+        storage: &dyn TransactionStore,
+        status: TransactionStatus,
+        worker_id: &str,
+    ) -> Result<Option<Transaction>> {
+        for namespace in self.candidates().into_iter().flatten() {
+            let claimed = storage
+                .claim_next(status.clone(), Some(&namespace), worker_id)
+                .await?;
+            if claimed.is_some() {
+                return Ok(claimed);
+            }
+        }
+
+        Ok(storage.claim_next(status, None, worker_id).await?)
+    }
+}
+
+#[cfg(test)]
+mod scheduler_tests {
+    use std::collections::HashMap;
+
+    use crate::storage::{
+        mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore,
+    };
+
+    use super::NamespaceScheduler;
+
+    fn weights(weights: &[(&str, u32)]) -> HashMap<String, u32> {
+        weights
+            .iter()
+            .map(|(namespace, weight)| (namespace.to_string(), *weight))
+            .collect()
+    }
+
+    #[test]
+    fn it_should_pick_proportionally() {
+        let mut scheduler = NamespaceScheduler::new(&weights(&[("a", 3), ("b", 1)]));
+
+        let mut picks: HashMap<String, u32> = HashMap::new();
+        for _ in 0..400 {
+            let namespace = scheduler.pick().unwrap().to_string();
+            *picks.entry(namespace).or_default() += 1;
+        }
+
+        assert!(picks["a"] == 300);
+        assert!(picks["b"] == 100);
+    }
+
+    #[test]
+    fn it_should_pick_none_without_weights() {
+        let mut scheduler = NamespaceScheduler::new(&weights(&[("a", 0)]));
+        assert!(scheduler.pick().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_take_transactions_by_weight() {
+        let storage = MockTransactionStore::default();
+        let txs: Vec<Transaction> = ["a", "b"]
+            .iter()
+            .flat_map(|namespace| {
+                (0..40).map(move |i| Transaction {
+                    id: format!("{namespace}{i}"),
+                    namespace: namespace.to_string(),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        storage.create(&txs).await.unwrap();
+
+        let mut scheduler = NamespaceScheduler::new(&weights(&[("a", 3), ("b", 1)]));
+        let mut taken: HashMap<String, u32> = HashMap::new();
+        for _ in 0..40 {
+            let mut tx = scheduler
+                .next(&storage, TransactionStatus::Pending)
+                .await
+                .unwrap()
+                .unwrap();
+            *taken.entry(tx.namespace.clone()).or_default() += 1;
+
+            tx.status = TransactionStatus::Validated;
+            storage.update(&tx).await.unwrap();
+        }
+
+        assert!(taken["a"] == 30);
+        assert!(taken["b"] == 10);
+    }
+
+    #[tokio::test]
+    async fn it_should_claim_transactions_by_weight() {
+        let storage = MockTransactionStore::default();
+        let txs: Vec<Transaction> = ["a", "b"]
+            .iter()
+            .flat_map(|namespace| {
+                (0..40).map(move |i| Transaction {
+                    id: format!("{namespace}{i}"),
+                    namespace: namespace.to_string(),
+                    status: TransactionStatus::Validated,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        storage.create(&txs).await.unwrap();
+
+        let mut scheduler = NamespaceScheduler::new(&weights(&[("a", 3), ("b", 1)]));
+        let mut claimed: HashMap<String, u32> = HashMap::new();
+        for _ in 0..40 {
+            let tx = scheduler
+                .claim_next(&storage, TransactionStatus::Validated, "worker")
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(tx.status == TransactionStatus::InFlight);
+            *claimed.entry(tx.namespace.clone()).or_default() += 1;
+        }
+
+        assert!(claimed["a"] == 30);
+        assert!(claimed["b"] == 10);
+    }
+
+    #[tokio::test]
+    async fn it_should_peek_the_transaction_claimed_next_in_its_namespace() {
+        let storage = MockTransactionStore::default();
+        let txs: Vec<Transaction> = ["b", "a"]
+            .iter()
+            .flat_map(|namespace| {
+                (0..4).map(move |i| Transaction {
+                    id: format!("{namespace}{i}"),
+                    namespace: namespace.to_string(),
+                    status: TransactionStatus::Validated,
+                    ..Default::default()
+                })
+            })
+            .collect();
+        storage.create(&txs).await.unwrap();
+
+        let mut scheduler = NamespaceScheduler::new(&weights(&[("a", 3), ("b", 1)]));
+
+        // the weights pick "a" first, ahead of the queue wide order
+        let peeked = storage
+            .peek(TransactionStatus::Validated, None)
+            .await
+            .unwrap();
+        assert!(peeked.is_some_and(|tx| tx.namespace == "b"));
+
+        for _ in 0..txs.len() {
+            let mut peeked = HashMap::new();
+            for namespace in ["a", "b"] {
+                let tx = storage
+                    .peek(TransactionStatus::Validated, Some(namespace))
+                    .await
+                    .unwrap();
+                peeked.insert(namespace.to_string(), tx.map(|tx| tx.id));
+            }
+
+            let claimed = scheduler
+                .claim_next(&storage, TransactionStatus::Validated, "worker")
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(peeked[&claimed.namespace].as_ref() == Some(&claimed.id));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_fall_back_to_other_namespaces() {
+        let storage = MockTransactionStore::default();
+        storage
+            .create(&vec![Transaction {
+                namespace: "unweighted".into(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let mut scheduler = NamespaceScheduler::new(&weights(&[("a", 3), ("b", 1)]));
+        let tx = scheduler
+            .next(&storage, TransactionStatus::Pending)
+            .await
+            .unwrap();
+        assert!(tx.unwrap().namespace == "unweighted");
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use pallas::crypto::hash::Hasher;
+use tonic::{service::Interceptor, Request, Status};
+
+/// Id of the API key a request was authenticated with, set by the
+/// [`ApiKeyInterceptor`] in the request extensions.
+#[derive(Clone)]
+pub struct Submitter(pub String);
+
+/// Identifies an API key without storing it, the first 16 hex characters of
+/// its blake2b-256 hash.
+pub fn key_id(api_key: &[u8]) -> String {
+    Hasher::<256>::hash(api_key).to_string()[..16].to_string()
+}
+
+/// Id of the API key the request was authenticated with, none when auth is
+/// disabled.
+pub fn submitter<T>(request: &Request<T>) -> Option<String> {
+    request
+        .extensions()
+        .get::<Submitter>()
+        .map(|submitter| submitter.0.clone())
+}
+
+/// Checks the `authorization` metadata against the configured API keys, every
+/// request is allowed when there are no keys.
+#[derive(Clone)]
+pub struct ApiKeyInterceptor {
+    api_keys: Arc<Vec<String>>,
+}
+
+impl ApiKeyInterceptor {
+    pub fn new(api_keys: Vec<String>) -> Self {
+        Self {
+            api_keys: Arc::new(api_keys),
+        }
+    }
+
+    /// Submitter of the `provided` key, none when there are no keys.
+    pub fn authenticate(&self, provided: Option<&[u8]>) -> Result<Option<Submitter>, Status> {
+        if self.api_keys.is_empty() {
+            return Ok(None);
+        }
+
+        let provided = provided.ok_or_else(|| Status::unauthenticated("missing authorization"))?;
+        if !self.is_allowed(provided) {
+            return Err(Status::unauthenticated("invalid api key"));
+        }
+
+        Ok(Some(Submitter(key_id(provided))))
+    }
+
+    fn is_allowed(&self, provided: &[u8]) -> bool {
+        // every key is compared, so the time doesn't tell which one matched
+        self.api_keys.iter().fold(false, |allowed, api_key| {
+            allowed | constant_time_eq(api_key.as_bytes(), provided)
+        })
+    }
+}
+
+impl Interceptor for ApiKeyInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let provided = request
+            .metadata()
+            .get("authorization")
+            .map(|value| value.as_bytes());
+
+        if let Some(submitter) = self.authenticate(provided)? {
+            request.extensions_mut().insert(submitter);
+        }
+
+        Ok(request)
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use tonic::{service::Interceptor, Code, Request};
+
+    use super::{key_id, submitter, ApiKeyInterceptor};
+
+    fn request(authorization: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(authorization) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn it_should_accept_configured_key() {
+        let mut interceptor = ApiKeyInterceptor::new(vec!["key1".into(), "key2".into()]);
+
+        assert!(interceptor.call(request(Some("key1"))).is_ok());
+        assert!(interceptor.call(request(Some("key2"))).is_ok());
+    }
+
+    #[test]
+    fn it_should_set_submitter_of_key() {
+        let mut interceptor = ApiKeyInterceptor::new(vec!["key1".into(), "key2".into()]);
+
+        let request = interceptor.call(request(Some("key2"))).unwrap();
+        assert!(submitter(&request) == Some(key_id(b"key2")));
+        assert!(key_id(b"key2").len() == 16);
+        assert!(key_id(b"key1") != key_id(b"key2"));
+
+        let mut interceptor = ApiKeyInterceptor::new(vec![]);
+        let request = interceptor.call(request(Some("key1"))).unwrap();
+        assert!(submitter(&request).is_none());
+    }
+
+    #[test]
+    fn it_should_reject_unknown_key() {
+        let mut interceptor = ApiKeyInterceptor::new(vec!["key1".into()]);
+
+        let result = interceptor.call(request(Some("key2")));
+        assert!(result.unwrap_err().code() == Code::Unauthenticated);
+
+        let result = interceptor.call(request(Some("key10")));
+        assert!(result.unwrap_err().code() == Code::Unauthenticated);
+    }
+
+    #[test]
+    fn it_should_reject_missing_key() {
+        let mut interceptor = ApiKeyInterceptor::new(vec!["key1".into()]);
+
+        let result = interceptor.call(request(None));
+        assert!(result.unwrap_err().code() == Code::Unauthenticated);
+    }
+
+    #[test]
+    fn it_should_allow_any_request_without_keys() {
+        let mut interceptor = ApiKeyInterceptor::new(vec![]);
+
+        assert!(interceptor.call(request(None)).is_ok());
+        assert!(interceptor.call(request(Some("key1"))).is_ok());
+    }
+}
@@ -0,0 +1,1597 @@
+use std::{collections::HashMap, pin::Pin, sync::Arc, time::Duration};
+
+use anyhow::{anyhow, Context, Result};
+use async_stream::stream;
+use chrono::DateTime;
+use futures_core::Stream;
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        Notify,
+    },
+    time::interval,
+};
+use tonic::{Request, Response, Status};
+use tracing::{error, info, warn};
+
+use crate::{
+    pipeline::ingest::IngestSender,
+    storage::{self, find_dependency_cycle, TransactionStore},
+    validation::{compute_tx_hash, validate_metadata, validate_tx_cbor},
+};
+
+use super::{auth, infer_dependencies, reserve_ingest};
+
+/// How often a watched transaction is re-read from the storage, to catch the
+/// transitions not written through the watched store.
+const WATCH_REFRESH: Duration = Duration::from_secs(10);
+
+const DEFAULT_LIST_LIMIT: u32 = 50;
+const MAX_LIST_LIMIT: u32 = 1000;
+
+pub mod proto {
+    tonic::include_proto!("boros.v1");
+
+    pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("boros_descriptor");
+}
+
+use proto::{
+    batch_tx::Input as TxInput, transaction_service_server, CancelTransactionRequest,
+    CancelTransactionResponse, ExportTransactionsRequest, ExportTransactionsResponse,
+    GetTransactionRequest, GetTransactionResponse, ListTransactionsRequest,
+    ListTransactionsResponse, PeekTransactionRequest, PeekTransactionResponse,
+    RequeueTransactionRequest, RequeueTransactionResponse, RequeueTransactionsRequest,
+    RequeueTransactionsResponse, ResetRequest, ResetResponse, ResubmitRequest, ResubmitResponse,
+    StatsRequest, StatsResponse, StatusCount, SubmitTxBatchRequest, SubmitTxBatchResponse,
+    UpdatePriorityRequest, UpdatePriorityResponse, WatchTransactionRequest,
+    WatchTransactionResponse,
+};
+
+impl From<&storage::TransactionStatus> for proto::TransactionStatus {
+    fn from(value: &storage::TransactionStatus) -> Self {
+        match value {
+            storage::TransactionStatus::Pending => Self::Pending,
+            storage::TransactionStatus::Validated => Self::Validated,
+            storage::TransactionStatus::InFlight => Self::InFlight,
+            storage::TransactionStatus::Confirmed => Self::Confirmed,
+            storage::TransactionStatus::Failed => Self::Failed,
+            storage::TransactionStatus::DeadLetter => Self::DeadLetter,
+            storage::TransactionStatus::Cancelled => Self::Cancelled,
+            storage::TransactionStatus::BlockedByDependency => Self::BlockedByDependency,
+        }
+    }
+}
+
+impl From<&storage::TransactionPriority> for proto::TransactionPriority {
+    fn from(value: &storage::TransactionPriority) -> Self {
+        match value {
+            storage::TransactionPriority::Low => Self::Low,
+            storage::TransactionPriority::Medium => Self::Medium,
+            storage::TransactionPriority::High => Self::High,
+        }
+    }
+}
+
+impl TryFrom<proto::TransactionPriority> for storage::TransactionPriority {
+    type Error = Status;
+
+    fn try_from(value: proto::TransactionPriority) -> Result<Self, Self::Error> {
+        match value {
+            proto::TransactionPriority::Unspecified => {
+                Err(Status::invalid_argument("transaction priority is required"))
+            }
+            proto::TransactionPriority::Low => Ok(Self::Low),
+            proto::TransactionPriority::Medium => Ok(Self::Medium),
+            proto::TransactionPriority::High => Ok(Self::High),
+        }
+    }
+}
+
+impl TryFrom<proto::TransactionStatus> for storage::TransactionStatus {
+    type Error = Status;
+
+    fn try_from(value: proto::TransactionStatus) -> Result<Self, Self::Error> {
+        match value {
+            proto::TransactionStatus::Unspecified => {
+                Err(Status::invalid_argument("transaction status is required"))
+            }
+            proto::TransactionStatus::Pending => Ok(Self::Pending),
+            proto::TransactionStatus::Validated => Ok(Self::Validated),
+            proto::TransactionStatus::InFlight => Ok(Self::InFlight),
+            proto::TransactionStatus::Confirmed => Ok(Self::Confirmed),
+            proto::TransactionStatus::Failed => Ok(Self::Failed),
+            proto::TransactionStatus::DeadLetter => Ok(Self::DeadLetter),
+            proto::TransactionStatus::Cancelled => Ok(Self::Cancelled),
+            proto::TransactionStatus::BlockedByDependency => Ok(Self::BlockedByDependency),
+        }
+    }
+}
+
+impl From<storage::Transaction> for proto::Transaction {
+    fn from(value: storage::Transaction) -> Self {
+        Self {
+            status: proto::TransactionStatus::from(&value.status).into(),
+            priority: proto::TransactionPriority::from(&value.priority).into(),
+            id: value.id,
+            attempts: value.attempts,
+            error: value.error,
+            slot: value.slot,
+            namespace: value.namespace,
+            expires_at: value.expires_at.map(|at| at.timestamp()),
+            metadata: value.metadata,
+            source: value.source,
+            submitter: value.submitter,
+            replaces: value.replaces,
+        }
+    }
+}
+
+/// Bytes of the transaction CBOR, sent either raw or hex encoded.
+pub fn decode_tx_input(input: &TxInput) -> Result<Vec<u8>> {
+    match input {
+        TxInput::Raw(raw) => Ok(raw.clone()),
+        TxInput::Hex(hex) => hex::decode(hex.trim()).context("invalid hex"),
+    }
+}
+
+pub struct TransactionServiceImpl {
+    tx_storage: Arc<dyn TransactionStore>,
+    tx_events: broadcast::Sender<storage::Transaction>,
+    ingest: Option<IngestSender>,
+    fanout: Option<Arc<Notify>>,
+    allow_reset: bool,
+}
+
+impl TransactionServiceImpl {
+    pub fn new(
+        tx_storage: Arc<dyn TransactionStore>,
+        tx_events: broadcast::Sender<storage::Transaction>,
+    ) -> Self {
+        Self {
+            tx_storage,
+            tx_events,
+            ingest: None,
+            fanout: None,
+            allow_reset: false,
+        }
+    }
+
+    /// Enables the `Reset` RPC, denied otherwise.
+    pub fn with_admin_reset(mut self, allowed: bool) -> Self {
+        self.allow_reset = allowed;
+        self
+    }
+
+    /// Takes a slot of the ingest buffer for every submitted transaction.
+    pub fn with_ingest(mut self, ingest: IngestSender) -> Self {
+        self.ingest = Some(ingest);
+        self
+    }
+
+    /// Wakes up the fanout when a transaction is resubmitted, so it's claimed
+    /// right away instead of on the next poll.
+    pub fn with_fanout(mut self, fanout: Arc<Notify>) -> Self {
+        self.fanout = Some(fanout);
+        self
+    }
+
+    async fn get(&self, id: &str) -> Result<storage::Transaction, Status> {
+        self.tx_storage
+            .get(id)
+            .await?
+            .ok_or_else(|| Status::not_found(format!("transaction {id} not found")))
+    }
+}
+
+#[async_trait::async_trait]
+impl transaction_service_server::TransactionService for TransactionServiceImpl {
+    type WatchTransactionStream =
+        Pin<Box<dyn Stream<Item = Result<WatchTransactionResponse, Status>> + Send + 'static>>;
+    type ExportTransactionsStream =
+        Pin<Box<dyn Stream<Item = Result<ExportTransactionsResponse, Status>> + Send + 'static>>;
+
+    async fn get_transaction(
+        &self,
+        request: Request<GetTransactionRequest>,
+    ) -> Result<Response<GetTransactionResponse>, Status> {
+        let message = request.into_inner();
+
+        let tx = self.get(&message.id).await?;
+
+        Ok(Response::new(GetTransactionResponse {
+            tx: Some(tx.into()),
+        }))
+    }
+
+    async fn watch_transaction(
+        &self,
+        request: Request<WatchTransactionRequest>,
+    ) -> Result<Response<Self::WatchTransactionStream>, Status> {
+        let id = request.into_inner().id;
+
+        // subscribe before reading, so no transition is lost in between
+        let mut events = self.tx_events.subscribe();
+        let tx = self.get(&id).await?;
+        let tx_storage = self.tx_storage.clone();
+
+        let stream = stream! {
+            let mut status = tx.status.clone();
+            yield Ok(WatchTransactionResponse { tx: Some(tx.into()) });
+
+            let mut refresh = interval(WATCH_REFRESH);
+            refresh.tick().await;
+
+            while !status.is_terminal() {
+                let tx = tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(tx) if tx.id == id => Ok(Some(tx)),
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(_)) => tx_storage.get(&id).await,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = refresh.tick() => tx_storage.get(&id).await,
+                };
+
+                let tx = match tx {
+                    Ok(Some(tx)) => tx,
+                    // deleted by a reset or the retention, it won't change anymore
+                    Ok(None) => {
+                        yield Err(Status::not_found(format!("transaction {id} not found")));
+                        break;
+                    }
+                    Err(_) => continue,
+                };
+
+                if tx.status == status {
+                    continue;
+                }
+
+                status = tx.status.clone();
+                yield Ok(WatchTransactionResponse { tx: Some(tx.into()) });
+            }
+        };
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn submit_tx_batch(
+        &self,
+        request: Request<SubmitTxBatchRequest>,
+    ) -> Result<Response<SubmitTxBatchResponse>, Status> {
+        let submitter = auth::submitter(&request);
+        let message = request.into_inner();
+        let namespace = match message.namespace.as_str() {
+            "" => storage::DEFAULT_NAMESPACE.to_string(),
+            namespace => namespace.to_string(),
+        };
+
+        let mut txs: Vec<storage::Transaction> = Vec::with_capacity(message.txs.len());
+        for (idx, batch_tx) in message.txs.into_iter().enumerate() {
+            let (hash, raw) = batch_tx
+                .input
+                .as_ref()
+                .ok_or_else(|| anyhow!("missing tx"))
+                .and_then(decode_tx_input)
+                .and_then(|raw| {
+                    validate_tx_cbor(&raw)?;
+                    Ok((compute_tx_hash(&raw)?, raw))
+                })
+                .map_err(|error| {
+                    Status::invalid_argument(format!("invalid tx at index {idx}: {error:#}"))
+                })?;
+
+            let mut tx = storage::Transaction::new(hash, raw);
+            tx.namespace = namespace.clone();
+            tx.source = Some("grpc".into());
+            tx.submitter = submitter.clone();
+            if !batch_tx.dependencies.is_empty() {
+                tx.dependencies = Some(batch_tx.dependencies);
+            }
+            if let Some(expires_at) = batch_tx.expires_at {
+                tx.expires_at = Some(DateTime::from_timestamp(expires_at, 0).ok_or_else(|| {
+                    Status::invalid_argument(format!("invalid expires_at at index {idx}"))
+                })?);
+            }
+            if let Some(metadata) = batch_tx.metadata {
+                validate_metadata(&metadata).map_err(|error| {
+                    Status::invalid_argument(format!("invalid metadata at index {idx}: {error}"))
+                })?;
+                tx.metadata = Some(metadata);
+            }
+            tx.replaces = batch_tx.replaces;
+            txs.push(tx);
+        }
+
+        infer_dependencies(self.tx_storage.as_ref(), &mut txs)
+            .await
+            .map_err(|error| {
+                error!(?error);
+                Status::internal("internal error")
+            })?;
+
+        let graph: HashMap<String, Vec<String>> = txs
+            .iter()
+            .map(|tx| (tx.id.clone(), tx.dependencies.clone().unwrap_or_default()))
+            .collect();
+        if graph.len() != txs.len() {
+            return Err(Status::invalid_argument("duplicated tx in batch"));
+        }
+        if let Some(cycle) = find_dependency_cycle(&graph) {
+            return Err(Status::invalid_argument(format!(
+                "dependency cycle detected: {}",
+                cycle.join(" -> ")
+            )));
+        }
+
+        for required_id in graph.values().flatten() {
+            if graph.contains_key(required_id) {
+                continue;
+            }
+            if self.get(required_id).await.is_err() {
+                return Err(Status::invalid_argument(format!(
+                    "unknown dependency {required_id}"
+                )));
+            }
+        }
+
+        let ids: Vec<String> = txs.iter().map(|tx| tx.id.clone()).collect();
+        if message.dry_run {
+            info!(?ids, "validated tx batch, dry run");
+            return Ok(Response::new(SubmitTxBatchResponse { ids, txs: vec![] }));
+        }
+
+        info!(?ids, %namespace, "submitting tx batch");
+
+        let permits = reserve_ingest(&self.ingest, txs.len()).await?;
+
+        // the transactions already stored are kept as they are
+        let stored = self.tx_storage.create_or_get(&txs).await?;
+
+        for (permit, id) in permits.into_iter().zip(&ids) {
+            permit.send(id.clone());
+        }
+
+        Ok(Response::new(SubmitTxBatchResponse {
+            ids,
+            txs: stored.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn requeue_transaction(
+        &self,
+        request: Request<RequeueTransactionRequest>,
+    ) -> Result<Response<RequeueTransactionResponse>, Status> {
+        let id = request.into_inner().id;
+
+        let status = self.get(&id).await?.status;
+        if !status.is_requeueable() {
+            return Err(Status::failed_precondition(format!(
+                "transaction {id} is {status}, only the failed and dead-lettered ones are requeued"
+            )));
+        }
+
+        self.tx_storage.requeue(&id).await?;
+        info!(tx_id = %id, "requeued transaction");
+
+        let tx = self.get(&id).await?;
+
+        Ok(Response::new(RequeueTransactionResponse {
+            tx: Some(tx.into()),
+        }))
+    }
+
+    async fn cancel_transaction(
+        &self,
+        request: Request<CancelTransactionRequest>,
+    ) -> Result<Response<CancelTransactionResponse>, Status> {
+        let id = request.into_inner().id;
+
+        let cancelled = self.tx_storage.cancel(&id).await?;
+
+        // read after cancelling, so a NOT_FOUND isn't answered for a tx that
+        // was cancelled by a concurrent call
+        let tx = self.get(&id).await?;
+        if !cancelled {
+            return Err(Status::failed_precondition(format!(
+                "transaction {id} is already {}",
+                tx.status
+            )));
+        }
+        info!(tx_id = %id, "cancelled transaction");
+
+        Ok(Response::new(CancelTransactionResponse {
+            tx: Some(tx.into()),
+        }))
+    }
+
+    async fn resubmit(
+        &self,
+        request: Request<ResubmitRequest>,
+    ) -> Result<Response<ResubmitResponse>, Status> {
+        let id = request.into_inner().id;
+
+        let resubmitted = self.tx_storage.resubmit(&id).await?;
+
+        let tx = self.get(&id).await?;
+        if !resubmitted {
+            return Err(Status::failed_precondition(format!(
+                "transaction {id} is already {}",
+                tx.status
+            )));
+        }
+        info!(tx_id = %id, "resubmitting transaction");
+        if let Some(fanout) = &self.fanout {
+            fanout.notify_one();
+        }
+
+        Ok(Response::new(ResubmitResponse {
+            tx: Some(tx.into()),
+        }))
+    }
+
+    async fn requeue_transactions(
+        &self,
+        request: Request<RequeueTransactionsRequest>,
+    ) -> Result<Response<RequeueTransactionsResponse>, Status> {
+        let status = storage::TransactionStatus::try_from(request.into_inner().status())?;
+        if !status.is_requeueable() {
+            return Err(Status::invalid_argument(format!(
+                "{status} transactions can't be requeued"
+            )));
+        }
+
+        let count = self.tx_storage.requeue_all(status.clone()).await?;
+        info!(count, %status, "requeued transactions");
+
+        Ok(Response::new(RequeueTransactionsResponse { count }))
+    }
+
+    async fn update_priority(
+        &self,
+        request: Request<UpdatePriorityRequest>,
+    ) -> Result<Response<UpdatePriorityResponse>, Status> {
+        let message = request.into_inner();
+        let priority = storage::TransactionPriority::try_from(message.priority())?;
+        let id = message.id;
+
+        if self.get(&id).await?.status.is_terminal() {
+            return Err(Status::failed_precondition(format!(
+                "transaction {id} is already terminal"
+            )));
+        }
+
+        self.tx_storage.set_priority(&id, priority.clone()).await?;
+        info!(tx_id = %id, ?priority, "updated transaction priority");
+
+        let tx = self.get(&id).await?;
+
+        Ok(Response::new(UpdatePriorityResponse {
+            tx: Some(tx.into()),
+        }))
+    }
+
+    async fn stats(
+        &self,
+        _request: Request<StatsRequest>,
+    ) -> Result<Response<StatsResponse>, Status> {
+        let counts = self.tx_storage.status_counts().await?;
+
+        // the list is newest first, so the oldest pending is the last one
+        let pending = counts
+            .get(&storage::TransactionStatus::Pending)
+            .copied()
+            .unwrap_or_default();
+        let oldest_pending = match pending {
+            0 => None,
+            pending => self
+                .tx_storage
+                .list(
+                    Some(storage::TransactionStatus::Pending),
+                    None,
+                    None,
+                    1,
+                    pending - 1,
+                )
+                .await?
+                .pop(),
+        };
+        let oldest_pending_age_secs = oldest_pending
+            .map(|tx| (chrono::Utc::now() - tx.created_at).num_seconds().max(0) as u64);
+
+        let mut counts: Vec<StatusCount> = counts
+            .iter()
+            .map(|(status, count)| StatusCount {
+                status: proto::TransactionStatus::from(status).into(),
+                count: *count,
+            })
+            .collect();
+        counts.sort_by_key(|count| count.status);
+
+        Ok(Response::new(StatsResponse {
+            counts,
+            oldest_pending_age_secs,
+        }))
+    }
+
+    async fn list_transactions(
+        &self,
+        request: Request<ListTransactionsRequest>,
+    ) -> Result<Response<ListTransactionsResponse>, Status> {
+        let message = request.into_inner();
+
+        let status = match message.status() {
+            proto::TransactionStatus::Unspecified => None,
+            status => Some(storage::TransactionStatus::try_from(status)?),
+        };
+        let limit = match message.limit {
+            0 => DEFAULT_LIST_LIMIT,
+            limit => limit.min(MAX_LIST_LIMIT),
+        };
+
+        let txs = self
+            .tx_storage
+            .list(status, None, None, limit.into(), message.offset.into())
+            .await?;
+
+        Ok(Response::new(ListTransactionsResponse {
+            txs: txs.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn peek_transaction(
+        &self,
+        request: Request<PeekTransactionRequest>,
+    ) -> Result<Response<PeekTransactionResponse>, Status> {
+        let message = request.into_inner();
+        let status = match message.status() {
+            proto::TransactionStatus::Unspecified => storage::TransactionStatus::Validated,
+            status => storage::TransactionStatus::try_from(status)?,
+        };
+        let namespace = match message.namespace.as_str() {
+            "" => None,
+            namespace => Some(namespace),
+        };
+
+        let tx = self.tx_storage.peek(status, namespace).await?;
+
+        Ok(Response::new(PeekTransactionResponse {
+            tx: tx.map(Into::into),
+        }))
+    }
+
+    async fn export_transactions(
+        &self,
+        request: Request<ExportTransactionsRequest>,
+    ) -> Result<Response<Self::ExportTransactionsStream>, Status> {
+        let page_size = match request.into_inner().page_size {
+            0 => DEFAULT_LIST_LIMIT,
+            page_size => page_size.min(MAX_LIST_LIMIT),
+        };
+        let tx_storage = self.tx_storage.clone();
+
+        let stream = stream! {
+            let mut after = None;
+            let mut after_id = String::new();
+
+            loop {
+                let txs = match tx_storage.list_after(after, &after_id, page_size.into()).await {
+                    Ok(txs) => txs,
+                    Err(error) => {
+                        yield Err(error.into());
+                        break;
+                    }
+                };
+
+                let Some(last) = txs.last() else {
+                    break;
+                };
+                after = Some(last.created_at);
+                after_id = last.id.clone();
+
+                for tx in txs {
+                    yield Ok(ExportTransactionsResponse { tx: Some(tx.into()) });
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn reset(
+        &self,
+        _request: Request<ResetRequest>,
+    ) -> Result<Response<ResetResponse>, Status> {
+        if !self.allow_reset {
+            return Err(Status::permission_denied(
+                "reset is disabled, set server.allow_admin_reset to enable it",
+            ));
+        }
+
+        self.tx_storage.truncate_all().await?;
+        warn!("deleted every transaction");
+
+        Ok(Response::new(ResetResponse {}))
+    }
+}
+
+#[cfg(test)]
+mod transaction_service_tests {
+    use std::sync::Arc;
+
+    use chrono::{Duration, Utc};
+    use futures::StreamExt;
+    use tokio::sync::{broadcast, Notify};
+    use tonic::{Code, Request};
+
+    use crate::{
+        fixtures::{TX_CBOR, TX_HASH},
+        pipeline::ingest::buffer,
+        server::auth::Submitter,
+        storage::{
+            mock::MockTransactionStore, watch::WatchedTransactionStore, Transaction,
+            TransactionStatus, TransactionStore,
+        },
+    };
+
+    use super::{
+        compute_tx_hash, decode_tx_input,
+        proto::{
+            self, transaction_service_server::TransactionService, BatchTx,
+            CancelTransactionRequest, ExportTransactionsRequest, GetTransactionRequest,
+            ListTransactionsRequest, PeekTransactionRequest, RequeueTransactionRequest,
+            RequeueTransactionsRequest, ResetRequest, ResubmitRequest, StatsRequest, StatusCount,
+            SubmitTxBatchRequest, UpdatePriorityRequest, WatchTransactionRequest,
+        },
+        TransactionServiceImpl, TxInput,
+    };
+
+    fn batch_request(dependencies: Vec<String>) -> Request<SubmitTxBatchRequest> {
+        Request::new(SubmitTxBatchRequest {
+            txs: vec![BatchTx {
+                input: Some(TxInput::Raw(hex::decode(TX_CBOR).unwrap())),
+                dependencies,
+                expires_at: None,
+                metadata: None,
+                replaces: None,
+            }],
+            dry_run: false,
+            namespace: String::new(),
+        })
+    }
+
+    fn watched_storage() -> (Arc<dyn TransactionStore>, TransactionServiceImpl) {
+        let (tx_events, _) = broadcast::channel(16);
+        let storage: Arc<dyn TransactionStore> = Arc::new(WatchedTransactionStore::new(
+            Arc::new(MockTransactionStore::default()),
+            tx_events.clone(),
+        ));
+        let service = TransactionServiceImpl::new(storage.clone(), tx_events);
+        (storage, service)
+    }
+
+    #[tokio::test]
+    async fn it_should_get_transaction() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                id: "hex1".into(),
+                attempts: 2,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .get_transaction(Request::new(GetTransactionRequest { id: "hex1".into() }))
+            .await;
+        assert!(result.is_ok());
+
+        let tx = result.unwrap().into_inner().tx.unwrap();
+        assert!(tx.id == "hex1");
+        assert!(tx.status() == proto::TransactionStatus::Pending);
+        assert!(tx.attempts == 2);
+        assert!(tx.error.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_get_unknown_transaction() {
+        let (_, service) = watched_storage();
+
+        let result = service
+            .get_transaction(Request::new(GetTransactionRequest {
+                id: "unknown".into(),
+            }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn it_should_watch_transaction_until_terminal() {
+        let (storage, service) = watched_storage();
+        let mut transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        let result = service
+            .watch_transaction(Request::new(WatchTransactionRequest {
+                id: transaction.id.clone(),
+            }))
+            .await;
+        assert!(result.is_ok());
+        let mut stream = result.unwrap().into_inner();
+
+        transaction.status = TransactionStatus::Validated;
+        storage.update(&transaction).await.unwrap();
+        transaction.status = TransactionStatus::Confirmed;
+        storage.update(&transaction).await.unwrap();
+
+        let mut statuses = vec![];
+        while let Some(message) = stream.next().await {
+            statuses.push(message.unwrap().tx.unwrap().status());
+        }
+
+        assert!(
+            statuses
+                == vec![
+                    proto::TransactionStatus::Pending,
+                    proto::TransactionStatus::Validated,
+                    proto::TransactionStatus::Confirmed,
+                ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_should_watch_terminal_transaction_once() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Failed,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .watch_transaction(Request::new(WatchTransactionRequest { id: "hex".into() }))
+            .await;
+        assert!(result.is_ok());
+
+        let messages: Vec<_> = result.unwrap().into_inner().collect().await;
+        assert!(messages.len() == 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_should_end_watch_when_transaction_is_deleted() {
+        let (storage, service) = watched_storage();
+        storage.create(&vec![Transaction::default()]).await.unwrap();
+
+        let result = service
+            .watch_transaction(Request::new(WatchTransactionRequest { id: "hex".into() }))
+            .await;
+        assert!(result.is_ok());
+        let mut stream = result.unwrap().into_inner();
+        assert!(stream.next().await.unwrap().is_ok());
+
+        storage.delete("hex").await.unwrap();
+
+        let message = stream.next().await.unwrap();
+        assert!(message.unwrap_err().code() == Code::NotFound);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_watch_unknown_transaction() {
+        let (_, service) = watched_storage();
+
+        let result = service
+            .watch_transaction(Request::new(WatchTransactionRequest {
+                id: "unknown".into(),
+            }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.err().unwrap().code() == Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_tx_batch() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                id: "required".into(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .submit_tx_batch(batch_request(vec!["required".into()]))
+            .await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().into_inner().ids == vec![TX_HASH.to_string()]);
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.dependencies == Some(vec!["required".into()]));
+    }
+
+    #[tokio::test]
+    async fn it_should_replace_pending_transaction() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                id: "original".into(),
+                status: TransactionStatus::Validated,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let mut request = batch_request(vec![]);
+        request.get_mut().txs[0].replaces = Some("original".into());
+        let result = service.submit_tx_batch(request).await;
+        assert!(result.is_ok());
+
+        let original = storage.get("original").await.unwrap().unwrap();
+        assert!(original.status == TransactionStatus::Cancelled);
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.replaces.as_deref() == Some("original"));
+        let result = storage.next(TransactionStatus::Pending, None).await;
+        assert!(result.unwrap().is_some_and(|tx| tx.id == TX_HASH));
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_replacing_submitted_transaction() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                id: "original".into(),
+                status: TransactionStatus::InFlight,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let mut request = batch_request(vec![]);
+        request.get_mut().txs[0].replaces = Some("original".into());
+        let result = service.submit_tx_batch(request).await;
+        assert!(result.unwrap_err().code() == Code::FailedPrecondition);
+
+        let original = storage.get("original").await.unwrap().unwrap();
+        assert!(original.status == TransactionStatus::InFlight);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_record_tx_batch_source() {
+        let (storage, service) = watched_storage();
+
+        let mut request = batch_request(vec![]);
+        request
+            .extensions_mut()
+            .insert(Submitter("0123456789abcdef".into()));
+        service.submit_tx_batch(request).await.unwrap();
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.source.as_deref() == Some("grpc"));
+        assert!(tx.submitter.as_deref() == Some("0123456789abcdef"));
+
+        let tx = service
+            .get_transaction(Request::new(GetTransactionRequest { id: TX_HASH.into() }))
+            .await
+            .unwrap()
+            .into_inner()
+            .tx
+            .unwrap();
+        assert!(tx.source.as_deref() == Some("grpc"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_should_reject_tx_batch_when_ingest_is_full() {
+        let (ingest, mut submitted) = buffer(1);
+        ingest
+            .reserve(1)
+            .await
+            .unwrap()
+            .remove(0)
+            .send("hex1".into());
+        let (storage, service) = watched_storage();
+        let service = service.with_ingest(ingest);
+
+        let result = service.submit_tx_batch(batch_request(vec![])).await;
+        assert!(result.unwrap_err().code() == Code::ResourceExhausted);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+
+        submitted.recv().await.unwrap();
+        let result = service.submit_tx_batch(batch_request(vec![])).await;
+        assert!(result.is_ok());
+        assert!(submitted.recv().await.unwrap() == TX_HASH);
+    }
+
+    #[tokio::test]
+    async fn it_should_return_submitted_transactions() {
+        let (_, service) = watched_storage();
+
+        let result = service.submit_tx_batch(batch_request(vec![])).await;
+        assert!(result.is_ok());
+
+        let response = result.unwrap().into_inner();
+        let hash = compute_tx_hash(&hex::decode(TX_CBOR).unwrap()).unwrap();
+        assert!(response.txs.len() == 1);
+        assert!(response.txs[0].id == hash);
+        assert!(response.txs[0].status() == proto::TransactionStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn it_should_infer_tx_batch_dependencies() {
+        let (storage, service) = watched_storage();
+        // the fixture spends outputs of this pending tx
+        let spent_id = "2000fbfaa10c6b316fe4b23c60b42313fc2ad89b51c7397f82a4a5ca97bd62a9";
+        storage
+            .create(&vec![Transaction {
+                id: spent_id.into(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service.submit_tx_batch(batch_request(vec![])).await;
+        assert!(result.is_ok());
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.dependencies == Some(vec![spent_id.into()]));
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_tx_batch_namespace() {
+        let (storage, service) = watched_storage();
+
+        let mut request = batch_request(vec![]);
+        request.get_mut().namespace = "dapp".into();
+
+        let result = service.submit_tx_batch(request).await;
+        assert!(result.is_ok());
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.namespace == "dapp");
+    }
+
+    #[tokio::test]
+    async fn it_should_resubmit_tx_batch() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                id: TX_HASH.into(),
+                raw: hex::decode(TX_CBOR).unwrap(),
+                status: TransactionStatus::InFlight,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service.submit_tx_batch(batch_request(vec![])).await;
+        assert!(result.is_ok());
+        let response = result.unwrap().into_inner();
+        assert!(response.ids == vec![TX_HASH.to_string()]);
+        // the current state of the stored one is returned
+        assert!(response.txs[0].status() == proto::TransactionStatus::InFlight);
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::InFlight);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_resubmit_tx_batch_conflicting() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                id: TX_HASH.into(),
+                raw: b"other witnesses".to_vec(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service.submit_tx_batch(batch_request(vec![])).await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::AlreadyExists);
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_tx_batch_hex() {
+        let (storage, service) = watched_storage();
+
+        let request = Request::new(SubmitTxBatchRequest {
+            txs: vec![BatchTx {
+                input: Some(TxInput::Hex(TX_CBOR.into())),
+                dependencies: vec![],
+                expires_at: None,
+                metadata: None,
+                replaces: None,
+            }],
+            dry_run: false,
+            namespace: String::new(),
+        });
+
+        let response = service.submit_tx_batch(request).await.unwrap();
+        assert!(response.into_inner().ids == vec![TX_HASH.to_string()]);
+        assert!(storage.get(TX_HASH).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_should_dry_run_tx_batch() {
+        let (storage, service) = watched_storage();
+
+        let mut request = batch_request(vec![]);
+        request.get_mut().dry_run = true;
+
+        let response = service.submit_tx_batch(request).await.unwrap();
+        assert!(response.into_inner().ids == vec![TX_HASH.to_string()]);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_dry_run_invalid_tx() {
+        let (storage, service) = watched_storage();
+
+        let mut request = batch_request(vec![]);
+        request.get_mut().dry_run = true;
+        request.get_mut().txs.push(BatchTx {
+            input: Some(TxInput::Raw(vec![0x82, 0xa0])),
+            dependencies: vec![],
+            expires_at: None,
+            metadata: None,
+            replaces: None,
+        });
+
+        let result = service.submit_tx_batch(request).await;
+        let status = result.unwrap_err();
+        assert!(status.code() == Code::InvalidArgument);
+        assert!(status.message().contains("invalid tx at index 1"));
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn it_should_decode_hex_tx_input() {
+        let raw = decode_tx_input(&TxInput::Hex(TX_CBOR.into())).unwrap();
+        assert!(raw == hex::decode(TX_CBOR).unwrap());
+
+        let raw = decode_tx_input(&TxInput::Raw(vec![0x82, 0xa0])).unwrap();
+        assert!(raw == vec![0x82, 0xa0]);
+    }
+
+    #[test]
+    fn it_should_fail_decode_odd_length_hex() {
+        let result = decode_tx_input(&TxInput::Hex(TX_CBOR[1..].into()));
+        assert!(format!("{:#}", result.unwrap_err()).contains("invalid hex"));
+    }
+
+    #[test]
+    fn it_should_fail_decode_non_hex() {
+        let result = decode_tx_input(&TxInput::Hex("not a tx".into()));
+        assert!(format!("{:#}", result.unwrap_err()).contains("invalid hex"));
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_submit_tx_batch_invalid_tx() {
+        let (storage, service) = watched_storage();
+
+        let mut request = batch_request(vec![]);
+        request.get_mut().txs.push(BatchTx {
+            input: Some(TxInput::Raw(vec![0x82, 0xa0])),
+            dependencies: vec![],
+            expires_at: None,
+            metadata: None,
+            replaces: None,
+        });
+
+        let result = service.submit_tx_batch(request).await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::InvalidArgument);
+
+        // the valid transaction of the batch is not stored either
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_submit_tx_batch_cycle() {
+        let (storage, service) = watched_storage();
+
+        let result = service
+            .submit_tx_batch(batch_request(vec![TX_HASH.into()]))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::InvalidArgument);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_submit_tx_batch_unknown_dependency() {
+        let (storage, service) = watched_storage();
+
+        let result = service
+            .submit_tx_batch(batch_request(vec!["unknown".into()]))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::InvalidArgument);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_cancel_transaction() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Validated,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .cancel_transaction(Request::new(CancelTransactionRequest { id: "hex".into() }))
+            .await;
+        assert!(result.is_ok());
+
+        let tx = result.unwrap().into_inner().tx.unwrap();
+        assert!(tx.status() == proto::TransactionStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_cancel_in_flight_transaction() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                slot: Some(100),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .cancel_transaction(Request::new(CancelTransactionRequest { id: "hex".into() }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::FailedPrecondition);
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::InFlight);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_cancel_unknown_transaction() {
+        let (_, service) = watched_storage();
+
+        let result = service
+            .cancel_transaction(Request::new(CancelTransactionRequest {
+                id: "unknown".into(),
+            }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn it_should_resubmit() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                slot: Some(100),
+                next_retry_at: Some(Utc::now() + Duration::minutes(10)),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .resubmit(Request::new(ResubmitRequest { id: "hex".into() }))
+            .await;
+        assert!(result.is_ok());
+
+        let tx = result.unwrap().into_inner().tx.unwrap();
+        assert!(tx.status() == proto::TransactionStatus::Validated);
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.next_retry_at.is_none());
+        assert!(tx.slot.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_wake_fanout_on_resubmit() {
+        let (storage, service) = watched_storage();
+        let fanout = Arc::new(Notify::new());
+        let service = service.with_fanout(fanout.clone());
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Validated,
+                next_retry_at: Some(Utc::now() + Duration::minutes(10)),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        service
+            .resubmit(Request::new(ResubmitRequest { id: "hex".into() }))
+            .await
+            .unwrap();
+
+        let woken =
+            tokio::time::timeout(std::time::Duration::from_secs(1), fanout.notified()).await;
+        assert!(woken.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_resubmit_terminal() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Confirmed,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .resubmit(Request::new(ResubmitRequest { id: "hex".into() }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn it_should_requeue_transaction() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::DeadLetter,
+                attempts: 10,
+                error: Some("peer rejected".into()),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .requeue_transaction(Request::new(RequeueTransactionRequest { id: "hex".into() }))
+            .await;
+        assert!(result.is_ok());
+
+        let tx = result.unwrap().into_inner().tx.unwrap();
+        assert!(tx.status() == proto::TransactionStatus::Pending);
+        assert!(tx.attempts == 0);
+        assert!(tx.error.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_requeue_confirmed_transaction() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Confirmed,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .requeue_transaction(Request::new(RequeueTransactionRequest { id: "hex".into() }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::FailedPrecondition);
+
+        let tx = storage.get("hex").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Confirmed);
+    }
+
+    #[tokio::test]
+    async fn it_should_requeue_transactions() {
+        let (storage, service) = watched_storage();
+        let transactions: Vec<Transaction> = [
+            ("hex1", TransactionStatus::Failed),
+            ("hex2", TransactionStatus::Failed),
+            ("hex3", TransactionStatus::Confirmed),
+        ]
+        .into_iter()
+        .map(|(id, status)| Transaction {
+            id: id.into(),
+            status,
+            ..Default::default()
+        })
+        .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let result = service
+            .requeue_transactions(Request::new(RequeueTransactionsRequest {
+                status: proto::TransactionStatus::Failed.into(),
+            }))
+            .await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().into_inner().count == 2);
+
+        let txs = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(txs.len() == 2);
+
+        let result = service
+            .requeue_transactions(Request::new(RequeueTransactionsRequest {
+                status: proto::TransactionStatus::Confirmed.into(),
+            }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn it_should_update_priority() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "older".into(),
+                    created_at: Utc::now() - Duration::minutes(1),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "newer".into(),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let result = service
+            .update_priority(Request::new(UpdatePriorityRequest {
+                id: "newer".into(),
+                priority: proto::TransactionPriority::High.into(),
+            }))
+            .await;
+        assert!(result.is_ok());
+
+        let tx = result.unwrap().into_inner().tx.unwrap();
+        assert!(tx.priority() == proto::TransactionPriority::High);
+
+        let next = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(next.unwrap().id == "newer");
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_update_priority_confirmed() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Confirmed,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .update_priority(Request::new(UpdatePriorityRequest {
+                id: "hex".into(),
+                priority: proto::TransactionPriority::High.into(),
+            }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_update_priority_unspecified() {
+        let (storage, service) = watched_storage();
+        storage.create(&vec![Transaction::default()]).await.unwrap();
+
+        let result = service
+            .update_priority(Request::new(UpdatePriorityRequest {
+                id: "hex".into(),
+                priority: proto::TransactionPriority::Unspecified.into(),
+            }))
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().code() == Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn it_should_get_stats() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    created_at: Utc::now() - Duration::minutes(10),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    created_at: Utc::now() - Duration::minutes(1),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex3".into(),
+                    status: TransactionStatus::Confirmed,
+                    created_at: Utc::now() - Duration::minutes(30),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let result = service.stats(Request::new(StatsRequest {})).await;
+        assert!(result.is_ok());
+
+        let stats = result.unwrap().into_inner();
+        assert!(
+            stats.counts
+                == vec![
+                    StatusCount {
+                        status: proto::TransactionStatus::Pending.into(),
+                        count: 2,
+                    },
+                    StatusCount {
+                        status: proto::TransactionStatus::Confirmed.into(),
+                        count: 1,
+                    },
+                ]
+        );
+        assert!(stats.oldest_pending_age_secs.is_some_and(|age| age >= 600));
+    }
+
+    #[tokio::test]
+    async fn it_should_get_stats_without_pending() {
+        let (_, service) = watched_storage();
+
+        let stats = service
+            .stats(Request::new(StatsRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(stats.counts.is_empty());
+        assert!(stats.oldest_pending_age_secs.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_peek_transaction() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                id: "hex1".into(),
+                status: TransactionStatus::Validated,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service
+            .peek_transaction(Request::new(PeekTransactionRequest::default()))
+            .await;
+        assert!(result
+            .unwrap()
+            .into_inner()
+            .tx
+            .is_some_and(|tx| tx.id == "hex1"));
+
+        let tx = storage.get("hex1").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Validated);
+
+        let result = service
+            .peek_transaction(Request::new(PeekTransactionRequest {
+                status: proto::TransactionStatus::Pending.into(),
+                ..Default::default()
+            }))
+            .await;
+        assert!(result.unwrap().into_inner().tx.is_none());
+
+        let result = service
+            .peek_transaction(Request::new(PeekTransactionRequest {
+                namespace: "dapp".into(),
+                ..Default::default()
+            }))
+            .await;
+        assert!(result.unwrap().into_inner().tx.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_export_transactions() {
+        let (storage, service) = watched_storage();
+        let now = Utc::now();
+        let txs: Vec<Transaction> = (0..5)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                created_at: now + Duration::seconds(i),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&txs).await.unwrap();
+
+        let result = service
+            .export_transactions(Request::new(ExportTransactionsRequest { page_size: 2 }))
+            .await;
+        assert!(result.is_ok());
+        let mut stream = result.unwrap().into_inner();
+
+        let mut ids = vec![];
+        while let Some(message) = stream.next().await {
+            ids.push(message.unwrap().tx.unwrap().id);
+        }
+
+        assert!(ids == vec!["hex0", "hex1", "hex2", "hex3", "hex4"]);
+    }
+
+    #[tokio::test]
+    async fn it_should_reset() {
+        let (storage, service) = watched_storage();
+        let service = service.with_admin_reset(true);
+        storage
+            .create(&vec![Transaction {
+                id: "hex1".into(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service.reset(Request::new(ResetRequest {})).await;
+        assert!(result.is_ok());
+        assert!(storage.get("hex1").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_reset_when_disabled() {
+        let (storage, service) = watched_storage();
+        storage
+            .create(&vec![Transaction {
+                id: "hex1".into(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = service.reset(Request::new(ResetRequest {})).await;
+        assert!(result.unwrap_err().code() == Code::PermissionDenied);
+        assert!(storage.get("hex1").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_should_list_transactions() {
+        let (storage, service) = watched_storage();
+        let txs: Vec<Transaction> = [
+            ("hex1", TransactionStatus::Failed, 3),
+            ("hex2", TransactionStatus::Failed, 2),
+            ("hex3", TransactionStatus::Failed, 1),
+            ("hex4", TransactionStatus::Confirmed, 0),
+        ]
+        .into_iter()
+        .map(|(id, status, minutes)| Transaction {
+            id: id.into(),
+            status,
+            created_at: Utc::now() - Duration::minutes(minutes),
+            ..Default::default()
+        })
+        .collect();
+        storage.create(&txs).await.unwrap();
+
+        let result = service
+            .list_transactions(Request::new(ListTransactionsRequest {
+                status: proto::TransactionStatus::Failed.into(),
+                limit: 2,
+                offset: 1,
+            }))
+            .await;
+        assert!(result.is_ok());
+
+        let ids: Vec<String> = result
+            .unwrap()
+            .into_inner()
+            .txs
+            .into_iter()
+            .map(|tx| tx.id)
+            .collect();
+        assert!(ids == vec!["hex2", "hex1"]);
+
+        let result = service
+            .list_transactions(Request::new(ListTransactionsRequest::default()))
+            .await;
+        assert!(result.unwrap().into_inner().txs.len() == 4);
+    }
+}
@@ -0,0 +1,128 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde_json::{json, Value};
+use tokio::sync::watch;
+use tracing::error;
+
+use crate::storage::TransactionStore;
+
+use super::{serve, Config};
+
+#[derive(Clone)]
+struct HealthState {
+    tx_storage: Arc<dyn TransactionStore>,
+    healthy_peers: watch::Receiver<Vec<String>>,
+}
+
+/// Serves the liveness (`/health`) and readiness (`/ready`) probes.
+pub async fn run(
+    config: Config,
+    tx_storage: Arc<dyn TransactionStore>,
+    healthy_peers: watch::Receiver<Vec<String>>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let Some(port) = config.health_port else {
+        return Ok(());
+    };
+    let address = SocketAddr::new(config.listen_address.ip(), port);
+
+    serve(
+        "Health",
+        address,
+        router(tx_storage, healthy_peers),
+        shutdown,
+    )
+    .await
+}
+
+pub fn router(
+    tx_storage: Arc<dyn TransactionStore>,
+    healthy_peers: watch::Receiver<Vec<String>>,
+) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/ready", get(ready))
+        .with_state(HealthState {
+            tx_storage,
+            healthy_peers,
+        })
+}
+
+async fn health() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Ready when the storage answers and at least one peer is connected.
+async fn ready(State(state): State<HealthState>) -> (StatusCode, Json<Value>) {
+    let mut failed = vec![];
+
+    if let Err(error) = state.tx_storage.ping().await {
+        error!(?error, "storage is not reachable");
+        failed.push("storage");
+    }
+    if state.healthy_peers.borrow().is_empty() {
+        failed.push("peers");
+    }
+
+    if failed.is_empty() {
+        return (StatusCode::OK, Json(json!({ "status": "ready" })));
+    }
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "status": "not ready", "failed": failed })),
+    )
+}
+
+#[cfg(test)]
+mod health_tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+        Router,
+    };
+    use tokio::sync::watch;
+    use tower::ServiceExt;
+
+    use crate::storage::mock::MockTransactionStore;
+
+    use super::router;
+
+    fn health_router(peers: Vec<String>) -> Router {
+        let (_, healthy_peers) = watch::channel(peers);
+        router(Arc::new(MockTransactionStore::default()), healthy_peers)
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_should_be_healthy() {
+        let response = health_router(vec![]).oneshot(get("/health")).await.unwrap();
+        assert!(response.status() == StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_should_be_ready() {
+        let response = health_router(vec!["Node:3001".into()])
+            .oneshot(get("/ready"))
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn it_should_not_be_ready_without_peers() {
+        let response = health_router(vec![]).oneshot(get("/ready")).await.unwrap();
+        assert!(response.status() == StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["failed"] == serde_json::json!(["peers"]));
+    }
+}
@@ -0,0 +1,571 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, Request, State},
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::watch;
+use tracing::{error, info};
+
+use crate::{
+    pipeline::ingest::IngestSender,
+    storage::{StorageError, Transaction, TransactionStore},
+    validation::{compute_tx_hash, validate_metadata, validate_tx_cbor},
+};
+
+use super::{
+    auth::{ApiKeyInterceptor, Submitter},
+    infer_dependencies, serve, Config,
+};
+
+#[derive(Clone)]
+struct HttpState {
+    tx_storage: Arc<dyn TransactionStore>,
+    ingest: Option<IngestSender>,
+}
+
+pub async fn run(
+    config: Config,
+    tx_storage: Arc<dyn TransactionStore>,
+    ingest: IngestSender,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let Some(port) = config.http_port else {
+        return Ok(());
+    };
+    let address = SocketAddr::new(config.listen_address.ip(), port);
+    let router = router(tx_storage, Some(ingest), config.api_keys);
+
+    serve("HTTP", address, router, shutdown).await
+}
+
+/// Takes a slot of the `ingest` buffer for every submitted transaction when
+/// set, answering `429` when it stays full. The requests are authenticated
+/// with the same `api_keys` as the gRPC calls.
+pub fn router(
+    tx_storage: Arc<dyn TransactionStore>,
+    ingest: Option<IngestSender>,
+    api_keys: Vec<String>,
+) -> Router {
+    Router::new()
+        .route("/tx", post(submit_tx))
+        .route("/tx/:id", get(get_tx))
+        .layer(middleware::from_fn_with_state(
+            ApiKeyInterceptor::new(api_keys),
+            require_api_key,
+        ))
+        .with_state(HttpState { tx_storage, ingest })
+}
+
+/// Reads the key from the `x-api-key` header or an `authorization: Bearer`
+/// one, answering `401` when it isn't one of the API keys.
+async fn require_api_key(
+    State(auth): State<ApiKeyInterceptor>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, HttpError> {
+    let headers = request.headers();
+    let provided = headers
+        .get("x-api-key")
+        .map(|value| value.as_bytes())
+        .or_else(|| {
+            headers
+                .get(AUTHORIZATION)
+                .and_then(|value| value.as_bytes().strip_prefix(b"Bearer "))
+        });
+
+    let submitter = auth
+        .authenticate(provided)
+        .map_err(|status| HttpError(StatusCode::UNAUTHORIZED, status.message().to_string()))?;
+    if let Some(submitter) = submitter {
+        request.extensions_mut().insert(submitter);
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[derive(Serialize)]
+struct TransactionView {
+    id: String,
+    status: String,
+    attempts: u32,
+    error: Option<String>,
+    slot: Option<u64>,
+    namespace: String,
+    expires_at: Option<i64>,
+    metadata: Option<String>,
+    source: Option<String>,
+    submitter: Option<String>,
+    replaces: Option<String>,
+}
+
+impl From<Transaction> for TransactionView {
+    fn from(value: Transaction) -> Self {
+        Self {
+            id: value.id,
+            status: value.status.to_string(),
+            attempts: value.attempts,
+            error: value.error,
+            slot: value.slot,
+            namespace: value.namespace,
+            expires_at: value.expires_at.map(|at| at.timestamp()),
+            metadata: value.metadata,
+            source: value.source,
+            submitter: value.submitter,
+            replaces: value.replaces,
+        }
+    }
+}
+
+struct HttpError(StatusCode, String);
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn internal_error(error: impl Into<anyhow::Error>) -> HttpError {
+    let error = error.into();
+    error!(?error);
+    HttpError(StatusCode::INTERNAL_SERVER_ERROR, "internal error".into())
+}
+
+#[derive(Deserialize)]
+struct SubmitParams {
+    /// Validates the transaction and computes its id without storing it.
+    #[serde(default)]
+    dry_run: bool,
+    /// Queue of the transaction, the default one when not set.
+    namespace: Option<String>,
+    /// Unix time in seconds after which the transaction is failed instead of
+    /// submitted.
+    expires_at: Option<i64>,
+    /// JSON returned as it is in the status queries.
+    metadata: Option<String>,
+    /// Id of a pending transaction this one replaces, it's cancelled once
+    /// this one is stored.
+    replaces: Option<String>,
+}
+
+/// The body is the raw CBOR, either as binary or hex encoded text.
+async fn submit_tx(
+    State(state): State<HttpState>,
+    Query(params): Query<SubmitParams>,
+    submitter: Option<Extension<Submitter>>,
+    body: Bytes,
+) -> Result<impl IntoResponse, HttpError> {
+    let raw = std::str::from_utf8(&body)
+        .ok()
+        .and_then(|text| hex::decode(text.trim()).ok())
+        .unwrap_or_else(|| body.to_vec());
+
+    let id = validate_tx_cbor(&raw)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| compute_tx_hash(&raw))
+        .map_err(|error| HttpError(StatusCode::BAD_REQUEST, format!("invalid tx: {error}")))?;
+
+    if params.dry_run {
+        info!(%id, "validated tx, dry run");
+        return Ok((StatusCode::OK, Json(json!({ "id": id }))));
+    }
+
+    let mut tx = Transaction::new(id.clone(), raw);
+    tx.source = Some("http".into());
+    tx.submitter = submitter.map(|Extension(submitter)| submitter.0);
+    if let Some(namespace) = params.namespace {
+        tx.namespace = namespace;
+    }
+    if let Some(expires_at) = params.expires_at {
+        tx.expires_at = Some(
+            DateTime::from_timestamp(expires_at, 0)
+                .ok_or_else(|| HttpError(StatusCode::BAD_REQUEST, "invalid expires_at".into()))?,
+        );
+    }
+
+    if let Some(metadata) = params.metadata {
+        validate_metadata(&metadata).map_err(|error| {
+            HttpError(
+                StatusCode::BAD_REQUEST,
+                format!("invalid metadata: {error}"),
+            )
+        })?;
+        tx.metadata = Some(metadata);
+    }
+    tx.replaces = params.replaces;
+
+    infer_dependencies(state.tx_storage.as_ref(), std::slice::from_mut(&mut tx))
+        .await
+        .map_err(internal_error)?;
+
+    let permit = match &state.ingest {
+        Some(ingest) => ingest
+            .reserve(1)
+            .await
+            .map_err(|error| HttpError(StatusCode::TOO_MANY_REQUESTS, error.to_string()))?
+            .pop(),
+        None => None,
+    };
+
+    info!(%id, namespace = %tx.namespace, "submitting tx");
+    // a resubmitted tx answers the status of the stored one
+    let tx = state
+        .tx_storage
+        .create_or_get(&vec![tx])
+        .await
+        .map_err(|error| match error {
+            StorageError::Conflict(_) => HttpError(StatusCode::CONFLICT, error.to_string()),
+            StorageError::NotReplaceable(_) => {
+                HttpError(StatusCode::PRECONDITION_FAILED, error.to_string())
+            }
+            StorageError::Cycle(_) => HttpError(StatusCode::BAD_REQUEST, error.to_string()),
+            error => internal_error(error),
+        })?
+        .remove(0);
+
+    if let Some(permit) = permit {
+        permit.send(id.clone());
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({ "id": id, "status": tx.status.to_string() })),
+    ))
+}
+
+async fn get_tx(
+    State(state): State<HttpState>,
+    Path(id): Path<String>,
+) -> Result<Json<TransactionView>, HttpError> {
+    let tx = state
+        .tx_storage
+        .get(&id)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| HttpError(StatusCode::NOT_FOUND, format!("transaction {id} not found")))?;
+
+    Ok(Json(tx.into()))
+}
+
+#[cfg(test)]
+mod http_tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    use crate::{
+        fixtures::{TX_CBOR, TX_HASH},
+        pipeline::ingest::buffer,
+        server::auth::key_id,
+        storage::{mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore},
+    };
+
+    use super::router;
+
+    fn submit_request(body: impl Into<Body>) -> Request<Body> {
+        Request::post("/tx").body(body.into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_hex_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(submit_request(TX_CBOR))
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::ACCEPTED);
+
+        let txs = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(txs.len() == 1);
+        assert!(txs[0].id == TX_HASH);
+        assert!(txs[0].source.as_deref() == Some("http"));
+        assert!(txs[0].submitter.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn it_should_reject_tx_when_ingest_is_full() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let (ingest, _submitted) = buffer(1);
+        ingest
+            .reserve(1)
+            .await
+            .unwrap()
+            .remove(0)
+            .send("hex1".into());
+
+        let response = router(storage.clone(), Some(ingest), vec![])
+            .oneshot(submit_request(TX_CBOR))
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::TOO_MANY_REQUESTS);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_binary_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(submit_request(hex::decode(TX_CBOR).unwrap()))
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::ACCEPTED);
+        assert!(storage.get(TX_HASH).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_should_resubmit_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                id: TX_HASH.into(),
+                raw: hex::decode(TX_CBOR).unwrap(),
+                status: TransactionStatus::InFlight,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(submit_request(TX_CBOR))
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::ACCEPTED);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["id"] == TX_HASH);
+        assert!(body["status"] == "inflight");
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_resubmit_conflicting_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                id: TX_HASH.into(),
+                raw: b"other witnesses".to_vec(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(submit_request(TX_CBOR))
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_replacement_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                id: "hex1".into(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(
+                Request::post("/tx?replaces=hex1")
+                    .body(Body::from(TX_CBOR))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::ACCEPTED);
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.replaces.as_deref() == Some("hex1"));
+        let tx = storage.get("hex1").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Cancelled);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_replacing_submitted_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                id: "hex1".into(),
+                status: TransactionStatus::InFlight,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(
+                Request::post("/tx?replaces=hex1")
+                    .body(Body::from(TX_CBOR))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::PRECONDITION_FAILED);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_submit_invalid_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(submit_request("deadbeef"))
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn it_should_dry_run_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(
+                Request::post("/tx?dry_run=true")
+                    .body(Body::from(TX_CBOR))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["id"] == TX_HASH);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_dry_run_invalid_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+
+        let response = router(storage.clone(), None, vec![])
+            .oneshot(
+                Request::post("/tx?dry_run=true")
+                    .body(Body::from("deadbeef"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::BAD_REQUEST);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["error"].as_str().unwrap().starts_with("invalid tx"));
+        assert!(storage
+            .find(TransactionStatus::Pending)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_get_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        router(storage.clone(), None, vec![])
+            .oneshot(submit_request(TX_CBOR))
+            .await
+            .unwrap();
+
+        let response = router(storage, None, vec![])
+            .oneshot(
+                Request::get(format!("/tx/{TX_HASH}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["id"] == TX_HASH);
+        assert!(body["status"] == "pending");
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_get_unknown_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+
+        let response = router(storage, None, vec![])
+            .oneshot(Request::get("/tx/unknown").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn it_should_reject_request_without_api_key() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let router = router(storage.clone(), None, vec!["key1".into()]);
+
+        let response = router
+            .clone()
+            .oneshot(submit_request(TX_CBOR))
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::UNAUTHORIZED);
+
+        let response = router
+            .oneshot(
+                Request::get(format!("/tx/{TX_HASH}"))
+                    .header("x-api-key", "key2")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::UNAUTHORIZED);
+        assert!(storage.get(TX_HASH).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_accept_request_with_api_key() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let router = router(storage.clone(), None, vec!["key1".into()]);
+
+        let response = router
+            .clone()
+            .oneshot(
+                Request::post("/tx")
+                    .header("x-api-key", "key1")
+                    .body(Body::from(TX_CBOR))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::ACCEPTED);
+
+        let tx = storage.get(TX_HASH).await.unwrap().unwrap();
+        assert!(tx.submitter == Some(key_id(b"key1")));
+
+        let response = router
+            .oneshot(
+                Request::get(format!("/tx/{TX_HASH}"))
+                    .header("authorization", "Bearer key1")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(response.status() == StatusCode::OK);
+    }
+}
@@ -1,49 +1,580 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use async_stream::stream;
 use pallas::interop::utxorpc::spec as u5c;
 use serde::Deserialize;
-use tonic::transport::Server;
+use tokio::net::UnixListener;
+use tonic::{
+    transport::{Identity, Server, ServerTlsConfig},
+    Status,
+};
 use tracing::{error, info};
 
-use crate::storage::sqlite::SqliteTransaction;
+use tokio::sync::{broadcast, mpsc, watch, Notify};
 
+use crate::{
+    pipeline::ingest::IngestSender,
+    shutdown,
+    storage::{StorageError, Transaction, TransactionStore},
+    validation::spent_tx_ids,
+};
+
+mod auth;
+mod boros;
+pub mod health;
+pub mod http;
 mod utxorpc;
 
-pub async fn run(config: Config, tx_storage: Arc<SqliteTransaction>) -> Result<()> {
-    tokio::spawn(async move {
-        let reflection = tonic_reflection::server::Builder::configure()
-            .register_encoded_file_descriptor_set(u5c::submit::FILE_DESCRIPTOR_SET)
-            .register_encoded_file_descriptor_set(u5c::cardano::FILE_DESCRIPTOR_SET)
-            .register_encoded_file_descriptor_set(protoc_wkt::google::protobuf::FILE_DESCRIPTOR_SET)
-            .build_v1alpha()
-            .unwrap();
+pub async fn run(
+    config: Config,
+    tx_storage: Arc<dyn TransactionStore>,
+    tx_events: broadcast::Sender<Transaction>,
+    ingest: IngestSender,
+    fanout: Arc<Notify>,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let tls = match &config.tls {
+        Some(tls) => tls.load()?,
+        None => None,
+    };
+
+    let mut server = Server::builder();
+    if let Some(tls) = tls {
+        server = server.tls_config(tls)?;
+    }
 
-        let submit_service = utxorpc::SubmitServiceImpl::new(tx_storage);
-        let submit_service =
-            u5c::submit::submit_service_server::SubmitServiceServer::new(submit_service);
+    // bound before serving so a bad path fails the startup
+    let uds = match &config.uds_path {
+        Some(path) => Some(bind_uds(path)?),
+        None => None,
+    };
 
-        info!(
-            address = config.listen_address.to_string(),
-            "GRPC server running"
+    let reflection = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(u5c::submit::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(u5c::cardano::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(boros::proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(protoc_wkt::google::protobuf::FILE_DESCRIPTOR_SET)
+        .build_v1alpha()?;
+
+    let auth = auth::ApiKeyInterceptor::new(config.api_keys.clone());
+
+    let transaction_service = boros::TransactionServiceImpl::new(tx_storage.clone(), tx_events)
+        .with_ingest(ingest.clone())
+        .with_fanout(fanout)
+        .with_admin_reset(config.allow_admin_reset);
+    let transaction_service =
+        boros::proto::transaction_service_server::TransactionServiceServer::with_interceptor(
+            transaction_service,
+            auth.clone(),
         );
 
-        let result = Server::builder()
-            .add_service(reflection)
-            .add_service(submit_service)
-            .serve(config.listen_address)
-            .await;
+    let submit_service = utxorpc::SubmitServiceImpl::new(tx_storage).with_ingest(ingest);
+    let submit_service = u5c::submit::submit_service_server::SubmitServiceServer::with_interceptor(
+        submit_service,
+        auth,
+    );
+
+    let uds_server = async {
+        let Some(listener) = uds else {
+            return Ok(());
+        };
+        let incoming = stream! {
+            loop {
+                yield listener.accept().await.map(|(stream, _)| stream);
+            }
+        };
+
+        Server::builder()
+            .add_service(reflection.clone())
+            .add_service(submit_service.clone())
+            .add_service(transaction_service.clone())
+            .serve_with_incoming_shutdown(incoming, shutdown::requested(shutdown.clone()))
+            .await?;
+
+        Ok::<_, anyhow::Error>(())
+    };
+
+    info!(
+        address = config.listen_address.to_string(),
+        uds_path = config
+            .uds_path
+            .as_ref()
+            .map(|path| path.display().to_string()),
+        tls = config.tls.is_some(),
+        auth = !config.api_keys.is_empty(),
+        "GRPC server running"
+    );
+
+    let tcp_server = async {
+        server
+            .add_service(reflection.clone())
+            .add_service(submit_service.clone())
+            .add_service(transaction_service.clone())
+            .serve_with_shutdown(config.listen_address, shutdown::requested(shutdown.clone()))
+            .await?;
+
+        Ok::<_, anyhow::Error>(())
+    };
+
+    tokio::try_join!(uds_server, tcp_server)?;
+
+    Ok(())
+}
+
+/// Serves `router` on `address` until the shutdown is requested. An error
+/// serving it is returned, so boros stops like when any other component fails.
+pub async fn serve(
+    name: &str,
+    address: SocketAddr,
+    router: axum::Router,
+    shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    info!(address = address.to_string(), "{name} server running");
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown::requested(shutdown))
+        .await?;
+
+    Ok(())
+}
+
+/// Slots of the ingest buffer for `count` transactions, the submission is
+/// rejected with `RESOURCE_EXHAUSTED` when the buffer stays full.
+async fn reserve_ingest(
+    ingest: &Option<IngestSender>,
+    count: usize,
+) -> Result<Vec<mpsc::Permit<'_, String>>, Status> {
+    match ingest {
+        Some(ingest) => ingest
+            .reserve(count)
+            .await
+            .map_err(|error| Status::resource_exhausted(error.to_string())),
+        None => Ok(vec![]),
+    }
+}
+
+/// Adds to the dependencies of each transaction the ones it spends an output
+/// of, when they're in the batch or stored and not terminal yet, so a chain is
+/// submitted in order without the client declaring it. The declared
+/// dependencies are kept.
+async fn infer_dependencies(storage: &dyn TransactionStore, txs: &mut [Transaction]) -> Result<()> {
+    let batch: HashSet<String> = txs.iter().map(|tx| tx.id.clone()).collect();
 
-        if let Err(error) = result {
-            error!(?error);
-            std::process::exit(1);
+    let mut spent_ids = Vec::with_capacity(txs.len());
+    for tx in txs.iter() {
+        spent_ids.push(spent_tx_ids(&tx.raw)?);
+    }
+
+    // the spent transactions out of the batch are looked up at once
+    let stored_ids: Vec<String> = spent_ids
+        .iter()
+        .flatten()
+        .filter(|id| !batch.contains(*id))
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let pending: HashSet<String> = storage
+        .get_many(&stored_ids)
+        .await?
+        .into_iter()
+        .filter(|stored| !stored.status.is_terminal())
+        .map(|stored| stored.id)
+        .chain(batch)
+        .collect();
+
+    for (tx, spent_ids) in txs.iter_mut().zip(spent_ids) {
+        let mut dependencies = tx.dependencies.take().unwrap_or_default();
+
+        for spent_id in spent_ids {
+            if !dependencies.contains(&spent_id) && pending.contains(&spent_id) {
+                dependencies.push(spent_id);
+            }
         }
-    });
+
+        if !dependencies.is_empty() {
+            tx.dependencies = Some(dependencies);
+        }
+    }
 
     Ok(())
 }
 
+/// The failures of the store a client can act on keep their message, the
+/// database and unexpected ones are logged and answered as `INTERNAL`.
+impl From<StorageError> for Status {
+    fn from(error: StorageError) -> Self {
+        match error {
+            StorageError::NotFound(_) => Status::not_found(error.to_string()),
+            StorageError::Conflict(_) => Status::already_exists(error.to_string()),
+            StorageError::Cycle(_) => Status::invalid_argument(error.to_string()),
+            StorageError::NotReplaceable(_) | StorageError::InvalidStatus(_) => {
+                Status::failed_precondition(error.to_string())
+            }
+            StorageError::Db(_) | StorageError::Other(_) => {
+                error!(?error);
+                Status::internal("internal error")
+            }
+        }
+    }
+}
+
+/// Binds the unix socket, removing the one left behind by a previous run.
+fn bind_uds(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        std::fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+
+    UnixListener::bind(path).with_context(|| format!("failed to bind socket {}", path.display()))
+}
+
 #[derive(Deserialize, Clone)]
 pub struct Config {
     pub listen_address: SocketAddr,
+    /// Also serves the gRPC API on this unix socket when set, in plaintext as
+    /// the access is controlled by the permissions of the file.
+    pub uds_path: Option<PathBuf>,
+    /// Starts the REST gateway on this port when set.
+    pub http_port: Option<u16>,
+    /// Serves the `/health` and `/ready` probes on this port when set.
+    pub health_port: Option<u16>,
+    /// Serves the Prometheus `/metrics` endpoint on this port when set.
+    pub metrics_port: Option<u16>,
+    /// Serves the gRPC API over TLS when set, plaintext otherwise.
+    pub tls: Option<TlsConfig>,
+    /// Requires one of these keys in the `authorization` metadata of the gRPC
+    /// calls, no auth when empty.
+    #[serde(default)]
+    pub api_keys: Vec<String>,
+    /// Enables the `Reset` RPC deleting every transaction, for development
+    /// only.
+    #[serde(default)]
+    pub allow_admin_reset: bool,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain.
+    pub cert_path: Option<PathBuf>,
+    /// PEM encoded private key of the certificate.
+    pub key_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    fn load(&self) -> Result<Option<ServerTlsConfig>> {
+        let (cert_path, key_path) = match (&self.cert_path, &self.key_path) {
+            (Some(cert_path), Some(key_path)) => (cert_path, key_path),
+            (None, None) => return Ok(None),
+            _ => bail!("server.tls requires both cert_path and key_path"),
+        };
+
+        let cert = std::fs::read(cert_path)
+            .with_context(|| format!("failed to read tls cert {}", cert_path.display()))?;
+        let key = std::fs::read(key_path)
+            .with_context(|| format!("failed to read tls key {}", key_path.display()))?;
+
+        Ok(Some(
+            ServerTlsConfig::new().identity(Identity::from_pem(cert, key)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod server_tests {
+    use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+
+    use hyper_util::rt::TokioIo;
+    use tokio::{
+        net::{TcpStream, UnixStream},
+        sync::{broadcast, watch, Notify},
+    };
+    use tonic::{
+        transport::{Certificate, ClientTlsConfig, Endpoint},
+        Code, Request, Status,
+    };
+    use tower::service_fn;
+
+    use crate::{
+        fixtures::{TX_CBOR, TX_HASH},
+        pipeline::ingest::buffer,
+        storage::{
+            mock::MockTransactionStore, StorageError, Transaction, TransactionStatus,
+            TransactionStore,
+        },
+    };
+
+    use super::{
+        boros::proto::{
+            batch_tx::Input, transaction_service_client::TransactionServiceClient, BatchTx,
+            GetTransactionRequest, SubmitTxBatchRequest,
+        },
+        infer_dependencies, run, Config, TlsConfig,
+    };
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tls")
+            .join(name)
+    }
+
+    fn config(tls: Option<TlsConfig>) -> Config {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let listen_address = listener.local_addr().unwrap();
+
+        Config {
+            listen_address,
+            uds_path: None,
+            http_port: None,
+            health_port: None,
+            metrics_port: None,
+            tls,
+            api_keys: vec![],
+            allow_admin_reset: false,
+        }
+    }
+
+    fn tls_config() -> TlsConfig {
+        TlsConfig {
+            cert_path: Some(fixture("server.pem")),
+            key_path: Some(fixture("server.key")),
+        }
+    }
+
+    async fn serve(config: Config) -> (SocketAddr, watch::Sender<bool>) {
+        let address = config.listen_address;
+        let (tx_events, _) = broadcast::channel(16);
+        let (ingest, _) = buffer(16);
+        let (shutdown, shutdown_rx) = watch::channel(false);
+
+        tokio::spawn(run(
+            config,
+            Arc::new(MockTransactionStore::default()),
+            tx_events,
+            ingest,
+            Arc::new(Notify::new()),
+            shutdown_rx,
+        ));
+
+        for _ in 0..50 {
+            if TcpStream::connect(address).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        (address, shutdown)
+    }
+
+    fn get_request() -> Request<GetTransactionRequest> {
+        Request::new(GetTransactionRequest { id: "hex1".into() })
+    }
+
+    #[tokio::test]
+    async fn it_should_serve_tls() {
+        let (address, _shutdown) = serve(config(Some(tls_config()))).await;
+
+        let ca = std::fs::read(fixture("ca.pem")).unwrap();
+        let channel = Endpoint::from_shared(format!("https://{address}"))
+            .unwrap()
+            .tls_config(
+                ClientTlsConfig::new()
+                    .ca_certificate(Certificate::from_pem(ca))
+                    .domain_name("localhost"),
+            )
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+
+        let result = TransactionServiceClient::new(channel)
+            .get_transaction(get_request())
+            .await;
+
+        // reaching the service is enough, the transaction doesn't exist
+        assert!(result.unwrap_err().code() == Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn it_should_reject_plaintext_client_on_tls() {
+        let (address, _shutdown) = serve(config(Some(tls_config()))).await;
+
+        // the handshake fails either while connecting or on the first call, a
+        // NotFound would mean the request reached the service in plaintext
+        let rejected = match Endpoint::from_shared(format!("http://{address}"))
+            .unwrap()
+            .connect()
+            .await
+        {
+            Ok(channel) => TransactionServiceClient::new(channel)
+                .get_transaction(get_request())
+                .await
+                .is_err_and(|status| status.code() != Code::NotFound),
+            Err(_) => true,
+        };
+
+        assert!(rejected);
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_over_uds() {
+        let path = std::env::temp_dir().join(format!("boros-{}.sock", std::process::id()));
+        let mut config = config(None);
+        config.uds_path = Some(path.clone());
+
+        let storage = Arc::new(MockTransactionStore::default());
+        let (tx_events, _) = broadcast::channel(16);
+        let (ingest, _submitted) = buffer(16);
+        let (_shutdown, shutdown_rx) = watch::channel(false);
+        tokio::spawn(run(
+            config,
+            storage.clone(),
+            tx_events,
+            ingest,
+            Arc::new(Notify::new()),
+            shutdown_rx,
+        ));
+
+        for _ in 0..50 {
+            if UnixStream::connect(&path).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        // the uri is ignored, every connection goes through the socket
+        let channel =
+            Endpoint::from_static("http://localhost")
+                .connect_with_connector(service_fn(move |_| {
+                    let path = path.clone();
+                    async move {
+                        Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(path).await?))
+                    }
+                }))
+                .await
+                .unwrap();
+
+        let result = TransactionServiceClient::new(channel)
+            .submit_tx_batch(Request::new(SubmitTxBatchRequest {
+                txs: vec![BatchTx {
+                    input: Some(Input::Raw(hex::decode(TX_CBOR).unwrap())),
+                    dependencies: vec![],
+                    expires_at: None,
+                    metadata: None,
+                    replaces: None,
+                }],
+                dry_run: false,
+                namespace: String::new(),
+            }))
+            .await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().into_inner().ids == vec![TX_HASH.to_string()]);
+
+        assert!(storage.get(TX_HASH).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_tls_without_key() {
+        let config = config(Some(TlsConfig {
+            cert_path: Some(fixture("server.pem")),
+            key_path: None,
+        }));
+        let (tx_events, _) = broadcast::channel(16);
+        let (ingest, _) = buffer(16);
+        let (_, shutdown) = watch::channel(false);
+
+        let result = run(
+            config,
+            Arc::new(MockTransactionStore::default()),
+            tx_events,
+            ingest,
+            Arc::new(Notify::new()),
+            shutdown,
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn it_should_map_storage_errors_to_status() {
+        let status = Status::from(StorageError::NotFound("hex1".into()));
+        assert!(status.code() == Code::NotFound);
+        assert!(status.message() == "transaction hex1 not found");
+
+        let status = Status::from(StorageError::Conflict("hex1".into()));
+        assert!(status.code() == Code::AlreadyExists);
+
+        let status = Status::from(StorageError::Cycle(vec!["hex1".into(), "hex2".into()]));
+        assert!(status.code() == Code::InvalidArgument);
+        assert!(status.message() == "dependency cycle detected: hex1 -> hex2");
+
+        let status = Status::from(StorageError::NotReplaceable("hex1".into()));
+        assert!(status.code() == Code::FailedPrecondition);
+
+        let status = Status::from(StorageError::InvalidStatus(
+            "transaction hex1 is already confirmed".into(),
+        ));
+        assert!(status.code() == Code::FailedPrecondition);
+        assert!(status.message() == "transaction hex1 is already confirmed");
+
+        let status = Status::from(StorageError::Db(sqlx::Error::PoolTimedOut));
+        assert!(status.code() == Code::Internal);
+        assert!(status.message() == "internal error");
+
+        let status = Status::from(StorageError::Other(anyhow::anyhow!("connection reset")));
+        assert!(status.code() == Code::Internal);
+        assert!(status.message() == "internal error");
+    }
+
+    const SPENT_ID: &str = "2000fbfaa10c6b316fe4b23c60b42313fc2ad89b51c7397f82a4a5ca97bd62a9";
+
+    #[tokio::test]
+    async fn it_should_infer_dependencies() {
+        let storage = MockTransactionStore::default();
+        storage
+            .create(&vec![Transaction {
+                id: SPENT_ID.into(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let mut txs = vec![Transaction {
+            raw: hex::decode(TX_CBOR).unwrap(),
+            dependencies: Some(vec!["declared".into()]),
+            ..Default::default()
+        }];
+        infer_dependencies(&storage, &mut txs).await.unwrap();
+
+        assert!(txs[0].dependencies == Some(vec!["declared".into(), SPENT_ID.into()]));
+    }
+
+    #[tokio::test]
+    async fn it_should_not_infer_confirmed_dependencies() {
+        let storage = MockTransactionStore::default();
+        storage
+            .create(&vec![Transaction {
+                id: SPENT_ID.into(),
+                status: TransactionStatus::Confirmed,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let mut txs = vec![Transaction {
+            raw: hex::decode(TX_CBOR).unwrap(),
+            ..Default::default()
+        }];
+        infer_dependencies(&storage, &mut txs).await.unwrap();
+
+        assert!(txs[0].dependencies.is_none());
+    }
 }
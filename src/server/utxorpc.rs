@@ -8,15 +8,31 @@ use pallas::{
 use tonic::{Request, Response, Status};
 use tracing::{error, info};
 
-use crate::storage::{sqlite::SqliteTransaction, Transaction};
+use crate::{
+    pipeline::ingest::IngestSender,
+    storage::{Transaction, TransactionStore},
+    validation::validate_tx_cbor,
+};
+
+use super::{auth, infer_dependencies, reserve_ingest};
 
 pub struct SubmitServiceImpl {
-    tx_storage: Arc<SqliteTransaction>,
+    tx_storage: Arc<dyn TransactionStore>,
+    ingest: Option<IngestSender>,
 }
 
 impl SubmitServiceImpl {
-    pub fn new(tx_storage: Arc<SqliteTransaction>) -> Self {
-        Self { tx_storage }
+    pub fn new(tx_storage: Arc<dyn TransactionStore>) -> Self {
+        Self {
+            tx_storage,
+            ingest: None,
+        }
+    }
+
+    /// Takes a slot of the ingest buffer for every submitted transaction.
+    pub fn with_ingest(mut self, ingest: IngestSender) -> Self {
+        self.ingest = Some(ingest);
+        self
     }
 }
 
@@ -32,6 +48,7 @@ impl submit_service_server::SubmitService for SubmitServiceImpl {
         &self,
         request: Request<SubmitTxRequest>,
     ) -> Result<Response<SubmitTxResponse>, Status> {
+        let submitter = auth::submitter(&request);
         let message = request.into_inner();
 
         // TODO: validate a better structure to have this code.
@@ -42,6 +59,11 @@ impl submit_service_server::SubmitService for SubmitServiceImpl {
         for (idx, tx_bytes) in message.tx.into_iter().flat_map(|x| x.r#type).enumerate() {
             match tx_bytes {
                 any_chain_tx::Type::Raw(bytes) => {
+                    validate_tx_cbor(&bytes).map_err(|error| {
+                        error!(?error);
+                        Status::failed_precondition(format!("invalid tx at index {idx}: {error}"))
+                    })?;
+
                     let tx = MultiEraTx::decode(&bytes).map_err(|error| {
                         error!(?error);
                         Status::failed_precondition(format!("invalid tx at index {idx}"))
@@ -49,18 +71,32 @@ impl submit_service_server::SubmitService for SubmitServiceImpl {
                     let hash = tx.hash();
 
                     hashes.push(hash.to_vec().into());
-                    txs.push(Transaction::new(hash.to_string(), bytes.to_vec()))
+                    let mut tx = Transaction::new(hash.to_string(), bytes.to_vec());
+                    tx.source = Some("grpc".into());
+                    tx.submitter = submitter.clone();
+                    txs.push(tx)
                 }
             }
         }
 
+        infer_dependencies(self.tx_storage.as_ref(), &mut txs)
+            .await
+            .map_err(|error| {
+                error!(?error);
+                Status::internal("internal error")
+            })?;
+
         let hashes_str: Vec<String> = hashes.iter().map(hex::encode).collect();
         info!(?hashes_str, "submitting txs");
 
-        self.tx_storage.create(&txs).await.map_err(|error| {
-            error!(?error);
-            Status::internal("internal error")
-        })?;
+        let permits = reserve_ingest(&self.ingest, txs.len()).await?;
+
+        // the transactions already stored are kept as they are
+        self.tx_storage.create_or_get(&txs).await?;
+
+        for (permit, id) in permits.into_iter().zip(hashes_str) {
+            permit.send(id);
+        }
 
         Ok(Response::new(SubmitTxResponse { r#ref: hashes }))
     }
@@ -93,3 +129,74 @@ impl submit_service_server::SubmitService for SubmitServiceImpl {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod submit_service_tests {
+    use std::sync::Arc;
+
+    use pallas::interop::utxorpc::spec::submit::{
+        any_chain_tx, submit_service_server::SubmitService, AnyChainTx, SubmitTxRequest,
+    };
+    use tonic::{Code, Request};
+
+    use crate::{
+        fixtures::{TX_CBOR, TX_HASH},
+        storage::{mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore},
+    };
+
+    use super::SubmitServiceImpl;
+
+    fn submit_request(raw: Vec<u8>) -> Request<SubmitTxRequest> {
+        Request::new(SubmitTxRequest {
+            tx: vec![AnyChainTx {
+                r#type: Some(any_chain_tx::Type::Raw(raw.into())),
+            }],
+        })
+    }
+
+    #[tokio::test]
+    async fn it_should_submit_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let service = SubmitServiceImpl::new(storage.clone());
+
+        let result = service
+            .submit_tx(submit_request(hex::decode(TX_CBOR).unwrap()))
+            .await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().into_inner().r#ref.len() == 1);
+
+        let txs = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(txs.len() == 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_submit_invalid_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        let service = SubmitServiceImpl::new(storage.clone());
+
+        let result = service.submit_tx(submit_request(vec![0x82, 0xa0])).await;
+        assert!(result.is_err());
+
+        let txs = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(txs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_submit_conflicting_tx() {
+        let storage = Arc::new(MockTransactionStore::default());
+        storage
+            .create(&vec![Transaction {
+                id: TX_HASH.into(),
+                raw: b"other witnesses".to_vec(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+        let service = SubmitServiceImpl::new(storage.clone());
+
+        let result = service
+            .submit_tx(submit_request(hex::decode(TX_CBOR).unwrap()))
+            .await;
+        assert!(result.unwrap_err().code() == Code::AlreadyExists);
+    }
+}
@@ -0,0 +1,41 @@
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::{error, info};
+
+/// Listens for ctrl-c and SIGTERM, the returned channel flips to `true` on the
+/// first of them so every component can stop.
+pub fn listen() -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
+
+    tokio::spawn(async move {
+        let terminate = async {
+            match signal(SignalKind::terminate()) {
+                Ok(mut terminate) => {
+                    terminate.recv().await;
+                }
+                Err(error) => {
+                    error!(?error, "failed to listen for SIGTERM");
+                    std::future::pending::<()>().await;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => info!("received ctrl-c, shutting down"),
+            _ = terminate => info!("received SIGTERM, shutting down"),
+        }
+
+        sender.send_replace(true);
+        // keeps the channel open, a closed one reads as a shutdown too
+        sender.closed().await;
+    });
+
+    receiver
+}
+
+/// Resolves once the shutdown was requested.
+pub async fn requested(mut shutdown: watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|requested| *requested).await;
+}
@@ -0,0 +1 @@
+ALTER TABLE tx ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
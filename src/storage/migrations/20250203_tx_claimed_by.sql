@@ -0,0 +1 @@
+ALTER TABLE tx ADD COLUMN claimed_by TEXT;
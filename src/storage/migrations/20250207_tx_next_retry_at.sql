@@ -0,0 +1 @@
+ALTER TABLE tx ADD COLUMN next_retry_at DATETIME;
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS tx_submission (
+  tx_id TEXT NOT NULL,
+  peer TEXT NOT NULL,
+  submitted_at DATETIME NOT NULL,
+  accepted BOOLEAN NOT NULL,
+  FOREIGN KEY (tx_id) REFERENCES tx(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_tx_submission_tx_id ON tx_submission (tx_id);
@@ -0,0 +1,3 @@
+ALTER TABLE tx ADD COLUMN namespace TEXT NOT NULL DEFAULT 'default';
+
+CREATE INDEX IF NOT EXISTS idx_tx_namespace_status ON tx (namespace, status);
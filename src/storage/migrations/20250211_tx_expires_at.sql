@@ -0,0 +1 @@
+ALTER TABLE tx ADD COLUMN expires_at DATETIME;
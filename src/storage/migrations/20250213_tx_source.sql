@@ -0,0 +1,2 @@
+ALTER TABLE tx ADD COLUMN source TEXT;
+ALTER TABLE tx ADD COLUMN submitter TEXT;
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_tx_status_slot ON tx (status, slot);
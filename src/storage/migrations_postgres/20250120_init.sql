@@ -0,0 +1,23 @@
+CREATE TABLE IF NOT EXISTS tx (
+  id TEXT PRIMARY KEY,
+  raw BYTEA NOT NULL,
+  status TEXT NOT NULL,
+  slot BIGINT,
+  priority INTEGER NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS tx_dependence (
+  dependent_id TEXT NOT NULL,
+  required_id TEXT NOT NULL,
+  PRIMARY KEY (dependent_id, required_id),
+  FOREIGN KEY (dependent_id) REFERENCES tx(id),
+  FOREIGN KEY (required_id) REFERENCES tx(id)
+);
+
+CREATE TABLE IF NOT EXISTS cursor (
+  id INTEGER PRIMARY KEY CHECK (id = 0),
+  slot BIGINT NOT NULL,
+  hash BYTEA NOT NULL
+);
@@ -0,0 +1,3 @@
+CREATE INDEX IF NOT EXISTS idx_tx_status_priority_created_at ON tx (status, priority, created_at);
+
+CREATE INDEX IF NOT EXISTS idx_tx_dependence_required_id ON tx_dependence (required_id);
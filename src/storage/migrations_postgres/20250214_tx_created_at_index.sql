@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_tx_created_at_id ON tx (created_at, id);
@@ -0,0 +1,787 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use super::{
+    StorageError, Submission, Transaction, TransactionPriority, TransactionStatus, TransactionStore,
+};
+
+/// In-memory implementation of the transaction store to be used in the tests
+/// that don't depend on the database behaviour.
+#[derive(Default)]
+pub struct MockTransactionStore {
+    pub txs: Mutex<Vec<Transaction>>,
+    pub submissions: Mutex<Vec<Submission>>,
+}
+
+impl MockTransactionStore {
+    fn sorted(&self, status: TransactionStatus) -> Vec<Transaction> {
+        let mut txs: Vec<Transaction> = self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tx| tx.status == status)
+            .cloned()
+            .collect();
+
+        txs.sort_by_key(|tx| (u32::from(tx.priority.clone()), tx.created_at));
+        txs
+    }
+}
+
+/// Blocks the transactions that require `parent_id`, directly or through
+/// another dependent, as the databases do when it fails or is cancelled.
+fn block_dependents(txs: &mut [Transaction], parent_id: &str, status: &TransactionStatus) {
+    let mut queue = vec![parent_id.to_string()];
+    let mut visited = HashSet::new();
+
+    while let Some(id) = queue.pop() {
+        for tx in txs.iter_mut() {
+            let requires = tx
+                .dependencies
+                .iter()
+                .flatten()
+                .any(|required| *required == id);
+            if !requires || !visited.insert(tx.id.clone()) {
+                continue;
+            }
+
+            queue.push(tx.id.clone());
+            if matches!(
+                tx.status,
+                TransactionStatus::Pending | TransactionStatus::Validated
+            ) {
+                tx.status = TransactionStatus::BlockedByDependency;
+                tx.error = Some(format!("dependency {parent_id} is {status}"));
+                tx.updated_at = Utc::now();
+            }
+        }
+    }
+}
+
+/// Moves the dependents blocked on `parent_id` back to `Pending` once none of
+/// the transactions they require blocks them anymore, as the databases do.
+fn unblock_dependents(txs: &mut [Transaction], parent_id: &str) {
+    let mut dependents = HashSet::new();
+    let mut queue = vec![parent_id.to_string()];
+    while let Some(id) = queue.pop() {
+        for tx in txs.iter() {
+            let requires = tx
+                .dependencies
+                .iter()
+                .flatten()
+                .any(|required| *required == id);
+            if requires && dependents.insert(tx.id.clone()) {
+                queue.push(tx.id.clone());
+            }
+        }
+    }
+
+    loop {
+        let blocking: HashSet<String> = txs
+            .iter()
+            .filter(|tx| {
+                matches!(
+                    tx.status,
+                    TransactionStatus::BlockedByDependency
+                        | TransactionStatus::Failed
+                        | TransactionStatus::DeadLetter
+                        | TransactionStatus::Cancelled
+                )
+            })
+            .map(|tx| tx.id.clone())
+            .collect();
+
+        let mut unblocked = false;
+        for tx in txs.iter_mut() {
+            if dependents.contains(&tx.id)
+                && tx.status == TransactionStatus::BlockedByDependency
+                && !tx
+                    .dependencies
+                    .iter()
+                    .flatten()
+                    .any(|required| blocking.contains(required))
+            {
+                tx.status = TransactionStatus::Pending;
+                tx.error = None;
+                tx.updated_at = Utc::now();
+                unblocked = true;
+            }
+        }
+        if !unblocked {
+            return;
+        }
+    }
+}
+
+fn requeue(tx: &mut Transaction) {
+    tx.status = TransactionStatus::Pending;
+    tx.slot = None;
+    tx.attempts = 0;
+    tx.error = None;
+    tx.claimed_by = None;
+    tx.claimed_at = None;
+    tx.next_retry_at = None;
+    tx.updated_at = Utc::now();
+}
+
+/// The error of a change that matched no transaction, as the databases answer
+/// it.
+fn unchanged(txs: &[Transaction], id: &str) -> StorageError {
+    match txs.iter().find(|tx| tx.id == id) {
+        Some(tx) => StorageError::InvalidStatus(format!("transaction {id} is {}", tx.status)),
+        None => StorageError::NotFound(id.to_string()),
+    }
+}
+
+#[async_trait::async_trait]
+impl TransactionStore for MockTransactionStore {
+    async fn create(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        let mut state = self.txs.lock().unwrap();
+
+        if let Some(tx) = txs
+            .iter()
+            .find(|tx| state.iter().any(|stored| stored.id == tx.id))
+        {
+            return Err(StorageError::Conflict(tx.id.clone()));
+        }
+
+        let replaced: Vec<usize> = txs
+            .iter()
+            .filter_map(|tx| tx.replaces.as_ref())
+            .map(|replaced_id| {
+                state
+                    .iter()
+                    .position(|stored| {
+                        &stored.id == replaced_id
+                            && matches!(
+                                stored.status,
+                                TransactionStatus::Pending | TransactionStatus::Validated
+                            )
+                    })
+                    .ok_or_else(|| StorageError::NotReplaceable(replaced_id.clone()))
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        for idx in replaced {
+            state[idx].status = TransactionStatus::Cancelled;
+            state[idx].updated_at = Utc::now();
+        }
+        for tx in txs {
+            let Some(replaced_id) = &tx.replaces else {
+                continue;
+            };
+            for stored in state.iter_mut() {
+                let Some(dependencies) = stored.dependencies.as_mut() else {
+                    continue;
+                };
+                let Some(position) = dependencies.iter().position(|id| id == replaced_id) else {
+                    continue;
+                };
+                if stored.id == tx.id || dependencies.contains(&tx.id) {
+                    dependencies.remove(position);
+                } else {
+                    dependencies[position] = tx.id.clone();
+                }
+            }
+        }
+
+        state.extend(txs.iter().cloned());
+        Ok(())
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Transaction>, StorageError> {
+        Ok(self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|tx| tx.id == id)
+            .cloned())
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Transaction>, StorageError> {
+        Ok(self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tx| ids.contains(&tx.id))
+            .cloned()
+            .collect())
+    }
+
+    async fn find(&self, status: TransactionStatus) -> Result<Vec<Transaction>, StorageError> {
+        Ok(self.sorted(status))
+    }
+
+    async fn find_to_rollback(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        Ok(self
+            .sorted(TransactionStatus::Confirmed)
+            .into_iter()
+            .filter(|tx| tx.slot.is_some_and(|s| s > slot))
+            .collect())
+    }
+
+    async fn find_in_flight_before(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        Ok(self
+            .sorted(TransactionStatus::InFlight)
+            .into_iter()
+            .filter(|tx| tx.slot.is_some_and(|s| s < slot))
+            .collect())
+    }
+
+    async fn next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        Ok(self
+            .next_batch(status, namespace, 1)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn next_batch(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        if status.is_terminal() {
+            return Ok(Vec::new());
+        }
+
+        let now = Utc::now();
+        Ok(self
+            .sorted(status)
+            .into_iter()
+            .filter(|tx| tx.next_retry_at.is_none_or(|at| at <= now))
+            .filter(|tx| namespace.is_none_or(|namespace| tx.namespace == namespace))
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn next_ready(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        let confirmed = self.sorted(TransactionStatus::Confirmed);
+
+        Ok(self
+            .next_batch(status, namespace, i64::MAX)
+            .await?
+            .into_iter()
+            .find(|tx| {
+                tx.dependencies
+                    .iter()
+                    .flatten()
+                    .all(|id| confirmed.iter().any(|c| &c.id == id))
+            }))
+    }
+
+    async fn claim_next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        worker_id: &str,
+    ) -> Result<Option<Transaction>, StorageError> {
+        let confirmed = self.sorted(TransactionStatus::Confirmed);
+        let ready = self
+            .next_batch(status, namespace, i64::MAX)
+            .await?
+            .into_iter()
+            .find(|tx| {
+                tx.dependencies
+                    .iter()
+                    .flatten()
+                    .all(|id| confirmed.iter().any(|c| &c.id == id))
+            });
+        let Some(mut transaction) = ready else {
+            return Ok(None);
+        };
+
+        transaction.status = TransactionStatus::InFlight;
+        transaction.claimed_by = Some(worker_id.to_string());
+        transaction.claimed_at = Some(Utc::now());
+
+        let mut state = self.txs.lock().unwrap();
+        if let Some(stored) = state.iter_mut().find(|stored| stored.id == transaction.id) {
+            *stored = transaction.clone();
+        }
+
+        Ok(Some(transaction))
+    }
+
+    async fn reclaim_expired(&self, older_than: Duration) -> Result<u64, StorageError> {
+        let claimed_before = Utc::now()
+            - chrono::Duration::from_std(older_than)
+                .map_err(|error| StorageError::Other(error.into()))?;
+        let mut reclaimed = 0;
+
+        for tx in self.txs.lock().unwrap().iter_mut() {
+            if tx.status == TransactionStatus::InFlight
+                && tx.slot.is_none()
+                && tx.claimed_at.is_some_and(|at| at < claimed_before)
+            {
+                tx.status = TransactionStatus::Pending;
+                tx.claimed_by = None;
+                tx.claimed_at = None;
+                reclaimed += 1;
+            }
+        }
+
+        Ok(reclaimed)
+    }
+
+    async fn release_claims(&self, worker_id: &str) -> Result<u64, StorageError> {
+        let mut released = 0;
+
+        for tx in self.txs.lock().unwrap().iter_mut() {
+            if tx.status == TransactionStatus::InFlight
+                && tx.slot.is_none()
+                && tx.claimed_by.as_deref() == Some(worker_id)
+            {
+                tx.status = TransactionStatus::Pending;
+                tx.claimed_by = None;
+                tx.claimed_at = None;
+                released += 1;
+            }
+        }
+
+        Ok(released)
+    }
+
+    async fn expire_past_ttl(&self, current_slot: u64) -> Result<u64, StorageError> {
+        let mut state = self.txs.lock().unwrap();
+        let mut expired = Vec::new();
+
+        for tx in state.iter_mut() {
+            if !tx.status.is_terminal() && tx.ttl.is_some_and(|ttl| ttl < current_slot) {
+                tx.status = TransactionStatus::Failed;
+                tx.error = Some("ttl expired".into());
+                expired.push(tx.id.clone());
+            }
+        }
+        for id in &expired {
+            block_dependents(&mut state, id, &TransactionStatus::Failed);
+        }
+
+        Ok(expired.len() as u64)
+    }
+
+    async fn expire_past_deadline(&self, now: DateTime<Utc>) -> Result<u64, StorageError> {
+        let mut state = self.txs.lock().unwrap();
+        let mut expired = Vec::new();
+
+        for tx in state.iter_mut() {
+            if matches!(
+                tx.status,
+                TransactionStatus::Pending | TransactionStatus::Validated
+            ) && tx.expires_at.is_some_and(|at| at < now)
+            {
+                tx.status = TransactionStatus::Failed;
+                tx.error = Some("expired before being submitted".into());
+                expired.push(tx.id.clone());
+            }
+        }
+        for id in &expired {
+            block_dependents(&mut state, id, &TransactionStatus::Failed);
+        }
+
+        Ok(expired.len() as u64)
+    }
+
+    async fn rollback_to(&self, slot: u64) -> Result<u64, StorageError> {
+        let mut reverted = 0;
+
+        for tx in self.txs.lock().unwrap().iter_mut() {
+            if tx.status == TransactionStatus::Confirmed
+                && tx.confirmed_slot.is_some_and(|confirmed| confirmed > slot)
+            {
+                tx.status = TransactionStatus::Pending;
+                tx.slot = None;
+                tx.confirmed_slot = None;
+                tx.claimed_by = None;
+                tx.claimed_at = None;
+                reverted += 1;
+            }
+        }
+
+        Ok(reverted)
+    }
+
+    async fn list(
+        &self,
+        status: Option<TransactionStatus>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let mut txs: Vec<Transaction> = self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tx| status.as_ref().is_none_or(|s| &tx.status == s))
+            .filter(|tx| created_after.is_none_or(|at| tx.created_at >= at))
+            .filter(|tx| created_before.is_none_or(|at| tx.created_at <= at))
+            .cloned()
+            .collect();
+
+        txs.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+
+        Ok(txs
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+
+    async fn list_after(
+        &self,
+        after_created_at: Option<DateTime<Utc>>,
+        after_id: &str,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let mut txs: Vec<Transaction> = self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tx| {
+                after_created_at.is_none_or(|at| (tx.created_at, tx.id.as_str()) > (at, after_id))
+            })
+            .cloned()
+            .collect();
+
+        txs.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+
+        Ok(txs.into_iter().take(limit as usize).collect())
+    }
+
+    async fn update(&self, tx: &Transaction) -> Result<(), StorageError> {
+        let mut state = self.txs.lock().unwrap();
+
+        if let Some(stored) = state.iter_mut().find(|stored| stored.id == tx.id) {
+            stored.raw = tx.raw.clone();
+            stored.status = tx.status.clone();
+            stored.slot = tx.slot;
+            stored.error = tx.error.clone();
+            stored.claimed_by = tx.claimed_by.clone();
+            stored.claimed_at = tx.claimed_at;
+            stored.ttl = tx.ttl;
+            stored.confirmed_slot = tx.confirmed_slot;
+            stored.next_retry_at = tx.next_retry_at;
+            stored.updated_at = Utc::now();
+        }
+        if tx.status.blocks_dependents() {
+            block_dependents(&mut state, &tx.id, &tx.status);
+        }
+
+        Ok(())
+    }
+
+    async fn update_batch(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        for tx in txs {
+            self.update(tx).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn mark_confirmed_many(&self, ids: &[String], slot: u64) -> Result<u64, StorageError> {
+        let mut confirmed = 0;
+
+        for tx in self.txs.lock().unwrap().iter_mut() {
+            if ids.contains(&tx.id) && tx.status != TransactionStatus::Confirmed {
+                tx.status = TransactionStatus::Confirmed;
+                tx.slot = Some(slot);
+                tx.confirmed_slot = Some(slot);
+                tx.claimed_by = None;
+                tx.claimed_at = None;
+                tx.updated_at = Utc::now();
+                confirmed += 1;
+            }
+        }
+
+        Ok(confirmed)
+    }
+
+    async fn mark_failed(&self, id: &str, reason: &str) -> Result<(), StorageError> {
+        let mut state = self.txs.lock().unwrap();
+
+        if let Some(stored) = state.iter_mut().find(|stored| stored.id == id) {
+            stored.status = TransactionStatus::Failed;
+            stored.error = Some(reason.to_string());
+            stored.updated_at = Utc::now();
+        }
+        block_dependents(&mut state, id, &TransactionStatus::Failed);
+
+        Ok(())
+    }
+
+    async fn requeue(&self, id: &str) -> Result<(), StorageError> {
+        let mut state = self.txs.lock().unwrap();
+
+        let Some(stored) = state
+            .iter_mut()
+            .find(|stored| stored.id == id && stored.status.is_requeueable())
+        else {
+            return Err(unchanged(&state, id));
+        };
+        requeue(stored);
+        unblock_dependents(&mut state, id);
+
+        Ok(())
+    }
+
+    async fn set_priority(
+        &self,
+        id: &str,
+        priority: TransactionPriority,
+    ) -> Result<(), StorageError> {
+        let mut state = self.txs.lock().unwrap();
+
+        let Some(stored) = state
+            .iter_mut()
+            .find(|stored| stored.id == id && !stored.status.is_terminal())
+        else {
+            return Err(unchanged(&state, id));
+        };
+        stored.priority = priority;
+        stored.updated_at = Utc::now();
+
+        Ok(())
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool, StorageError> {
+        let mut state = self.txs.lock().unwrap();
+
+        let Some(stored) = state.iter_mut().find(|stored| {
+            stored.id == id
+                && matches!(
+                    stored.status,
+                    TransactionStatus::Pending | TransactionStatus::Validated
+                )
+        }) else {
+            return Ok(false);
+        };
+        stored.status = TransactionStatus::Cancelled;
+        stored.updated_at = Utc::now();
+        block_dependents(&mut state, id, &TransactionStatus::Cancelled);
+
+        Ok(true)
+    }
+
+    async fn resubmit(&self, id: &str) -> Result<bool, StorageError> {
+        let mut state = self.txs.lock().unwrap();
+
+        let Some(stored) = state
+            .iter_mut()
+            .find(|stored| stored.id == id && !stored.status.is_terminal())
+        else {
+            return Ok(false);
+        };
+        if stored.status == TransactionStatus::InFlight && stored.slot.is_some() {
+            stored.status = TransactionStatus::Validated;
+            stored.slot = None;
+            stored.claimed_by = None;
+            stored.claimed_at = None;
+        }
+        stored.next_retry_at = None;
+        stored.updated_at = Utc::now();
+
+        Ok(true)
+    }
+
+    async fn requeue_all(&self, from: TransactionStatus) -> Result<u64, StorageError> {
+        if !from.is_requeueable() {
+            return Err(StorageError::InvalidStatus(format!(
+                "{from} transactions can't be requeued"
+            )));
+        }
+
+        let mut state = self.txs.lock().unwrap();
+        let mut requeued = Vec::new();
+        for tx in state.iter_mut() {
+            if tx.status == from {
+                requeue(tx);
+                requeued.push(tx.id.clone());
+            }
+        }
+        for id in &requeued {
+            unblock_dependents(&mut state, id);
+        }
+
+        Ok(requeued.len() as u64)
+    }
+
+    async fn increment_attempts(&self, id: &str) -> Result<u32, StorageError> {
+        let mut state = self.txs.lock().unwrap();
+
+        let stored = state
+            .iter_mut()
+            .find(|stored| stored.id == id)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))?;
+        stored.attempts += 1;
+
+        Ok(stored.attempts)
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), StorageError> {
+        self.delete_many(&[id.to_string()]).await
+    }
+
+    async fn delete_many(&self, ids: &[String]) -> Result<(), StorageError> {
+        self.txs
+            .lock()
+            .unwrap()
+            .retain(|tx| !ids.iter().any(|id| id == &tx.id));
+        self.submissions
+            .lock()
+            .unwrap()
+            .retain(|submission| !ids.contains(&submission.tx_id));
+
+        Ok(())
+    }
+
+    async fn truncate_all(&self) -> Result<(), StorageError> {
+        self.txs.lock().unwrap().clear();
+        self.submissions.lock().unwrap().clear();
+
+        Ok(())
+    }
+
+    async fn compact_confirmed_dependencies(&self, slot: u64) -> Result<u64, StorageError> {
+        let mut state = self.txs.lock().unwrap();
+        let is_stable = |tx: &Transaction| {
+            tx.status == TransactionStatus::Confirmed
+                && tx.confirmed_slot.is_some_and(|confirmed| confirmed < slot)
+        };
+        let confirmed: Vec<String> = state
+            .iter()
+            .filter(|tx| is_stable(tx))
+            .map(|tx| tx.id.clone())
+            .collect();
+
+        let mut removed = 0;
+        for tx in state.iter_mut() {
+            if !is_stable(tx) {
+                continue;
+            }
+            if let Some(dependencies) = tx.dependencies.as_mut() {
+                let before = dependencies.len();
+                dependencies.retain(|required_id| !confirmed.contains(required_id));
+                removed += (before - dependencies.len()) as u64;
+                if dependencies.is_empty() {
+                    tx.dependencies = None;
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+
+    async fn prune_confirmed_before(&self, slot: u64) -> Result<u64, StorageError> {
+        let mut state = self.txs.lock().unwrap();
+        let pruned: HashSet<String> = state
+            .iter()
+            .filter(|tx| {
+                tx.status == TransactionStatus::Confirmed
+                    && tx.confirmed_slot.is_some_and(|confirmed| confirmed < slot)
+            })
+            .map(|tx| tx.id.clone())
+            .collect();
+
+        state.retain(|tx| !pruned.contains(&tx.id));
+        for tx in state.iter_mut() {
+            if let Some(dependencies) = tx.dependencies.as_mut() {
+                dependencies.retain(|required_id| !pruned.contains(required_id));
+                if dependencies.is_empty() {
+                    tx.dependencies = None;
+                }
+            }
+        }
+        self.submissions
+            .lock()
+            .unwrap()
+            .retain(|submission| !pruned.contains(&submission.tx_id));
+
+        Ok(pruned.len() as u64)
+    }
+
+    async fn dependents_of(&self, id: &str) -> Result<Vec<String>, StorageError> {
+        let state = self.txs.lock().unwrap();
+        let mut ids: Vec<String> = state
+            .iter()
+            .filter(|tx| {
+                tx.dependencies
+                    .iter()
+                    .flatten()
+                    .any(|required_id| required_id == id)
+            })
+            .map(|tx| tx.id.clone())
+            .collect();
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    async fn status_counts(&self) -> Result<HashMap<TransactionStatus, i64>, StorageError> {
+        let mut counts = HashMap::new();
+        for tx in self.txs.lock().unwrap().iter() {
+            *counts.entry(tx.status.clone()).or_default() += 1;
+        }
+        Ok(counts)
+    }
+
+    async fn oldest_queued_at(&self) -> Result<Option<DateTime<Utc>>, StorageError> {
+        Ok(self
+            .txs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|tx| !tx.status.is_terminal())
+            .map(|tx| tx.created_at)
+            .min())
+    }
+
+    async fn record_submission(
+        &self,
+        tx_id: &str,
+        peer: &str,
+        accepted: bool,
+    ) -> Result<(), StorageError> {
+        self.submissions.lock().unwrap().push(Submission {
+            tx_id: tx_id.into(),
+            peer: peer.into(),
+            submitted_at: Utc::now(),
+            accepted,
+        });
+        Ok(())
+    }
+
+    async fn submissions_for(&self, tx_id: &str) -> Result<Vec<Submission>, StorageError> {
+        Ok(self
+            .submissions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|submission| submission.tx_id == tx_id)
+            .cloned()
+            .collect())
+    }
+}
@@ -1,13 +1,410 @@
-use std::{fmt::Display, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
+use anyhow::{anyhow, bail, Result};
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sqlx::migrate::{MigrateError, Migrator};
+use thiserror::Error;
 
+pub mod clock;
+pub mod postgres;
+pub mod retention;
 pub mod sqlite;
+pub mod timed;
+pub mod watch;
+
+#[cfg(test)]
+pub mod mock;
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Sqlite,
+    Postgres,
+}
 
 #[derive(Deserialize, Clone)]
 pub struct Config {
+    #[serde(default)]
+    pub backend: Backend,
+    #[serde(default)]
     pub db_path: String,
+    pub url: Option<String>,
+    #[serde(default = "default_busy_timeout_ms")]
+    pub busy_timeout_ms: u64,
+    #[serde(default = "default_max_connections")]
+    pub max_connections: u32,
+    #[serde(default)]
+    pub min_connections: u32,
+    /// Compacts the database on this interval, disabled when not set.
+    pub maintenance_interval_secs: Option<u64>,
+    /// Logs the queries slower than this, disabled when not set.
+    pub slow_query_ms: Option<u64>,
+    /// Times a sqlite write is retried when the database is busy or locked.
+    #[serde(default = "default_write_retries")]
+    pub write_retries: u32,
+    /// Keeps the confirmed transactions this long after their confirmation,
+    /// in seconds, then deletes them. At least [`ROLLBACK_WINDOW_SLOTS`], a
+    /// rollback could revert them until then. Kept forever when not set.
+    pub confirmed_retention_secs: Option<u64>,
+}
+
+/// Slots a block can still be rolled back within, the `3k/f` of the Cardano
+/// networks, 36 hours.
+pub const ROLLBACK_WINDOW_SLOTS: u64 = 129_600;
+
+fn default_busy_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_max_connections() -> u32 {
+    5
+}
+
+fn default_write_retries() -> u32 {
+    3
+}
+
+/// Connection pool of the configured backend, kept to close it on shutdown.
+pub enum Database {
+    Sqlite(Arc<sqlite::SqliteStorage>),
+    Postgres(Arc<postgres::PostgresStorage>),
+}
+impl Database {
+    pub async fn close(&self) {
+        match self {
+            Self::Sqlite(storage) => storage.close().await,
+            Self::Postgres(storage) => storage.close().await,
+        }
+    }
+}
+
+/// Names the migration a [`MigrateError`] is about instead of the raw sqlx
+/// error. Every migration runs in a transaction, so a failing one leaves
+/// nothing of it behind and can be fixed and run again.
+pub fn migration_error(migrator: &Migrator, error: MigrateError) -> anyhow::Error {
+    let name = |version: i64| {
+        migrator
+            .iter()
+            .find(|migration| migration.version == version)
+            .map(|migration| format!("{version} ({})", migration.description))
+            .unwrap_or_else(|| version.to_string())
+    };
+
+    match error {
+        MigrateError::ExecuteMigration(error, version) => {
+            anyhow!("migration {} failed and was rolled back: {error}", name(version))
+        }
+        MigrateError::Dirty(version) => anyhow!(
+            "migration {} was left partially applied by a previous run, fix the schema and remove it from _sqlx_migrations",
+            name(version)
+        ),
+        MigrateError::VersionMismatch(version) => {
+            anyhow!("migration {} was changed after it was applied", name(version))
+        }
+        MigrateError::VersionMissing(version) => anyhow!(
+            "migration {version} is applied but missing, the database is newer than this boros"
+        ),
+        error => anyhow::Error::new(error).context("failed to run the migrations"),
+    }
+}
+
+#[async_trait::async_trait]
+pub trait TransactionStore: Send + Sync {
+    /// Stores the transactions as a whole. The ones with `replaces` cancel the
+    /// transaction they replace, failing with [`StorageError::NotReplaceable`]
+    /// when it isn't `Pending` or `Validated`, and an id already stored fails
+    /// with [`StorageError::Conflict`].
+    async fn create(&self, txs: &Vec<Transaction>) -> Result<(), StorageError>;
+    /// Creates the transactions not stored yet and returns the stored ones in
+    /// the same order, so submitting the same transaction again is a no-op.
+    /// Fails with [`StorageError::Conflict`] when an id is stored with a
+    /// different raw. The backends override it to check and insert in a
+    /// single database transaction, so concurrent submissions can't race.
+    async fn create_or_get(
+        &self,
+        txs: &Vec<Transaction>,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let mut stored = Vec::with_capacity(txs.len());
+        let mut new = Vec::new();
+
+        for tx in txs {
+            match self.get(&tx.id).await? {
+                Some(existing) if existing.raw != tx.raw => {
+                    return Err(StorageError::Conflict(tx.id.clone()));
+                }
+                Some(existing) => stored.push(existing),
+                None => {
+                    new.push(tx.clone());
+                    stored.push(tx.clone());
+                }
+            }
+        }
+
+        if !new.is_empty() {
+            self.create(&new).await?;
+        }
+        Ok(stored)
+    }
+    async fn get(&self, id: &str) -> Result<Option<Transaction>, StorageError>;
+    /// The stored transactions among `ids`, in no particular order.
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Transaction>, StorageError>;
+    async fn find(&self, status: TransactionStatus) -> Result<Vec<Transaction>, StorageError>;
+    async fn find_to_rollback(&self, slot: u64) -> Result<Vec<Transaction>, StorageError>;
+    /// In flight transactions submitted before `slot`, the ones that should
+    /// have reached the chain by now.
+    async fn find_in_flight_before(&self, slot: u64) -> Result<Vec<Transaction>, StorageError>;
+    /// Next ready transaction in `status`, only of `namespace` when set.
+    async fn next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError>;
+    async fn next_batch(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError>;
+    async fn next_ready(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError>;
+    /// The transaction `claim_next` takes next from `status` and `namespace`,
+    /// without claiming it. Both pick with the same filter and order, so the
+    /// same row is returned unless it changes in between. With namespace
+    /// weights the fanout claims through `NamespaceScheduler`, which picks the
+    /// namespace first, so only the peek of that namespace matches its claim.
+    async fn peek(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        self.next_ready(status, namespace).await
+    }
+    /// Atomically takes the next ready transaction in `status`, of `namespace`
+    /// when given, and moves it to `InFlight` owned by `worker_id`, so
+    /// concurrent workers never get the same row.
+    async fn claim_next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        worker_id: &str,
+    ) -> Result<Option<Transaction>, StorageError>;
+    /// Moves transactions claimed longer than `older_than` ago, and never
+    /// submitted to a peer, back to `Pending`. Returns how many were reclaimed.
+    async fn reclaim_expired(&self, older_than: Duration) -> Result<u64, StorageError>;
+    /// Moves the transactions claimed by `worker_id`, and never submitted to a
+    /// peer, back to `Pending`. Returns how many were released.
+    async fn release_claims(&self, worker_id: &str) -> Result<u64, StorageError>;
+    /// Fails every non-terminal transaction whose ttl is lower than
+    /// `current_slot`. Returns how many were expired.
+    async fn expire_past_ttl(&self, current_slot: u64) -> Result<u64, StorageError>;
+    /// Fails every `Pending` or `Validated` transaction whose `expires_at` is
+    /// before `now`, so it's never submitted. Returns how many were expired.
+    async fn expire_past_deadline(&self, now: DateTime<Utc>) -> Result<u64, StorageError>;
+    /// Moves the transactions confirmed after `slot` back to `Pending`, so they
+    /// are submitted again after the chain rolls back. Returns how many were
+    /// reverted.
+    async fn rollback_to(&self, slot: u64) -> Result<u64, StorageError>;
+    /// Transactions newest first, only of `status` and created within the
+    /// bounds when set. The bounds are inclusive.
+    async fn list(
+        &self,
+        status: Option<TransactionStatus>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Transaction>, StorageError>;
+    /// Transactions oldest first, created after the one at `after_created_at`
+    /// with `after_id`, from the first one when not set. Unlike `offset` it
+    /// doesn't skip the previous rows, so iterating a large table stays cheap
+    /// and stable while transactions are created.
+    async fn list_after(
+        &self,
+        after_created_at: Option<DateTime<Utc>>,
+        after_id: &str,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError>;
+    /// Transactions that exceeded the max attempts, newest first.
+    async fn list_dead_letter(
+        &self,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.list(
+            Some(TransactionStatus::DeadLetter),
+            None,
+            None,
+            limit,
+            offset,
+        )
+        .await
+    }
+    async fn update(&self, tx: &Transaction) -> Result<(), StorageError>;
+    async fn update_batch(&self, txs: &Vec<Transaction>) -> Result<(), StorageError>;
+    /// Moves the transactions of `ids` not confirmed yet to `Confirmed` at
+    /// `slot` in bulk. Returns how many were confirmed.
+    async fn mark_confirmed_many(&self, ids: &[String], slot: u64) -> Result<u64, StorageError>;
+    /// Moves the transaction to `Failed` and the ones that require it to
+    /// `BlockedByDependency`, as [`TransactionStore::update`] does when it
+    /// moves one to `Failed` or `DeadLetter`.
+    async fn mark_failed(&self, id: &str, reason: &str) -> Result<(), StorageError>;
+    /// Moves a failed or dead-lettered transaction back to `Pending` as a new
+    /// one, clearing the error, attempts and backoff. Fails with
+    /// [`StorageError::NotFound`] when it's unknown and
+    /// [`StorageError::InvalidStatus`] when it's in another status.
+    async fn requeue(&self, id: &str) -> Result<(), StorageError>;
+    /// Requeues every transaction in `from`, which has to be `Failed` or
+    /// `DeadLetter`. Returns how many were requeued.
+    async fn requeue_all(&self, from: TransactionStatus) -> Result<u64, StorageError>;
+    /// Changes the priority the transaction is picked with. Fails with
+    /// [`StorageError::NotFound`] when it's unknown and
+    /// [`StorageError::InvalidStatus`] when already terminal.
+    async fn set_priority(
+        &self,
+        id: &str,
+        priority: TransactionPriority,
+    ) -> Result<(), StorageError>;
+    /// Moves a `Pending` or `Validated` transaction to `Cancelled`. Returns
+    /// false when it's unknown or was already handed to the fanout, so a
+    /// submitted transaction is never taken back.
+    async fn cancel(&self, id: &str) -> Result<bool, StorageError>;
+    /// Makes a transaction ready to be submitted right away, skipping its
+    /// backoff. A submitted one is moved back to `Validated` so the fanout
+    /// hands it to the peers again. Returns false when it's unknown or terminal.
+    async fn resubmit(&self, id: &str) -> Result<bool, StorageError>;
+    async fn increment_attempts(&self, id: &str) -> Result<u32, StorageError>;
+    async fn delete(&self, id: &str) -> Result<(), StorageError>;
+    async fn delete_many(&self, ids: &[String]) -> Result<(), StorageError>;
+    /// Deletes every transaction with its dependencies and submissions, e.g.
+    /// to start over in development.
+    async fn truncate_all(&self) -> Result<(), StorageError>;
+    /// Deletes the dependencies between two transactions confirmed before
+    /// `slot`, as they no longer hold anything back. The ones confirmed
+    /// later are kept, a rollback can still revert them. Returns how many were
+    /// deleted.
+    async fn compact_confirmed_dependencies(&self, slot: u64) -> Result<u64, StorageError>;
+    /// Deletes the transactions confirmed before `slot`, with their
+    /// dependencies and submissions. Returns how many transactions were
+    /// deleted.
+    async fn prune_confirmed_before(&self, slot: u64) -> Result<u64, StorageError>;
+    /// Ids of the transactions that directly require `id`.
+    async fn dependents_of(&self, id: &str) -> Result<Vec<String>, StorageError>;
+    /// Ids of every transaction that requires `id`, directly or through
+    /// another dependent, closest first. E.g. to fail or hold the whole subtree
+    /// of a failed transaction.
+    async fn all_dependents(&self, id: &str) -> Result<Vec<String>, StorageError> {
+        let mut visited: HashSet<String> = HashSet::from([id.to_string()]);
+        let mut queue: VecDeque<String> = VecDeque::from([id.to_string()]);
+        let mut dependents = Vec::new();
+
+        while let Some(current) = queue.pop_front() {
+            for dependent_id in self.dependents_of(&current).await? {
+                if visited.insert(dependent_id.clone()) {
+                    dependents.push(dependent_id.clone());
+                    queue.push_back(dependent_id);
+                }
+            }
+        }
+
+        Ok(dependents)
+    }
+    /// Runs a trivial query to check the database is reachable.
+    async fn ping(&self) -> Result<(), StorageError>;
+    /// Number of transactions in each status, statuses without any are omitted.
+    async fn status_counts(&self) -> Result<HashMap<TransactionStatus, i64>, StorageError>;
+    /// When the oldest transaction still in the queue, pending, validated or
+    /// in flight, was created. `None` when the queue is empty.
+    async fn oldest_queued_at(&self) -> Result<Option<DateTime<Utc>>, StorageError>;
+    /// Records the transaction was handed to `peer`, and whether it took it.
+    async fn record_submission(
+        &self,
+        tx_id: &str,
+        peer: &str,
+        accepted: bool,
+    ) -> Result<(), StorageError>;
+    /// Every peer the transaction was handed to, oldest first.
+    async fn submissions_for(&self, tx_id: &str) -> Result<Vec<Submission>, StorageError>;
+    /// Serializes the non-terminal transactions to JSON, to move the queue to
+    /// another boros with [`TransactionStore::import_snapshot`].
+    ///
+    /// The table is paged with [`TransactionStore::list_after`], so the
+    /// transactions moving between statuses meanwhile aren't skipped nor
+    /// exported twice as they would be with an offset.
+    async fn export_snapshot(&self) -> Result<Vec<u8>, StorageError> {
+        let mut snapshot = Vec::new();
+        let mut after = None;
+        let mut after_id = String::new();
+
+        loop {
+            let page = self
+                .list_after(after, &after_id, SNAPSHOT_PAGE_SIZE)
+                .await?;
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(last.created_at);
+            after_id = last.id.clone();
+
+            snapshot.extend(
+                page.into_iter()
+                    .filter(|tx| !tx.status.is_terminal())
+                    .map(SnapshotTx::from),
+            );
+        }
+
+        serde_json::to_vec(&snapshot).map_err(|error| StorageError::Other(error.into()))
+    }
+    /// Creates the transactions of a snapshot made by
+    /// [`TransactionStore::export_snapshot`], skipping the ones already stored.
+    /// The in flight ones are imported as `Validated` to be submitted again by
+    /// this node, and the dependencies that aren't in the snapshot nor stored
+    /// are dropped, as they were confirmed in the meantime.
+    async fn import_snapshot(&self, bytes: &[u8]) -> Result<u64, StorageError> {
+        let snapshot: Vec<SnapshotTx> =
+            serde_json::from_slice(bytes).map_err(|error| StorageError::Other(error.into()))?;
+        let ids: HashSet<String> = snapshot.iter().map(|tx| tx.id.clone()).collect();
+
+        let mut txs = Vec::with_capacity(snapshot.len());
+        for entry in snapshot {
+            let mut tx = entry.into_transaction().map_err(StorageError::Other)?;
+
+            if let Some(dependencies) = tx.dependencies.take() {
+                let mut kept = Vec::new();
+                for required_id in dependencies {
+                    if ids.contains(&required_id) || self.get(&required_id).await?.is_some() {
+                        kept.push(required_id);
+                    }
+                }
+                if !kept.is_empty() {
+                    tx.dependencies = Some(kept);
+                }
+            }
+
+            txs.push(tx);
+        }
+
+        let txs = required_first(txs);
+        let count = txs.len() as u64;
+        self.create_or_get(&txs).await?;
+        Ok(count)
+    }
+}
+
+#[async_trait::async_trait]
+pub trait CursorStore: Send + Sync {
+    async fn set(&self, cursor: &Cursor) -> Result<()>;
+    async fn current(&self) -> Result<Option<Cursor>>;
 }
 
 #[derive(Clone)]
@@ -18,6 +415,30 @@ pub struct Transaction {
     pub priority: TransactionPriority,
     pub slot: Option<u64>,
     pub dependencies: Option<Vec<String>>,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub claimed_by: Option<String>,
+    pub claimed_at: Option<DateTime<Utc>>,
+    pub ttl: Option<u64>,
+    /// Slot of the block the transaction was found in.
+    pub confirmed_slot: Option<u64>,
+    /// The transaction isn't picked by the pipeline before this time.
+    pub next_retry_at: Option<DateTime<Utc>>,
+    /// Set by the client, the transaction is failed instead of submitted after
+    /// this time. Unrelated to the ttl, which is checked against the chain.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// JSON set by the client to correlate the transaction, e.g. its own id.
+    /// Opaque to boros, it's returned as it was submitted.
+    pub metadata: Option<String>,
+    /// API the transaction was submitted through, e.g. `grpc` or `http`.
+    pub source: Option<String>,
+    /// Id of the API key that submitted the transaction, when authenticated.
+    pub submitter: Option<String>,
+    /// Transaction this one replaced, e.g. with a higher fee. It was cancelled
+    /// when this one was created.
+    pub replaces: Option<String>,
+    /// Queue the transaction belongs to, isolating the ones of each dApp.
+    pub namespace: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -30,13 +451,86 @@ impl Transaction {
             priority: TransactionPriority::Low,
             slot: None,
             dependencies: None,
+            attempts: 0,
+            error: None,
+            claimed_by: None,
+            claimed_at: None,
+            ttl: None,
+            confirmed_slot: None,
+            next_retry_at: None,
+            expires_at: None,
+            metadata: None,
+            source: None,
+            submitter: None,
+            replaces: None,
+            namespace: DEFAULT_NAMESPACE.into(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
     }
 }
 
-#[derive(Clone)]
+/// Namespace of the transactions submitted without one.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Errors of the [`TransactionStore`] methods, so the callers can tell the
+/// ones caused by the request from the failures of the database.
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("transaction {0} not found")]
+    NotFound(String),
+    /// The id is already stored, with a different raw when the same
+    /// transaction is accepted again, e.g. the same body signed with other
+    /// witnesses.
+    #[error("transaction {0} already exists")]
+    Conflict(String),
+    #[error("dependency cycle detected: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    /// The transaction a replacement points to is unknown or no longer pending
+    /// or validated, it may already be in the mempool of the peers.
+    #[error("transaction {0} can't be replaced, it's unknown or was already submitted")]
+    NotReplaceable(String),
+    /// The transaction is stored but its status doesn't allow the change, e.g.
+    /// requeueing a confirmed one.
+    #[error("{0}")]
+    InvalidStatus(String),
+    #[error(transparent)]
+    Db(#[from] sqlx::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+/// Order the pipeline picks the transactions of a status in.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueOrder {
+    /// Higher priority first, then by arrival.
+    #[default]
+    Priority,
+    /// By arrival only, regardless of the priority.
+    Fifo,
+}
+
+impl QueueOrder {
+    fn order_by(&self) -> &'static str {
+        match self {
+            QueueOrder::Priority => "priority ASC, created_at ASC",
+            QueueOrder::Fifo => "created_at ASC",
+        }
+    }
+}
+
+/// A transaction handed to a peer by the fanout.
+#[derive(Clone, Debug)]
+pub struct Submission {
+    pub tx_id: String,
+    pub peer: String,
+    pub submitted_at: DateTime<Utc>,
+    /// The peer took the transaction in its mempool.
+    pub accepted: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum TransactionPriority {
     Low,
     Medium,
@@ -54,24 +548,53 @@ impl TryFrom<u32> for TransactionPriority {
         }
     }
 }
-impl TryFrom<TransactionPriority> for u32 {
-    type Error = anyhow::Error;
-
-    fn try_from(value: TransactionPriority) -> Result<Self, Self::Error> {
+impl From<TransactionPriority> for u32 {
+    fn from(value: TransactionPriority) -> Self {
         match value {
-            TransactionPriority::High => Ok(1),
-            TransactionPriority::Medium => Ok(2),
-            TransactionPriority::Low => Ok(3),
+            TransactionPriority::High => 1,
+            TransactionPriority::Medium => 2,
+            TransactionPriority::Low => 3,
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum TransactionStatus {
     Pending,
     Validated,
     InFlight,
     Confirmed,
+    Failed,
+    /// Exceeded the max attempts, it's no longer retried.
+    DeadLetter,
+    /// Withdrawn by the client before being submitted to a peer.
+    Cancelled,
+    /// Requires a transaction that failed, so it can never be valid.
+    BlockedByDependency,
+}
+impl TransactionStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            Self::Confirmed
+                | Self::Failed
+                | Self::DeadLetter
+                | Self::Cancelled
+                | Self::BlockedByDependency
+        )
+    }
+
+    /// Whether the transactions that require one in this status are blocked.
+    pub fn blocks_dependents(&self) -> bool {
+        matches!(self, Self::Failed | Self::DeadLetter | Self::Cancelled)
+    }
+
+    /// Whether a transaction in this status can be requeued. The others are
+    /// either on their way, e.g. already submitted, or replaced, moving them
+    /// back to pending could submit them twice.
+    pub fn is_requeueable(&self) -> bool {
+        matches!(self, Self::Failed | Self::DeadLetter)
+    }
 }
 impl FromStr for TransactionStatus {
     type Err = anyhow::Error;
@@ -82,6 +605,10 @@ impl FromStr for TransactionStatus {
             "validated" => Ok(Self::Validated),
             "inflight" => Ok(Self::InFlight),
             "confirmed" => Ok(Self::Confirmed),
+            "failed" => Ok(Self::Failed),
+            "deadletter" => Ok(Self::DeadLetter),
+            "cancelled" => Ok(Self::Cancelled),
+            "blocked" => Ok(Self::BlockedByDependency),
             _ => Err(anyhow::Error::msg("transaction status not supported")),
         }
     }
@@ -93,8 +620,157 @@ impl Display for TransactionStatus {
             Self::Validated => write!(f, "validated"),
             Self::InFlight => write!(f, "inflight"),
             Self::Confirmed => write!(f, "confirmed"),
+            Self::Failed => write!(f, "failed"),
+            Self::DeadLetter => write!(f, "deadletter"),
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::BlockedByDependency => write!(f, "blocked"),
+        }
+    }
+}
+
+const SNAPSHOT_PAGE_SIZE: i64 = 500;
+
+/// A transaction of the queue as written by
+/// [`TransactionStore::export_snapshot`].
+#[derive(Serialize, Deserialize)]
+struct SnapshotTx {
+    id: String,
+    /// CBOR of the transaction in hex.
+    raw: String,
+    status: String,
+    priority: u32,
+    #[serde(default)]
+    dependencies: Vec<String>,
+    #[serde(default = "default_namespace")]
+    namespace: String,
+    /// Unix time in seconds.
+    #[serde(default)]
+    expires_at: Option<i64>,
+    #[serde(default)]
+    metadata: Option<String>,
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    submitter: Option<String>,
+    #[serde(default)]
+    replaces: Option<String>,
+}
+impl From<Transaction> for SnapshotTx {
+    fn from(tx: Transaction) -> Self {
+        Self {
+            id: tx.id,
+            raw: hex::encode(&tx.raw),
+            status: tx.status.to_string(),
+            priority: tx.priority.into(),
+            dependencies: tx.dependencies.unwrap_or_default(),
+            namespace: tx.namespace,
+            expires_at: tx.expires_at.map(|at| at.timestamp()),
+            metadata: tx.metadata,
+            source: tx.source,
+            submitter: tx.submitter,
+            replaces: tx.replaces,
+        }
+    }
+}
+impl SnapshotTx {
+    fn into_transaction(self) -> Result<Transaction> {
+        let status = match self.status.parse()? {
+            TransactionStatus::InFlight => TransactionStatus::Validated,
+            status if status.is_terminal() => {
+                bail!("transaction {} is {status} and can't be imported", self.id)
+            }
+            status => status,
+        };
+
+        let mut tx = Transaction::new(self.id, hex::decode(&self.raw)?);
+        tx.status = status;
+        tx.priority = self.priority.try_into()?;
+        tx.namespace = self.namespace;
+        tx.metadata = self.metadata;
+        tx.source = self.source;
+        tx.submitter = self.submitter;
+        tx.replaces = self.replaces;
+        tx.expires_at = self
+            .expires_at
+            .map(|at| DateTime::from_timestamp(at, 0).ok_or_else(|| anyhow!("invalid expires_at")))
+            .transpose()?;
+        if !self.dependencies.is_empty() {
+            tx.dependencies = Some(self.dependencies);
+        }
+        Ok(tx)
+    }
+}
+
+fn default_namespace() -> String {
+    DEFAULT_NAMESPACE.into()
+}
+
+/// Orders the transactions so the ones required by another come before it, as
+/// a dependency must be stored before its dependents. The ones left in a cycle
+/// keep their order and are rejected by the store.
+fn required_first(mut txs: Vec<Transaction>) -> Vec<Transaction> {
+    let mut placed: HashSet<String> = HashSet::new();
+    let pending: HashSet<String> = txs.iter().map(|tx| tx.id.clone()).collect();
+    let mut ordered = Vec::with_capacity(txs.len());
+
+    while !txs.is_empty() {
+        let (ready, waiting): (Vec<Transaction>, Vec<Transaction>) =
+            txs.into_iter().partition(|tx| {
+                tx.dependencies.iter().flatten().all(|required_id| {
+                    placed.contains(required_id) || !pending.contains(required_id)
+                })
+            });
+
+        if ready.is_empty() {
+            ordered.extend(waiting);
+            break;
+        }
+
+        placed.extend(ready.iter().map(|tx| tx.id.clone()));
+        ordered.extend(ready);
+        txs = waiting;
+    }
+
+    ordered
+}
+
+/// Walks the dependency graph (dependent id -> required ids) with a DFS and
+/// returns the first cycle found as the list of ids that form it.
+pub fn find_dependency_cycle(graph: &HashMap<String, Vec<String>>) -> Option<Vec<String>> {
+    fn visit<'a>(
+        id: &'a str,
+        graph: &'a HashMap<String, Vec<String>>,
+        path: &mut Vec<&'a str>,
+        visited: &mut HashSet<&'a str>,
+    ) -> Option<Vec<String>> {
+        if let Some(position) = path.iter().position(|p| *p == id) {
+            let mut cycle: Vec<String> = path[position..].iter().map(|p| p.to_string()).collect();
+            cycle.push(id.to_string());
+            return Some(cycle);
+        }
+
+        if visited.contains(id) {
+            return None;
         }
+
+        path.push(id);
+        for required_id in graph.get(id).into_iter().flatten() {
+            if let Some(cycle) = visit(required_id, graph, path, visited) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        visited.insert(id);
+
+        None
     }
+
+    let mut ids: Vec<&String> = graph.keys().collect();
+    ids.sort();
+
+    let mut visited = HashSet::new();
+    ids.into_iter()
+        .find_map(|id| visit(id, graph, &mut Vec::new(), &mut visited))
 }
 
 #[derive(Clone)]
@@ -121,12 +797,42 @@ mod tests {
                 priority: TransactionPriority::Low,
                 slot: None,
                 dependencies: None,
+                attempts: 0,
+                error: None,
+                claimed_by: None,
+                claimed_at: None,
+                ttl: None,
+                confirmed_slot: None,
+                next_retry_at: None,
+                expires_at: None,
+                metadata: None,
+                source: None,
+                submitter: None,
+                replaces: None,
+                namespace: DEFAULT_NAMESPACE.into(),
                 created_at: Utc::now(),
                 updated_at: Utc::now(),
             }
         }
     }
 
+    impl Default for Config {
+        fn default() -> Self {
+            Self {
+                backend: Backend::Sqlite,
+                db_path: "boros.db".into(),
+                url: None,
+                busy_timeout_ms: default_busy_timeout_ms(),
+                max_connections: default_max_connections(),
+                min_connections: 0,
+                maintenance_interval_secs: None,
+                slow_query_ms: None,
+                write_retries: default_write_retries(),
+                confirmed_retention_secs: None,
+            }
+        }
+    }
+
     impl Default for Cursor {
         fn default() -> Self {
             Self {
@@ -135,4 +841,71 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn it_should_convert_priority() {
+        for priority in [
+            TransactionPriority::High,
+            TransactionPriority::Medium,
+            TransactionPriority::Low,
+        ] {
+            let value: u32 = priority.clone().into();
+            assert!(TransactionPriority::try_from(value).unwrap() == priority);
+        }
+    }
+
+    #[test]
+    fn it_should_fail_convert_priority_out_of_range() {
+        assert!(TransactionPriority::try_from(0).is_err());
+        assert!(TransactionPriority::try_from(4).is_err());
+    }
+
+    #[test]
+    fn it_should_find_dependency_cycle() {
+        let graph = HashMap::from([
+            ("a".to_string(), vec!["b".to_string()]),
+            ("b".to_string(), vec!["c".to_string()]),
+            ("c".to_string(), vec!["a".to_string()]),
+        ]);
+
+        let cycle = find_dependency_cycle(&graph).unwrap();
+        assert!(cycle == vec!["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn it_should_not_find_dependency_cycle() {
+        let graph = HashMap::from([
+            ("a".to_string(), vec!["b".to_string(), "c".to_string()]),
+            ("b".to_string(), vec!["c".to_string()]),
+        ]);
+
+        assert!(find_dependency_cycle(&graph).is_none());
+    }
+
+    #[test]
+    fn it_should_parse_terminal_status() {
+        let status: TransactionStatus = "confirmed".parse().unwrap();
+        assert!(matches!(status, TransactionStatus::Confirmed));
+        assert!(status.to_string() == "confirmed");
+
+        let status: TransactionStatus = "failed".parse().unwrap();
+        assert!(matches!(status, TransactionStatus::Failed));
+        assert!(status.to_string() == "failed");
+
+        let status: TransactionStatus = "deadletter".parse().unwrap();
+        assert!(matches!(status, TransactionStatus::DeadLetter));
+        assert!(status.is_terminal());
+        assert!(status.to_string() == "deadletter");
+
+        let status: TransactionStatus = "cancelled".parse().unwrap();
+        assert!(matches!(status, TransactionStatus::Cancelled));
+        assert!(status.is_terminal());
+        assert!(status.to_string() == "cancelled");
+    }
+
+    #[test]
+    fn it_should_fail_parse_unknown_status() {
+        let result = "something".parse::<TransactionStatus>();
+        assert!(result.is_err());
+    }
 }
@@ -0,0 +1,2179 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Error, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    migrate::Migrator,
+    postgres::{PgConnection, PgRow},
+    FromRow, Row,
+};
+use tokio::time::interval;
+use tracing::{error, info};
+
+use super::{
+    find_dependency_cycle, migration_error, Config, Cursor, CursorStore, QueueOrder, StorageError,
+    Submission, Transaction, TransactionPriority, TransactionStatus, TransactionStore,
+    ROLLBACK_WINDOW_SLOTS,
+};
+
+static MIGRATOR: Migrator = sqlx::migrate!("src/storage/migrations_postgres");
+
+pub struct PostgresStorage {
+    db: sqlx::postgres::PgPool,
+}
+
+impl PostgresStorage {
+    pub async fn new(url: &str, config: &Config) -> Result<Self> {
+        let db = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .connect(url)
+            .await?;
+
+        Ok(Self { db })
+    }
+
+    pub async fn migrate(&self) -> Result<()> {
+        MIGRATOR
+            .run(&self.db)
+            .await
+            .map_err(|error| migration_error(&MIGRATOR, error))
+    }
+
+    pub async fn close(&self) {
+        self.db.close().await;
+    }
+}
+
+/// Moves every transaction that requires `parent_id`, directly or through
+/// another dependent, to `BlockedByDependency`, as the parent is `status`.
+/// The submitted and terminal ones are kept as they are.
+async fn block_dependents(
+    db: &mut PgConnection,
+    parent_id: &str,
+    status: &TransactionStatus,
+) -> Result<u64, StorageError> {
+    let result = sqlx::query(
+        r#"
+            WITH RECURSIVE dependents(id) AS (
+            	SELECT
+            		dependent_id
+            	FROM
+            		tx_dependence
+            	WHERE
+            		required_id = $1
+            	UNION
+            	SELECT
+            		tx_dependence.dependent_id
+            	FROM
+            		tx_dependence
+            		JOIN dependents ON tx_dependence.required_id = dependents.id
+            )
+            UPDATE
+            	tx
+            SET
+            	status = $2,
+            	error = $3,
+            	updated_at = $4
+            WHERE
+            	id IN (SELECT id FROM dependents)
+            	AND status IN ($5, $6);
+        "#,
+    )
+    .bind(parent_id)
+    .bind(TransactionStatus::BlockedByDependency.to_string())
+    .bind(format!("dependency {parent_id} is {status}"))
+    .bind(Utc::now())
+    .bind(TransactionStatus::Pending.to_string())
+    .bind(TransactionStatus::Validated.to_string())
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Moves the dependents [`block_dependents`] blocked on `parent_id` back to
+/// `Pending` once none of the transactions they require blocks them anymore,
+/// e.g. after it was requeued. A level of dependents is unblocked at a time,
+/// as each waits for the ones it requires to be unblocked first.
+async fn unblock_dependents(db: &mut PgConnection, parent_id: &str) -> Result<u64, StorageError> {
+    let mut unblocked = 0;
+    loop {
+        let result = sqlx::query(
+            r#"
+                WITH RECURSIVE dependents(id) AS (
+                	SELECT
+                		dependent_id
+                	FROM
+                		tx_dependence
+                	WHERE
+                		required_id = $1
+                	UNION
+                	SELECT
+                		tx_dependence.dependent_id
+                	FROM
+                		tx_dependence
+                		JOIN dependents ON tx_dependence.required_id = dependents.id
+                )
+                UPDATE
+                	tx
+                SET
+                	status = $2,
+                	error = NULL,
+                	updated_at = $3
+                WHERE
+                	id IN (SELECT id FROM dependents)
+                	AND status = $4
+                	AND NOT EXISTS (
+                		SELECT
+                			1
+                		FROM
+                			tx_dependence
+                			JOIN tx AS required ON required.id = tx_dependence.required_id
+                		WHERE
+                			tx_dependence.dependent_id = tx.id
+                			AND required.status IN ($4, $5, $6, $7)
+                	);
+            "#,
+        )
+        .bind(parent_id)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(Utc::now())
+        .bind(TransactionStatus::BlockedByDependency.to_string())
+        .bind(TransactionStatus::Failed.to_string())
+        .bind(TransactionStatus::DeadLetter.to_string())
+        .bind(TransactionStatus::Cancelled.to_string())
+        .execute(&mut *db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(unblocked);
+        }
+        unblocked += result.rows_affected();
+    }
+}
+
+/// Deletes the dependencies between transactions confirmed past the rollback
+/// window every `period`, the space is given back by the autovacuum of
+/// postgres.
+pub async fn maintain(storage: Arc<PostgresStorage>, period: Duration) {
+    let transactions = PostgresTransaction::new(storage.clone());
+    let cursor = PostgresCursor::new(storage);
+    let mut interval = interval(period);
+    // the first tick completes immediately, there's nothing to compact at startup
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        // nothing is compacted before the first block is processed
+        match cursor.current().await {
+            Ok(Some(cursor)) => {
+                let stable_slot = cursor.slot.saturating_sub(ROLLBACK_WINDOW_SLOTS);
+                match transactions
+                    .compact_confirmed_dependencies(stable_slot)
+                    .await
+                {
+                    Ok(removed) => info!(removed, "confirmed dependencies compacted"),
+                    Err(error) => error!(?error, "failed to compact the confirmed dependencies"),
+                }
+            }
+            Ok(None) => {}
+            Err(error) => error!(?error, "failed to read the cursor to compact"),
+        }
+    }
+}
+
+impl FromRow<'_, PgRow> for Transaction {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        let status: &str = row.try_get("status")?;
+        let priority: i32 = row.try_get("priority")?;
+        let slot: Option<i64> = row.try_get("slot")?;
+        let attempts: i32 = row.try_get("attempts")?;
+        let ttl: Option<i64> = row.try_get("ttl")?;
+        let confirmed_slot: Option<i64> = row.try_get("confirmed_slot")?;
+
+        Ok(Self {
+            id: row.try_get("id")?,
+            raw: row.try_get("raw")?,
+            status: status
+                .parse()
+                .map_err(|err: Error| sqlx::Error::Decode(err.into()))?,
+            priority: (priority as u32)
+                .try_into()
+                .map_err(|err: Error| sqlx::Error::Decode(err.into()))?,
+            slot: slot.map(|v| v as u64),
+            dependencies: None,
+            attempts: attempts as u32,
+            error: row.try_get("error")?,
+            claimed_by: row.try_get("claimed_by")?,
+            claimed_at: row.try_get("claimed_at")?,
+            ttl: ttl.map(|v| v as u64),
+            confirmed_slot: confirmed_slot.map(|v| v as u64),
+            next_retry_at: row.try_get("next_retry_at")?,
+            expires_at: row.try_get("expires_at")?,
+            metadata: row.try_get("metadata")?,
+            source: row.try_get("source")?,
+            submitter: row.try_get("submitter")?,
+            replaces: row.try_get("replaces")?,
+            namespace: row.try_get("namespace")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
+
+pub struct PostgresTransaction {
+    postgres: Arc<PostgresStorage>,
+    ordering: QueueOrder,
+}
+
+impl PostgresTransaction {
+    pub fn new(postgres: Arc<PostgresStorage>) -> Self {
+        Self {
+            postgres,
+            ordering: QueueOrder::default(),
+        }
+    }
+
+    /// Changes the order the transactions of a status are picked in.
+    pub fn with_ordering(mut self, ordering: QueueOrder) -> Self {
+        self.ordering = ordering;
+        self
+    }
+
+    pub async fn hydrate_dependencies(&self, txs: &mut [Transaction]) -> Result<(), StorageError> {
+        if txs.is_empty() {
+            return Ok(());
+        }
+
+        let ids: Vec<String> = txs.iter().map(|tx| tx.id.clone()).collect();
+
+        let edges = sqlx::query_as::<_, (String, String)>(
+            r#"
+                    SELECT
+                    	dependent_id,
+                    	required_id
+                    FROM
+                    	tx_dependence
+                    WHERE
+                    	dependent_id = ANY($1);
+            "#,
+        )
+        .bind(ids)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        let mut dependencies: HashMap<String, Vec<String>> = HashMap::new();
+        for (dependent_id, required_id) in edges {
+            dependencies
+                .entry(dependent_id)
+                .or_default()
+                .push(required_id);
+        }
+        for tx in txs.iter_mut() {
+            tx.dependencies = dependencies.remove(&tx.id);
+        }
+
+        Ok(())
+    }
+
+    /// The error of a change that matched no transaction, as it's either
+    /// unknown or in a status the change doesn't apply to.
+    async fn unchanged(&self, id: &str) -> StorageError {
+        let status: Result<Option<String>, sqlx::Error> =
+            sqlx::query_scalar("SELECT status FROM tx WHERE id = $1;")
+                .bind(id)
+                .fetch_optional(&self.postgres.db)
+                .await;
+
+        match status {
+            Ok(Some(status)) => {
+                StorageError::InvalidStatus(format!("transaction {id} is {status}"))
+            }
+            Ok(None) => StorageError::NotFound(id.to_string()),
+            Err(error) => error.into(),
+        }
+    }
+
+    /// Stores the transactions in a single database transaction. A
+    /// transaction already stored is a `Conflict`, unless `keep_stored` and
+    /// it has the same raw, then it's kept as it is. Returns the ids of the
+    /// kept ones.
+    async fn insert(
+        &self,
+        txs: &[Transaction],
+        keep_stored: bool,
+    ) -> Result<HashSet<String>, StorageError> {
+        let mut db_tx = self.postgres.db.begin().await?;
+
+        // inserted first, so a concurrent submission of the same transaction
+        // waits for this one to commit instead of failing on the primary key
+        let mut kept = HashSet::new();
+        for tx in txs {
+            let status = tx.status.clone().to_string();
+            let priority: u32 = tx.priority.clone().into();
+
+            let result = sqlx::query(
+                r#"
+                    INSERT INTO tx (
+                        id,
+                        raw,
+                        status,
+                        priority,
+                        attempts,
+                        ttl,
+                        namespace,
+                        expires_at,
+                        metadata,
+                        source,
+                        submitter,
+                        replaces,
+                        created_at,
+                        updated_at
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+                    ON CONFLICT (id) DO NOTHING
+                "#,
+            )
+            .bind(&tx.id)
+            .bind(&tx.raw)
+            .bind(status)
+            .bind(priority as i32)
+            .bind(tx.attempts as i32)
+            .bind(tx.ttl.map(|v| v as i64))
+            .bind(&tx.namespace)
+            .bind(tx.expires_at)
+            .bind(&tx.metadata)
+            .bind(&tx.source)
+            .bind(&tx.submitter)
+            .bind(&tx.replaces)
+            .bind(tx.created_at)
+            .bind(tx.updated_at)
+            .execute(&mut *db_tx)
+            .await?;
+            if result.rows_affected() == 1 {
+                continue;
+            }
+
+            let stored_raw: Option<Vec<u8>> =
+                sqlx::query_scalar("SELECT raw FROM tx WHERE id = $1;")
+                    .bind(&tx.id)
+                    .fetch_optional(&mut *db_tx)
+                    .await?;
+            if !keep_stored || stored_raw.is_some_and(|raw| raw != tx.raw) {
+                return Err(StorageError::Conflict(tx.id.clone()));
+            }
+            kept.insert(tx.id.clone());
+        }
+        let txs: Vec<&Transaction> = txs.iter().filter(|tx| !kept.contains(&tx.id)).collect();
+
+        if txs.iter().any(|tx| tx.dependencies.is_some()) {
+            // a cycle has to go through a new edge, so only the stored edges
+            // reachable from the new dependencies are walked
+            let required_ids: Vec<String> = txs
+                .iter()
+                .flat_map(|tx| tx.dependencies.iter().flatten().cloned())
+                .collect();
+            let edges = sqlx::query_as::<_, (String, String)>(
+                r#"
+                    WITH RECURSIVE reachable(id) AS (
+                    	SELECT
+                    		UNNEST($1::TEXT[])
+                    	UNION
+                    	SELECT
+                    		tx_dependence.required_id
+                    	FROM
+                    		tx_dependence
+                    		JOIN reachable ON tx_dependence.dependent_id = reachable.id
+                    )
+                    SELECT
+                    	dependent_id,
+                    	required_id
+                    FROM
+                    	tx_dependence
+                    WHERE
+                    	dependent_id IN (SELECT id FROM reachable);
+                "#,
+            )
+            .bind(&required_ids)
+            .fetch_all(&mut *db_tx)
+            .await?;
+
+            let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+            for (dependent_id, required_id) in edges {
+                graph.entry(dependent_id).or_default().push(required_id);
+            }
+            for tx in &txs {
+                if let Some(dependencies) = &tx.dependencies {
+                    graph
+                        .entry(tx.id.clone())
+                        .or_default()
+                        .extend(dependencies.iter().cloned());
+                }
+            }
+
+            if let Some(cycle) = find_dependency_cycle(&graph) {
+                return Err(StorageError::Cycle(cycle));
+            }
+        }
+
+        // inserted once every transaction of the batch is stored, as an edge
+        // can require a transaction after it in the batch, and in a single
+        // statement however many there are
+        let (dependent_ids, required_ids): (Vec<String>, Vec<String>) = txs
+            .iter()
+            .flat_map(|tx| {
+                tx.dependencies
+                    .iter()
+                    .flatten()
+                    .map(move |required_id| (tx.id.clone(), required_id.clone()))
+            })
+            .unzip();
+        if !dependent_ids.is_empty() {
+            sqlx::query(
+                r#"
+                    INSERT INTO tx_dependence (
+                    	dependent_id,
+                    	required_id
+                    )
+                    SELECT
+                    	*
+                    FROM
+                    	UNNEST($1::TEXT[], $2::TEXT[]);
+                "#,
+            )
+            .bind(&dependent_ids)
+            .bind(&required_ids)
+            .execute(&mut *db_tx)
+            .await?;
+        }
+
+        for tx in &txs {
+            let Some(replaced_id) = &tx.replaces else {
+                continue;
+            };
+            let result = sqlx::query(
+                r#"
+                    UPDATE
+                    	tx
+                    SET
+                    	status = $1,
+                    	updated_at = $2
+                    WHERE
+                    	id = $3
+                    	AND status IN ($4, $5);
+                "#,
+            )
+            .bind(TransactionStatus::Cancelled.to_string())
+            .bind(Utc::now())
+            .bind(replaced_id)
+            .bind(TransactionStatus::Pending.to_string())
+            .bind(TransactionStatus::Validated.to_string())
+            .execute(&mut *db_tx)
+            .await?;
+
+            if result.rows_affected() == 0 {
+                return Err(StorageError::NotReplaceable(replaced_id.clone()));
+            }
+
+            // the dependents of the replaced one now wait for the replacement,
+            // a cancelled dependency would hold them back forever
+            sqlx::query(
+                r#"
+                    UPDATE
+                    	tx_dependence
+                    SET
+                    	required_id = $1
+                    WHERE
+                    	required_id = $2
+                    	AND dependent_id <> $1
+                    	AND NOT EXISTS (
+                    		SELECT
+                    			1
+                    		FROM
+                    			tx_dependence AS existing
+                    		WHERE
+                    			existing.dependent_id = tx_dependence.dependent_id
+                    			AND existing.required_id = $1
+                    	);
+                "#,
+            )
+            .bind(&tx.id)
+            .bind(replaced_id)
+            .execute(&mut *db_tx)
+            .await?;
+            sqlx::query(
+                r#"
+                    DELETE FROM
+                    	tx_dependence
+                    WHERE
+                    	required_id = $1;
+                "#,
+            )
+            .bind(replaced_id)
+            .execute(&mut *db_tx)
+            .await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(kept)
+    }
+}
+
+#[async_trait::async_trait]
+impl TransactionStore for PostgresTransaction {
+    async fn create(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        self.insert(txs, false).await?;
+        Ok(())
+    }
+
+    async fn create_or_get(
+        &self,
+        txs: &Vec<Transaction>,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let kept = self.insert(txs, true).await?;
+
+        let mut stored = Vec::with_capacity(txs.len());
+        for tx in txs {
+            match kept.contains(&tx.id) {
+                true => stored.push(self.get(&tx.id).await?.unwrap_or_else(|| tx.clone())),
+                false => stored.push(tx.clone()),
+            }
+        }
+        Ok(stored)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Transaction>, StorageError> {
+        let mut transaction = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.id = $1;
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.postgres.db)
+        .await?;
+
+        if let Some(transaction) = transaction.as_mut() {
+            self.hydrate_dependencies(std::slice::from_mut(transaction))
+                .await?;
+        }
+
+        Ok(transaction)
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Transaction>, StorageError> {
+        let mut transactions = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.id = ANY($1);
+            "#,
+        )
+        .bind(ids)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        self.hydrate_dependencies(&mut transactions).await?;
+
+        Ok(transactions)
+    }
+
+    async fn find(&self, status: TransactionStatus) -> Result<Vec<Transaction>, StorageError> {
+        let transactions = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.status = $1;
+            "#,
+        )
+        .bind(status.to_string())
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        Ok(transactions)
+    }
+
+    async fn find_to_rollback(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        let status = TransactionStatus::Confirmed.to_string();
+        let slot = slot as i64;
+
+        let transactions = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.status = $1 AND tx.slot > $2;
+            "#,
+        )
+        .bind(status)
+        .bind(slot)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        Ok(transactions)
+    }
+
+    async fn find_in_flight_before(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        let status = TransactionStatus::InFlight.to_string();
+        let slot = slot as i64;
+
+        let transactions = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.status = $1 AND tx.slot < $2;
+            "#,
+        )
+        .bind(status)
+        .bind(slot)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        Ok(transactions)
+    }
+
+    async fn next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        Ok(self
+            .next_batch(status, namespace, 1)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn next_batch(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        if status.is_terminal() {
+            return Ok(Vec::new());
+        }
+
+        let mut transactions = sqlx::query_as::<_, Transaction>(&format!(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.status = $1
+                    	AND (tx.next_retry_at IS NULL OR tx.next_retry_at <= $3)
+                    	AND ($4::TEXT IS NULL OR tx.namespace = $4)
+                    ORDER BY
+                    	{order_by}
+                    LIMIT $2;
+            "#,
+            order_by = self.ordering.order_by(),
+        ))
+        .bind(status.to_string())
+        .bind(limit)
+        .bind(Utc::now())
+        .bind(namespace)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        self.hydrate_dependencies(&mut transactions).await?;
+
+        Ok(transactions)
+    }
+
+    async fn next_ready(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        if status.is_terminal() {
+            return Ok(None);
+        }
+
+        let mut transaction = sqlx::query_as::<_, Transaction>(&format!(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.status = $1
+                    	AND (tx.next_retry_at IS NULL OR tx.next_retry_at <= $3)
+                    	AND ($4::TEXT IS NULL OR tx.namespace = $4)
+                    	AND NOT EXISTS (
+                    		SELECT
+                    			1
+                    		FROM
+                    			tx_dependence
+                    			INNER JOIN tx AS required ON required.id = tx_dependence.required_id
+                    		WHERE
+                    			tx_dependence.dependent_id = tx.id
+                    			AND required.status != $2
+                    	)
+                    ORDER BY
+                    	{order_by}
+                    LIMIT 1;
+            "#,
+            order_by = self.ordering.order_by(),
+        ))
+        .bind(status.to_string())
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(Utc::now())
+        .bind(namespace)
+        .fetch_optional(&self.postgres.db)
+        .await?;
+
+        if let Some(transaction) = transaction.as_mut() {
+            self.hydrate_dependencies(std::slice::from_mut(transaction))
+                .await?;
+        }
+
+        Ok(transaction)
+    }
+
+    async fn claim_next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        worker_id: &str,
+    ) -> Result<Option<Transaction>, StorageError> {
+        if status.is_terminal() {
+            return Ok(None);
+        }
+
+        let updated_at = Utc::now();
+
+        // SKIP LOCKED lets concurrent workers move past a row that is being claimed
+        // instead of waiting for it.
+        let mut transaction = sqlx::query_as::<_, Transaction>(&format!(
+            r#"
+                    UPDATE
+                    	tx
+                    SET
+                    	status = $1,
+                    	claimed_by = $2,
+                    	claimed_at = $3,
+                    	updated_at = $3
+                    WHERE
+                    	id = (
+                    		SELECT
+                    			id
+                    		FROM
+                    			tx AS candidate
+                    		WHERE
+                    			candidate.status = $4
+                    			AND (candidate.next_retry_at IS NULL OR candidate.next_retry_at <= $3)
+                    			AND ($6::TEXT IS NULL OR candidate.namespace = $6)
+                    			AND NOT EXISTS (
+                    				SELECT
+                    					1
+                    				FROM
+                    					tx_dependence
+                    					INNER JOIN tx AS required ON required.id = tx_dependence.required_id
+                    				WHERE
+                    					tx_dependence.dependent_id = candidate.id
+                    					AND required.status != $5
+                    			)
+                    		ORDER BY
+                    			{order_by}
+                    		LIMIT 1
+                    		FOR UPDATE SKIP LOCKED
+                    	)
+                    RETURNING
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace;
+            "#,
+            order_by = self.ordering.order_by(),
+        ))
+        .bind(TransactionStatus::InFlight.to_string())
+        .bind(worker_id)
+        .bind(updated_at)
+        .bind(status.to_string())
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(namespace)
+        .fetch_optional(&self.postgres.db)
+        .await?;
+
+        if let Some(transaction) = transaction.as_mut() {
+            self.hydrate_dependencies(std::slice::from_mut(transaction))
+                .await?;
+        }
+
+        Ok(transaction)
+    }
+
+    async fn reclaim_expired(&self, older_than: Duration) -> Result<u64, StorageError> {
+        let updated_at = Utc::now();
+        let claimed_before = updated_at
+            - chrono::Duration::from_std(older_than)
+                .map_err(|error| StorageError::Other(error.into()))?;
+
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	updated_at = $2
+                WHERE
+                	status = $3
+                	AND slot IS NULL
+                	AND claimed_at < $4;
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(updated_at)
+        .bind(TransactionStatus::InFlight.to_string())
+        .bind(claimed_before)
+        .execute(&self.postgres.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn release_claims(&self, worker_id: &str) -> Result<u64, StorageError> {
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	updated_at = $2
+                WHERE
+                	status = $3
+                	AND slot IS NULL
+                	AND claimed_by = $4;
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(Utc::now())
+        .bind(TransactionStatus::InFlight.to_string())
+        .bind(worker_id)
+        .execute(&self.postgres.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn rollback_to(&self, slot: u64) -> Result<u64, StorageError> {
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	slot = NULL,
+                	confirmed_slot = NULL,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	updated_at = $2
+                WHERE
+                	status = $3
+                	AND confirmed_slot > $4;
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(Utc::now())
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&self.postgres.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn expire_past_ttl(&self, current_slot: u64) -> Result<u64, StorageError> {
+        let updated_at = Utc::now();
+        let current_slot = current_slot as i64;
+
+        let mut db_tx = self.postgres.db.begin().await?;
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	error = $2,
+                	updated_at = $3
+                WHERE
+                	ttl < $4
+                	AND status IN ($5, $6, $7)
+                RETURNING
+                	id;
+            "#,
+        )
+        .bind(TransactionStatus::Failed.to_string())
+        .bind("ttl expired")
+        .bind(updated_at)
+        .bind(current_slot)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .bind(TransactionStatus::InFlight.to_string())
+        .fetch_all(&mut *db_tx)
+        .await?;
+
+        for id in &ids {
+            block_dependents(&mut db_tx, id, &TransactionStatus::Failed).await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(ids.len() as u64)
+    }
+
+    async fn expire_past_deadline(&self, now: DateTime<Utc>) -> Result<u64, StorageError> {
+        let mut db_tx = self.postgres.db.begin().await?;
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	error = $2,
+                	updated_at = $3
+                WHERE
+                	expires_at < $3
+                	AND status IN ($4, $5)
+                RETURNING
+                	id;
+            "#,
+        )
+        .bind(TransactionStatus::Failed.to_string())
+        .bind("expired before being submitted")
+        .bind(now)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .fetch_all(&mut *db_tx)
+        .await?;
+
+        for id in &ids {
+            block_dependents(&mut db_tx, id, &TransactionStatus::Failed).await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(ids.len() as u64)
+    }
+
+    async fn list(
+        &self,
+        status: Option<TransactionStatus>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let mut transactions = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	($1::TEXT IS NULL OR tx.status = $1)
+                    	AND ($4::TIMESTAMPTZ IS NULL OR tx.created_at >= $4)
+                    	AND ($5::TIMESTAMPTZ IS NULL OR tx.created_at <= $5)
+                    ORDER BY
+                    	created_at DESC
+                    LIMIT $2
+                    OFFSET $3;
+            "#,
+        )
+        .bind(status.map(|s| s.to_string()))
+        .bind(limit)
+        .bind(offset)
+        .bind(created_after)
+        .bind(created_before)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        self.hydrate_dependencies(&mut transactions).await?;
+
+        Ok(transactions)
+    }
+
+    async fn list_after(
+        &self,
+        after_created_at: Option<DateTime<Utc>>,
+        after_id: &str,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let mut transactions = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	($1::TIMESTAMPTZ IS NULL OR (tx.created_at, tx.id) > ($1, $2))
+                    ORDER BY
+                    	created_at,
+                    	id
+                    LIMIT $3;
+            "#,
+        )
+        .bind(after_created_at)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        self.hydrate_dependencies(&mut transactions).await?;
+
+        Ok(transactions)
+    }
+
+    async fn update(&self, tx: &Transaction) -> Result<(), StorageError> {
+        let status = tx.status.to_string();
+        let updated_at = Utc::now();
+        let slot = tx.slot.map(|v| v as i64);
+
+        let mut db_tx = self.postgres.db.begin().await?;
+        sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	raw = $1,
+                	status = $2,
+                	slot = $3,
+                	error = $4,
+                	claimed_by = $5,
+                	claimed_at = $6,
+                	ttl = $7,
+                	confirmed_slot = $8,
+                	next_retry_at = $9,
+                	updated_at = $10
+                WHERE
+                	id = $11;
+            "#,
+        )
+        .bind(&tx.raw)
+        .bind(status)
+        .bind(slot)
+        .bind(&tx.error)
+        .bind(&tx.claimed_by)
+        .bind(tx.claimed_at)
+        .bind(tx.ttl.map(|v| v as i64))
+        .bind(tx.confirmed_slot.map(|v| v as i64))
+        .bind(tx.next_retry_at)
+        .bind(updated_at)
+        .bind(&tx.id)
+        .execute(&mut *db_tx)
+        .await?;
+
+        if tx.status.blocks_dependents() {
+            block_dependents(&mut db_tx, &tx.id, &tx.status).await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_batch(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        let mut db_tx = self.postgres.db.begin().await?;
+
+        for tx in txs {
+            let status = tx.status.to_string();
+            let updated_at = Utc::now();
+            let slot = tx.slot.map(|v| v as i64);
+
+            sqlx::query(
+                r#"
+                UPDATE
+                	tx
+                SET
+                	raw = $1,
+                	status = $2,
+                	slot = $3,
+                	error = $4,
+                	claimed_by = $5,
+                	claimed_at = $6,
+                	ttl = $7,
+                	confirmed_slot = $8,
+                	next_retry_at = $9,
+                	updated_at = $10
+                WHERE
+                	id = $11;
+            "#,
+            )
+            .bind(&tx.raw)
+            .bind(status)
+            .bind(slot)
+            .bind(&tx.error)
+            .bind(&tx.claimed_by)
+            .bind(tx.claimed_at)
+            .bind(tx.ttl.map(|v| v as i64))
+            .bind(tx.confirmed_slot.map(|v| v as i64))
+            .bind(tx.next_retry_at)
+            .bind(updated_at)
+            .bind(&tx.id)
+            .execute(&mut *db_tx)
+            .await?;
+
+            if tx.status.blocks_dependents() {
+                block_dependents(&mut db_tx, &tx.id, &tx.status).await?;
+            }
+        }
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn mark_confirmed_many(&self, ids: &[String], slot: u64) -> Result<u64, StorageError> {
+        let updated_at = Utc::now();
+        let slot = slot as i64;
+
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	slot = $2,
+                	confirmed_slot = $2,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	updated_at = $3
+                WHERE
+                	status <> $1
+                	AND id = ANY($4);
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot)
+        .bind(updated_at)
+        .bind(ids)
+        .execute(&self.postgres.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn mark_failed(&self, id: &str, reason: &str) -> Result<(), StorageError> {
+        let status = TransactionStatus::Failed.to_string();
+        let updated_at = Utc::now();
+
+        let mut db_tx = self.postgres.db.begin().await?;
+        sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	error = $2,
+                	updated_at = $3
+                WHERE
+                	id = $4;
+            "#,
+        )
+        .bind(status)
+        .bind(reason)
+        .bind(updated_at)
+        .bind(id)
+        .execute(&mut *db_tx)
+        .await?;
+
+        block_dependents(&mut db_tx, id, &TransactionStatus::Failed).await?;
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn requeue(&self, id: &str) -> Result<(), StorageError> {
+        let mut db_tx = self.postgres.db.begin().await?;
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	slot = NULL,
+                	attempts = 0,
+                	error = NULL,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	next_retry_at = NULL,
+                	updated_at = $2
+                WHERE
+                	id = $3
+                	AND status IN ($4, $5);
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(Utc::now())
+        .bind(id)
+        .bind(TransactionStatus::Failed.to_string())
+        .bind(TransactionStatus::DeadLetter.to_string())
+        .execute(&mut *db_tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            db_tx.rollback().await?;
+            return Err(self.unchanged(id).await);
+        }
+        // the dependents it blocked can be valid again
+        unblock_dependents(&mut db_tx, id).await?;
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn requeue_all(&self, from: TransactionStatus) -> Result<u64, StorageError> {
+        if !from.is_requeueable() {
+            return Err(StorageError::InvalidStatus(format!(
+                "{from} transactions can't be requeued"
+            )));
+        }
+
+        let mut db_tx = self.postgres.db.begin().await?;
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	slot = NULL,
+                	attempts = 0,
+                	error = NULL,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	next_retry_at = NULL,
+                	updated_at = $2
+                WHERE
+                	status = $3
+                RETURNING
+                	id;
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(Utc::now())
+        .bind(from.to_string())
+        .fetch_all(&mut *db_tx)
+        .await?;
+
+        for id in &ids {
+            unblock_dependents(&mut db_tx, id).await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(ids.len() as u64)
+    }
+
+    async fn set_priority(
+        &self,
+        id: &str,
+        priority: TransactionPriority,
+    ) -> Result<(), StorageError> {
+        let priority: u32 = priority.into();
+
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	priority = $1,
+                	updated_at = $2
+                WHERE
+                	id = $3
+                	AND status NOT IN ($4, $5, $6, $7, $8);
+            "#,
+        )
+        .bind(priority as i32)
+        .bind(Utc::now())
+        .bind(id)
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(TransactionStatus::Failed.to_string())
+        .bind(TransactionStatus::DeadLetter.to_string())
+        .bind(TransactionStatus::Cancelled.to_string())
+        .bind(TransactionStatus::BlockedByDependency.to_string())
+        .execute(&self.postgres.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(self.unchanged(id).await);
+        }
+
+        Ok(())
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool, StorageError> {
+        // claim_next moves the tx to InFlight in a single statement, so a tx
+        // picked by the fanout can't be cancelled afterwards
+        let mut db_tx = self.postgres.db.begin().await?;
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	updated_at = $2
+                WHERE
+                	id = $3
+                	AND status IN ($4, $5);
+            "#,
+        )
+        .bind(TransactionStatus::Cancelled.to_string())
+        .bind(Utc::now())
+        .bind(id)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .execute(&mut *db_tx)
+        .await?;
+
+        let cancelled = result.rows_affected() > 0;
+        if cancelled {
+            block_dependents(&mut db_tx, id, &TransactionStatus::Cancelled).await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(cancelled)
+    }
+
+    async fn resubmit(&self, id: &str) -> Result<bool, StorageError> {
+        // a claimed tx has no slot until the fanout submits it, it's left to
+        // the fanout instead of being submitted twice
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = CASE WHEN status = $1 AND slot IS NOT NULL THEN $2 ELSE status END,
+                	claimed_by = CASE WHEN status = $1 AND slot IS NOT NULL THEN NULL ELSE claimed_by END,
+                	claimed_at = CASE WHEN status = $1 AND slot IS NOT NULL THEN NULL ELSE claimed_at END,
+                	slot = CASE WHEN status = $1 AND slot IS NOT NULL THEN NULL ELSE slot END,
+                	next_retry_at = NULL,
+                	updated_at = $3
+                WHERE
+                	id = $4
+                	AND status IN ($5, $6, $1);
+            "#,
+        )
+        .bind(TransactionStatus::InFlight.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .bind(Utc::now())
+        .bind(id)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .execute(&self.postgres.db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn increment_attempts(&self, id: &str) -> Result<u32, StorageError> {
+        let updated_at = Utc::now();
+
+        let attempts = sqlx::query_scalar::<_, i32>(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	attempts = attempts + 1,
+                	updated_at = $1
+                WHERE
+                	id = $2
+                RETURNING
+                	attempts;
+            "#,
+        )
+        .bind(updated_at)
+        .bind(id)
+        .fetch_optional(&self.postgres.db)
+        .await?;
+
+        attempts
+            .map(|v| v as u32)
+            .ok_or_else(|| StorageError::NotFound(id.to_string()))
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), StorageError> {
+        self.delete_many(&[id.to_string()]).await
+    }
+
+    async fn delete_many(&self, ids: &[String]) -> Result<(), StorageError> {
+        let mut db_tx = self.postgres.db.begin().await?;
+
+        for id in ids {
+            sqlx::query(
+                r#"
+                    DELETE FROM
+                    	tx_dependence
+                    WHERE
+                    	dependent_id = $1 OR required_id = $1;
+                "#,
+            )
+            .bind(id)
+            .execute(&mut *db_tx)
+            .await?;
+
+            sqlx::query(
+                r#"
+                    DELETE FROM
+                    	tx_submission
+                    WHERE
+                    	tx_id = $1;
+                "#,
+            )
+            .bind(id)
+            .execute(&mut *db_tx)
+            .await?;
+
+            sqlx::query(
+                r#"
+                    DELETE FROM
+                    	tx
+                    WHERE
+                    	id = $1;
+                "#,
+            )
+            .bind(id)
+            .execute(&mut *db_tx)
+            .await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn truncate_all(&self) -> Result<(), StorageError> {
+        let mut db_tx = self.postgres.db.begin().await?;
+
+        for table in ["tx_dependence", "tx_submission", "tx"] {
+            sqlx::query(&format!("DELETE FROM {table};"))
+                .execute(&mut *db_tx)
+                .await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn compact_confirmed_dependencies(&self, slot: u64) -> Result<u64, StorageError> {
+        let result = sqlx::query(
+            r#"
+                DELETE FROM
+                	tx_dependence
+                WHERE
+                	dependent_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2)
+                	AND required_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2);
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&self.postgres.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn prune_confirmed_before(&self, slot: u64) -> Result<u64, StorageError> {
+        let mut db_tx = self.postgres.db.begin().await?;
+
+        sqlx::query(
+            r#"
+                DELETE FROM
+                	tx_dependence
+                WHERE
+                	dependent_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2)
+                	OR required_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2);
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&mut *db_tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+                DELETE FROM
+                	tx_submission
+                WHERE
+                	tx_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2);
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&mut *db_tx)
+        .await?;
+
+        let result = sqlx::query(
+            r#"
+                DELETE FROM
+                	tx
+                WHERE
+                	status = $1
+                	AND confirmed_slot < $2;
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&mut *db_tx)
+        .await?;
+
+        db_tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn dependents_of(&self, id: &str) -> Result<Vec<String>, StorageError> {
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+                SELECT
+                	dependent_id
+                FROM
+                	tx_dependence
+                WHERE
+                	required_id = $1
+                ORDER BY
+                	dependent_id;
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        Ok(ids)
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        sqlx::query("SELECT 1").execute(&self.postgres.db).await?;
+        Ok(())
+    }
+
+    async fn status_counts(&self) -> Result<HashMap<TransactionStatus, i64>, StorageError> {
+        let rows = sqlx::query_as::<_, (String, i64)>(
+            r#"
+                SELECT
+                	status,
+                	COUNT(*)
+                FROM
+                	tx
+                GROUP BY
+                	status;
+            "#,
+        )
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        rows.into_iter()
+            .map(|(status, count)| Ok((status.parse::<TransactionStatus>()?, count)))
+            .collect()
+    }
+
+    async fn oldest_queued_at(&self) -> Result<Option<DateTime<Utc>>, StorageError> {
+        let oldest = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+            r#"
+                SELECT
+                	MIN(created_at)
+                FROM
+                	tx
+                WHERE
+                	status IN ($1, $2, $3);
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .bind(TransactionStatus::InFlight.to_string())
+        .fetch_one(&self.postgres.db)
+        .await?;
+
+        Ok(oldest)
+    }
+
+    async fn record_submission(
+        &self,
+        tx_id: &str,
+        peer: &str,
+        accepted: bool,
+    ) -> Result<(), StorageError> {
+        sqlx::query(
+            r#"
+                INSERT INTO tx_submission(
+                	tx_id,
+                	peer,
+                	submitted_at,
+                	accepted
+                )
+                VALUES ($1, $2, $3, $4);
+            "#,
+        )
+        .bind(tx_id)
+        .bind(peer)
+        .bind(Utc::now())
+        .bind(accepted)
+        .execute(&self.postgres.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn submissions_for(&self, tx_id: &str) -> Result<Vec<Submission>, StorageError> {
+        let rows = sqlx::query_as::<_, (String, String, DateTime<Utc>, bool)>(
+            r#"
+                SELECT
+                	tx_id,
+                	peer,
+                	submitted_at,
+                	accepted
+                FROM
+                	tx_submission
+                WHERE
+                	tx_id = $1
+                ORDER BY
+                	submitted_at;
+            "#,
+        )
+        .bind(tx_id)
+        .fetch_all(&self.postgres.db)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(tx_id, peer, submitted_at, accepted)| Submission {
+                tx_id,
+                peer,
+                submitted_at,
+                accepted,
+            })
+            .collect())
+    }
+}
+
+impl FromRow<'_, PgRow> for Cursor {
+    fn from_row(row: &PgRow) -> sqlx::Result<Self> {
+        let slot: i64 = row.try_get("slot")?;
+
+        Ok(Self {
+            slot: slot as u64,
+            hash: row.try_get("hash")?,
+        })
+    }
+}
+
+pub struct PostgresCursor {
+    postgres: Arc<PostgresStorage>,
+}
+impl PostgresCursor {
+    pub fn new(postgres: Arc<PostgresStorage>) -> Self {
+        Self { postgres }
+    }
+}
+
+#[async_trait::async_trait]
+impl CursorStore for PostgresCursor {
+    async fn set(&self, cursor: &Cursor) -> Result<()> {
+        let slot = cursor.slot as i64;
+
+        sqlx::query(
+            r#"
+                INSERT INTO cursor(
+                    id,
+                    slot,
+                    hash
+                )
+                VALUES (0, $1, $2)
+                ON CONFLICT (id) DO UPDATE SET
+                    slot = EXCLUDED.slot,
+                    hash = EXCLUDED.hash;
+            "#,
+        )
+        .bind(slot)
+        .bind(&cursor.hash)
+        .execute(&self.postgres.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn current(&self) -> Result<Option<Cursor>> {
+        let cursor = sqlx::query_as::<_, Cursor>(
+            r#"
+                    SELECT
+                    	slot,
+                    	hash
+                    FROM
+                    	cursor;
+            "#,
+        )
+        .fetch_optional(&self.postgres.db)
+        .await?;
+
+        Ok(cursor)
+    }
+}
+
+// These run against the database at BOROS_TEST_POSTGRES_URL, each in a schema of
+// its own, and are skipped when it isn't set.
+#[cfg(test)]
+mod postgres_transaction_tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use sqlx::postgres::PgPoolOptions;
+
+    use crate::storage::{StorageError, Transaction, TransactionStatus, TransactionStore};
+
+    use super::{PostgresStorage, PostgresTransaction};
+
+    async fn mock_postgres() -> Option<(PostgresTransaction, String)> {
+        let url = std::env::var("BOROS_TEST_POSTGRES_URL").ok()?;
+        let schema = format!("boros_{}", Utc::now().timestamp_nanos_opt().unwrap());
+
+        let search_path = format!("SET search_path TO {schema}");
+        let db = PgPoolOptions::new()
+            .max_connections(5)
+            .after_connect(move |conn, _| {
+                let search_path = search_path.clone();
+                Box::pin(async move {
+                    sqlx::query(&search_path).execute(conn).await?;
+                    Ok(())
+                })
+            })
+            .connect(&url)
+            .await
+            .unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {schema}"))
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let postgres_storage = Arc::new(PostgresStorage { db });
+        postgres_storage.migrate().await.unwrap();
+
+        Some((PostgresTransaction::new(postgres_storage), schema))
+    }
+
+    async fn drop_postgres(storage: PostgresTransaction, schema: &str) {
+        sqlx::query(&format!("DROP SCHEMA {schema} CASCADE"))
+            .execute(&storage.postgres.db)
+            .await
+            .unwrap();
+        storage.postgres.close().await;
+    }
+
+    async fn create_chain(storage: &PostgresTransaction) {
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "a".into(),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "b".into(),
+                    status: TransactionStatus::Validated,
+                    dependencies: Some(vec!["a".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "c".into(),
+                    dependencies: Some(vec!["b".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "other".into(),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_should_create_and_get() {
+        let Some((storage, schema)) = mock_postgres().await else {
+            return;
+        };
+
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
+
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+
+        let mut transaction_3 = Transaction::default();
+        transaction_3.id = "hex3".into();
+        transaction_3.dependencies = Some(vec!["hex1".into(), "hex2".into()]);
+
+        let result = storage
+            .create(&vec![transaction_1, transaction_2, transaction_3])
+            .await;
+        assert!(result.is_ok());
+
+        let transaction = storage.get("hex3").await.unwrap().unwrap();
+        assert!(transaction.id == "hex3");
+        assert!(matches!(transaction.status, TransactionStatus::Pending));
+
+        let mut dependencies = transaction.dependencies.unwrap();
+        dependencies.sort();
+        assert!(dependencies == vec!["hex1", "hex2"]);
+
+        let result = storage.get("hex4").await.unwrap();
+        assert!(result.is_none());
+
+        drop_postgres(storage, &schema).await;
+    }
+
+    #[tokio::test]
+    async fn it_should_create_or_get_existing() {
+        let Some((storage, schema)) = mock_postgres().await else {
+            return;
+        };
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let new = Transaction {
+            id: "hex2".into(),
+            ..Default::default()
+        };
+        let stored = storage
+            .create_or_get(&vec![Transaction::default(), new])
+            .await
+            .unwrap();
+        assert!(stored.len() == 2);
+        assert!(stored[0].status == TransactionStatus::InFlight);
+        assert!(stored[1].id == "hex2");
+
+        let result = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(result.len() == 1);
+
+        drop_postgres(storage, &schema).await;
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_create_with_dependency_cycle() {
+        let Some((storage, schema)) = mock_postgres().await else {
+            return;
+        };
+
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
+        transaction_1.dependencies = Some(vec!["hex2".into()]);
+
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+        transaction_2.dependencies = Some(vec!["hex1".into()]);
+
+        let result = storage.create(&vec![transaction_1, transaction_2]).await;
+        assert!(matches!(result, Err(StorageError::Cycle(_))));
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("hex1") && error.contains("hex2"));
+
+        drop_postgres(storage, &schema).await;
+    }
+
+    #[tokio::test]
+    async fn it_should_claim_distinct_transactions_concurrently() {
+        let Some((storage, schema)) = mock_postgres().await else {
+            return;
+        };
+
+        let transactions: Vec<Transaction> = (0..2)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let (claim_1, claim_2) = tokio::join!(
+            storage.claim_next(TransactionStatus::Pending, None, "worker1"),
+            storage.claim_next(TransactionStatus::Pending, None, "worker2"),
+        );
+
+        let claim_1 = claim_1.unwrap().unwrap();
+        let claim_2 = claim_2.unwrap().unwrap();
+        assert!(claim_1.id != claim_2.id);
+        assert!(claim_1.status == TransactionStatus::InFlight);
+
+        drop_postgres(storage, &schema).await;
+    }
+
+    #[tokio::test]
+    async fn it_should_requeue() {
+        let Some((storage, schema)) = mock_postgres().await else {
+            return;
+        };
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+        storage.increment_attempts(&transaction.id).await.unwrap();
+        storage
+            .mark_failed(&transaction.id, "invalid tx")
+            .await
+            .unwrap();
+
+        let result = storage.requeue(&transaction.id).await;
+        assert!(result.is_ok());
+
+        let result = storage.get(&transaction.id).await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Pending));
+        assert!(result.error.is_none());
+        assert!(result.attempts == 0);
+        assert!(result.next_retry_at.is_none());
+
+        let result = storage.requeue(&transaction.id).await;
+        assert!(matches!(result, Err(StorageError::InvalidStatus(_))));
+
+        drop_postgres(storage, &schema).await;
+    }
+
+    #[tokio::test]
+    async fn it_should_block_dependents_of_failed() {
+        let Some((storage, schema)) = mock_postgres().await else {
+            return;
+        };
+        create_chain(&storage).await;
+
+        let result = storage.mark_failed("a", "invalid tx").await;
+        assert!(result.is_ok());
+
+        for id in ["b", "c"] {
+            let tx = storage.get(id).await.unwrap().unwrap();
+            assert!(tx.status == TransactionStatus::BlockedByDependency);
+            assert!(tx.error == Some("dependency a is failed".into()));
+        }
+
+        let tx = storage.get("other").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+
+        drop_postgres(storage, &schema).await;
+    }
+
+    #[tokio::test]
+    async fn it_should_unblock_dependents_of_requeued() {
+        let Some((storage, schema)) = mock_postgres().await else {
+            return;
+        };
+        create_chain(&storage).await;
+        storage.mark_failed("a", "invalid tx").await.unwrap();
+
+        let result = storage.requeue("a").await;
+        assert!(result.is_ok());
+
+        for id in ["a", "b", "c"] {
+            let tx = storage.get(id).await.unwrap().unwrap();
+            assert!(tx.status == TransactionStatus::Pending);
+            assert!(tx.error.is_none());
+        }
+
+        drop_postgres(storage, &schema).await;
+    }
+}
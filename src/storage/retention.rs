@@ -0,0 +1,40 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::time::interval;
+use tracing::{error, info};
+
+use super::{CursorStore, TransactionStore};
+
+/// How often the confirmed transactions past the retention are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Periodically deletes the transactions confirmed longer than `retention`
+/// ago, kept until then to audit them. It's measured in slots from the
+/// cursor, a slot a second, so it follows the chain rather than the clock.
+pub async fn run(
+    storage: Arc<dyn TransactionStore>,
+    cursor: Arc<dyn CursorStore>,
+    retention: Duration,
+) {
+    let mut interval = interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // nothing is pruned before the first block is processed
+        let slot = match cursor.current().await {
+            Ok(Some(cursor)) => cursor.slot.saturating_sub(retention.as_secs()),
+            Ok(None) => continue,
+            Err(error) => {
+                error!(?error, "failed to read the cursor to prune");
+                continue;
+            }
+        };
+
+        match storage.prune_confirmed_before(slot).await {
+            Ok(0) => {}
+            Ok(count) => info!(count, "pruned confirmed transactions"),
+            Err(error) => error!(?error, "failed to prune confirmed transactions"),
+        }
+    }
+}
@@ -1,44 +1,320 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::{Error, Result};
-use chrono::Utc;
-use sqlx::{sqlite::SqliteRow, FromRow, Row};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteRow, SqliteSynchronous},
+    FromRow, QueryBuilder, Row, Sqlite, SqliteConnection,
+};
+use tokio::time::interval;
+use tracing::{error, info, warn};
 
-use super::{Cursor, Transaction, TransactionStatus};
+use super::{
+    clock::{Clock, SystemClock},
+    find_dependency_cycle, migration_error, Config, Cursor, CursorStore, QueueOrder, StorageError,
+    Submission, Transaction, TransactionPriority, TransactionStatus, TransactionStore,
+    ROLLBACK_WINDOW_SLOTS,
+};
+
+static MIGRATOR: Migrator = sqlx::migrate!("src/storage/migrations");
+
+/// Ids bound in a single `IN (...)`, well below the 999 parameters older
+/// SQLite versions accept in a statement.
+const IN_CHUNK_SIZE: usize = 500;
+
+/// Dependency edges inserted in a single statement, binding two parameters
+/// each.
+const EDGE_CHUNK_SIZE: usize = IN_CHUNK_SIZE / 2;
+
+/// Primary result codes of a database held by another connection, the extended
+/// codes keep them in the low byte.
+const SQLITE_BUSY: i64 = 5;
+const SQLITE_LOCKED: i64 = 6;
+
+/// Wait before the first retry of a busy write, doubled on each attempt.
+const BUSY_BACKOFF_MS: u64 = 10;
 
 pub struct SqliteStorage {
     db: sqlx::sqlite::SqlitePool,
+    write_retries: u32,
 }
 
 impl SqliteStorage {
-    pub async fn new(path: &Path) -> Result<Self> {
-        let url = format!("sqlite:{}?mode=rwc", path.display());
-        let db = sqlx::sqlite::SqlitePoolOptions::new().connect(&url).await?;
+    pub async fn new(config: &Config) -> Result<Self> {
+        // WAL lets the pipeline read while the server writes, and the busy timeout makes
+        // concurrent writers wait for the lock instead of failing with "database is locked".
+        let options = SqliteConnectOptions::new()
+            .filename(&config.db_path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(Duration::from_millis(config.busy_timeout_ms));
+
+        let db = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .connect_with(options)
+            .await?;
 
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            write_retries: config.write_retries,
+        })
     }
 
     pub async fn migrate(&self) -> Result<()> {
-        sqlx::migrate!("src/storage/migrations")
+        self.run_migrations(&MIGRATOR).await
+    }
+
+    async fn run_migrations(&self, migrator: &Migrator) -> Result<()> {
+        migrator
             .run(&self.db)
-            .await?;
+            .await
+            .map_err(|error| migration_error(migrator, error))
+    }
+
+    pub async fn close(&self) {
+        self.db.close().await;
+    }
+
+    /// Moves the WAL into the database file and truncates it, then vacuums the
+    /// file to give back the pages freed by deleted transactions. The vacuum is
+    /// skipped when the checkpoint can't complete, as another connection is
+    /// using the database and vacuuming would hold the lock for long.
+    pub async fn maintenance(&self) -> Result<()> {
+        if !self.checkpoint().await? {
+            info!("database busy, skipping vacuum");
+            return Ok(());
+        }
+
+        sqlx::query("VACUUM;").execute(&self.db).await?;
+        // the vacuum itself is written to the WAL
+        self.checkpoint().await?;
 
         Ok(())
     }
 
+    /// Returns whether the whole WAL was checkpointed.
+    async fn checkpoint(&self) -> Result<bool> {
+        let (busy, _, _): (i64, i64, i64) = sqlx::query_as("PRAGMA wal_checkpoint(TRUNCATE);")
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(busy == 0)
+    }
+
     #[cfg(test)]
     pub async fn ephemeral() -> Result<Self> {
         let db = sqlx::sqlite::SqlitePoolOptions::new()
             .connect("sqlite::memory:")
             .await?;
 
-        let out = Self { db };
+        let out = Self {
+            db,
+            write_retries: 0,
+        };
         out.migrate().await?;
 
         Ok(out)
     }
 }
 
+/// Retries `write` while the database answers busy or locked, which the busy
+/// timeout doesn't cover, e.g. a read transaction that can't be upgraded to a
+/// write one. Any other error is returned right away.
+async fn retry_busy<T, F, Fut>(retries: u32, mut write: F) -> Result<T, StorageError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, StorageError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match write().await {
+            Err(error) if attempt < retries && is_busy(&error) => {
+                attempt += 1;
+                let backoff = busy_backoff(attempt);
+                warn!(attempt, ?backoff, "database busy, retrying the write");
+                tokio::time::sleep(backoff).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_busy(error: &StorageError) -> bool {
+    match error {
+        StorageError::Db(sqlx::Error::Database(error)) => error
+            .code()
+            .and_then(|code| code.parse::<i64>().ok())
+            .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED)),
+        _ => false,
+    }
+}
+
+/// Doubles the wait on each attempt, with a jitter of up to the same amount so
+/// the writers waiting for the same lock don't retry at once.
+fn busy_backoff(attempt: u32) -> Duration {
+    let base = BUSY_BACKOFF_MS << attempt.saturating_sub(1).min(10);
+    let jitter = u64::from(Utc::now().timestamp_subsec_nanos()) % base;
+    Duration::from_millis(base + jitter)
+}
+
+/// Moves every transaction that requires `parent_id`, directly or through
+/// another dependent, to `BlockedByDependency`, as it can never be valid
+/// without it, e.g. once it failed or was cancelled with `status`. The
+/// submitted and terminal ones are kept as they are.
+async fn block_dependents(
+    db: &mut SqliteConnection,
+    parent_id: &str,
+    status: &TransactionStatus,
+    updated_at: DateTime<Utc>,
+) -> Result<u64, StorageError> {
+    let result = sqlx::query(
+        r#"
+            WITH RECURSIVE dependents(id) AS (
+            	SELECT
+            		dependent_id
+            	FROM
+            		tx_dependence
+            	WHERE
+            		required_id = $1
+            	UNION
+            	SELECT
+            		tx_dependence.dependent_id
+            	FROM
+            		tx_dependence
+            		JOIN dependents ON tx_dependence.required_id = dependents.id
+            )
+            UPDATE
+            	tx
+            SET
+            	status = $2,
+            	error = $3,
+            	updated_at = $4
+            WHERE
+            	id IN (SELECT id FROM dependents)
+            	AND status IN ($5, $6);
+        "#,
+    )
+    .bind(parent_id)
+    .bind(TransactionStatus::BlockedByDependency.to_string())
+    .bind(format!("dependency {parent_id} is {status}"))
+    .bind(updated_at)
+    .bind(TransactionStatus::Pending.to_string())
+    .bind(TransactionStatus::Validated.to_string())
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Moves the dependents [`block_dependents`] blocked on `parent_id` back to
+/// `Pending` once none of the transactions they require blocks them anymore,
+/// e.g. after it was requeued. A level of dependents is unblocked at a time,
+/// as each waits for the ones it requires to be unblocked first.
+async fn unblock_dependents(
+    db: &mut SqliteConnection,
+    parent_id: &str,
+    updated_at: DateTime<Utc>,
+) -> Result<u64, StorageError> {
+    let mut unblocked = 0;
+    loop {
+        let result = sqlx::query(
+            r#"
+                WITH RECURSIVE dependents(id) AS (
+                	SELECT
+                		dependent_id
+                	FROM
+                		tx_dependence
+                	WHERE
+                		required_id = $1
+                	UNION
+                	SELECT
+                		tx_dependence.dependent_id
+                	FROM
+                		tx_dependence
+                		JOIN dependents ON tx_dependence.required_id = dependents.id
+                )
+                UPDATE
+                	tx
+                SET
+                	status = $2,
+                	error = NULL,
+                	updated_at = $3
+                WHERE
+                	id IN (SELECT id FROM dependents)
+                	AND status = $4
+                	AND NOT EXISTS (
+                		SELECT
+                			1
+                		FROM
+                			tx_dependence
+                			JOIN tx AS required ON required.id = tx_dependence.required_id
+                		WHERE
+                			tx_dependence.dependent_id = tx.id
+                			AND required.status IN ($4, $5, $6, $7)
+                	);
+            "#,
+        )
+        .bind(parent_id)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(updated_at)
+        .bind(TransactionStatus::BlockedByDependency.to_string())
+        .bind(TransactionStatus::Failed.to_string())
+        .bind(TransactionStatus::DeadLetter.to_string())
+        .bind(TransactionStatus::Cancelled.to_string())
+        .execute(&mut *db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(unblocked);
+        }
+        unblocked += result.rows_affected();
+    }
+}
+
+/// Runs the maintenance of the database every `period`, the dependencies
+/// between transactions confirmed past the rollback window are deleted before
+/// the vacuum so their pages are given back too.
+pub async fn maintain(storage: Arc<SqliteStorage>, period: Duration) {
+    let transactions = SqliteTransaction::new(storage.clone());
+    let cursor = SqliteCursor::new(storage.clone());
+    let mut interval = interval(period);
+    // the first tick completes immediately, there's nothing to compact at startup
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        // nothing is compacted before the first block is processed
+        match cursor.current().await {
+            Ok(Some(cursor)) => {
+                let stable_slot = cursor.slot.saturating_sub(ROLLBACK_WINDOW_SLOTS);
+                match transactions
+                    .compact_confirmed_dependencies(stable_slot)
+                    .await
+                {
+                    Ok(removed) => info!(removed, "confirmed dependencies compacted"),
+                    Err(error) => error!(?error, "failed to compact the confirmed dependencies"),
+                }
+            }
+            Ok(None) => {}
+            Err(error) => error!(?error, "failed to read the cursor to compact"),
+        }
+
+        match storage.maintenance().await {
+            Ok(()) => info!("database maintenance completed"),
+            Err(error) => error!(?error, "failed to run database maintenance"),
+        }
+    }
+}
+
 impl FromRow<'_, SqliteRow> for Transaction {
     fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
         let status: &str = row.try_get("status")?;
@@ -55,6 +331,19 @@ impl FromRow<'_, SqliteRow> for Transaction {
                 .map_err(|err: Error| sqlx::Error::Decode(err.into()))?,
             slot: row.try_get("slot")?,
             dependencies: None,
+            attempts: row.try_get("attempts")?,
+            error: row.try_get("error")?,
+            claimed_by: row.try_get("claimed_by")?,
+            claimed_at: row.try_get("claimed_at")?,
+            ttl: row.try_get("ttl")?,
+            confirmed_slot: row.try_get("confirmed_slot")?,
+            next_retry_at: row.try_get("next_retry_at")?,
+            expires_at: row.try_get("expires_at")?,
+            metadata: row.try_get("metadata")?,
+            source: row.try_get("source")?,
+            submitter: row.try_get("submitter")?,
+            replaces: row.try_get("replaces")?,
+            namespace: row.try_get("namespace")?,
             created_at: row.try_get("created_at")?,
             updated_at: row.try_get("updated_at")?,
         })
@@ -63,66 +352,529 @@ impl FromRow<'_, SqliteRow> for Transaction {
 
 pub struct SqliteTransaction {
     sqlite: Arc<SqliteStorage>,
+    ordering: QueueOrder,
+    clock: Arc<dyn Clock>,
 }
 
 impl SqliteTransaction {
     pub fn new(sqlite: Arc<SqliteStorage>) -> Self {
-        Self { sqlite }
+        Self {
+            sqlite,
+            ordering: QueueOrder::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Changes where the timestamps written and the times the transactions
+    /// are compared against come from, e.g. a `MockClock` in the tests.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Changes the order the transactions of a status are picked in.
+    pub fn with_ordering(mut self, ordering: QueueOrder) -> Self {
+        self.ordering = ordering;
+        self
+    }
+
+    /// Query of `next_batch`, apart so its plan can be checked.
+    fn next_batch_query(&self) -> String {
+        format!(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.status = $1
+                    	AND (tx.next_retry_at IS NULL OR tx.next_retry_at <= $3)
+                    	AND ($4 IS NULL OR tx.namespace = $4)
+                    ORDER BY
+                    	{order_by}
+                    LIMIT $2;
+            "#,
+            order_by = self.ordering.order_by(),
+        )
+    }
+
+    pub async fn hydrate_dependencies(&self, txs: &mut [Transaction]) -> Result<(), StorageError> {
+        if txs.is_empty() {
+            return Ok(());
+        }
+
+        let mut dependencies: HashMap<String, Vec<String>> = HashMap::new();
+        for chunk in txs.chunks(IN_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                r#"
+                    SELECT
+                    	dependent_id,
+                    	required_id
+                    FROM
+                    	tx_dependence
+                    WHERE
+                    	dependent_id IN (
+                "#,
+            );
+            let mut separated = query.separated(", ");
+            for tx in chunk {
+                separated.push_bind(tx.id.clone());
+            }
+            separated.push_unseparated(")");
+
+            let edges = query
+                .build_query_as::<(String, String)>()
+                .fetch_all(&self.sqlite.db)
+                .await?;
+            for (dependent_id, required_id) in edges {
+                dependencies
+                    .entry(dependent_id)
+                    .or_default()
+                    .push(required_id);
+            }
+        }
+        for tx in txs.iter_mut() {
+            tx.dependencies = dependencies.remove(&tx.id);
+        }
+
+        Ok(())
     }
 
-    pub async fn create(&self, txs: &Vec<Transaction>) -> Result<()> {
+    /// A single attempt of `create`, rolled back as a whole when it fails. A
+    /// transaction already stored is a `Conflict`, unless `keep_stored` and
+    /// it has the same raw, then it's kept as it is. Returns the ids of the
+    /// kept ones.
+    async fn try_create(
+        &self,
+        txs: &[Transaction],
+        keep_stored: bool,
+    ) -> Result<HashSet<String>, StorageError> {
         let mut db_tx = self.sqlite.db.begin().await?;
 
+        // inserted first, so a concurrent submission of the same transaction
+        // waits for this one to commit instead of failing on the primary key
+        let mut kept = HashSet::new();
         for tx in txs {
             let status = tx.status.clone().to_string();
-            let priority: u32 = tx.priority.clone().try_into()?;
+            let priority: u32 = tx.priority.clone().into();
 
-            sqlx::query!(
+            let result = sqlx::query!(
                 r#"
                     INSERT INTO tx (
                         id,
                         raw,
                         status,
                         priority,
+                        attempts,
+                        ttl,
+                        namespace,
+                        expires_at,
+                        metadata,
+                        source,
+                        submitter,
+                        replaces,
                         created_at,
                         updated_at
                     )
-                    VALUES ($1, $2, $3, $4, $5, $6)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+                    ON CONFLICT (id) DO NOTHING
                 "#,
                 tx.id,
                 tx.raw,
                 status,
                 priority,
+                tx.attempts,
+                tx.ttl.map(|v| v as i64),
+                tx.namespace,
+                tx.expires_at,
+                tx.metadata,
+                tx.source,
+                tx.submitter,
+                tx.replaces,
                 tx.created_at,
                 tx.updated_at
             )
             .execute(&mut *db_tx)
             .await?;
+            if result.rows_affected() == 1 {
+                continue;
+            }
 
-            if let Some(dependencies) = &tx.dependencies {
-                for required_id in dependencies {
-                    sqlx::query!(
-                        r#"
-                            INSERT INTO tx_dependence (
-                                dependent_id,
-                                required_id
-                            )
-                            VALUES ($1, $2)
-                        "#,
-                        tx.id,
-                        required_id,
+            let stored_raw: Option<Vec<u8>> =
+                sqlx::query_scalar("SELECT raw FROM tx WHERE id = $1;")
+                    .bind(&tx.id)
+                    .fetch_optional(&mut *db_tx)
+                    .await?;
+            if !keep_stored || stored_raw.is_some_and(|raw| raw != tx.raw) {
+                return Err(StorageError::Conflict(tx.id.clone()));
+            }
+            kept.insert(tx.id.clone());
+        }
+        let txs: Vec<&Transaction> = txs.iter().filter(|tx| !kept.contains(&tx.id)).collect();
+
+        if txs.iter().any(|tx| tx.dependencies.is_some()) {
+            // a cycle has to go through a new edge, so only the stored edges
+            // reachable from the new dependencies are walked
+            let required_ids: Vec<&String> = txs
+                .iter()
+                .flat_map(|tx| tx.dependencies.iter().flatten())
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+
+            let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+            let mut walked: HashSet<(String, String)> = HashSet::new();
+            for chunk in required_ids.chunks(IN_CHUNK_SIZE) {
+                let mut query = QueryBuilder::<Sqlite>::new("WITH RECURSIVE reachable(id) AS (");
+                query.push_values(chunk, |mut row, required_id| {
+                    row.push_bind(required_id.to_string());
+                });
+                query.push(
+                    r#"
+                    	UNION
+                    	SELECT
+                    		tx_dependence.required_id
+                    	FROM
+                    		tx_dependence
+                    		JOIN reachable ON tx_dependence.dependent_id = reachable.id
                     )
-                    .execute(&mut *db_tx)
+                    SELECT
+                    	dependent_id,
+                    	required_id
+                    FROM
+                    	tx_dependence
+                    WHERE
+                    	dependent_id IN (SELECT id FROM reachable);
+                "#,
+                );
+
+                let edges = query
+                    .build_query_as::<(String, String)>()
+                    .fetch_all(&mut *db_tx)
                     .await?;
+                for edge in edges {
+                    if walked.insert(edge.clone()) {
+                        graph.entry(edge.0).or_default().push(edge.1);
+                    }
+                }
+            }
+            for tx in &txs {
+                if let Some(dependencies) = &tx.dependencies {
+                    graph
+                        .entry(tx.id.clone())
+                        .or_default()
+                        .extend(dependencies.iter().cloned());
                 }
             }
+
+            if let Some(cycle) = find_dependency_cycle(&graph) {
+                return Err(StorageError::Cycle(cycle));
+            }
+        }
+
+        // inserted once every transaction of the batch is stored, as an edge
+        // can require a transaction after it in the batch
+        let edges: Vec<(&String, &String)> = txs
+            .iter()
+            .flat_map(|tx| {
+                tx.dependencies
+                    .iter()
+                    .flatten()
+                    .map(move |required_id| (&tx.id, required_id))
+            })
+            .collect();
+        for chunk in edges.chunks(EDGE_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                r#"
+                    INSERT INTO tx_dependence (
+                    	dependent_id,
+                    	required_id
+                    )
+                "#,
+            );
+            query.push_values(chunk, |mut row, (dependent_id, required_id)| {
+                row.push_bind(dependent_id.to_string())
+                    .push_bind(required_id.to_string());
+            });
+
+            query.build().execute(&mut *db_tx).await?;
+        }
+
+        for tx in &txs {
+            let Some(replaced_id) = &tx.replaces else {
+                continue;
+            };
+            let result = sqlx::query(
+                r#"
+                    UPDATE
+                    	tx
+                    SET
+                    	status = $1,
+                    	updated_at = $2
+                    WHERE
+                    	id = $3
+                    	AND status IN ($4, $5);
+                "#,
+            )
+            .bind(TransactionStatus::Cancelled.to_string())
+            .bind(self.clock.now())
+            .bind(replaced_id)
+            .bind(TransactionStatus::Pending.to_string())
+            .bind(TransactionStatus::Validated.to_string())
+            .execute(&mut *db_tx)
+            .await?;
+
+            if result.rows_affected() == 0 {
+                return Err(StorageError::NotReplaceable(replaced_id.clone()));
+            }
+
+            // the dependents of the replaced one now wait for the replacement,
+            // a cancelled dependency would hold them back forever
+            sqlx::query(
+                r#"
+                    UPDATE
+                    	tx_dependence
+                    SET
+                    	required_id = $1
+                    WHERE
+                    	required_id = $2
+                    	AND dependent_id <> $1
+                    	AND NOT EXISTS (
+                    		SELECT
+                    			1
+                    		FROM
+                    			tx_dependence AS existing
+                    		WHERE
+                    			existing.dependent_id = tx_dependence.dependent_id
+                    			AND existing.required_id = $1
+                    	);
+                "#,
+            )
+            .bind(&tx.id)
+            .bind(replaced_id)
+            .execute(&mut *db_tx)
+            .await?;
+            sqlx::query(
+                r#"
+                    DELETE FROM
+                    	tx_dependence
+                    WHERE
+                    	required_id = $1;
+                "#,
+            )
+            .bind(replaced_id)
+            .execute(&mut *db_tx)
+            .await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(kept)
+    }
+
+    /// A single attempt of `update`.
+    async fn try_update(&self, tx: &Transaction) -> Result<(), StorageError> {
+        let status = tx.status.to_string();
+        let updated_at = self.clock.now();
+        // TODO: check the maximium size of i64 and compare with cardano slot.
+        let slot = tx.slot.map(|v| v as i64);
+
+        let mut db_tx = self.sqlite.db.begin().await?;
+        sqlx::query!(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	raw = $1,
+                	status = $2,
+                	slot = $3,
+                	error = $4,
+                	claimed_by = $5,
+                	claimed_at = $6,
+                	ttl = $7,
+                	confirmed_slot = $8,
+                	next_retry_at = $9,
+                	updated_at = $10
+                WHERE
+                	id = $11;
+            "#,
+            tx.raw,
+            status,
+            slot,
+            tx.error,
+            tx.claimed_by,
+            tx.claimed_at,
+            tx.ttl.map(|v| v as i64),
+            tx.confirmed_slot.map(|v| v as i64),
+            tx.next_retry_at,
+            updated_at,
+            tx.id,
+        )
+        .execute(&mut *db_tx)
+        .await?;
+
+        if tx.status.blocks_dependents() {
+            block_dependents(&mut db_tx, &tx.id, &tx.status, self.clock.now()).await?;
         }
 
         db_tx.commit().await?;
         Ok(())
     }
 
-    pub async fn find(&self, status: TransactionStatus) -> Result<Vec<Transaction>> {
+    /// The error of a change that matched no transaction, as it's either
+    /// unknown or in a status the change doesn't apply to.
+    async fn unchanged(&self, id: &str) -> StorageError {
+        let status: Result<Option<String>, sqlx::Error> =
+            sqlx::query_scalar("SELECT status FROM tx WHERE id = $1;")
+                .bind(id)
+                .fetch_optional(&self.sqlite.db)
+                .await;
+
+        match status {
+            Ok(Some(status)) => {
+                StorageError::InvalidStatus(format!("transaction {id} is {status}"))
+            }
+            Ok(None) => StorageError::NotFound(id.to_string()),
+            Err(error) => error.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TransactionStore for SqliteTransaction {
+    async fn create(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        retry_busy(self.sqlite.write_retries, || self.try_create(txs, false)).await?;
+        Ok(())
+    }
+
+    async fn create_or_get(
+        &self,
+        txs: &Vec<Transaction>,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let kept = retry_busy(self.sqlite.write_retries, || self.try_create(txs, true)).await?;
+
+        let mut stored = Vec::with_capacity(txs.len());
+        for tx in txs {
+            match kept.contains(&tx.id) {
+                true => stored.push(self.get(&tx.id).await?.unwrap_or_else(|| tx.clone())),
+                false => stored.push(tx.clone()),
+            }
+        }
+        Ok(stored)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Transaction>, StorageError> {
+        let mut transaction = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.id = $1;
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.sqlite.db)
+        .await?;
+
+        if let Some(transaction) = transaction.as_mut() {
+            self.hydrate_dependencies(std::slice::from_mut(transaction))
+                .await?;
+        }
+
+        Ok(transaction)
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Transaction>, StorageError> {
+        let mut transactions = Vec::new();
+        for chunk in ids.chunks(IN_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.id IN (
+                "#,
+            );
+            let mut separated = query.separated(", ");
+            for id in chunk {
+                separated.push_bind(id.clone());
+            }
+            separated.push_unseparated(")");
+
+            transactions.extend(
+                query
+                    .build_query_as::<Transaction>()
+                    .fetch_all(&self.sqlite.db)
+                    .await?,
+            );
+        }
+
+        self.hydrate_dependencies(&mut transactions).await?;
+
+        Ok(transactions)
+    }
+
+    async fn find(&self, status: TransactionStatus) -> Result<Vec<Transaction>, StorageError> {
         let transactions = sqlx::query_as::<_, Transaction>(
             r#"
                     SELECT
@@ -132,7 +884,20 @@ impl SqliteTransaction {
                         slot,
                     	priority,
                     	created_at,
-                    	updated_at
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
                     FROM
                     	tx
                     WHERE
@@ -146,7 +911,7 @@ impl SqliteTransaction {
         Ok(transactions)
     }
 
-    pub async fn find_to_rollback(&self, slot: u64) -> Result<Vec<Transaction>> {
+    async fn find_to_rollback(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
         let status = TransactionStatus::Confirmed.to_string();
         let slot = slot as i64;
 
@@ -159,7 +924,20 @@ impl SqliteTransaction {
                         slot,
                     	priority,
                     	created_at,
-                    	updated_at
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
                     FROM
                     	tx
                     WHERE
@@ -174,8 +952,11 @@ impl SqliteTransaction {
         Ok(transactions)
     }
 
-    pub async fn next(&self, status: TransactionStatus) -> Result<Option<Transaction>> {
-        let transaction = sqlx::query_as::<_, Transaction>(
+    async fn find_in_flight_before(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        let status = TransactionStatus::InFlight.to_string();
+        let slot = slot as i64;
+
+        let transactions = sqlx::query_as::<_, Transaction>(
             r#"
                     SELECT
                     	id,
@@ -184,170 +965,3331 @@ impl SqliteTransaction {
                         slot,
                     	priority,
                     	created_at,
-                    	updated_at
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
                     FROM
                     	tx
                     WHERE
-                    	tx.status = $1
-                    ORDER BY
-                    	priority,
-                    	created_at ASC
-                    LIMIT 1;
+                    	tx.status = $1 AND tx.slot < $2;
             "#,
         )
-        .bind(status.to_string())
-        .fetch_optional(&self.sqlite.db)
+        .bind(status)
+        .bind(slot)
+        .fetch_all(&self.sqlite.db)
         .await?;
 
-        Ok(transaction)
+        Ok(transactions)
     }
 
-    pub async fn update(&self, tx: &Transaction) -> Result<()> {
-        let status = tx.status.to_string();
-        let updated_at = Utc::now();
-        // TODO: check the maximium size of i64 and compare with cardano slot.
-        let slot = tx.slot.map(|v| v as i64);
+    async fn next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        Ok(self
+            .next_batch(status, namespace, 1)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn next_batch(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        if status.is_terminal() {
+            return Ok(Vec::new());
+        }
+
+        let mut transactions = sqlx::query_as::<_, Transaction>(&self.next_batch_query())
+            .bind(status.to_string())
+            .bind(limit)
+            .bind(self.clock.now())
+            .bind(namespace)
+            .fetch_all(&self.sqlite.db)
+            .await?;
+
+        self.hydrate_dependencies(&mut transactions).await?;
+
+        Ok(transactions)
+    }
+
+    async fn next_ready(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        if status.is_terminal() {
+            return Ok(None);
+        }
+
+        let mut transaction = sqlx::query_as::<_, Transaction>(&format!(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	tx.status = $1
+                    	AND (tx.next_retry_at IS NULL OR tx.next_retry_at <= $3)
+                    	AND ($4 IS NULL OR tx.namespace = $4)
+                    	AND NOT EXISTS (
+                    		SELECT
+                    			1
+                    		FROM
+                    			tx_dependence
+                    			INNER JOIN tx AS required ON required.id = tx_dependence.required_id
+                    		WHERE
+                    			tx_dependence.dependent_id = tx.id
+                    			AND required.status != $2
+                    	)
+                    ORDER BY
+                    	{order_by}
+                    LIMIT 1;
+            "#,
+            order_by = self.ordering.order_by(),
+        ))
+        .bind(status.to_string())
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(self.clock.now())
+        .bind(namespace)
+        .fetch_optional(&self.sqlite.db)
+        .await?;
+
+        if let Some(transaction) = transaction.as_mut() {
+            self.hydrate_dependencies(std::slice::from_mut(transaction))
+                .await?;
+        }
+
+        Ok(transaction)
+    }
+
+    async fn claim_next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        worker_id: &str,
+    ) -> Result<Option<Transaction>, StorageError> {
+        if status.is_terminal() {
+            return Ok(None);
+        }
+
+        let updated_at = self.clock.now();
+
+        // The select and the transition run as a single statement, so the row can't be
+        // claimed twice.
+        let mut transaction = sqlx::query_as::<_, Transaction>(&format!(
+            r#"
+                    UPDATE
+                    	tx
+                    SET
+                    	status = $1,
+                    	claimed_by = $2,
+                    	claimed_at = $3,
+                    	updated_at = $3
+                    WHERE
+                    	id = (
+                    		SELECT
+                    			id
+                    		FROM
+                    			tx AS candidate
+                    		WHERE
+                    			candidate.status = $4
+                    			AND (candidate.next_retry_at IS NULL OR candidate.next_retry_at <= $3)
+                    			AND ($6 IS NULL OR candidate.namespace = $6)
+                    			AND NOT EXISTS (
+                    				SELECT
+                    					1
+                    				FROM
+                    					tx_dependence
+                    					INNER JOIN tx AS required ON required.id = tx_dependence.required_id
+                    				WHERE
+                    					tx_dependence.dependent_id = candidate.id
+                    					AND required.status != $5
+                    			)
+                    		ORDER BY
+                    			{order_by}
+                    		LIMIT 1
+                    	)
+                    RETURNING
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace;
+            "#,
+            order_by = self.ordering.order_by(),
+        ))
+        .bind(TransactionStatus::InFlight.to_string())
+        .bind(worker_id)
+        .bind(updated_at)
+        .bind(status.to_string())
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(namespace)
+        .fetch_optional(&self.sqlite.db)
+        .await?;
+
+        if let Some(transaction) = transaction.as_mut() {
+            self.hydrate_dependencies(std::slice::from_mut(transaction))
+                .await?;
+        }
+
+        Ok(transaction)
+    }
+
+    async fn reclaim_expired(&self, older_than: Duration) -> Result<u64, StorageError> {
+        let updated_at = self.clock.now();
+        let claimed_before = updated_at
+            - chrono::Duration::from_std(older_than)
+                .map_err(|error| StorageError::Other(error.into()))?;
+
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	updated_at = $2
+                WHERE
+                	status = $3
+                	AND slot IS NULL
+                	AND claimed_at < $4;
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(updated_at)
+        .bind(TransactionStatus::InFlight.to_string())
+        .bind(claimed_before)
+        .execute(&self.sqlite.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn release_claims(&self, worker_id: &str) -> Result<u64, StorageError> {
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	updated_at = $2
+                WHERE
+                	status = $3
+                	AND slot IS NULL
+                	AND claimed_by = $4;
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(self.clock.now())
+        .bind(TransactionStatus::InFlight.to_string())
+        .bind(worker_id)
+        .execute(&self.sqlite.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn rollback_to(&self, slot: u64) -> Result<u64, StorageError> {
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	slot = NULL,
+                	confirmed_slot = NULL,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	updated_at = $2
+                WHERE
+                	status = $3
+                	AND confirmed_slot > $4;
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(self.clock.now())
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&self.sqlite.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn expire_past_ttl(&self, current_slot: u64) -> Result<u64, StorageError> {
+        let updated_at = self.clock.now();
+        let current_slot = current_slot as i64;
+
+        let mut db_tx = self.sqlite.db.begin().await?;
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	error = $2,
+                	updated_at = $3
+                WHERE
+                	ttl < $4
+                	AND status IN ($5, $6, $7)
+                RETURNING
+                	id;
+            "#,
+        )
+        .bind(TransactionStatus::Failed.to_string())
+        .bind("ttl expired")
+        .bind(updated_at)
+        .bind(current_slot)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .bind(TransactionStatus::InFlight.to_string())
+        .fetch_all(&mut *db_tx)
+        .await?;
+
+        for id in &ids {
+            block_dependents(&mut db_tx, id, &TransactionStatus::Failed, self.clock.now()).await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(ids.len() as u64)
+    }
+
+    async fn expire_past_deadline(&self, now: DateTime<Utc>) -> Result<u64, StorageError> {
+        let mut db_tx = self.sqlite.db.begin().await?;
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	error = $2,
+                	updated_at = $3
+                WHERE
+                	expires_at < $3
+                	AND status IN ($4, $5)
+                RETURNING
+                	id;
+            "#,
+        )
+        .bind(TransactionStatus::Failed.to_string())
+        .bind("expired before being submitted")
+        .bind(now)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .fetch_all(&mut *db_tx)
+        .await?;
+
+        for id in &ids {
+            block_dependents(&mut db_tx, id, &TransactionStatus::Failed, self.clock.now()).await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(ids.len() as u64)
+    }
+
+    async fn list(
+        &self,
+        status: Option<TransactionStatus>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let mut transactions = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	($1 IS NULL OR tx.status = $1)
+                    	AND ($4 IS NULL OR tx.created_at >= $4)
+                    	AND ($5 IS NULL OR tx.created_at <= $5)
+                    ORDER BY
+                    	created_at DESC
+                    LIMIT $2
+                    OFFSET $3;
+            "#,
+        )
+        .bind(status.map(|s| s.to_string()))
+        .bind(limit)
+        .bind(offset)
+        .bind(created_after)
+        .bind(created_before)
+        .fetch_all(&self.sqlite.db)
+        .await?;
+
+        self.hydrate_dependencies(&mut transactions).await?;
+
+        Ok(transactions)
+    }
+
+    async fn list_after(
+        &self,
+        after_created_at: Option<DateTime<Utc>>,
+        after_id: &str,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let mut transactions = sqlx::query_as::<_, Transaction>(
+            r#"
+                    SELECT
+                    	id,
+                    	raw,
+                    	status,
+                        slot,
+                    	priority,
+                    	created_at,
+                    	updated_at,
+                    	attempts,
+                    	error,
+                    	claimed_by,
+                    	claimed_at,
+                    	ttl,
+                    	confirmed_slot,
+                    	next_retry_at,
+                    	expires_at,
+                    	metadata,
+                    	source,
+                    	submitter,
+                    	replaces,
+                    	namespace
+                    FROM
+                    	tx
+                    WHERE
+                    	($1 IS NULL OR (tx.created_at, tx.id) > ($1, $2))
+                    ORDER BY
+                    	created_at,
+                    	id
+                    LIMIT $3;
+            "#,
+        )
+        .bind(after_created_at)
+        .bind(after_id)
+        .bind(limit)
+        .fetch_all(&self.sqlite.db)
+        .await?;
+
+        self.hydrate_dependencies(&mut transactions).await?;
+
+        Ok(transactions)
+    }
+
+    async fn update(&self, tx: &Transaction) -> Result<(), StorageError> {
+        retry_busy(self.sqlite.write_retries, || self.try_update(tx)).await
+    }
+
+    async fn update_batch(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        let mut db_tx = self.sqlite.db.begin().await?;
+
+        for tx in txs {
+            let status = tx.status.to_string();
+            let updated_at = self.clock.now();
+            // TODO: check the maximium size of i64 and compare with cardano slot.
+            let slot = tx.slot.map(|v| v as i64);
+
+            sqlx::query!(
+                r#"
+                UPDATE
+                	tx
+                SET
+                	raw = $1,
+                	status = $2,
+                	slot = $3,
+                	error = $4,
+                	claimed_by = $5,
+                	claimed_at = $6,
+                	ttl = $7,
+                	confirmed_slot = $8,
+                	next_retry_at = $9,
+                	updated_at = $10
+                WHERE
+                	id = $11;
+            "#,
+                tx.raw,
+                status,
+                slot,
+                tx.error,
+                tx.claimed_by,
+                tx.claimed_at,
+                tx.ttl.map(|v| v as i64),
+                tx.confirmed_slot.map(|v| v as i64),
+                tx.next_retry_at,
+                updated_at,
+                tx.id,
+            )
+            .execute(&mut *db_tx)
+            .await?;
+
+            if tx.status.blocks_dependents() {
+                block_dependents(&mut db_tx, &tx.id, &tx.status, self.clock.now()).await?;
+            }
+        }
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn mark_confirmed_many(&self, ids: &[String], slot: u64) -> Result<u64, StorageError> {
+        let updated_at = self.clock.now();
+        let slot = slot as i64;
+        let mut confirmed = 0;
+
+        let mut db_tx = self.sqlite.db.begin().await?;
+        for chunk in ids.chunks(IN_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Sqlite>::new(
+                r#"
+                    UPDATE
+                    	tx
+                    SET
+                    	status = "#,
+            );
+            query
+                .push_bind(TransactionStatus::Confirmed.to_string())
+                .push(", slot = ")
+                .push_bind(slot)
+                .push(", confirmed_slot = ")
+                .push_bind(slot)
+                .push(", claimed_by = NULL, claimed_at = NULL, updated_at = ")
+                .push_bind(updated_at)
+                .push(" WHERE status <> ")
+                .push_bind(TransactionStatus::Confirmed.to_string())
+                .push(" AND id IN (");
+            let mut separated = query.separated(", ");
+            for id in chunk {
+                separated.push_bind(id);
+            }
+            separated.push_unseparated(")");
+
+            confirmed += query.build().execute(&mut *db_tx).await?.rows_affected();
+        }
+        db_tx.commit().await?;
+
+        Ok(confirmed)
+    }
+
+    async fn mark_failed(&self, id: &str, reason: &str) -> Result<(), StorageError> {
+        let status = TransactionStatus::Failed.to_string();
+        let updated_at = self.clock.now();
+
+        let mut db_tx = self.sqlite.db.begin().await?;
+        sqlx::query!(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	error = $2,
+                	updated_at = $3
+                WHERE
+                	id = $4;
+            "#,
+            status,
+            reason,
+            updated_at,
+            id,
+        )
+        .execute(&mut *db_tx)
+        .await?;
+
+        block_dependents(&mut db_tx, id, &TransactionStatus::Failed, self.clock.now()).await?;
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn requeue(&self, id: &str) -> Result<(), StorageError> {
+        let mut db_tx = self.sqlite.db.begin().await?;
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	slot = NULL,
+                	attempts = 0,
+                	error = NULL,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	next_retry_at = NULL,
+                	updated_at = $2
+                WHERE
+                	id = $3
+                	AND status IN ($4, $5);
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(self.clock.now())
+        .bind(id)
+        .bind(TransactionStatus::Failed.to_string())
+        .bind(TransactionStatus::DeadLetter.to_string())
+        .execute(&mut *db_tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            db_tx.rollback().await?;
+            return Err(self.unchanged(id).await);
+        }
+        // the dependents it blocked can be valid again
+        unblock_dependents(&mut db_tx, id, self.clock.now()).await?;
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn requeue_all(&self, from: TransactionStatus) -> Result<u64, StorageError> {
+        if !from.is_requeueable() {
+            return Err(StorageError::InvalidStatus(format!(
+                "{from} transactions can't be requeued"
+            )));
+        }
+
+        let mut db_tx = self.sqlite.db.begin().await?;
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	slot = NULL,
+                	attempts = 0,
+                	error = NULL,
+                	claimed_by = NULL,
+                	claimed_at = NULL,
+                	next_retry_at = NULL,
+                	updated_at = $2
+                WHERE
+                	status = $3
+                RETURNING
+                	id;
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(self.clock.now())
+        .bind(from.to_string())
+        .fetch_all(&mut *db_tx)
+        .await?;
+
+        for id in &ids {
+            unblock_dependents(&mut db_tx, id, self.clock.now()).await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(ids.len() as u64)
+    }
+
+    async fn set_priority(
+        &self,
+        id: &str,
+        priority: TransactionPriority,
+    ) -> Result<(), StorageError> {
+        let priority: u32 = priority.into();
+
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	priority = $1,
+                	updated_at = $2
+                WHERE
+                	id = $3
+                	AND status NOT IN ($4, $5, $6, $7, $8);
+            "#,
+        )
+        .bind(priority)
+        .bind(self.clock.now())
+        .bind(id)
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(TransactionStatus::Failed.to_string())
+        .bind(TransactionStatus::DeadLetter.to_string())
+        .bind(TransactionStatus::Cancelled.to_string())
+        .bind(TransactionStatus::BlockedByDependency.to_string())
+        .execute(&self.sqlite.db)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(self.unchanged(id).await);
+        }
+
+        Ok(())
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool, StorageError> {
+        // claim_next moves the tx to InFlight in a single statement, so a tx
+        // picked by the fanout can't be cancelled afterwards
+        let mut db_tx = self.sqlite.db.begin().await?;
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = $1,
+                	updated_at = $2
+                WHERE
+                	id = $3
+                	AND status IN ($4, $5);
+            "#,
+        )
+        .bind(TransactionStatus::Cancelled.to_string())
+        .bind(self.clock.now())
+        .bind(id)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .execute(&mut *db_tx)
+        .await?;
+
+        let cancelled = result.rows_affected() > 0;
+        if cancelled {
+            block_dependents(
+                &mut db_tx,
+                id,
+                &TransactionStatus::Cancelled,
+                self.clock.now(),
+            )
+            .await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(cancelled)
+    }
+
+    async fn resubmit(&self, id: &str) -> Result<bool, StorageError> {
+        // a claimed tx has no slot until the fanout submits it, it's left to
+        // the fanout instead of being submitted twice
+        let result = sqlx::query(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	status = CASE WHEN status = $1 AND slot IS NOT NULL THEN $2 ELSE status END,
+                	claimed_by = CASE WHEN status = $1 AND slot IS NOT NULL THEN NULL ELSE claimed_by END,
+                	claimed_at = CASE WHEN status = $1 AND slot IS NOT NULL THEN NULL ELSE claimed_at END,
+                	slot = CASE WHEN status = $1 AND slot IS NOT NULL THEN NULL ELSE slot END,
+                	next_retry_at = NULL,
+                	updated_at = $3
+                WHERE
+                	id = $4
+                	AND status IN ($5, $6, $1);
+            "#,
+        )
+        .bind(TransactionStatus::InFlight.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .bind(self.clock.now())
+        .bind(id)
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .execute(&self.sqlite.db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn increment_attempts(&self, id: &str) -> Result<u32, StorageError> {
+        let updated_at = self.clock.now();
+
+        let attempts = sqlx::query_scalar::<_, u32>(
+            r#"
+                UPDATE
+                	tx
+                SET
+                	attempts = attempts + 1,
+                	updated_at = $1
+                WHERE
+                	id = $2
+                RETURNING
+                	attempts;
+            "#,
+        )
+        .bind(updated_at)
+        .bind(id)
+        .fetch_optional(&self.sqlite.db)
+        .await?;
+
+        attempts.ok_or_else(|| StorageError::NotFound(id.to_string()))
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), StorageError> {
+        self.delete_many(&[id.to_string()]).await
+    }
+
+    async fn delete_many(&self, ids: &[String]) -> Result<(), StorageError> {
+        let mut db_tx = self.sqlite.db.begin().await?;
+
+        for id in ids {
+            sqlx::query!(
+                r#"
+                    DELETE FROM
+                    	tx_dependence
+                    WHERE
+                    	dependent_id = $1 OR required_id = $1;
+                "#,
+                id,
+            )
+            .execute(&mut *db_tx)
+            .await?;
+
+            sqlx::query(
+                r#"
+                    DELETE FROM
+                    	tx_submission
+                    WHERE
+                    	tx_id = $1;
+                "#,
+            )
+            .bind(id)
+            .execute(&mut *db_tx)
+            .await?;
+
+            sqlx::query!(
+                r#"
+                    DELETE FROM
+                    	tx
+                    WHERE
+                    	id = $1;
+                "#,
+                id,
+            )
+            .execute(&mut *db_tx)
+            .await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn truncate_all(&self) -> Result<(), StorageError> {
+        let mut db_tx = self.sqlite.db.begin().await?;
+
+        for table in ["tx_dependence", "tx_submission", "tx"] {
+            sqlx::query(&format!("DELETE FROM {table};"))
+                .execute(&mut *db_tx)
+                .await?;
+        }
+
+        db_tx.commit().await?;
+        Ok(())
+    }
+
+    async fn compact_confirmed_dependencies(&self, slot: u64) -> Result<u64, StorageError> {
+        let result = sqlx::query(
+            r#"
+                DELETE FROM
+                	tx_dependence
+                WHERE
+                	dependent_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2)
+                	AND required_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2);
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&self.sqlite.db)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn prune_confirmed_before(&self, slot: u64) -> Result<u64, StorageError> {
+        let mut db_tx = self.sqlite.db.begin().await?;
+
+        sqlx::query(
+            r#"
+                DELETE FROM
+                	tx_dependence
+                WHERE
+                	dependent_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2)
+                	OR required_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2);
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&mut *db_tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+                DELETE FROM
+                	tx_submission
+                WHERE
+                	tx_id IN (SELECT id FROM tx WHERE status = $1 AND confirmed_slot < $2);
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&mut *db_tx)
+        .await?;
+
+        let result = sqlx::query(
+            r#"
+                DELETE FROM
+                	tx
+                WHERE
+                	status = $1
+                	AND confirmed_slot < $2;
+            "#,
+        )
+        .bind(TransactionStatus::Confirmed.to_string())
+        .bind(slot as i64)
+        .execute(&mut *db_tx)
+        .await?;
+
+        db_tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn dependents_of(&self, id: &str) -> Result<Vec<String>, StorageError> {
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+                SELECT
+                	dependent_id
+                FROM
+                	tx_dependence
+                WHERE
+                	required_id = $1
+                ORDER BY
+                	dependent_id;
+            "#,
+        )
+        .bind(id)
+        .fetch_all(&self.sqlite.db)
+        .await?;
+
+        Ok(ids)
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        sqlx::query("SELECT 1").execute(&self.sqlite.db).await?;
+        Ok(())
+    }
+
+    async fn status_counts(&self) -> Result<HashMap<TransactionStatus, i64>, StorageError> {
+        let rows = sqlx::query_as::<_, (String, i64)>(
+            r#"
+                SELECT
+                	status,
+                	COUNT(*)
+                FROM
+                	tx
+                GROUP BY
+                	status;
+            "#,
+        )
+        .fetch_all(&self.sqlite.db)
+        .await?;
+
+        rows.into_iter()
+            .map(|(status, count)| Ok((status.parse::<TransactionStatus>()?, count)))
+            .collect()
+    }
+
+    async fn oldest_queued_at(&self) -> Result<Option<DateTime<Utc>>, StorageError> {
+        let oldest = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+            r#"
+                SELECT
+                	MIN(created_at)
+                FROM
+                	tx
+                WHERE
+                	status IN ($1, $2, $3);
+            "#,
+        )
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(TransactionStatus::Validated.to_string())
+        .bind(TransactionStatus::InFlight.to_string())
+        .fetch_one(&self.sqlite.db)
+        .await?;
+
+        Ok(oldest)
+    }
+
+    async fn record_submission(
+        &self,
+        tx_id: &str,
+        peer: &str,
+        accepted: bool,
+    ) -> Result<(), StorageError> {
+        sqlx::query(
+            r#"
+                INSERT INTO tx_submission(
+                	tx_id,
+                	peer,
+                	submitted_at,
+                	accepted
+                )
+                VALUES ($1, $2, $3, $4);
+            "#,
+        )
+        .bind(tx_id)
+        .bind(peer)
+        .bind(self.clock.now())
+        .bind(accepted)
+        .execute(&self.sqlite.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn submissions_for(&self, tx_id: &str) -> Result<Vec<Submission>, StorageError> {
+        let rows = sqlx::query_as::<_, (String, String, DateTime<Utc>, bool)>(
+            r#"
+                SELECT
+                	tx_id,
+                	peer,
+                	submitted_at,
+                	accepted
+                FROM
+                	tx_submission
+                WHERE
+                	tx_id = $1
+                ORDER BY
+                	submitted_at;
+            "#,
+        )
+        .bind(tx_id)
+        .fetch_all(&self.sqlite.db)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(tx_id, peer, submitted_at, accepted)| Submission {
+                tx_id,
+                peer,
+                submitted_at,
+                accepted,
+            })
+            .collect())
+    }
+}
+
+impl FromRow<'_, SqliteRow> for Cursor {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            slot: row.try_get("slot")?,
+            hash: row.try_get("hash")?,
+        })
+    }
+}
+
+pub struct SqliteCursor {
+    sqlite: Arc<SqliteStorage>,
+}
+impl SqliteCursor {
+    pub fn new(sqlite: Arc<SqliteStorage>) -> Self {
+        Self { sqlite }
+    }
+}
+
+#[async_trait::async_trait]
+impl CursorStore for SqliteCursor {
+    async fn set(&self, cursor: &Cursor) -> Result<()> {
+        let slot = cursor.slot as i64;
+
+        sqlx::query!(
+            r#"
+                INSERT OR REPLACE INTO cursor(
+                    id,
+	                slot,
+	                hash
+                )
+                VALUES (0, $1, $2);
+            "#,
+            slot,
+            cursor.hash
+        )
+        .execute(&self.sqlite.db)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn current(&self) -> Result<Option<Cursor>> {
+        let cursor = sqlx::query_as::<_, Cursor>(
+            r#"
+                    SELECT
+                    	slot,
+                    	hash
+                    FROM
+                    	cursor;
+            "#,
+        )
+        .fetch_optional(&self.sqlite.db)
+        .await?;
+
+        Ok(cursor)
+    }
+}
+
+#[cfg(test)]
+mod sqlite_transaction_tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use chrono::{Duration, Utc};
+    use sqlx::migrate::Migrator;
+
+    use crate::storage::{
+        clock::MockClock, Config, QueueOrder, StorageError, Transaction, TransactionPriority,
+        TransactionStatus, TransactionStore,
+    };
+
+    use super::{is_busy, retry_busy, SqliteStorage, SqliteTransaction};
+
+    async fn mock_sqlite() -> SqliteTransaction {
+        let sqlite_storage = Arc::new(SqliteStorage::ephemeral().await.unwrap());
+        SqliteTransaction::new(sqlite_storage)
+    }
+
+    // In-memory databases share a cache across the pool connections, which fails on
+    // concurrent writes, so tests exercising locks use a temporary file.
+    async fn file_sqlite() -> (SqliteTransaction, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "boros-{}.db",
+            Utc::now().timestamp_nanos_opt().unwrap()
+        ));
+        let config = Config {
+            db_path: path.display().to_string(),
+            ..Default::default()
+        };
+
+        let sqlite_storage = Arc::new(SqliteStorage::new(&config).await.unwrap());
+        sqlite_storage.migrate().await.unwrap();
+
+        (SqliteTransaction::new(sqlite_storage), path)
+    }
+
+    fn remove_file_sqlite(path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_run_maintenance() {
+        let (storage, path) = file_sqlite().await;
+
+        let transactions: Vec<Transaction> = (0..200)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                raw: vec![0; 1024],
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+        let ids: Vec<String> = transactions.into_iter().map(|tx| tx.id).collect();
+        storage.delete_many(&ids).await.unwrap();
+
+        let freelist = || async {
+            sqlx::query_scalar::<_, i64>("PRAGMA freelist_count;")
+                .fetch_one(&storage.sqlite.db)
+                .await
+                .unwrap()
+        };
+        assert!(freelist().await > 0);
+
+        let result = storage.sqlite.maintenance().await;
+        assert!(result.is_ok());
+        assert!(freelist().await == 0);
+
+        let wal = std::fs::metadata(format!("{}-wal", path.display())).unwrap();
+        assert!(wal.len() == 0);
+
+        remove_file_sqlite(&path);
+    }
+
+    #[tokio::test]
+    async fn it_should_name_failed_migration() {
+        let path = std::env::temp_dir().join(format!(
+            "boros-{}.db",
+            Utc::now().timestamp_nanos_opt().unwrap()
+        ));
+        let config = Config {
+            db_path: path.display().to_string(),
+            ..Default::default()
+        };
+        let storage = SqliteStorage::new(&config).await.unwrap();
+
+        let migrator = Migrator::new(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/migrations_broken"),
+        )
+        .await
+        .unwrap();
+        let result = storage.run_migrations(&migrator).await;
+
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.starts_with("migration 20250102 (broken) failed and was rolled back"));
+
+        let tables: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table';")
+                .fetch_all(&storage.db)
+                .await
+                .unwrap();
+        // the migrations before it are kept, nothing of the failed one is left
+        assert!(tables.contains(&"kept".to_string()));
+        assert!(!tables.contains(&"partial".to_string()));
+
+        remove_file_sqlite(&path);
+    }
+
+    #[tokio::test]
+    async fn it_should_create() {
+        let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
+
+        let result = storage.create(&vec![transaction]).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_should_create_with_concurrent_writers() {
+        let (storage, path) = file_sqlite().await;
+
+        let writes = (0..10).map(|i| {
+            let transaction = Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            };
+            let storage = &storage;
+            async move { storage.create(&vec![transaction]).await }
+        });
+        let results = futures::future::join_all(writes).await;
+        assert!(results.iter().all(|result| result.is_ok()));
+
+        let result = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(result.len() == 10);
+
+        remove_file_sqlite(&path);
+    }
+
+    // A second pool on the same file that fails right away on a lock instead of
+    // waiting for it, as the busy timeout would.
+    async fn contender_sqlite(path: &Path, write_retries: u32) -> SqliteTransaction {
+        let config = Config {
+            db_path: path.display().to_string(),
+            busy_timeout_ms: 0,
+            write_retries,
+            ..Default::default()
+        };
+
+        SqliteTransaction::new(Arc::new(SqliteStorage::new(&config).await.unwrap()))
+    }
+
+    #[tokio::test]
+    async fn it_should_retry_busy_write() {
+        let (storage, path) = file_sqlite().await;
+        let contender = contender_sqlite(&path, 0).await;
+
+        let mut lock = storage.sqlite.db.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE;")
+            .execute(&mut *lock)
+            .await
+            .unwrap();
+        let busy: StorageError = sqlx::query("DELETE FROM tx;")
+            .execute(&contender.sqlite.db)
+            .await
+            .unwrap_err()
+            .into();
+        assert!(is_busy(&busy));
+        sqlx::query("ROLLBACK;").execute(&mut *lock).await.unwrap();
+
+        // busy on the first attempt, then it goes through
+        let mut busy = Some(busy);
+        let mut attempts = 0;
+        let result = retry_busy(3, || {
+            attempts += 1;
+            let result = busy.take().map_or(Ok(()), Err);
+            async move { result }
+        })
+        .await;
+        assert!(result.is_ok());
+        assert!(attempts == 2);
+
+        remove_file_sqlite(&path);
+    }
+
+    #[tokio::test]
+    async fn it_should_create_after_lock_released() {
+        let (storage, path) = file_sqlite().await;
+        let contender = contender_sqlite(&path, 3).await;
+
+        let mut lock = storage.sqlite.db.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE;")
+            .execute(&mut *lock)
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(15)).await;
+            sqlx::query("ROLLBACK;").execute(&mut *lock).await.unwrap();
+        });
+
+        let result = contender.create(&vec![Transaction::default()]).await;
+        assert!(result.is_ok());
+
+        remove_file_sqlite(&path);
+    }
+
+    #[tokio::test]
+    async fn it_should_not_retry_other_errors() {
+        let mut attempts = 0;
+        let result = retry_busy(3, || {
+            attempts += 1;
+            async { Err::<(), _>(StorageError::Cycle(vec!["hex1".into(), "hex1".into()])) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert!(attempts == 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn it_should_claim_once_across_workers() {
+        let (storage, path) = file_sqlite().await;
+        let storage = Arc::new(storage);
+
+        let transactions: Vec<Transaction> = (0..100)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                status: TransactionStatus::Validated,
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let storage = storage.clone();
+                tokio::spawn(async move {
+                    let mut claimed = Vec::new();
+                    while let Some(tx) = storage
+                        .claim_next(
+                            TransactionStatus::Validated,
+                            None,
+                            &format!("worker{worker}"),
+                        )
+                        .await
+                        .unwrap()
+                    {
+                        claimed.push(tx.id);
+                    }
+                    claimed
+                })
+            })
+            .collect();
+
+        let mut claimed: Vec<String> = Vec::new();
+        for worker in workers {
+            claimed.extend(worker.await.unwrap());
+        }
+        claimed.sort();
+
+        let mut ids: Vec<String> = transactions.into_iter().map(|tx| tx.id).collect();
+        ids.sort();
+        assert!(claimed == ids);
+
+        remove_file_sqlite(&path);
+    }
+
+    #[tokio::test]
+    async fn it_should_create_with_dependencies() {
+        let storage = mock_sqlite().await;
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
+
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+        transaction_2.dependencies = Some(vec![transaction_1.id.clone()]);
+
+        let result = storage.create(&vec![transaction_1, transaction_2]).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_should_create_with_many_dependencies() {
+        let storage = mock_sqlite().await;
+
+        let required_ids: Vec<String> = (0..2000).map(|i| format!("hex{i}")).collect();
+        // the dependent goes first, before the transactions it requires
+        let mut transactions = vec![Transaction {
+            id: "dependent".into(),
+            dependencies: Some(required_ids.clone()),
+            ..Default::default()
+        }];
+        transactions.extend(required_ids.iter().map(|id| Transaction {
+            id: id.clone(),
+            ..Default::default()
+        }));
+
+        let result = storage.create(&transactions).await;
+        assert!(result.is_ok());
+
+        let tx = storage.get("dependent").await.unwrap().unwrap();
+        assert!(tx
+            .dependencies
+            .is_some_and(|dependencies| dependencies.len() == 2000));
+    }
+
+    #[tokio::test]
+    async fn it_should_find_many_with_dependencies() {
+        let storage = mock_sqlite().await;
+
+        let mut transactions: Vec<Transaction> = (0..1200)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            })
+            .collect();
+        transactions[1100].dependencies = Some(vec!["hex0".into()]);
+        storage.create(&transactions).await.unwrap();
+
+        let result = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(result.len() == 1200);
+
+        let tx = result.iter().find(|tx| tx.id == "hex1100").unwrap();
+        assert!(tx.dependencies == Some(vec!["hex0".into()]));
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_create_with_invalid_dependencies() {
+        let storage = mock_sqlite().await;
+
+        let mut transaction = Transaction::default();
+        transaction.dependencies = Some(vec!["something".into()]);
+
+        let result = storage.create(&vec![transaction]).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_create_with_dependency_cycle() {
+        let storage = mock_sqlite().await;
+
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
+        transaction_1.dependencies = Some(vec!["hex2".into()]);
+
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+        transaction_2.dependencies = Some(vec!["hex1".into()]);
+
+        let result = storage.create(&vec![transaction_1, transaction_2]).await;
+        assert!(matches!(result, Err(StorageError::Cycle(_))));
+
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("cycle"));
+        assert!(error.contains("hex1") && error.contains("hex2"));
+    }
+
+    #[tokio::test]
+    async fn it_should_check_cycles_over_stored_dependencies() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    dependencies: Some(vec!["hex1".into()]),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let result = storage
+            .create(&vec![
+                Transaction {
+                    id: "hex3".into(),
+                    dependencies: Some(vec!["hex2".into(), "hex4".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex4".into(),
+                    dependencies: Some(vec!["hex3".into()]),
+                    ..Default::default()
+                },
+            ])
+            .await;
+        assert!(matches!(result, Err(StorageError::Cycle(_))));
+
+        let result = storage
+            .create(&vec![Transaction {
+                id: "hex3".into(),
+                dependencies: Some(vec!["hex2".into()]),
+                ..Default::default()
+            }])
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_create_with_conflict() {
+        let storage = mock_sqlite().await;
+        storage.create(&vec![Transaction::default()]).await.unwrap();
+
+        let result = storage.create(&vec![Transaction::default()]).await;
+        assert!(matches!(result, Err(StorageError::Conflict(id)) if id == "hex"));
+    }
+
+    #[tokio::test]
+    async fn it_should_create_or_get_existing() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::InFlight,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let new = Transaction {
+            id: "hex2".into(),
+            ..Default::default()
+        };
+        let stored = storage
+            .create_or_get(&vec![Transaction::default(), new])
+            .await
+            .unwrap();
+        assert!(stored.len() == 2);
+        assert!(stored[0].status == TransactionStatus::InFlight);
+        assert!(stored[1].id == "hex2");
+
+        let result = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(result.len() == 1);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_create_or_get_conflicting() {
+        let storage = mock_sqlite().await;
+        storage.create(&vec![Transaction::default()]).await.unwrap();
+
+        let conflicting = Transaction {
+            raw: "other witnesses".into(),
+            ..Default::default()
+        };
+        let result = storage.create_or_get(&vec![conflicting]).await;
+        assert!(matches!(result, Err(StorageError::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn it_should_create_or_get_concurrently() {
+        let (storage, path) = file_sqlite().await;
+
+        let submits = (0..10).map(|_| {
+            let storage = &storage;
+            async move { storage.create_or_get(&vec![Transaction::default()]).await }
+        });
+        let results = futures::future::join_all(submits).await;
+        assert!(results.iter().all(|result| result.is_ok()));
+
+        let result = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(result.len() == 1);
+
+        remove_file_sqlite(&path);
+    }
+
+    #[tokio::test]
+    async fn it_should_get() {
+        let storage = mock_sqlite().await;
+
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
+
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+
+        let mut transaction_3 = Transaction::default();
+        transaction_3.id = "hex3".into();
+        transaction_3.dependencies = Some(vec!["hex1".into(), "hex2".into()]);
+
+        storage
+            .create(&vec![transaction_1, transaction_2, transaction_3])
+            .await
+            .unwrap();
+
+        let result = storage.get("hex3").await;
+        assert!(result.is_ok());
+
+        let transaction = result.unwrap().unwrap();
+        assert!(transaction.id == "hex3");
+        assert!(matches!(transaction.status, TransactionStatus::Pending));
+
+        let mut dependencies = transaction.dependencies.unwrap();
+        dependencies.sort();
+        assert!(dependencies == vec!["hex1", "hex2"]);
+    }
+
+    #[tokio::test]
+    async fn it_should_return_none_get_when_it_does_not_exist() {
+        let storage = mock_sqlite().await;
+
+        let result = storage.get("something").await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_find_next() {
+        let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
+
+        storage.create(&vec![transaction]).await.unwrap();
+
+        let result = storage.next(TransactionStatus::Pending, None).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_should_find_next_with_dependencies() {
+        let storage = mock_sqlite().await;
+
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
+        transaction_1.priority = TransactionPriority::Low;
+
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+        transaction_2.priority = TransactionPriority::High;
+        transaction_2.dependencies = Some(vec![transaction_1.id.clone()]);
+
+        storage
+            .create(&vec![transaction_1, transaction_2])
+            .await
+            .unwrap();
+
+        let result = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        let transaction = result.unwrap();
+        assert!(transaction.id == "hex2");
+        assert!(transaction.dependencies == Some(vec!["hex1".into()]));
+
+        let result = storage.list(None, None, None, 10, 0).await.unwrap();
+        let transaction = result.iter().find(|tx| tx.id == "hex1").unwrap();
+        assert!(transaction.dependencies.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_find_next_by_priority() {
+        let storage = mock_sqlite().await;
+
+        let transactions = vec![
+            Transaction {
+                id: "low".into(),
+                priority: TransactionPriority::Low,
+                ..Default::default()
+            },
+            Transaction {
+                id: "high".into(),
+                priority: TransactionPriority::High,
+                ..Default::default()
+            },
+            Transaction {
+                id: "medium".into(),
+                priority: TransactionPriority::Medium,
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
+
+        for id in ["high", "medium", "low"] {
+            let mut transaction = storage
+                .next(TransactionStatus::Pending, None)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(transaction.id == id);
+
+            transaction.status = TransactionStatus::Validated;
+            storage.update(&transaction).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_find_next_batch() {
+        let storage = mock_sqlite().await;
+
+        let now = Utc::now();
+        let transactions = vec![
+            Transaction {
+                id: "low".into(),
+                priority: TransactionPriority::Low,
+                created_at: now,
+                ..Default::default()
+            },
+            Transaction {
+                id: "high".into(),
+                priority: TransactionPriority::High,
+                created_at: now + Duration::seconds(1),
+                ..Default::default()
+            },
+            Transaction {
+                id: "low_newer".into(),
+                priority: TransactionPriority::Low,
+                created_at: now + Duration::seconds(2),
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
+
+        let result = storage
+            .next_batch(TransactionStatus::Pending, None, 2)
+            .await;
+        assert!(result.is_ok());
+
+        let ids: Vec<String> = result.unwrap().into_iter().map(|tx| tx.id).collect();
+        assert!(ids == vec!["high", "low"]);
+
+        let result = storage
+            .next_batch(TransactionStatus::Pending, None, 10)
+            .await;
+        assert!(result.unwrap().len() == 3);
+    }
+
+    #[tokio::test]
+    async fn it_should_get_many() {
+        let storage = mock_sqlite().await;
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let ids = vec![
+            "hex0".to_string(),
+            "hex2".to_string(),
+            "unknown".to_string(),
+        ];
+        let mut result: Vec<String> = storage
+            .get_many(&ids)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|tx| tx.id)
+            .collect();
+        result.sort();
+        assert!(result == vec!["hex0", "hex2"]);
+    }
+
+    #[tokio::test]
+    async fn it_should_next_by_namespace() {
+        let storage = mock_sqlite().await;
+        let now = Utc::now();
+
+        let transactions = vec![
+            Transaction {
+                id: "default".into(),
+                created_at: now,
+                ..Default::default()
+            },
+            Transaction {
+                id: "dapp".into(),
+                namespace: "dapp".into(),
+                created_at: now + Duration::seconds(1),
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
+
+        let result = storage
+            .next(TransactionStatus::Pending, Some("dapp"))
+            .await
+            .unwrap();
+        assert!(result.unwrap().id == "dapp");
+
+        let result = storage
+            .next_batch(TransactionStatus::Pending, Some("default"), 10)
+            .await
+            .unwrap();
+        let ids: Vec<String> = result.into_iter().map(|tx| tx.id).collect();
+        assert!(ids == vec!["default"]);
+
+        let result = storage
+            .next(TransactionStatus::Pending, Some("unknown"))
+            .await
+            .unwrap();
+        assert!(result.is_none());
+
+        let result = storage
+            .next_batch(TransactionStatus::Pending, None, 10)
+            .await
+            .unwrap();
+        assert!(result.len() == 2);
+    }
+
+    #[tokio::test]
+    async fn it_should_claim_next_by_namespace() {
+        let storage = mock_sqlite().await;
+        let now = Utc::now();
+
+        let transactions = vec![
+            Transaction {
+                id: "default".into(),
+                status: TransactionStatus::Validated,
+                created_at: now,
+                ..Default::default()
+            },
+            Transaction {
+                id: "dapp".into(),
+                namespace: "dapp".into(),
+                status: TransactionStatus::Validated,
+                created_at: now + Duration::seconds(1),
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
+
+        let result = storage
+            .claim_next(TransactionStatus::Validated, Some("dapp"), "worker")
+            .await
+            .unwrap();
+        assert!(result.unwrap().id == "dapp");
+
+        let result = storage
+            .claim_next(TransactionStatus::Validated, Some("dapp"), "worker")
+            .await
+            .unwrap();
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_use_index_on_next() {
+        let storage = mock_sqlite().await;
+
+        let plan: Vec<(i64, i64, i64, String)> = sqlx::query_as(&format!(
+            "EXPLAIN QUERY PLAN {}",
+            storage.next_batch_query()
+        ))
+        .bind(TransactionStatus::Pending.to_string())
+        .bind(1)
+        .bind(Utc::now())
+        .bind(None::<String>)
+        .fetch_all(&storage.sqlite.db)
+        .await
+        .unwrap();
+        let plan: Vec<String> = plan.into_iter().map(|(_, _, _, detail)| detail).collect();
+
+        assert!(plan
+            .iter()
+            .any(|detail| detail.contains("USING INDEX idx_tx_status_priority_created_at")));
+        // the index already gives the order, no sort over the matching rows
+        assert!(!plan.iter().any(|detail| detail.contains("TEMP B-TREE")));
+    }
+
+    fn mixed_priority_transactions() -> Vec<Transaction> {
+        let now = Utc::now();
+        vec![
+            Transaction {
+                id: "low_older".into(),
+                priority: TransactionPriority::Low,
+                created_at: now,
+                ..Default::default()
+            },
+            Transaction {
+                id: "high".into(),
+                priority: TransactionPriority::High,
+                created_at: now + Duration::seconds(1),
+                ..Default::default()
+            },
+            Transaction {
+                id: "medium".into(),
+                priority: TransactionPriority::Medium,
+                created_at: now + Duration::seconds(2),
+                ..Default::default()
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn it_should_order_next_by_priority() {
+        let storage = mock_sqlite().await.with_ordering(QueueOrder::Priority);
+        storage
+            .create(&mixed_priority_transactions())
+            .await
+            .unwrap();
+
+        let result = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(result.unwrap().id == "high");
+
+        let result = storage
+            .next_batch(TransactionStatus::Pending, None, 3)
+            .await;
+        let ids: Vec<String> = result.unwrap().into_iter().map(|tx| tx.id).collect();
+        assert!(ids == vec!["high", "medium", "low_older"]);
+    }
+
+    #[tokio::test]
+    async fn it_should_order_next_by_arrival_in_fifo() {
+        let storage = mock_sqlite().await.with_ordering(QueueOrder::Fifo);
+        storage
+            .create(&mixed_priority_transactions())
+            .await
+            .unwrap();
+
+        let result = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(result.unwrap().id == "low_older");
+
+        let result = storage
+            .next_batch(TransactionStatus::Pending, None, 3)
+            .await;
+        let ids: Vec<String> = result.unwrap().into_iter().map(|tx| tx.id).collect();
+        assert!(ids == vec!["low_older", "high", "medium"]);
+
+        let result = storage
+            .claim_next(TransactionStatus::Pending, None, "worker")
+            .await
+            .unwrap();
+        assert!(result.unwrap().id == "low_older");
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_next_with_invalid_priority() {
+        let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction]).await.unwrap();
+
+        sqlx::query("UPDATE tx SET priority = 9;")
+            .execute(&storage.sqlite.db)
+            .await
+            .unwrap();
+
+        let result = storage.next(TransactionStatus::Pending, None).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn it_should_not_find_next_ready_when_dependency_is_pending() {
+        let storage = mock_sqlite().await;
+
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
+
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+        transaction_2.dependencies = Some(vec![transaction_1.id.clone()]);
+
+        storage
+            .create(&vec![transaction_1, transaction_2.clone()])
+            .await
+            .unwrap();
+
+        transaction_2.status = TransactionStatus::Validated;
+        storage.update(&transaction_2).await.unwrap();
+
+        let result = storage.next_ready(TransactionStatus::Validated, None).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_find_next_ready_when_dependency_is_confirmed() {
+        let storage = mock_sqlite().await;
+
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
+
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+        transaction_2.dependencies = Some(vec![transaction_1.id.clone()]);
+
+        storage
+            .create(&vec![transaction_1.clone(), transaction_2])
+            .await
+            .unwrap();
+
+        transaction_1.status = TransactionStatus::Confirmed;
+        transaction_1.slot = Some(1);
+        storage.update(&transaction_1).await.unwrap();
+
+        let result = storage
+            .next_ready(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(result.unwrap().id == "hex2");
+    }
+
+    #[tokio::test]
+    async fn it_should_peek_next_claimed() {
+        let storage = mock_sqlite().await;
+        let txs: Vec<Transaction> = [
+            ("hex1", TransactionPriority::Low, None),
+            ("hex2", TransactionPriority::High, Some(vec!["hex1".into()])),
+            ("hex3", TransactionPriority::Medium, None),
+        ]
+        .into_iter()
+        .map(|(id, priority, dependencies)| Transaction {
+            id: id.into(),
+            priority,
+            dependencies,
+            ..Default::default()
+        })
+        .collect();
+        storage.create(&txs).await.unwrap();
+
+        let peeked = storage
+            .peek(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(peeked.as_ref().is_some_and(|tx| tx.id == "hex3"));
+        assert!(peeked.unwrap().status == TransactionStatus::Pending);
+
+        let peeked = storage
+            .peek(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        let claimed = storage
+            .claim_next(TransactionStatus::Pending, None, "worker")
+            .await
+            .unwrap();
+        assert!(peeked.unwrap().id == claimed.unwrap().id);
+    }
+
+    #[tokio::test]
+    async fn it_should_claim_next() {
+        let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        let result = storage
+            .claim_next(TransactionStatus::Pending, None, "worker")
+            .await;
+        assert!(result.is_ok());
+
+        let claimed = result.unwrap().unwrap();
+        assert!(claimed.id == transaction.id);
+        assert!(matches!(claimed.status, TransactionStatus::InFlight));
+        assert!(claimed.claimed_by == Some("worker".into()));
+
+        let result = storage
+            .claim_next(TransactionStatus::Pending, None, "worker")
+            .await;
+        assert!(result.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_not_claim_next_before_retry_time() {
+        let storage = mock_sqlite().await;
+
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        let transaction = Transaction {
+            status: TransactionStatus::Validated,
+            next_retry_at: Some(Utc::now() + Duration::minutes(1)),
+            ..transaction
+        };
+        storage.update(&transaction).await.unwrap();
+
+        let result = storage
+            .claim_next(TransactionStatus::Validated, None, "worker")
+            .await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+
+        let result = storage.next(TransactionStatus::Validated, None).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_none());
+
+        let transaction = Transaction {
+            next_retry_at: Some(Utc::now() - Duration::minutes(1)),
+            ..transaction
+        };
+        storage.update(&transaction).await.unwrap();
+
+        let result = storage
+            .claim_next(TransactionStatus::Validated, None, "worker")
+            .await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_should_retry_when_clock_reaches_next_retry_at() {
+        let now = Utc::now();
+        let clock = Arc::new(MockClock::new(now));
+        let storage = mock_sqlite().await.with_clock(clock.clone());
+        let transaction = Transaction {
+            id: "hex1".into(),
+            ..Default::default()
+        };
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+        storage
+            .update(&Transaction {
+                status: TransactionStatus::Validated,
+                next_retry_at: Some(now + Duration::minutes(1)),
+                ..transaction
+            })
+            .await
+            .unwrap();
+
+        let result = storage.next(TransactionStatus::Validated, None).await;
+        assert!(result.unwrap().is_none());
+
+        clock.advance(Duration::minutes(1));
+
+        let result = storage.next(TransactionStatus::Validated, None).await;
+        assert!(result.unwrap().is_some_and(|tx| tx.id == "hex1"));
+
+        let result = storage.mark_failed("hex1", "failed").await;
+        assert!(result.is_ok());
+        let tx = storage.get("hex1").await.unwrap().unwrap();
+        assert!(tx.updated_at == now + Duration::minutes(1));
+    }
+
+    #[tokio::test]
+    async fn it_should_claim_distinct_transactions_concurrently() {
+        let (storage, path) = file_sqlite().await;
+
+        let transactions: Vec<Transaction> = (0..2)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let (claim_1, claim_2) = tokio::join!(
+            storage.claim_next(TransactionStatus::Pending, None, "worker1"),
+            storage.claim_next(TransactionStatus::Pending, None, "worker2"),
+        );
+
+        let claim_1 = claim_1.unwrap().unwrap();
+        let claim_2 = claim_2.unwrap().unwrap();
+        assert!(claim_1.id != claim_2.id);
+
+        remove_file_sqlite(&path);
+    }
+
+    #[tokio::test]
+    async fn it_should_reclaim_expired() {
+        let storage = mock_sqlite().await;
+
+        let transactions: Vec<Transaction> = (0..2)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let mut expired = storage
+            .claim_next(TransactionStatus::Pending, None, "worker")
+            .await
+            .unwrap()
+            .unwrap();
+        expired.claimed_at = Some(Utc::now() - Duration::minutes(10));
+        storage.update(&expired).await.unwrap();
+
+        let active = storage
+            .claim_next(TransactionStatus::Pending, None, "worker")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let result = storage
+            .reclaim_expired(std::time::Duration::from_secs(60))
+            .await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 1);
+
+        let result = storage.get(&expired.id).await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Pending));
+        assert!(result.claimed_by.is_none());
+
+        let result = storage.get(&active.id).await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::InFlight));
+    }
+
+    #[tokio::test]
+    async fn it_should_release_claims() {
+        let storage = mock_sqlite().await;
+
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let claimed = storage
+            .claim_next(TransactionStatus::Pending, None, "worker")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut submitted = storage
+            .claim_next(TransactionStatus::Pending, None, "worker")
+            .await
+            .unwrap()
+            .unwrap();
+        submitted.slot = Some(1);
+        storage.update(&submitted).await.unwrap();
+
+        let other = storage
+            .claim_next(TransactionStatus::Pending, None, "other")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let result = storage.release_claims("worker").await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 1);
+
+        let result = storage.get(&claimed.id).await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Pending));
+        assert!(result.claimed_by.is_none());
+
+        let result = storage.get(&submitted.id).await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::InFlight));
+
+        let result = storage.get(&other.id).await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::InFlight));
+    }
+
+    #[tokio::test]
+    async fn it_should_expire_past_ttl() {
+        let storage = mock_sqlite().await;
+
+        let transactions = vec![
+            Transaction {
+                id: "expired".into(),
+                ttl: Some(10),
+                ..Default::default()
+            },
+            Transaction {
+                id: "valid".into(),
+                ttl: Some(100),
+                ..Default::default()
+            },
+            Transaction {
+                id: "no_ttl".into(),
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
+
+        let result = storage.expire_past_ttl(50).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 1);
+
+        let result = storage.get("expired").await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Failed));
+        assert!(result.error.is_some());
+
+        let result = storage.get("valid").await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Pending));
+
+        let result = storage.get("no_ttl").await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Pending));
+    }
+
+    #[tokio::test]
+    async fn it_should_keep_metadata_unchanged() {
+        let storage = mock_sqlite().await;
+        let metadata = r#"{ "order_id": "42", "tags": ["swap", "retry"] }"#;
+
+        let transaction = Transaction {
+            metadata: Some(metadata.into()),
+            ..Default::default()
+        };
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        let result = storage.get(&transaction.id).await.unwrap().unwrap();
+        assert!(result.metadata.as_deref() == Some(metadata));
+
+        let result = storage.list(None, None, None, 10, 0).await.unwrap();
+        assert!(result[0].metadata.as_deref() == Some(metadata));
+    }
+
+    #[tokio::test]
+    async fn it_should_keep_source() {
+        let storage = mock_sqlite().await;
+
+        let transaction = Transaction {
+            source: Some("grpc".into()),
+            submitter: Some("0123456789abcdef".into()),
+            ..Default::default()
+        };
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        let result = storage.get(&transaction.id).await.unwrap().unwrap();
+        assert!(result.source.as_deref() == Some("grpc"));
+        assert!(result.submitter.as_deref() == Some("0123456789abcdef"));
+
+        let result = storage.list(None, None, None, 10, 0).await.unwrap();
+        assert!(result[0].source.as_deref() == Some("grpc"));
+    }
+
+    #[tokio::test]
+    async fn it_should_cancel_replaced() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    status: TransactionStatus::InFlight,
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let result = storage
+            .create(&vec![Transaction {
+                id: "hex3".into(),
+                replaces: Some("hex1".into()),
+                ..Default::default()
+            }])
+            .await;
+        assert!(result.is_ok());
+
+        let tx = storage.get("hex1").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Cancelled);
+        let tx = storage.get("hex3").await.unwrap().unwrap();
+        assert!(tx.replaces.as_deref() == Some("hex1"));
+
+        let result = storage
+            .create(&vec![Transaction {
+                id: "hex4".into(),
+                replaces: Some("hex2".into()),
+                ..Default::default()
+            }])
+            .await;
+        assert!(matches!(result, Err(StorageError::NotReplaceable(_))));
+        assert!(storage.get("hex4").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_repoint_dependents_to_replacement() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    dependencies: Some(vec!["hex1".into()]),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        storage
+            .create(&vec![Transaction {
+                id: "hex3".into(),
+                replaces: Some("hex1".into()),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let tx = storage.get("hex2").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+        assert!(tx.dependencies == Some(vec!["hex3".into()]));
+        assert!(storage.dependents_of("hex1").await.unwrap().is_empty());
+
+        // the dependent waits for the replacement, not the cancelled one
+        let next = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(next.unwrap().id == "hex3");
+    }
+
+    #[tokio::test]
+    async fn it_should_mark_confirmed_many() {
+        let storage = mock_sqlite().await;
+
+        let ids: Vec<String> = (0..5).map(|i| format!("hex{i}")).collect();
+        let mut transactions: Vec<Transaction> = ids
+            .iter()
+            .map(|id| Transaction {
+                id: id.clone(),
+                status: TransactionStatus::InFlight,
+                ..Default::default()
+            })
+            .collect();
+        transactions.push(Transaction {
+            id: "other".into(),
+            status: TransactionStatus::InFlight,
+            ..Default::default()
+        });
+        storage.create(&transactions).await.unwrap();
+
+        transactions[0].claimed_by = Some("worker".into());
+        transactions[0].claimed_at = Some(Utc::now());
+        storage.update(&transactions[0]).await.unwrap();
+
+        let result = storage.mark_confirmed_many(&ids, 42).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 5);
+
+        for id in &ids {
+            let result = storage.get(id).await.unwrap().unwrap();
+            assert!(result.status == TransactionStatus::Confirmed);
+            assert!(result.confirmed_slot == Some(42));
+            assert!(result.claimed_by.is_none());
+            assert!(result.claimed_at.is_none());
+        }
+
+        let result = storage.get("other").await.unwrap().unwrap();
+        assert!(result.status == TransactionStatus::InFlight);
+
+        // the ones already confirmed aren't counted again
+        let result = storage.mark_confirmed_many(&ids, 42).await;
+        assert!(result.unwrap() == 0);
+    }
+
+    #[tokio::test]
+    async fn it_should_find_all_dependents() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "a".into(),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "b".into(),
+                    dependencies: Some(vec!["a".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "c".into(),
+                    dependencies: Some(vec!["b".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "other".into(),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let result = storage.dependents_of("a").await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == vec!["b".to_string()]);
+
+        let result = storage.all_dependents("a").await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == vec!["b".to_string(), "c".to_string()]);
+
+        let result = storage.all_dependents("c").await;
+        assert!(result.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_truncate_all() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    dependencies: Some(vec!["hex1".into()]),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+        storage
+            .record_submission("hex1", "Node:3001", true)
+            .await
+            .unwrap();
+
+        let result = storage.truncate_all().await;
+        assert!(result.is_ok());
+
+        for table in ["tx", "tx_dependence", "tx_submission"] {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table};"))
+                .fetch_one(&storage.sqlite.db)
+                .await
+                .unwrap();
+            assert!(count == 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_list_after_in_pages() {
+        let storage = mock_sqlite().await;
+        let now = Utc::now();
+        // pairs created at the same time, ordered by id between them
+        let txs: Vec<Transaction> = (0..7)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                created_at: now + Duration::seconds(i / 2),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&txs).await.unwrap();
+
+        let mut ids = vec![];
+        let mut after = None;
+        let mut after_id = String::new();
+        loop {
+            let page = storage.list_after(after, &after_id, 3).await.unwrap();
+            let Some(last) = page.last() else {
+                break;
+            };
+            after = Some(last.created_at);
+            after_id = last.id.clone();
+            ids.extend(page.into_iter().map(|tx| tx.id));
+        }
+
+        let expected: Vec<String> = (0..7).map(|i| format!("hex{i}")).collect();
+        assert!(ids == expected);
+    }
+
+    #[tokio::test]
+    async fn it_should_prune_confirmed_before() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "old".into(),
+                    status: TransactionStatus::InFlight,
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "recent".into(),
+                    status: TransactionStatus::InFlight,
+                    dependencies: Some(vec!["old".into()]),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+        storage
+            .mark_confirmed_many(&["old".into()], 10)
+            .await
+            .unwrap();
+        storage
+            .mark_confirmed_many(&["recent".into()], 100)
+            .await
+            .unwrap();
+        storage
+            .record_submission("old", "Node:3001", true)
+            .await
+            .unwrap();
+
+        // both were just updated, only the slot they were confirmed in counts
+        let result = storage.prune_confirmed_before(50).await;
+        assert!(result.is_ok_and(|pruned| pruned == 1));
+
+        assert!(storage.get("old").await.unwrap().is_none());
+        let tx = storage.get("recent").await.unwrap().unwrap();
+        assert!(tx.dependencies.is_none());
+        assert!(storage.submissions_for("old").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_compact_confirmed_dependencies() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    status: TransactionStatus::InFlight,
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    status: TransactionStatus::InFlight,
+                    dependencies: Some(vec!["hex1".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex3".into(),
+                    status: TransactionStatus::InFlight,
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex4".into(),
+                    dependencies: Some(vec!["hex3".into()]),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+        let ids: Vec<String> = vec!["hex1".into(), "hex2".into(), "hex3".into()];
+        storage.mark_confirmed_many(&ids, 10).await.unwrap();
+
+        let result = storage.compact_confirmed_dependencies(100).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 1);
+
+        let txs = storage.list(None, None, None, 10, 0).await.unwrap();
+        let tx_2 = txs.iter().find(|tx| tx.id == "hex2").unwrap();
+        assert!(tx_2.dependencies.is_none());
+        let tx_4 = txs.iter().find(|tx| tx.id == "hex4").unwrap();
+        assert!(tx_4.dependencies == Some(vec!["hex3".into()]));
+
+        // nothing left to compact
+        let result = storage.compact_confirmed_dependencies(100).await;
+        assert!(result.unwrap() == 0);
+    }
+
+    #[tokio::test]
+    async fn it_should_keep_dependencies_a_rollback_reverts() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    status: TransactionStatus::InFlight,
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    status: TransactionStatus::InFlight,
+                    dependencies: Some(vec!["hex1".into()]),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+        let ids: Vec<String> = vec!["hex1".into(), "hex2".into()];
+        storage.mark_confirmed_many(&ids, 200).await.unwrap();
+
+        // confirmed within the window, so kept
+        let result = storage.compact_confirmed_dependencies(100).await;
+        assert!(result.unwrap() == 0);
+
+        let result = storage.rollback_to(150).await;
+        assert!(result.unwrap() == 2);
+
+        let tx_2 = storage.get("hex2").await.unwrap().unwrap();
+        assert!(tx_2.status == TransactionStatus::Pending);
+        assert!(tx_2.dependencies == Some(vec!["hex1".into()]));
+
+        // the required one is submitted first again
+        let next = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(next.unwrap().id == "hex1");
+    }
+
+    #[tokio::test]
+    async fn it_should_expire_past_deadline() {
+        let storage = mock_sqlite().await;
+
+        let transactions = vec![
+            Transaction {
+                id: "expired".into(),
+                expires_at: Some(Utc::now() - Duration::minutes(1)),
+                ..Default::default()
+            },
+            Transaction {
+                id: "valid".into(),
+                expires_at: Some(Utc::now() + Duration::minutes(1)),
+                ..Default::default()
+            },
+            Transaction {
+                id: "submitted".into(),
+                status: TransactionStatus::InFlight,
+                expires_at: Some(Utc::now() - Duration::minutes(1)),
+                ..Default::default()
+            },
+            Transaction {
+                id: "no_deadline".into(),
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
+
+        let result = storage.expire_past_deadline(Utc::now()).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 1);
+
+        let result = storage.get("expired").await.unwrap().unwrap();
+        assert!(result.status == TransactionStatus::Failed);
+        assert!(result.error.is_some());
+
+        for id in ["valid", "submitted", "no_deadline"] {
+            let result = storage.get(id).await.unwrap().unwrap();
+            assert!(result.status != TransactionStatus::Failed);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_update() {
+        let storage = mock_sqlite().await;
+
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction]).await.unwrap();
+
+        let mut transaction = Transaction::default();
+        transaction.status = TransactionStatus::Validated;
+        let result = storage.update(&transaction).await;
+        assert!(result.is_ok());
+
+        let result = storage.next(TransactionStatus::Validated, None).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn it_should_update_batch() {
+        let storage = mock_sqlite().await;
+
+        let mut batch = Vec::new();
+
+        let mut transaction = Transaction::default();
+        transaction.id = "hex1".into();
+        batch.push(transaction.clone());
+        storage.create(&vec![transaction]).await.unwrap();
+
+        let mut transaction = Transaction::default();
+        transaction.id = "hex2".into();
+        batch.push(transaction.clone());
+        storage.create(&vec![transaction]).await.unwrap();
+
+        let batch = batch
+            .iter_mut()
+            .map(|tx| {
+                tx.status = TransactionStatus::Confirmed;
+                tx.slot = Some(1);
+                tx.clone()
+            })
+            .collect();
+        let result = storage.update_batch(&batch).await;
+        assert!(result.is_ok());
+
+        let result = storage.find(TransactionStatus::Confirmed).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().len() == 2);
+    }
+
+    #[tokio::test]
+    async fn it_should_skip_terminal_on_next() {
+        let storage = mock_sqlite().await;
+
+        let mut transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        transaction.status = TransactionStatus::Confirmed;
+        transaction.slot = Some(1);
+        storage.update(&transaction).await.unwrap();
+
+        let result = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(result.is_none());
+
+        let result = storage
+            .next(TransactionStatus::Confirmed, None)
+            .await
+            .unwrap();
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_update_error() {
+        let storage = mock_sqlite().await;
+
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction]).await.unwrap();
+
+        let transaction = Transaction {
+            status: TransactionStatus::Validated,
+            error: Some("peer unavailable".into()),
+            ..Default::default()
+        };
+        storage.update(&transaction).await.unwrap();
+
+        let result = storage
+            .next(TransactionStatus::Validated, None)
+            .await
+            .unwrap();
+        assert!(result.unwrap().error == Some("peer unavailable".into()));
+    }
+
+    async fn create_chain(storage: &SqliteTransaction) {
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "a".into(),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "b".into(),
+                    status: TransactionStatus::Validated,
+                    dependencies: Some(vec!["a".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "c".into(),
+                    dependencies: Some(vec!["b".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "other".into(),
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_should_block_dependents_of_failed() {
+        let storage = mock_sqlite().await;
+        create_chain(&storage).await;
+
+        let result = storage.mark_failed("a", "invalid tx").await;
+        assert!(result.is_ok());
+
+        for id in ["b", "c"] {
+            let tx = storage.get(id).await.unwrap().unwrap();
+            assert!(tx.status == TransactionStatus::BlockedByDependency);
+            assert!(tx.error == Some("dependency a is failed".into()));
+        }
+
+        let tx = storage.get("other").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn it_should_block_dependents_of_dead_letter() {
+        let storage = mock_sqlite().await;
+        create_chain(&storage).await;
+
+        let mut tx = storage.get("a").await.unwrap().unwrap();
+        tx.status = TransactionStatus::DeadLetter;
+        storage.update(&tx).await.unwrap();
+
+        let result = storage
+            .find(TransactionStatus::BlockedByDependency)
+            .await
+            .unwrap();
+        assert!(result.len() == 2);
+    }
+
+    #[tokio::test]
+    async fn it_should_block_dependents_of_cancelled() {
+        let storage = mock_sqlite().await;
+        create_chain(&storage).await;
+
+        let result = storage.cancel("a").await;
+        assert!(result.unwrap());
+
+        for id in ["b", "c"] {
+            let tx = storage.get(id).await.unwrap().unwrap();
+            assert!(tx.status == TransactionStatus::BlockedByDependency);
+            assert!(tx.error == Some("dependency a is cancelled".into()));
+        }
+
+        let tx = storage.get("other").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn it_should_unblock_dependents_of_requeued() {
+        let storage = mock_sqlite().await;
+        create_chain(&storage).await;
+        storage.mark_failed("a", "invalid tx").await.unwrap();
+
+        let result = storage.requeue("a").await;
+        assert!(result.is_ok());
+
+        for id in ["a", "b", "c"] {
+            let tx = storage.get(id).await.unwrap().unwrap();
+            assert!(tx.status == TransactionStatus::Pending);
+            assert!(tx.error.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_keep_dependents_blocked_by_another_dependency() {
+        let storage = mock_sqlite().await;
+        create_chain(&storage).await;
+        storage
+            .create(&vec![Transaction {
+                id: "d".into(),
+                dependencies: Some(vec!["b".into(), "other".into()]),
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+        storage.mark_failed("a", "invalid tx").await.unwrap();
+        storage.mark_failed("other", "invalid tx").await.unwrap();
+
+        storage.requeue("a").await.unwrap();
+        for id in ["b", "c"] {
+            let tx = storage.get(id).await.unwrap().unwrap();
+            assert!(tx.status == TransactionStatus::Pending);
+        }
+        let tx = storage.get("d").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::BlockedByDependency);
+
+        let result = storage.requeue_all(TransactionStatus::Failed).await;
+        assert!(result.is_ok_and(|requeued| requeued == 1));
+        let tx = storage.get("d").await.unwrap().unwrap();
+        assert!(tx.status == TransactionStatus::Pending);
+    }
+
+    #[tokio::test]
+    async fn it_should_mark_failed() {
+        let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        let result = storage.mark_failed(&transaction.id, "invalid tx").await;
+        assert!(result.is_ok());
+
+        let result = storage.find(TransactionStatus::Failed).await.unwrap();
+        assert!(result.len() == 1);
+        assert!(result[0].error == Some("invalid tx".into()));
+    }
+
+    #[tokio::test]
+    async fn it_should_requeue() {
+        let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+        storage.increment_attempts(&transaction.id).await.unwrap();
+        storage
+            .mark_failed(&transaction.id, "invalid tx")
+            .await
+            .unwrap();
+
+        let result = storage.requeue(&transaction.id).await;
+        assert!(result.is_ok());
+
+        let result = storage.get(&transaction.id).await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Pending));
+        assert!(result.error.is_none());
+        assert!(result.attempts == 0);
+        assert!(result.next_retry_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_requeue_confirmed() {
+        let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        let transaction = Transaction {
+            status: TransactionStatus::Confirmed,
+            ..transaction
+        };
+        storage.update(&transaction).await.unwrap();
+
+        let result = storage.requeue(&transaction.id).await;
+        assert!(matches!(result, Err(StorageError::InvalidStatus(_))));
+
+        let result = storage.requeue("unknown").await;
+        assert!(matches!(result, Err(StorageError::NotFound(_))));
+
+        let result = storage.get(&transaction.id).await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Confirmed));
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_requeue_not_failed() {
+        let storage = mock_sqlite().await;
+        let statuses = [
+            TransactionStatus::Pending,
+            TransactionStatus::InFlight,
+            TransactionStatus::Cancelled,
+            TransactionStatus::BlockedByDependency,
+        ];
+        let transactions: Vec<Transaction> = statuses
+            .iter()
+            .enumerate()
+            .map(|(i, status)| Transaction {
+                id: format!("hex{i}"),
+                status: status.clone(),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        for (tx, status) in transactions.iter().zip(statuses) {
+            let result = storage.requeue(&tx.id).await;
+            assert!(matches!(result, Err(StorageError::InvalidStatus(_))));
+
+            let result = storage.requeue_all(status.clone()).await;
+            assert!(matches!(result, Err(StorageError::InvalidStatus(_))));
+
+            let stored = storage.get(&tx.id).await.unwrap().unwrap();
+            assert!(stored.status == status);
+        }
+    }
+
+    #[tokio::test]
+    async fn it_should_set_priority() {
+        let storage = mock_sqlite().await;
+
+        let now = Utc::now();
+        let transactions = vec![
+            Transaction {
+                id: "older".into(),
+                created_at: now,
+                ..Default::default()
+            },
+            Transaction {
+                id: "newer".into(),
+                created_at: now + Duration::seconds(1),
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
+
+        let result = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(result.unwrap().id == "older");
+
+        let result = storage
+            .set_priority("newer", TransactionPriority::High)
+            .await;
+        assert!(result.is_ok());
+
+        let result = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        let transaction = result.unwrap();
+        assert!(transaction.id == "newer");
+        assert!(transaction.priority == TransactionPriority::High);
+    }
+
+    #[tokio::test]
+    async fn it_should_fail_set_priority_terminal() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Confirmed,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        let result = storage.set_priority("hex", TransactionPriority::High).await;
+        assert!(matches!(result, Err(StorageError::InvalidStatus(_))));
+
+        let result = storage
+            .set_priority("unknown", TransactionPriority::High)
+            .await;
+        assert!(matches!(result, Err(StorageError::NotFound(_))));
+
+        let transaction = storage.get("hex").await.unwrap().unwrap();
+        assert!(transaction.priority == TransactionPriority::Low);
+    }
+
+    #[tokio::test]
+    async fn it_should_cancel() {
+        let storage = mock_sqlite().await;
+        let transactions: Vec<Transaction> =
+            [TransactionStatus::Pending, TransactionStatus::Validated]
+                .into_iter()
+                .enumerate()
+                .map(|(i, status)| Transaction {
+                    id: format!("hex{i}"),
+                    status,
+                    ..Default::default()
+                })
+                .collect();
+        storage.create(&transactions).await.unwrap();
 
-        sqlx::query!(
-            r#"
-                UPDATE
-                	tx
-                SET
-                	raw = $1,
-                	status = $2,
-                	slot = $3,
-                	updated_at = $4
-                WHERE
-                	id = $5;
-            "#,
-            tx.raw,
-            status,
-            slot,
-            updated_at,
-            tx.id,
-        )
-        .execute(&self.sqlite.db)
-        .await?;
+        for transaction in transactions {
+            let result = storage.cancel(&transaction.id).await;
+            assert!(result.unwrap());
 
-        Ok(())
+            let transaction = storage.get(&transaction.id).await.unwrap().unwrap();
+            assert!(transaction.status == TransactionStatus::Cancelled);
+        }
     }
 
-    pub async fn update_batch(&self, txs: &Vec<Transaction>) -> Result<()> {
-        let mut db_tx = self.sqlite.db.begin().await?;
+    #[tokio::test]
+    async fn it_should_not_cancel_submitted() {
+        let storage = mock_sqlite().await;
+        let transactions: Vec<Transaction> = [
+            TransactionStatus::InFlight,
+            TransactionStatus::Confirmed,
+            TransactionStatus::Failed,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, status)| Transaction {
+            id: format!("hex{i}"),
+            status,
+            ..Default::default()
+        })
+        .collect();
+        storage.create(&transactions).await.unwrap();
 
-        for tx in txs {
-            let status = tx.status.to_string();
-            let updated_at = Utc::now();
-            // TODO: check the maximium size of i64 and compare with cardano slot.
-            let slot = tx.slot.map(|v| v as i64);
+        for transaction in transactions {
+            let result = storage.cancel(&transaction.id).await;
+            assert!(!result.unwrap());
 
-            sqlx::query!(
-                r#"
-                UPDATE
-                	tx
-                SET
-                	raw = $1,
-                	status = $2,
-                	slot = $3,
-                	updated_at = $4
-                WHERE
-                	id = $5;
-            "#,
-                tx.raw,
-                status,
-                slot,
-                updated_at,
-                tx.id,
-            )
-            .execute(&mut *db_tx)
-            .await?;
+            let stored = storage.get(&transaction.id).await.unwrap().unwrap();
+            assert!(stored.status == transaction.status);
         }
 
-        db_tx.commit().await?;
-        Ok(())
+        let result = storage.cancel("unknown").await;
+        assert!(!result.unwrap());
     }
-}
 
-impl FromRow<'_, SqliteRow> for Cursor {
-    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
-        Ok(Self {
-            slot: row.try_get("slot")?,
-            hash: row.try_get("hash")?,
-        })
+    #[tokio::test]
+    async fn it_should_resubmit() {
+        let storage = mock_sqlite().await;
+        let next_retry_at = Some(Utc::now() + Duration::minutes(10));
+        let transactions = vec![
+            Transaction {
+                id: "pending".into(),
+                next_retry_at,
+                ..Default::default()
+            },
+            Transaction {
+                id: "submitted".into(),
+                status: TransactionStatus::InFlight,
+                slot: Some(100),
+                claimed_by: Some("fanout-1".into()),
+                ..Default::default()
+            },
+            Transaction {
+                id: "claimed".into(),
+                status: TransactionStatus::InFlight,
+                claimed_by: Some("fanout-1".into()),
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
+        // the claim, slot and backoff aren't set by create
+        storage.update_batch(&transactions).await.unwrap();
+
+        for transaction in &transactions {
+            let result = storage.resubmit(&transaction.id).await;
+            assert!(result.unwrap());
+        }
+
+        let transaction = storage.get("pending").await.unwrap().unwrap();
+        assert!(transaction.status == TransactionStatus::Pending);
+        assert!(transaction.next_retry_at.is_none());
+
+        let transaction = storage.get("submitted").await.unwrap().unwrap();
+        assert!(transaction.status == TransactionStatus::Validated);
+        assert!(transaction.slot.is_none());
+        assert!(transaction.claimed_by.is_none());
+
+        // being submitted right now, it's left to the fanout holding it
+        let transaction = storage.get("claimed").await.unwrap().unwrap();
+        assert!(transaction.status == TransactionStatus::InFlight);
+        assert!(transaction.claimed_by.as_deref() == Some("fanout-1"));
     }
-}
 
-pub struct SqliteCursor {
-    sqlite: Arc<SqliteStorage>,
-}
-impl SqliteCursor {
-    pub fn new(sqlite: Arc<SqliteStorage>) -> Self {
-        Self { sqlite }
+    #[tokio::test]
+    async fn it_should_not_resubmit_terminal() {
+        let storage = mock_sqlite().await;
+        storage
+            .create(&vec![Transaction {
+                status: TransactionStatus::Confirmed,
+                ..Default::default()
+            }])
+            .await
+            .unwrap();
+
+        assert!(!storage.resubmit("hex").await.unwrap());
+        assert!(!storage.resubmit("unknown").await.unwrap());
     }
 
-    pub async fn set(&self, cursor: &Cursor) -> Result<()> {
-        let slot = cursor.slot as i64;
+    #[tokio::test]
+    async fn it_should_requeue_all() {
+        let storage = mock_sqlite().await;
 
-        sqlx::query!(
-            r#"
-                INSERT OR REPLACE INTO cursor(
-                    id,
-	                slot,
-	                hash
-                )
-                VALUES (0, $1, $2);
-            "#,
-            slot,
-            cursor.hash
-        )
-        .execute(&self.sqlite.db)
-        .await?;
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
 
-        Ok(())
-    }
+        let updated: Vec<Transaction> = transactions
+            .into_iter()
+            .zip([
+                TransactionStatus::DeadLetter,
+                TransactionStatus::DeadLetter,
+                TransactionStatus::Confirmed,
+            ])
+            .map(|(tx, status)| Transaction {
+                status,
+                error: Some("peer rejected".into()),
+                next_retry_at: Some(Utc::now() + Duration::minutes(1)),
+                ..tx
+            })
+            .collect();
+        storage.update_batch(&updated).await.unwrap();
 
-    pub async fn current(&self) -> Result<Option<Cursor>> {
-        let cursor = sqlx::query_as::<_, Cursor>(
-            r#"
-                    SELECT
-                    	slot,
-                    	hash
-                    FROM
-                    	cursor;
-            "#,
-        )
-        .fetch_optional(&self.sqlite.db)
-        .await?;
+        let result = storage.requeue_all(TransactionStatus::DeadLetter).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 2);
 
-        Ok(cursor)
+        let result = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(result.len() == 2);
+        assert!(result
+            .iter()
+            .all(|tx| tx.error.is_none() && tx.next_retry_at.is_none()));
+
+        let result = storage.find(TransactionStatus::Confirmed).await.unwrap();
+        assert!(result.len() == 1);
+
+        let result = storage.requeue_all(TransactionStatus::Confirmed).await;
+        assert!(matches!(result, Err(StorageError::InvalidStatus(_))));
     }
-}
 
-#[cfg(test)]
-mod sqlite_transaction_tests {
-    use std::sync::Arc;
+    #[tokio::test]
+    async fn it_should_increment_attempts() {
+        let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
 
-    use crate::storage::{Transaction, TransactionStatus};
+        let result = storage.increment_attempts(&transaction.id).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 1);
 
-    use super::{SqliteStorage, SqliteTransaction};
+        let result = storage.increment_attempts(&transaction.id).await;
+        assert!(result.unwrap() == 2);
 
-    async fn mock_sqlite() -> SqliteTransaction {
-        let sqlite_storage = Arc::new(SqliteStorage::ephemeral().await.unwrap());
-        SqliteTransaction::new(sqlite_storage)
+        let result = storage
+            .next(TransactionStatus::Pending, None)
+            .await
+            .unwrap();
+        assert!(result.unwrap().attempts == 2);
     }
 
     #[tokio::test]
-    async fn it_should_create() {
+    async fn it_should_fail_increment_attempts_when_it_does_not_exist() {
+        let storage = mock_sqlite().await;
+
+        let result = storage.increment_attempts("something").await;
+        assert!(matches!(result, Err(StorageError::NotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn it_should_delete() {
         let storage = mock_sqlite().await;
         let transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
 
-        let result = storage.create(&vec![transaction]).await;
+        let result = storage.delete(&transaction.id).await;
         assert!(result.is_ok());
+
+        let result = storage.find(TransactionStatus::Pending).await;
+        assert!(result.unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn it_should_create_with_dependencies() {
+    async fn it_should_delete_when_it_does_not_exist() {
+        let storage = mock_sqlite().await;
+
+        let result = storage.delete("something").await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn it_should_delete_many_with_dependencies() {
         let storage = mock_sqlite().await;
+
         let mut transaction_1 = Transaction::default();
         transaction_1.id = "hex1".into();
 
@@ -355,92 +4297,241 @@ mod sqlite_transaction_tests {
         transaction_2.id = "hex2".into();
         transaction_2.dependencies = Some(vec![transaction_1.id.clone()]);
 
-        let result = storage.create(&vec![transaction_1, transaction_2]).await;
+        let mut transaction_3 = Transaction::default();
+        transaction_3.id = "hex3".into();
+
+        storage
+            .create(&vec![transaction_1, transaction_2, transaction_3])
+            .await
+            .unwrap();
+
+        let result = storage.delete_many(&["hex1".into(), "hex2".into()]).await;
         assert!(result.is_ok());
+
+        let result = storage.find(TransactionStatus::Pending).await.unwrap();
+        assert!(result.len() == 1);
+        assert!(result[0].id == "hex3");
     }
 
     #[tokio::test]
-    async fn it_should_fail_create_with_invalid_dependencies() {
+    async fn it_should_find() {
         let storage = mock_sqlite().await;
+        let transaction = Transaction::default();
 
-        let mut transaction = Transaction::default();
-        transaction.dependencies = Some(vec!["something".into()]);
+        storage.create(&vec![transaction]).await.unwrap();
 
-        let result = storage.create(&vec![transaction]).await;
-        assert!(result.is_err());
+        let result = storage.find(TransactionStatus::Pending).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap().len() == 1);
     }
 
     #[tokio::test]
-    async fn it_should_find_next() {
+    async fn it_should_count_by_status() {
         let storage = mock_sqlite().await;
-        let transaction = Transaction::default();
 
-        storage.create(&vec![transaction]).await.unwrap();
+        let transactions = vec![
+            Transaction {
+                id: "hex1".into(),
+                ..Default::default()
+            },
+            Transaction {
+                id: "hex2".into(),
+                ..Default::default()
+            },
+            Transaction {
+                id: "hex3".into(),
+                status: TransactionStatus::Confirmed,
+                ..Default::default()
+            },
+        ];
+        storage.create(&transactions).await.unwrap();
 
-        let result = storage.next(TransactionStatus::Pending).await;
+        let result = storage.status_counts().await;
         assert!(result.is_ok());
-        assert!(result.unwrap().is_some());
+
+        let counts = result.unwrap();
+        assert!(counts.len() == 2);
+        assert!(counts[&TransactionStatus::Pending] == 2);
+        assert!(counts[&TransactionStatus::Confirmed] == 1);
     }
 
     #[tokio::test]
-    async fn it_should_update() {
+    async fn it_should_get_oldest_queued_at() {
         let storage = mock_sqlite().await;
+        let now = Utc::now();
 
-        let transaction = Transaction::default();
-        storage.create(&vec![transaction]).await.unwrap();
+        let result = storage.oldest_queued_at().await;
+        assert!(result.unwrap().is_none());
 
-        let mut transaction = Transaction::default();
-        transaction.status = TransactionStatus::Validated;
-        let result = storage.update(&transaction).await;
+        storage
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    status: TransactionStatus::Confirmed,
+                    created_at: now - Duration::hours(1),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    created_at: now - Duration::minutes(10),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex3".into(),
+                    created_at: now,
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let result = storage.oldest_queued_at().await;
+        assert!(result.unwrap() == Some(now - Duration::minutes(10)));
+    }
+
+    #[tokio::test]
+    async fn it_should_record_submissions() {
+        let storage = mock_sqlite().await;
+        storage.create(&vec![Transaction::default()]).await.unwrap();
+
+        storage
+            .record_submission("hex", "Node1:3001", true)
+            .await
+            .unwrap();
+        storage
+            .record_submission("hex", "Node2:3001", false)
+            .await
+            .unwrap();
+
+        let result = storage.submissions_for("hex").await;
         assert!(result.is_ok());
 
-        let result = storage.next(TransactionStatus::Validated).await;
+        let mut submissions = result.unwrap();
+        submissions.sort_by(|a, b| a.peer.cmp(&b.peer));
+        assert!(submissions.len() == 2);
+        assert!(submissions[0].peer == "Node1:3001" && submissions[0].accepted);
+        assert!(submissions[1].peer == "Node2:3001" && !submissions[1].accepted);
+        assert!(submissions
+            .iter()
+            .all(|submission| submission.tx_id == "hex"));
+
+        let result = storage.submissions_for("unknown").await.unwrap();
+        assert!(result.is_empty());
+    }
+
+    #[tokio::test]
+    async fn it_should_delete_with_submissions() {
+        let storage = mock_sqlite().await;
+        storage.create(&vec![Transaction::default()]).await.unwrap();
+        storage
+            .record_submission("hex", "Node1:3001", true)
+            .await
+            .unwrap();
+
+        let result = storage.delete("hex").await;
         assert!(result.is_ok());
-        assert!(result.unwrap().is_some());
+        assert!(storage.submissions_for("hex").await.unwrap().is_empty());
     }
 
     #[tokio::test]
-    async fn it_should_update_batch() {
+    async fn it_should_list() {
         let storage = mock_sqlite().await;
 
-        let mut batch = Vec::new();
+        let now = Utc::now();
+        let transactions: Vec<Transaction> = (0..3)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                created_at: now + Duration::seconds(i),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
 
-        let mut transaction = Transaction::default();
-        transaction.id = "hex1".into();
-        batch.push(transaction.clone());
-        storage.create(&vec![transaction]).await.unwrap();
+        let result = storage.list(None, None, None, 10, 0).await.unwrap();
+        let ids: Vec<String> = result.into_iter().map(|tx| tx.id).collect();
+        assert!(ids == vec!["hex2", "hex1", "hex0"]);
 
-        let mut transaction = Transaction::default();
-        transaction.id = "hex2".into();
-        batch.push(transaction.clone());
-        storage.create(&vec![transaction]).await.unwrap();
+        let result = storage.list(None, None, None, 1, 1).await.unwrap();
+        assert!(result.len() == 1);
+        assert!(result[0].id == "hex1");
+    }
 
-        let batch = batch
-            .iter_mut()
-            .map(|tx| {
-                tx.status = TransactionStatus::Confirmed;
-                tx.slot = Some(1);
-                tx.clone()
+    #[tokio::test]
+    async fn it_should_list_by_created_at() {
+        let storage = mock_sqlite().await;
+
+        let now = Utc::now();
+        let transactions: Vec<Transaction> = (0..5)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                status: if i == 2 {
+                    TransactionStatus::Validated
+                } else {
+                    TransactionStatus::Pending
+                },
+                created_at: now + Duration::seconds(i),
+                ..Default::default()
             })
             .collect();
-        let result = storage.update_batch(&batch).await;
-        assert!(result.is_ok());
+        storage.create(&transactions).await.unwrap();
 
-        let result = storage.next(TransactionStatus::Confirmed).await;
-        assert!(result.is_ok());
-        assert!(result.unwrap().is_some());
+        let ids = |result: Vec<Transaction>| -> Vec<String> {
+            result.into_iter().map(|tx| tx.id).collect()
+        };
+
+        // the bounds are inclusive
+        let after = Some(now + Duration::seconds(1));
+        let before = Some(now + Duration::seconds(3));
+        let result = storage.list(None, after, before, 10, 0).await.unwrap();
+        assert!(ids(result) == vec!["hex3", "hex2", "hex1"]);
+
+        let result = storage.list(None, after, None, 10, 0).await.unwrap();
+        assert!(ids(result) == vec!["hex4", "hex3", "hex2", "hex1"]);
+
+        let result = storage.list(None, None, before, 10, 0).await.unwrap();
+        assert!(ids(result) == vec!["hex3", "hex2", "hex1", "hex0"]);
+
+        let result = storage
+            .list(Some(TransactionStatus::Pending), after, before, 10, 0)
+            .await
+            .unwrap();
+        assert!(ids(result) == vec!["hex3", "hex1"]);
     }
 
     #[tokio::test]
-    async fn it_should_find() {
+    async fn it_should_list_by_status() {
         let storage = mock_sqlite().await;
-        let transaction = Transaction::default();
 
-        storage.create(&vec![transaction]).await.unwrap();
+        let mut transaction_1 = Transaction::default();
+        transaction_1.id = "hex1".into();
 
-        let result = storage.find(TransactionStatus::Pending).await;
-        assert!(result.is_ok());
-        assert!(result.unwrap().len() == 1);
+        let mut transaction_2 = Transaction::default();
+        transaction_2.id = "hex2".into();
+
+        storage
+            .create(&vec![transaction_1, transaction_2.clone()])
+            .await
+            .unwrap();
+
+        transaction_2.status = TransactionStatus::Validated;
+        storage.update(&transaction_2).await.unwrap();
+
+        let result = storage
+            .list(Some(TransactionStatus::Validated), None, None, 10, 0)
+            .await
+            .unwrap();
+        assert!(result.len() == 1);
+        assert!(result[0].id == "hex2");
+
+        let result = storage
+            .list(Some(TransactionStatus::Pending), None, None, 10, 0)
+            .await
+            .unwrap();
+        assert!(result.len() == 1);
+        assert!(result[0].id == "hex1");
+
+        let result = storage.list(None, None, None, 10, 0).await.unwrap();
+        assert!(result.len() == 2);
     }
 
     #[tokio::test]
@@ -462,6 +4553,44 @@ mod sqlite_transaction_tests {
         assert!(result.unwrap().len() == 1);
     }
 
+    #[tokio::test]
+    async fn it_should_rollback_to() {
+        let storage = mock_sqlite().await;
+
+        let transactions: Vec<Transaction> = (0..2)
+            .map(|i| Transaction {
+                id: format!("hex{i}"),
+                ..Default::default()
+            })
+            .collect();
+        storage.create(&transactions).await.unwrap();
+
+        let confirmed: Vec<Transaction> = [5, 15]
+            .into_iter()
+            .zip(transactions)
+            .map(|(slot, tx)| Transaction {
+                status: TransactionStatus::Confirmed,
+                slot: Some(slot),
+                confirmed_slot: Some(slot),
+                ..tx
+            })
+            .collect();
+        storage.update_batch(&confirmed).await.unwrap();
+
+        let result = storage.rollback_to(10).await;
+        assert!(result.is_ok());
+        assert!(result.unwrap() == 1);
+
+        let result = storage.get("hex0").await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Confirmed));
+        assert!(result.confirmed_slot == Some(5));
+
+        let result = storage.get("hex1").await.unwrap().unwrap();
+        assert!(matches!(result.status, TransactionStatus::Pending));
+        assert!(result.slot.is_none());
+        assert!(result.confirmed_slot.is_none());
+    }
+
     #[tokio::test]
     async fn it_should_return_empty_find_to_rollback_when_tx_slot_lower_than_block_slot() {
         let storage = mock_sqlite().await;
@@ -480,13 +4609,64 @@ mod sqlite_transaction_tests {
         assert!(result.is_ok());
         assert!(result.unwrap().is_empty());
     }
+
+    #[tokio::test]
+    async fn it_should_import_exported_snapshot() {
+        let source = mock_sqlite().await;
+        source
+            .create(&vec![
+                Transaction {
+                    id: "hex1".into(),
+                    raw: vec![1],
+                    priority: TransactionPriority::High,
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex2".into(),
+                    raw: vec![2],
+                    status: TransactionStatus::Validated,
+                    dependencies: Some(vec!["hex1".into()]),
+                    ..Default::default()
+                },
+                Transaction {
+                    id: "hex3".into(),
+                    raw: vec![3],
+                    status: TransactionStatus::Confirmed,
+                    ..Default::default()
+                },
+            ])
+            .await
+            .unwrap();
+
+        let snapshot = source.export_snapshot().await.unwrap();
+
+        let target = mock_sqlite().await;
+        assert!(target.import_snapshot(&snapshot).await.unwrap() == 2);
+        assert!(target.import_snapshot(&snapshot).await.is_ok());
+
+        let tx_1 = target.get("hex1").await.unwrap().unwrap();
+        assert!(tx_1.raw == vec![1]);
+        assert!(tx_1.status == TransactionStatus::Pending);
+        assert!(tx_1.priority == TransactionPriority::High);
+
+        let txs = target
+            .list(Some(TransactionStatus::Validated), None, None, 10, 0)
+            .await
+            .unwrap();
+        assert!(txs.len() == 1);
+        assert!(txs[0].id == "hex2");
+        assert!(txs[0].raw == vec![2]);
+        assert!(txs[0].dependencies == Some(vec!["hex1".into()]));
+
+        assert!(target.get("hex3").await.unwrap().is_none());
+    }
 }
 
 #[cfg(test)]
 mod sqlite_cursor_tests {
     use std::sync::Arc;
 
-    use crate::storage::Cursor;
+    use crate::storage::{Cursor, CursorStore};
 
     use super::{SqliteCursor, SqliteStorage};
 
@@ -524,6 +4704,17 @@ mod sqlite_cursor_tests {
         assert!(result.unwrap().unwrap().slot == 2);
     }
 
+    #[tokio::test]
+    async fn it_should_load_saved_cursor() {
+        let storage = mock_sqlite().await;
+        let cursor = Cursor::new(42, vec![0xab, 0xcd]);
+        storage.set(&cursor).await.unwrap();
+
+        let result = storage.current().await.unwrap().unwrap();
+        assert!(result.slot == 42);
+        assert!(result.hash == vec![0xab, 0xcd]);
+    }
+
     #[tokio::test]
     async fn it_should_find_current() {
         let storage = mock_sqlite().await;
@@ -0,0 +1,322 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+use crate::metrics::METRICS;
+
+use super::{
+    StorageError, Submission, Transaction, TransactionPriority, TransactionStatus, TransactionStore,
+};
+
+/// Decorates a transaction store timing the queries the pipeline runs the most
+/// into `storage_seconds`, and warning about the ones slower than
+/// `slow_query`.
+pub struct TimedTransactionStore {
+    inner: Arc<dyn TransactionStore>,
+    slow_query: Option<Duration>,
+}
+
+impl TimedTransactionStore {
+    pub fn new(inner: Arc<dyn TransactionStore>, slow_query: Option<Duration>) -> Self {
+        Self { inner, slow_query }
+    }
+
+    async fn timed<T>(
+        &self,
+        method: &str,
+        query: impl Future<Output = Result<T, StorageError>>,
+    ) -> Result<T, StorageError> {
+        let started = Instant::now();
+        let output = query.await;
+        let elapsed = started.elapsed();
+
+        METRICS
+            .storage_seconds
+            .with_label_values(&[method])
+            .observe(elapsed.as_secs_f64());
+
+        if self
+            .slow_query
+            .is_some_and(|slow_query| elapsed > slow_query)
+        {
+            warn!(
+                method,
+                elapsed_ms = elapsed.as_millis() as u64,
+                "slow storage query"
+            );
+            METRICS.slow_queries.with_label_values(&[method]).inc();
+        }
+
+        output
+    }
+}
+
+#[async_trait::async_trait]
+impl TransactionStore for TimedTransactionStore {
+    async fn create(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        self.timed("create", self.inner.create(txs)).await
+    }
+
+    async fn create_or_get(
+        &self,
+        txs: &Vec<Transaction>,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.timed("create", self.inner.create_or_get(txs)).await
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Transaction>, StorageError> {
+        self.inner.get(id).await
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.get_many(ids).await
+    }
+
+    async fn find(&self, status: TransactionStatus) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.find(status).await
+    }
+
+    async fn find_to_rollback(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.find_to_rollback(slot).await
+    }
+
+    async fn find_in_flight_before(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.find_in_flight_before(slot).await
+    }
+
+    async fn next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        self.timed("next", self.inner.next(status, namespace)).await
+    }
+
+    async fn next_batch(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.timed(
+            "next_batch",
+            self.inner.next_batch(status, namespace, limit),
+        )
+        .await
+    }
+
+    async fn next_ready(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        self.inner.next_ready(status, namespace).await
+    }
+
+    async fn claim_next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        worker_id: &str,
+    ) -> Result<Option<Transaction>, StorageError> {
+        self.timed(
+            "claim_next",
+            self.inner.claim_next(status, namespace, worker_id),
+        )
+        .await
+    }
+
+    async fn reclaim_expired(&self, older_than: Duration) -> Result<u64, StorageError> {
+        self.inner.reclaim_expired(older_than).await
+    }
+
+    async fn release_claims(&self, worker_id: &str) -> Result<u64, StorageError> {
+        self.inner.release_claims(worker_id).await
+    }
+
+    async fn expire_past_ttl(&self, current_slot: u64) -> Result<u64, StorageError> {
+        self.inner.expire_past_ttl(current_slot).await
+    }
+
+    async fn expire_past_deadline(&self, now: DateTime<Utc>) -> Result<u64, StorageError> {
+        self.inner.expire_past_deadline(now).await
+    }
+
+    async fn rollback_to(&self, slot: u64) -> Result<u64, StorageError> {
+        self.inner.rollback_to(slot).await
+    }
+
+    async fn list(
+        &self,
+        status: Option<TransactionStatus>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.inner
+            .list(status, created_after, created_before, limit, offset)
+            .await
+    }
+
+    async fn list_after(
+        &self,
+        after_created_at: Option<DateTime<Utc>>,
+        after_id: &str,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.inner
+            .list_after(after_created_at, after_id, limit)
+            .await
+    }
+
+    async fn update(&self, tx: &Transaction) -> Result<(), StorageError> {
+        self.timed("update", self.inner.update(tx)).await
+    }
+
+    async fn update_batch(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        self.timed("update_batch", self.inner.update_batch(txs))
+            .await
+    }
+
+    async fn mark_confirmed_many(&self, ids: &[String], slot: u64) -> Result<u64, StorageError> {
+        self.timed(
+            "mark_confirmed_many",
+            self.inner.mark_confirmed_many(ids, slot),
+        )
+        .await
+    }
+
+    async fn mark_failed(&self, id: &str, reason: &str) -> Result<(), StorageError> {
+        self.inner.mark_failed(id, reason).await
+    }
+
+    async fn requeue(&self, id: &str) -> Result<(), StorageError> {
+        self.inner.requeue(id).await
+    }
+
+    async fn requeue_all(&self, from: TransactionStatus) -> Result<u64, StorageError> {
+        self.inner.requeue_all(from).await
+    }
+
+    async fn set_priority(
+        &self,
+        id: &str,
+        priority: TransactionPriority,
+    ) -> Result<(), StorageError> {
+        self.inner.set_priority(id, priority).await
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool, StorageError> {
+        self.inner.cancel(id).await
+    }
+
+    async fn resubmit(&self, id: &str) -> Result<bool, StorageError> {
+        self.inner.resubmit(id).await
+    }
+
+    async fn increment_attempts(&self, id: &str) -> Result<u32, StorageError> {
+        self.inner.increment_attempts(id).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), StorageError> {
+        self.inner.delete(id).await
+    }
+
+    async fn delete_many(&self, ids: &[String]) -> Result<(), StorageError> {
+        self.inner.delete_many(ids).await
+    }
+
+    async fn truncate_all(&self) -> Result<(), StorageError> {
+        self.inner.truncate_all().await
+    }
+
+    async fn compact_confirmed_dependencies(&self, slot: u64) -> Result<u64, StorageError> {
+        self.inner.compact_confirmed_dependencies(slot).await
+    }
+
+    async fn prune_confirmed_before(&self, slot: u64) -> Result<u64, StorageError> {
+        self.inner.prune_confirmed_before(slot).await
+    }
+
+    async fn dependents_of(&self, id: &str) -> Result<Vec<String>, StorageError> {
+        self.inner.dependents_of(id).await
+    }
+
+    async fn all_dependents(&self, id: &str) -> Result<Vec<String>, StorageError> {
+        self.inner.all_dependents(id).await
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.inner.ping().await
+    }
+
+    async fn status_counts(&self) -> Result<HashMap<TransactionStatus, i64>, StorageError> {
+        self.inner.status_counts().await
+    }
+
+    async fn oldest_queued_at(&self) -> Result<Option<DateTime<Utc>>, StorageError> {
+        self.inner.oldest_queued_at().await
+    }
+
+    async fn record_submission(
+        &self,
+        tx_id: &str,
+        peer: &str,
+        accepted: bool,
+    ) -> Result<(), StorageError> {
+        self.inner.record_submission(tx_id, peer, accepted).await
+    }
+
+    async fn submissions_for(&self, tx_id: &str) -> Result<Vec<Submission>, StorageError> {
+        self.inner.submissions_for(tx_id).await
+    }
+}
+
+#[cfg(test)]
+mod timed_transaction_store_tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        metrics::METRICS,
+        storage::{mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore},
+    };
+
+    use super::TimedTransactionStore;
+
+    #[tokio::test]
+    async fn it_should_count_slow_queries() {
+        let storage = TimedTransactionStore::new(
+            Arc::new(MockTransactionStore::default()),
+            Some(Duration::ZERO),
+        );
+        let count = |method: &str| METRICS.slow_queries.with_label_values(&[method]).get();
+        let (created, updated) = (count("create"), count("update"));
+        let timed = METRICS
+            .storage_seconds
+            .with_label_values(&["update"])
+            .get_sample_count();
+
+        let mut transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        transaction.status = TransactionStatus::Validated;
+        storage.update(&transaction).await.unwrap();
+
+        assert!(count("create") > created);
+        assert!(count("update") > updated);
+        assert!(
+            METRICS
+                .storage_seconds
+                .with_label_values(&["update"])
+                .get_sample_count()
+                > timed
+        );
+    }
+}
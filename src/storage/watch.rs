@@ -0,0 +1,326 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+
+use super::{
+    StorageError, Submission, Transaction, TransactionPriority, TransactionStatus, TransactionStore,
+};
+
+/// Decorates a transaction store publishing every transaction written through
+/// it, so subscribers are notified of the status transitions without polling.
+pub struct WatchedTransactionStore {
+    inner: Arc<dyn TransactionStore>,
+    events: broadcast::Sender<Transaction>,
+}
+
+impl WatchedTransactionStore {
+    pub fn new(inner: Arc<dyn TransactionStore>, events: broadcast::Sender<Transaction>) -> Self {
+        Self { inner, events }
+    }
+
+    fn publish(&self, tx: &Transaction) {
+        // an error only means there are no subscribers
+        let _ = self.events.send(tx.clone());
+    }
+
+    async fn publish_id(&self, id: &str) {
+        if let Ok(Some(tx)) = self.inner.get(id).await {
+            self.publish(&tx);
+        }
+    }
+
+    /// Publishes the transactions blocked by the failure of `id`.
+    async fn publish_dependents(&self, id: &str) {
+        for dependent_id in self.inner.all_dependents(id).await.unwrap_or_default() {
+            self.publish_id(&dependent_id).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TransactionStore for WatchedTransactionStore {
+    async fn create(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        self.inner.create(txs).await?;
+        txs.iter().for_each(|tx| self.publish(tx));
+        for replaced_id in txs.iter().filter_map(|tx| tx.replaces.as_ref()) {
+            self.publish_id(replaced_id).await;
+        }
+        Ok(())
+    }
+
+    async fn create_or_get(
+        &self,
+        txs: &Vec<Transaction>,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        let stored = self.inner.create_or_get(txs).await?;
+        stored.iter().for_each(|tx| self.publish(tx));
+        for replaced_id in txs.iter().filter_map(|tx| tx.replaces.as_ref()) {
+            self.publish_id(replaced_id).await;
+        }
+        Ok(stored)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Transaction>, StorageError> {
+        self.inner.get(id).await
+    }
+
+    async fn get_many(&self, ids: &[String]) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.get_many(ids).await
+    }
+
+    async fn find(&self, status: TransactionStatus) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.find(status).await
+    }
+
+    async fn find_to_rollback(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.find_to_rollback(slot).await
+    }
+
+    async fn find_in_flight_before(&self, slot: u64) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.find_in_flight_before(slot).await
+    }
+
+    async fn next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        self.inner.next(status, namespace).await
+    }
+
+    async fn next_batch(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.inner.next_batch(status, namespace, limit).await
+    }
+
+    async fn next_ready(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+    ) -> Result<Option<Transaction>, StorageError> {
+        self.inner.next_ready(status, namespace).await
+    }
+
+    async fn claim_next(
+        &self,
+        status: TransactionStatus,
+        namespace: Option<&str>,
+        worker_id: &str,
+    ) -> Result<Option<Transaction>, StorageError> {
+        let tx = self.inner.claim_next(status, namespace, worker_id).await?;
+        if let Some(tx) = &tx {
+            self.publish(tx);
+        }
+        Ok(tx)
+    }
+
+    async fn reclaim_expired(&self, older_than: Duration) -> Result<u64, StorageError> {
+        self.inner.reclaim_expired(older_than).await
+    }
+
+    async fn release_claims(&self, worker_id: &str) -> Result<u64, StorageError> {
+        self.inner.release_claims(worker_id).await
+    }
+
+    async fn expire_past_ttl(&self, current_slot: u64) -> Result<u64, StorageError> {
+        self.inner.expire_past_ttl(current_slot).await
+    }
+
+    async fn expire_past_deadline(&self, now: DateTime<Utc>) -> Result<u64, StorageError> {
+        self.inner.expire_past_deadline(now).await
+    }
+
+    async fn rollback_to(&self, slot: u64) -> Result<u64, StorageError> {
+        self.inner.rollback_to(slot).await
+    }
+
+    async fn list(
+        &self,
+        status: Option<TransactionStatus>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.inner
+            .list(status, created_after, created_before, limit, offset)
+            .await
+    }
+
+    async fn list_after(
+        &self,
+        after_created_at: Option<DateTime<Utc>>,
+        after_id: &str,
+        limit: i64,
+    ) -> Result<Vec<Transaction>, StorageError> {
+        self.inner
+            .list_after(after_created_at, after_id, limit)
+            .await
+    }
+
+    async fn update(&self, tx: &Transaction) -> Result<(), StorageError> {
+        self.inner.update(tx).await?;
+        self.publish(tx);
+        if tx.status.blocks_dependents() {
+            self.publish_dependents(&tx.id).await;
+        }
+        Ok(())
+    }
+
+    async fn update_batch(&self, txs: &Vec<Transaction>) -> Result<(), StorageError> {
+        self.inner.update_batch(txs).await?;
+        for tx in txs {
+            self.publish(tx);
+            if tx.status.blocks_dependents() {
+                self.publish_dependents(&tx.id).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn mark_confirmed_many(&self, ids: &[String], slot: u64) -> Result<u64, StorageError> {
+        let confirmed = self.inner.mark_confirmed_many(ids, slot).await?;
+        for id in ids {
+            self.publish_id(id).await;
+        }
+        Ok(confirmed)
+    }
+
+    async fn mark_failed(&self, id: &str, reason: &str) -> Result<(), StorageError> {
+        self.inner.mark_failed(id, reason).await?;
+        self.publish_id(id).await;
+        self.publish_dependents(id).await;
+        Ok(())
+    }
+
+    async fn requeue(&self, id: &str) -> Result<(), StorageError> {
+        self.inner.requeue(id).await?;
+        self.publish_id(id).await;
+        Ok(())
+    }
+
+    async fn requeue_all(&self, from: TransactionStatus) -> Result<u64, StorageError> {
+        self.inner.requeue_all(from).await
+    }
+
+    async fn set_priority(
+        &self,
+        id: &str,
+        priority: TransactionPriority,
+    ) -> Result<(), StorageError> {
+        self.inner.set_priority(id, priority).await?;
+        self.publish_id(id).await;
+        Ok(())
+    }
+
+    async fn cancel(&self, id: &str) -> Result<bool, StorageError> {
+        let cancelled = self.inner.cancel(id).await?;
+        if cancelled {
+            self.publish_id(id).await;
+            self.publish_dependents(id).await;
+        }
+        Ok(cancelled)
+    }
+
+    async fn resubmit(&self, id: &str) -> Result<bool, StorageError> {
+        let resubmitted = self.inner.resubmit(id).await?;
+        if resubmitted {
+            self.publish_id(id).await;
+        }
+        Ok(resubmitted)
+    }
+
+    async fn increment_attempts(&self, id: &str) -> Result<u32, StorageError> {
+        self.inner.increment_attempts(id).await
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), StorageError> {
+        self.inner.delete(id).await
+    }
+
+    async fn delete_many(&self, ids: &[String]) -> Result<(), StorageError> {
+        self.inner.delete_many(ids).await
+    }
+
+    async fn truncate_all(&self) -> Result<(), StorageError> {
+        self.inner.truncate_all().await
+    }
+
+    async fn compact_confirmed_dependencies(&self, slot: u64) -> Result<u64, StorageError> {
+        self.inner.compact_confirmed_dependencies(slot).await
+    }
+
+    async fn prune_confirmed_before(&self, slot: u64) -> Result<u64, StorageError> {
+        self.inner.prune_confirmed_before(slot).await
+    }
+
+    async fn dependents_of(&self, id: &str) -> Result<Vec<String>, StorageError> {
+        self.inner.dependents_of(id).await
+    }
+
+    async fn all_dependents(&self, id: &str) -> Result<Vec<String>, StorageError> {
+        self.inner.all_dependents(id).await
+    }
+
+    async fn ping(&self) -> Result<(), StorageError> {
+        self.inner.ping().await
+    }
+
+    async fn status_counts(&self) -> Result<HashMap<TransactionStatus, i64>, StorageError> {
+        self.inner.status_counts().await
+    }
+
+    async fn oldest_queued_at(&self) -> Result<Option<DateTime<Utc>>, StorageError> {
+        self.inner.oldest_queued_at().await
+    }
+
+    async fn record_submission(
+        &self,
+        tx_id: &str,
+        peer: &str,
+        accepted: bool,
+    ) -> Result<(), StorageError> {
+        self.inner.record_submission(tx_id, peer, accepted).await
+    }
+
+    async fn submissions_for(&self, tx_id: &str) -> Result<Vec<Submission>, StorageError> {
+        self.inner.submissions_for(tx_id).await
+    }
+}
+
+#[cfg(test)]
+mod watched_transaction_store_tests {
+    use std::sync::Arc;
+
+    use tokio::sync::broadcast;
+
+    use crate::storage::{
+        mock::MockTransactionStore, Transaction, TransactionStatus, TransactionStore,
+    };
+
+    use super::WatchedTransactionStore;
+
+    #[tokio::test]
+    async fn it_should_publish_updates() {
+        let (sender, mut events) = broadcast::channel(16);
+        let storage =
+            WatchedTransactionStore::new(Arc::new(MockTransactionStore::default()), sender);
+
+        let mut transaction = Transaction::default();
+        storage.create(&vec![transaction.clone()]).await.unwrap();
+
+        transaction.status = TransactionStatus::Validated;
+        storage.update(&transaction).await.unwrap();
+
+        storage.mark_failed(&transaction.id, "boom").await.unwrap();
+
+        assert!(events.recv().await.unwrap().status == TransactionStatus::Pending);
+        assert!(events.recv().await.unwrap().status == TransactionStatus::Validated);
+        assert!(events.recv().await.unwrap().status == TransactionStatus::Failed);
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::Result;
+use itertools::Itertools;
+use pallas::ledger::{
+    addresses::Address,
+    traverse::{Era, MultiEraTx},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ValidationError {
+    #[error("empty transaction")]
+    Empty,
+
+    #[error("not a well-formed conway transaction: {0}")]
+    Malformed(#[from] pallas::ledger::traverse::Error),
+}
+
+/// Checks the bytes decode as a Conway-era transaction so garbage is
+/// rejected before it reaches storage.
+pub fn validate_tx_cbor(bytes: &[u8]) -> Result<(), ValidationError> {
+    if bytes.is_empty() {
+        return Err(ValidationError::Empty);
+    }
+
+    MultiEraTx::decode_for_era(Era::Conway, bytes)?;
+    Ok(())
+}
+
+/// Blake2b-256 of the transaction body, hex encoded. This is the canonical tx
+/// id, so it must be used instead of any id supplied by a client.
+pub fn compute_tx_hash(raw: &[u8]) -> Result<String> {
+    let tx = MultiEraTx::decode(raw)?;
+    Ok(tx.hash().to_string())
+}
+
+/// Ids of the transactions the transaction spends an output of.
+pub fn spent_tx_ids(raw: &[u8]) -> Result<Vec<String>> {
+    let tx = MultiEraTx::decode(raw)?;
+    Ok(tx
+        .inputs()
+        .iter()
+        .map(|input| input.hash().to_string())
+        .unique()
+        .collect())
+}
+
+/// Network ids, 1 on mainnet and 0 on the testnets, of the addresses the
+/// transaction pays to or withdraws from. Byron addresses carry none.
+pub fn tx_network_ids(raw: &[u8]) -> Result<Vec<u8>> {
+    let tx = MultiEraTx::decode(raw)?;
+
+    let mut addresses = tx
+        .outputs()
+        .iter()
+        .map(|output| output.address())
+        .collect::<Result<Vec<_>, _>>()?;
+    for (account, _) in tx.withdrawals_sorted_set() {
+        addresses.push(Address::from_bytes(account)?);
+    }
+
+    Ok(addresses
+        .iter()
+        .filter_map(Address::network)
+        .map(|network| network.value())
+        .unique()
+        .collect())
+}
+
+/// The metadata of a transaction is opaque to boros, it's only checked to be
+/// JSON.
+pub fn validate_metadata(metadata: &str) -> Result<()> {
+    serde_json::from_str::<serde_json::Value>(metadata)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod validation_tests {
+    use crate::fixtures::{TX_CBOR, TX_HASH};
+
+    use super::{compute_tx_hash, spent_tx_ids, tx_network_ids, validate_tx_cbor, ValidationError};
+
+    #[test]
+    fn it_should_validate_tx_cbor() {
+        let raw = hex::decode(TX_CBOR).unwrap();
+
+        let result = validate_tx_cbor(&raw);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn it_should_fail_validate_truncated_tx_cbor() {
+        let raw = hex::decode(TX_CBOR).unwrap();
+
+        let result = validate_tx_cbor(&raw[..raw.len() / 2]);
+        assert!(matches!(result, Err(ValidationError::Malformed(_))));
+    }
+
+    #[test]
+    fn it_should_fail_validate_empty_tx_cbor() {
+        let result = validate_tx_cbor(&[]);
+        assert!(matches!(result, Err(ValidationError::Empty)));
+    }
+
+    #[test]
+    fn it_should_compute_tx_hash() {
+        let raw = hex::decode(TX_CBOR).unwrap();
+
+        let result = compute_tx_hash(&raw);
+        assert!(result.is_ok());
+        assert!(result.unwrap() == TX_HASH);
+    }
+
+    #[test]
+    fn it_should_read_spent_tx_ids() {
+        let raw = hex::decode(TX_CBOR).unwrap();
+
+        // both inputs spend outputs of the same tx
+        let result = spent_tx_ids(&raw);
+        assert!(result.is_ok());
+        assert!(
+            result.unwrap()
+                == vec!["2000fbfaa10c6b316fe4b23c60b42313fc2ad89b51c7397f82a4a5ca97bd62a9"]
+        );
+    }
+
+    #[test]
+    fn it_should_read_tx_network_ids() {
+        let raw = hex::decode(TX_CBOR).unwrap();
+
+        // pays to a testnet enterprise address
+        let result = tx_network_ids(&raw);
+        assert!(result.is_ok());
+        assert!(result.unwrap() == vec![0]);
+    }
+
+    #[test]
+    fn it_should_fail_compute_tx_hash_malformed_cbor() {
+        let result = compute_tx_hash(&[0xde, 0xad, 0xbe, 0xef]);
+        assert!(result.is_err());
+    }
+}
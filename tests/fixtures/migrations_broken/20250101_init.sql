@@ -0,0 +1 @@
+CREATE TABLE kept (id INTEGER NOT NULL);
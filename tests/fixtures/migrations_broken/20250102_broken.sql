@@ -0,0 +1,3 @@
+-- Creates a table and then fails, the table must be rolled back with it
+CREATE TABLE partial (id INTEGER NOT NULL);
+INSERT INTO missing (id) VALUES (1);